@@ -0,0 +1,54 @@
+use kash::kash;
+use moka::Expiry;
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+struct Response {
+    body: String,
+    max_age: Duration,
+}
+
+// Expire each response after its own `max_age`, like a `Cache-Control: max-age` header
+struct MaxAge;
+
+impl Expiry<String, Response> for MaxAge {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &Response,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.max_age)
+    }
+}
+
+#[kash(expiry = "MaxAge")]
+fn fetch(url: String) -> Response {
+    println!("fetching {url}");
+    let max_age = if url.ends_with("/static") {
+        Duration::from_secs(60)
+    } else {
+        Duration::from_secs(1)
+    };
+    Response {
+        body: format!("body of {url}"),
+        max_age,
+    }
+}
+
+pub fn main() {
+    let _ = fetch("https://example.com/static".to_string());
+    let _ = fetch("https://example.com/dynamic".to_string());
+
+    sleep(Duration::from_millis(1500));
+
+    println!("Only the dynamic response is fetched again...");
+    let _ = fetch("https://example.com/static".to_string());
+    let response = fetch("https://example.com/dynamic".to_string());
+    println!("{}", response.body);
+
+    println!("done!");
+}
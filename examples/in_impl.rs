@@ -1,9 +1,10 @@
-use kash::kash;
+use kash::{kash, kash_impl};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 struct Example;
 
+#[kash_impl]
 impl Example {
     #[kash(size = "50", in_impl)]
     pub fn slow_fn(n: u32) -> String {
@@ -93,5 +94,8 @@ pub fn main() {
         // make sure the cache-lock is dropped
     }
 
+    // Clear the caches of all the methods at once
+    Example::kash_clear_all();
+
     println!("done!");
 }
@@ -0,0 +1,20 @@
+use kash::kash;
+
+// An `impl Iterator` can't be cached, so `collect` caches the collected values,
+// and returns an iterator over a clone of them
+#[kash(collect)]
+fn primes_up_to(n: u32) -> impl Iterator<Item = u32> {
+    println!("sieving the primes up to {n}");
+    (2..=n).filter(|i| (2..*i).take_while(|d| d * d <= *i).all(|d| i % d != 0))
+}
+
+pub fn main() {
+    let primes: Vec<u32> = primes_up_to(30).collect();
+    println!("{primes:?}");
+
+    println!("The primes are cached...");
+    let sum: u32 = primes_up_to(30).sum();
+    println!("{sum}");
+
+    println!("done!");
+}
@@ -0,0 +1,39 @@
+use kash::kash;
+use std::hash::{Hash, Hasher};
+
+// A user name compared ignoring its case, which keeps the name as it was typed,
+// unlike a `normalize` lowercasing it
+#[derive(Clone, Debug)]
+struct UserName(String);
+
+impl PartialEq for UserName {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for UserName {}
+
+impl Hash for UserName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.0.bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+    }
+}
+
+#[kash(key_wrapper = "UserName")]
+fn profile(name: String) -> String {
+    println!("loading the profile of {name}");
+    format!("the profile of {name}")
+}
+
+pub fn main() {
+    println!("{}", profile("Alice".to_string()));
+
+    println!("The profile of alice is cached...");
+    println!("{}", profile("alice".to_string()));
+    println!("{}", profile("ALICE".to_string()));
+
+    println!("done!");
+}
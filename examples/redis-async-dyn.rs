@@ -0,0 +1,69 @@
+/*
+Choose the store of a cache at runtime, behind a `Box<dyn IOKashAsync>`.
+Without the env variable, the example uses a store in memory:
+    cargo run --example redis-async-dyn --features "redis_tokio"
+To use redis, start a redis docker image if you don't already have it running locally:
+    docker run --rm --name async-kash-redis-dyn-example -p 6379:6379 -d redis
+Set the env variable and run this example:
+    KASH_REDIS_CONNECTION_STRING=redis://127.0.0.1:6379 cargo run --example redis-async-dyn --features "redis_tokio"
+Cleanup the redis docker container:
+    docker rm -f async-kash-redis-dyn-example
+ */
+
+use kash::{async_trait, AsyncRedisCache, IOKashAsync, RedisCacheError};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+type Store = Box<dyn IOKashAsync<String, String, Error = RedisCacheError> + Send + Sync>;
+
+// a store in memory, with the error of the redis store, so both can be used as a `Store`
+#[derive(Default)]
+struct MemoryStore(Mutex<HashMap<String, String>>);
+
+#[async_trait]
+impl IOKashAsync<String, String> for MemoryStore {
+    type Error = RedisCacheError;
+
+    async fn get(&self, k: &String) -> Result<Option<String>, Self::Error> {
+        Ok(self.0.lock().unwrap().get(k).cloned())
+    }
+
+    async fn set(&self, k: String, v: String) -> Result<Option<String>, Self::Error> {
+        Ok(self.0.lock().unwrap().insert(k, v))
+    }
+
+    async fn remove(&self, k: &String) -> Result<Option<String>, Self::Error> {
+        Ok(self.0.lock().unwrap().remove(k))
+    }
+}
+
+async fn store() -> Store {
+    if std::env::var("KASH_REDIS_CONNECTION_STRING").is_ok() {
+        println!("using redis");
+        let cache = AsyncRedisCache::new("kash-redis-dyn-example:", Some(30))
+            .build()
+            .await
+            .expect("error building the redis cache");
+        Box::new(cache)
+    } else {
+        println!("using memory");
+        Box::new(MemoryStore::default())
+    }
+}
+
+async fn greet(store: &Store, name: &str) -> Result<String, RedisCacheError> {
+    if let Some(greeting) = store.get(&name.to_string()).await? {
+        return Ok(greeting);
+    }
+    let greeting = format!("hello, {name}");
+    store.set(name.to_string(), greeting.clone()).await?;
+    Ok(greeting)
+}
+
+#[tokio::main]
+async fn main() {
+    let store = store().await;
+    println!("{}", greet(&store, "kash").await.unwrap());
+    println!("{}", greet(&store, "kash").await.unwrap());
+    store.remove(&"kash".to_string()).await.unwrap();
+}
@@ -0,0 +1,26 @@
+use kash::kash;
+use smartstring::alias::String;
+
+// Any key type implementing `Hash + Eq + Clone + Send + Sync + 'static` can be used,
+// here the arguments are used as the key, so the key type is `smartstring::alias::String`
+#[kash(size = "2")]
+fn kash_smartstring(s: String) -> String {
+    println!("comparing {s}");
+    if s == "very stringy" {
+        String::from("equal")
+    } else {
+        String::from("not equal")
+    }
+}
+
+pub fn main() {
+    let string = String::from("very stringy");
+    assert_eq!("equal", kash_smartstring(string.clone()));
+
+    println!("Only the new string is compared...");
+    assert_eq!("equal", kash_smartstring(string.clone()));
+    assert!(KASH_SMARTSTRING.contains_key(&string));
+    assert_eq!("not equal", kash_smartstring(String::from("also stringy")));
+
+    println!("done!");
+}
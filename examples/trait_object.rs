@@ -0,0 +1,57 @@
+use kash::kash;
+use std::sync::Arc;
+
+// A trait object can be cached, since an `Arc<dyn Trait>` is `Clone` and sized, if it's
+// `Send + Sync`, like every cached value
+trait Shape {
+    fn area(&self) -> f64;
+}
+
+struct Square(f64);
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.0 * self.0
+    }
+}
+
+struct Circle(f64);
+
+impl Shape for Circle {
+    fn area(&self) -> f64 {
+        std::f64::consts::PI * self.0 * self.0
+    }
+}
+
+// Return the `Arc` itself
+#[kash]
+fn shape(name: String) -> Arc<dyn Shape + Send + Sync> {
+    println!("making a {name}");
+    match name.as_str() {
+        "square" => Arc::new(Square(2.0)),
+        _ => Arc::new(Circle(1.0)),
+    }
+}
+
+// Or box the trait object, and cache the box in an `Arc` with `arc`
+#[kash(arc)]
+fn boxed_shape(name: String) -> Box<dyn Shape + Send + Sync> {
+    println!("boxing a {name}");
+    match name.as_str() {
+        "square" => Box::new(Square(3.0)),
+        _ => Box::new(Circle(2.0)),
+    }
+}
+
+pub fn main() {
+    println!("{}", shape("square".to_string()).area());
+    println!("{}", shape("circle".to_string()).area());
+
+    println!("The shapes are cached...");
+    println!("{}", shape("square".to_string()).area());
+    let boxed: Arc<Box<dyn Shape + Send + Sync>> = boxed_shape("circle".to_string());
+    println!("{}", boxed.area());
+    println!("{}", boxed_shape("circle".to_string()).area());
+
+    println!("done!");
+}
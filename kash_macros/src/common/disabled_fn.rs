@@ -0,0 +1,85 @@
+use crate::common::macro_args::MacroArgs;
+use crate::common::{gen_normalize, get_input_names};
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_quote, Block, Item, ItemFn, ReturnType};
+
+// the items of a `disabled` cache, i.e., the items generated for the cache, whose functions evaluating or storing
+// a value are rewritten to evaluate the function without the cache, or to store nothing, so the call sites of all
+// the generated items compile unchanged. The cache is still generated, but stays empty, so the functions inspecting
+// it (e.g. `{fn}_cache()` or `{fn}_key`) are kept as they are.
+// `wrap` wraps the value of the original function like the cached function, e.g. in an `Arc` with `arc`
+pub(crate) fn gen_disabled_fns(
+    input: &ItemFn,
+    args: &MacroArgs,
+    items: TokenStream,
+    wrap: fn(&MacroArgs, TokenStream) -> TokenStream,
+) -> TokenStream {
+    let signature = &input.sig;
+    let fn_ident = &signature.ident;
+    let (maybe_with_self_names, _) = get_input_names(&signature.inputs);
+    let call_prefix = if args.in_impl {
+        quote! { Self:: }
+    } else {
+        quote! {}
+    };
+    let may_await = if signature.asyncness.is_some() {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    let no_cache_fn_ident = quote::format_ident!("{}_no_cache", fn_ident);
+    let normalize = gen_normalize(args);
+    let value = wrap(
+        args,
+        quote! { #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await },
+    );
+
+    let evaluated: Block = parse_quote! {{
+        #normalize
+        #value
+    }};
+    let refreshed: Block = parse_quote! {{
+        #normalize
+        (#value, false)
+    }};
+    let computed: Block = if signature.asyncness.is_some() {
+        parse_quote! {{ kash_compute.await }}
+    } else {
+        parse_quote! {{ kash_compute() }}
+    };
+    let not_cached: Block = parse_quote! {{ false }};
+
+    let mut file = syn::parse2::<syn::File>(items).expect("the generated items are valid");
+    for item in &mut file.items {
+        let Item::Fn(item_fn) = item else {
+            continue;
+        };
+        let Some(suffix) = item_fn
+            .sig
+            .ident
+            .to_string()
+            .strip_prefix(&fn_ident.to_string())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let block = match suffix.as_str() {
+            "" | "_prime_cache" | "_with_ttl" | "_refresh_if_stale" => evaluated.clone(),
+            "_refresh_cache" => refreshed.clone(),
+            "_get_or_compute" => computed.clone(),
+            "_is_cached" => not_cached.clone(),
+            // the `Result` of the stores of `disk` and `redis` caches
+            "_set_cache" => match item_fn.sig.output {
+                ReturnType::Default => parse_quote! {{}},
+                ReturnType::Type(..) => parse_quote! {{ Ok(()) }},
+            },
+            _ => continue,
+        };
+        *item_fn.block = block;
+        item_fn
+            .attrs
+            .push(parse_quote! { #[allow(unused_mut, unused_variables)] });
+    }
+    file.into_token_stream()
+}
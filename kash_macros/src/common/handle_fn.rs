@@ -0,0 +1,85 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{Ident, ItemFn};
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{gen_cache_vis, gen_trait_bounds};
+
+// the operations of a `KashHandle` for a backend, as closures which don't capture anything
+pub struct HandleOps {
+    pub len: TokenStream,
+    pub clear: TokenStream,
+    pub remove: TokenStream,
+    pub contains: TokenStream,
+    pub iter: TokenStream,
+}
+
+// types of a `KashHandle`, see its docs
+pub struct HandleTypes {
+    pub key: TokenStream,
+    pub value: TokenStream,
+    pub error: TokenStream,
+    pub stored_key: TokenStream,
+}
+
+// struct for the function returning a `KashHandle` to the cache
+pub struct HandleFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+    types: HandleTypes,
+    ops: HandleOps,
+}
+
+impl<'a> HandleFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs, types: HandleTypes, ops: HandleOps) -> Self {
+        Self {
+            input,
+            args,
+            types,
+            ops,
+        }
+    }
+}
+
+impl ToTokens for HandleFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        // the operations of async caches are async, so they don't fit in a `KashHandle`
+        if self.input.sig.asyncness.is_some() {
+            return;
+        }
+
+        let fn_ident = &self.input.sig.ident;
+        let handle_fn_ident = Ident::new(&format!("{}_cache", fn_ident), fn_ident.span());
+        let handle_fn_ident_doc = format!(
+            "Returns a handle to the cache of the function [`{}`].",
+            fn_ident
+        );
+        let visibility = gen_cache_vis(self.args, self.input);
+
+        let HandleTypes {
+            key,
+            value,
+            error,
+            stored_key,
+        } = &self.types;
+        let HandleOps {
+            len,
+            clear,
+            remove,
+            contains,
+            iter,
+        } = &self.ops;
+
+        let where_clause = gen_trait_bounds(self.args);
+
+        let expanded = quote! {
+            #[doc = #handle_fn_ident_doc]
+            #[allow(dead_code)]
+            #visibility fn #handle_fn_ident() -> ::kash::KashHandle<#key, #value, #error, #stored_key> #where_clause {
+                ::kash::KashHandle::new(#len, #clear, #remove, #contains, #iter)
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
@@ -1,8 +1,17 @@
+use std::collections::HashMap;
+
 use darling::{ast::NestedMeta, FromMeta};
 use proc_macro::TokenStream;
-use quote::quote;
-use std::ops::Deref;
-use syn::{Error, ItemFn, PathArguments, ReturnType, Type};
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, Parser};
+use syn::spanned::Spanned;
+use syn::{
+    parse_str, Block, Error, Expr, ExprLit, ExprPath, ItemFn, Lit, LitStr, Meta, Path, ReturnType,
+    Type, Visibility,
+};
+
+use crate::common::{find_generic_param, first_type_argument, get_input_types, iterator_item_type};
 
 #[derive(FromMeta, Clone, Debug)]
 #[darling(and_then = "Self::init_validate")]
@@ -10,42 +19,141 @@ pub struct MacroArgs {
     #[darling(default)]
     pub name: Option<String>,
     #[darling(default)]
+    pub display_name: Option<String>,
+    #[darling(default)]
+    pub cache_vis: Option<String>,
+    #[darling(default)]
+    pub shared_cache: Option<String>,
+    #[darling(default)]
     pub ttl: Option<String>,
     #[darling(default)]
+    pub ttl_ms: Option<String>,
+    #[darling(default)]
+    pub expiry: Option<String>,
+    #[darling(default)]
+    pub ttl_for: Option<String>,
+    #[darling(default)]
+    pub skip_if: Option<String>,
+    #[darling(default)]
     pub key: Option<KeyArgs>,
     #[darling(default)]
     pub result: bool,
     #[darling(default)]
     pub option: bool,
     #[darling(default)]
+    pub result_option: bool,
+    #[darling(default)]
+    pub cache_errors: bool,
+    #[darling(default)]
     pub in_impl: bool,
+    #[darling(default)]
+    pub in_trait: bool,
+    #[darling(default)]
+    pub disabled: bool,
+    #[darling(default)]
+    pub trace: bool,
+    #[darling(default)]
+    pub redact_key: bool,
+    #[darling(default)]
+    pub refresh_cache: bool,
+    #[darling(default)]
+    pub stats: bool,
+    #[darling(default)]
+    pub normalize: Option<String>,
+    #[darling(default)]
+    pub generation: Option<String>,
+    #[darling(default)]
+    pub schema_version: Option<String>,
+    #[darling(default)]
+    pub invalidate_if: bool,
+    #[darling(default)]
+    pub must_use: bool,
+    #[darling(default)]
+    pub recursive_no_cache: bool,
+    #[darling(default)]
+    pub lock_timeout: Option<u64>,
+    #[darling(default)]
+    pub refresh_ahead: Option<String>,
+    #[darling(default)]
+    pub with_ttl: bool,
+    #[darling(default)]
+    pub arc: bool,
+    #[darling(default)]
+    pub guard: bool,
+    #[darling(default)]
+    pub collect: bool,
+    #[darling(default)]
+    pub key_arc: bool,
+    #[darling(default)]
+    pub key_digest: Option<KeyDigest>,
+    #[darling(default)]
+    pub key_wrapper: Option<String>,
+    #[darling(default)]
+    pub persist: bool,
+    #[darling(default)]
+    pub max_key_len: Option<usize>,
+    #[darling(default)]
+    pub key_overflow: KeyOverflow,
 
     #[darling(default)]
     pub size: Option<String>,
     #[darling(default)]
+    pub initial_capacity: Option<String>,
+    #[darling(default)]
+    pub segments: Option<String>,
+    #[darling(default)]
     pub eviction_policy: EvictionPolicy,
 
     #[darling(default)]
     pub disk: Option<DiskArgs>,
     #[darling(default)]
     pub redis: Option<RedisArgs>,
+    #[darling(default)]
+    pub fallback: Option<String>,
+    #[darling(default)]
+    pub maybe_async: Option<String>,
+
+    // the spans of the names and values of the attributes, to report the type errors of their code at them
+    #[darling(skip)]
+    pub attr_spans: HashMap<String, (Span, Span)>,
 }
 
-#[derive(Default, Clone, Debug, FromMeta)]
+#[derive(Default, Clone, Debug, PartialEq, FromMeta)]
 pub enum EvictionPolicy {
     #[default]
     Lfu,
     Lru,
 }
 
+#[derive(Clone, Debug, PartialEq, FromMeta)]
+pub enum KeyDigest {
+    Hash,
+    Verify,
+}
+
+// the length of the keys hashed by `kash::stores::hash_long_key`, i.e., `#sha256:` and the hex digest
+const HASHED_KEY_LEN: usize = 72;
+
+#[derive(Default, Clone, Debug, PartialEq, FromMeta)]
+pub enum KeyOverflow {
+    #[default]
+    Bypass,
+    Hash,
+    Error,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct RedisArgs {
+    pub namespace: Option<String>,
+    pub prefix: Option<String>,
     pub prefix_block: Option<String>,
 }
 
 impl From<RedisArgsHelper> for RedisArgs {
     fn from(value: RedisArgsHelper) -> Self {
         Self {
+            namespace: value.namespace,
+            prefix: value.prefix,
             prefix_block: value.prefix_block,
         }
     }
@@ -54,6 +162,10 @@ impl From<RedisArgsHelper> for RedisArgs {
 // TODO there should be a better way to handle this directly in RedisArgs
 #[derive(FromMeta)]
 struct RedisArgsHelper {
+    #[darling(default)]
+    pub namespace: Option<String>,
+    #[darling(default)]
+    pub prefix: Option<String>,
     #[darling(default)]
     pub prefix_block: Option<String>,
 }
@@ -74,6 +186,10 @@ pub struct DiskArgs {
     pub connection_config: Option<String>,
     pub sync_to_disk_on_cache_change: bool,
     pub dir: Option<String>,
+    pub use_compression: bool,
+    pub compression_factor: Option<i32>,
+    pub shared_db: Option<String>,
+    pub flush_on_exit: bool,
 }
 
 impl From<DiskArgsHelper> for DiskArgs {
@@ -82,6 +198,10 @@ impl From<DiskArgsHelper> for DiskArgs {
             connection_config: value.connection_config,
             sync_to_disk_on_cache_change: value.sync_to_disk_on_cache_change,
             dir: value.dir,
+            use_compression: value.use_compression,
+            compression_factor: value.compression_factor,
+            shared_db: value.shared_db,
+            flush_on_exit: value.flush_on_exit,
         }
     }
 }
@@ -94,6 +214,14 @@ struct DiskArgsHelper {
     pub sync_to_disk_on_cache_change: bool,
     #[darling(default)]
     pub dir: Option<String>,
+    #[darling(default)]
+    pub use_compression: bool,
+    #[darling(default)]
+    pub compression_factor: Option<i32>,
+    #[darling(default)]
+    pub shared_db: Option<String>,
+    #[darling(default)]
+    pub flush_on_exit: bool,
 }
 
 impl FromMeta for DiskArgs {
@@ -110,7 +238,12 @@ impl FromMeta for DiskArgs {
 #[derive(Clone, Debug, FromMeta)]
 pub struct KeyArgs {
     pub ty: String,
-    pub expr: String,
+    #[darling(default)]
+    pub expr: Option<String>,
+    #[darling(default)]
+    pub try_expr: Option<String>,
+    #[darling(default)]
+    pub with: Option<String>,
 }
 
 // struct IntOrStr {
@@ -135,6 +268,43 @@ pub struct KeyArgs {
 //     }
 // }
 
+// the spans of the `name = "value"` attributes, by name, which is `list(name)` for the ones in a list,
+// e.g. `key(expr)`
+fn attr_spans(attr_args: &[NestedMeta]) -> HashMap<String, (Span, Span)> {
+    let mut spans = HashMap::new();
+    for arg in attr_args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(name_value)) => {
+                if let (
+                    Some(name),
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(value),
+                        ..
+                    }),
+                ) = (name_value.path.get_ident(), &name_value.value)
+                {
+                    spans.insert(name.to_string(), (name_value.path.span(), value.span()));
+                }
+            }
+            NestedMeta::Meta(Meta::List(list)) => {
+                let (Some(list_name), Ok(nested)) = (
+                    list.path.get_ident(),
+                    NestedMeta::parse_meta_list(list.tokens.clone()),
+                ) else {
+                    continue;
+                };
+                spans.extend(
+                    attr_spans(&nested)
+                        .into_iter()
+                        .map(|(name, spans)| (format!("{}({})", list_name, name), spans)),
+                );
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
 impl MacroArgs {
     pub fn try_from(args: TokenStream) -> Result<Self, Error> {
         let attr_args = match NestedMeta::parse_meta_list(args.into()) {
@@ -144,12 +314,38 @@ impl MacroArgs {
             }
         };
         match Self::from_list(&attr_args) {
-            Ok(v) => Ok(v),
+            Ok(mut v) => {
+                v.attr_spans = attr_spans(&attr_args);
+                // the code is only parsed once its spans are known, to report the errors at the attributes
+                let mut acc = darling::Error::accumulator();
+                v.validate_code(&mut acc);
+                acc.finish()?;
+                Ok(v)
+            }
             Err(e) => Err(e.into()),
         }
     }
 
-    pub fn init_validate(self) -> darling::Result<Self> {
+    // the code of a string attribute, spanned at its value, checked to be a `ty`, e.g. `u64` for `ttl`,
+    // so a mistyped const is reported at the attribute, and its name, instead of the generated code
+    pub fn typed_expr(&self, attr: &str, code: &str, ty: TokenStream2) -> TokenStream2 {
+        let Some((name_span, value_span)) = self.attr_spans.get(attr) else {
+            let expr = parse_str::<Expr>(code).expect("the code is parsed by `validate_code`");
+            return quote! { #expr };
+        };
+        let expr = LitStr::new(code, *value_span)
+            .parse::<Expr>()
+            .expect("the code is parsed by `validate_code`");
+        let ty = quote_spanned! { *name_span=> #ty };
+        quote! {
+            {
+                let kash_value: #ty = #expr;
+                kash_value
+            }
+        }
+    }
+
+    pub fn init_validate(mut self) -> darling::Result<Self> {
         let mut acc = darling::Error::accumulator();
 
         if self.disk.is_some() && self.redis.is_some() {
@@ -158,12 +354,33 @@ impl MacroArgs {
             ));
         }
 
+        if let Some(key) = &self.key {
+            let exprs = [&key.expr, &key.try_expr, &key.with];
+            if exprs.iter().filter(|expr| expr.is_some()).count() != 1 {
+                acc.push(darling::Error::custom(
+                    "`key` needs exactly one of `expr`, `try_expr` and `with`",
+                ));
+            }
+        }
+
         if self.result && self.option {
             acc.push(darling::Error::custom(
                 "the `result` and `option` attributes are mutually exclusive",
             ));
         }
 
+        if self.result_option && (self.result || self.option || self.cache_errors) {
+            acc.push(darling::Error::custom(
+                "the `result_option` attribute is mutually exclusive with `result`, `option` and `cache_errors`",
+            ));
+        }
+
+        if self.cache_errors && (self.result || self.option) {
+            acc.push(darling::Error::custom(
+                "the `cache_errors` attribute is mutually exclusive with `result` and `option`",
+            ));
+        }
+
         if self.disk.is_some() && cfg!(not(feature = "disk_store")) {
             acc.push(darling::Error::custom(
                 "you are using `disk` caching, but forgot to enable `disk_store` feature",
@@ -176,49 +393,656 @@ impl MacroArgs {
             ));
         }
 
+        if let Some(disk) = &self.disk {
+            if disk.use_compression && cfg!(not(feature = "disk_compression")) {
+                acc.push(darling::Error::custom(
+                    "you are using `use_compression`, but forgot to enable `disk_compression` feature",
+                ));
+            }
+
+            if let Some(compression_factor) = disk.compression_factor {
+                if !disk.use_compression {
+                    acc.push(darling::Error::custom(
+                        "`compression_factor` is only used with `use_compression`",
+                    ));
+                }
+                if !(1..=22).contains(&compression_factor) {
+                    acc.push(darling::Error::custom(format!(
+                        "`compression_factor` must be from 1 up to 22, found {}",
+                        compression_factor
+                    )));
+                }
+            }
+
+            if disk.shared_db.is_some()
+                && (disk.dir.is_some() || disk.connection_config.is_some() || disk.use_compression)
+            {
+                acc.push(darling::Error::custom(
+                    "`dir`, `connection_config` and `use_compression` can't be used with `shared_db`, configure them where the shared database is opened",
+                ));
+            }
+        }
+
+        if let Some(redis) = &self.redis {
+            if redis.prefix.is_some() && redis.prefix_block.is_some() {
+                acc.push(darling::Error::custom(
+                    "`prefix` and `prefix_block` are mutually exclusive",
+                ));
+            }
+        }
+
+        if self.disk.is_none() && self.redis.is_none() && cfg!(not(feature = "mem_store")) {
+            acc.push(darling::Error::custom(
+                "you are using memory caching, but forgot to enable `mem_store` feature, or use `disk` or `redis`",
+            ));
+        }
+
+        if self.refresh_cache && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`refresh_cache` is only supported by memory caches",
+            ));
+        }
+
+        if self.invalidate_if && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`invalidate_if` is only supported by memory caches",
+            ));
+        }
+
+        if self.invalidate_if && self.shared_cache.is_some() {
+            acc.push(darling::Error::custom(
+                "`invalidate_if` can't be used with `shared_cache`, enable `support_invalidation_closures` where the shared cache is created and call its `invalidate_entries_if` instead",
+            ));
+        }
+
+        if self.lock_timeout.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`lock_timeout` is only supported by memory caches",
+            ));
+        }
+
+        if self.refresh_ahead.is_some() {
+            if self.disk.is_some() || self.redis.is_some() {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` is only supported by memory caches",
+                ));
+            }
+            if self.ttl.is_none() && self.ttl_ms.is_none() {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` requires a `ttl` or `ttl_ms`, the values are refreshed before it expires them",
+                ));
+            }
+            if self.expiry.is_some() || self.ttl_for.is_some() {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` can't be used with `expiry` or `ttl_for`, the values must expire after the `ttl`",
+                ));
+            }
+            if self.lock_timeout.is_some() || self.key_arc || self.in_trait {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` can't be used with `lock_timeout`, `key_arc` or `in_trait`",
+                ));
+            }
+            if self.skip_if.is_some() {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` can't be used with `skip_if`, a skipped value would never be refreshed",
+                ));
+            }
+        }
+
+        // the ttls of the memory caches are given to their `WithTtl` expiry, by a static next to the cache
+        if self.with_ttl && self.disk.is_none() && self.redis.is_none() {
+            if self.expiry.is_some() || self.ttl_for.is_some() {
+                acc.push(darling::Error::custom(
+                    "`with_ttl` of memory caches can't be used with `expiry` or `ttl_for`",
+                ));
+            }
+            if self.shared_cache.is_some()
+                || self.in_impl
+                || self.in_trait
+                || self.refresh_ahead.is_some()
+            {
+                acc.push(darling::Error::custom(
+                    "`with_ttl` of memory caches can't be used with `shared_cache`, `in_impl`, `in_trait` or `refresh_ahead`",
+                ));
+            }
+        }
+
+        if self.display_name.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`display_name` is only supported by memory caches",
+            ));
+        }
+
+        if (self.initial_capacity.is_some() || self.segments.is_some())
+            && (self.disk.is_some() || self.redis.is_some())
+        {
+            acc.push(darling::Error::custom(
+                "`initial_capacity` and `segments` are only supported by memory caches",
+            ));
+        }
+
+        if self.expiry.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`expiry` is only supported by memory caches",
+            ));
+        }
+
+        if self.shared_cache.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`shared_cache` is only supported by memory caches",
+            ));
+        }
+
+        if self.shared_cache.is_some()
+            && (self.size.is_some()
+                || self.ttl.is_some()
+                || self.ttl_ms.is_some()
+                || self.expiry.is_some()
+                || self.ttl_for.is_some()
+                || self.display_name.is_some()
+                || self.initial_capacity.is_some()
+                || self.segments.is_some()
+                || self.eviction_policy != EvictionPolicy::default())
+        {
+            acc.push(darling::Error::custom(
+                "`size`, `eviction_policy`, `ttl`, `ttl_ms`, `expiry`, `ttl_for`, `display_name`, `initial_capacity` and `segments` can't be used with `shared_cache`, configure them where the shared cache is created",
+            ));
+        }
+
+        if self.shared_cache.is_some() && self.in_trait {
+            acc.push(darling::Error::custom(
+                "`shared_cache` can't be used with `in_trait`, since each implementor has its own cache",
+            ));
+        }
+
+        if self.ttl.is_some() && self.ttl_ms.is_some() {
+            acc.push(darling::Error::custom(
+                "the `ttl` and `ttl_ms` attributes are mutually exclusive",
+            ));
+        }
+
+        if self.ttl_for.is_some() && self.expiry.is_some() {
+            acc.push(darling::Error::custom(
+                "the `ttl_for` and `expiry` attributes are mutually exclusive",
+            ));
+        }
+
+        if self.arc && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`arc` is only supported by memory caches",
+            ));
+        }
+
+        if self.guard && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`guard` is only supported by memory caches",
+            ));
+        }
+
+        if self.guard && self.arc {
+            acc.push(darling::Error::custom(
+                "the `guard` and `arc` attributes are mutually exclusive, `guard` returns a `kash::Cached` instead of an `Arc`",
+            ));
+        }
+
+        if self.collect && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`collect` is only supported by memory caches",
+            ));
+        }
+
+        if self.collect && (self.result || self.option) {
+            acc.push(darling::Error::custom(
+                "`collect` can't be used with `result` or `option`, the function must return `impl Iterator<Item = T>`",
+            ));
+        }
+
+        if self.key_arc && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`key_arc` is only supported by memory caches",
+            ));
+        }
+
+        if self.key_arc && self.key.is_some() {
+            acc.push(darling::Error::custom(
+                "`key_arc` only applies to the default key, it can't be used with `key`",
+            ));
+        }
+
+        if self.key_digest.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`key_digest` is only supported by memory caches",
+            ));
+        }
+
+        if self.key_wrapper.is_some() && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`key_wrapper` is only supported by memory caches, the keys of `disk` and `redis` caches are strings",
+            ));
+        }
+
+        if self.key_wrapper.is_some() && self.key_digest.is_some() {
+            acc.push(darling::Error::custom(
+                "`key_wrapper` can't be used with `key_digest`, which only stores the digest of the key",
+            ));
+        }
+
+        if self.key_digest.is_some() && (self.key.is_some() || self.key_arc) {
+            acc.push(darling::Error::custom(
+                "`key_digest` only applies to the default key, it can't be used with `key` or `key_arc`",
+            ));
+        }
+
+        if self.in_trait && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`in_trait` is only supported by memory caches",
+            ));
+        }
+
+        if self.in_trait && self.cache_vis.is_some() {
+            acc.push(darling::Error::custom(
+                "`cache_vis` can't be used with `in_trait`, since trait items have no visibility",
+            ));
+        }
+
+        if self.persist && (self.disk.is_some() || self.redis.is_some()) {
+            acc.push(darling::Error::custom(
+                "`persist` is only supported by memory caches",
+            ));
+        }
+
+        if self.persist && cfg!(not(feature = "mem_persist")) {
+            acc.push(darling::Error::custom(
+                "you are using `persist`, but forgot to enable `mem_persist` feature",
+            ));
+        }
+
+        if self.max_key_len.is_some() && self.disk.is_none() && self.redis.is_none() {
+            acc.push(darling::Error::custom(
+                "`max_key_len` is only supported by `disk` and `redis` caches",
+            ));
+        }
+
+        if self.key_overflow != KeyOverflow::default() && self.max_key_len.is_none() {
+            acc.push(darling::Error::custom(
+                "`key_overflow` is only used with `max_key_len`",
+            ));
+        }
+
+        if self.key_overflow == KeyOverflow::Hash
+            && self.max_key_len.is_some_and(|max| max < HASHED_KEY_LEN)
+        {
+            acc.push(darling::Error::custom(format!(
+                "`max_key_len` must be at least {HASHED_KEY_LEN} with `key_overflow = \"hash\"`, the length of the hashed keys"
+            )));
+        }
+
+        if self.stats && self.disk.is_none() {
+            acc.push(darling::Error::custom(
+                "`stats` is only supported by `disk` caches, use the `metrics` feature for the other caches",
+            ));
+        }
+
+        if self.schema_version.is_some() && self.disk.is_none() && self.redis.is_none() {
+            acc.push(darling::Error::custom(
+                "`schema_version` is only supported by `disk` and `redis` caches, since memory caches don't outlive the process",
+            ));
+        }
+
+        if self.fallback.is_some() && self.disk.is_none() && self.redis.is_none() {
+            acc.push(darling::Error::custom(
+                "`fallback` is only used with `disk` or `redis`, to use a memory cache when its `cfg` is false",
+            ));
+        }
+
+        if self.fallback.is_some() && cfg!(not(feature = "mem_store")) {
+            acc.push(darling::Error::custom(
+                "you are using `fallback`, but forgot to enable `mem_store` feature",
+            ));
+        }
+
+        if self.trace && cfg!(not(feature = "trace")) {
+            acc.push(darling::Error::custom(
+                "you are using `trace`, but forgot to enable `trace` feature",
+            ));
+        }
+
+        // `guard` shares the values like `arc`, with a `kash::Cached`
+        if self.guard {
+            self.arc = true;
+        }
+
+        // the methods of a trait are cached like the ones of an `impl`, but with a cache per implementor
+        if self.in_trait {
+            self.in_impl = true;
+        }
+
+        // `disk` and `redis` functions always return `Result`, so `option` already means `Result<Option<T>, E>`,
+        // for memory caches `result_option` is `result` and `option` combined
+        if self.result_option {
+            self.option = true;
+            self.result = self.disk.is_none() && self.redis.is_none();
+        }
+
         acc.finish_with(self)
     }
 
+    // the arguments of the memory cache used instead of the `disk` or `redis` cache when the `fallback` cfg is false,
+    // which caches the `Ok` values of the `Result` like the io caches, or the whole `Result` with `cache_errors`
+    pub fn memory_fallback(&self) -> Self {
+        Self {
+            disk: None,
+            redis: None,
+            fallback: None,
+            schema_version: None,
+            max_key_len: None,
+            key_overflow: KeyOverflow::default(),
+            result: !self.cache_errors,
+            ..self.clone()
+        }
+    }
+
     pub fn validate(&self, input: &ItemFn) -> darling::Result<()> {
         let output = &input.sig.output;
 
         let mut acc = darling::Error::accumulator();
 
-        if self.disk.is_some() && self.redis.is_some() {
-            match output {
-                ReturnType::Default => {
-                    let output_ty = match output {
-                        ReturnType::Default => quote! {()},
-                        ReturnType::Type(_, ty) => quote! {#ty},
-                    };
+        if let Some(constness) = &input.sig.constness {
+            acc.push(
+                darling::Error::custom(
+                    "a `const fn` can't be cached, since its cache is created and used at runtime",
+                )
+                .with_span(constness),
+            );
+        }
 
-                    acc.push(darling::Error::custom(format!(
-                        "`disk` and `redis` caches must return `Result`, found {:?}",
-                        output_ty.to_string().replace(' ', "")
-                    )));
-                }
-                ReturnType::Type(_, ty) => {
-                    if let Type::Path(typepath) = ty.deref() {
-                        let segments = &typepath.path.segments;
-                        if let PathArguments::AngleBracketed(_) =
-                            &segments.last().unwrap().arguments
-                        {
-                        } else {
-                            acc.push(darling::Error::custom(
-                                "`disk` and `redis` caches must return `Result`",
-                            ));
-                        }
-                    } else {
-                        acc.push(
-                        darling::Error::custom(
-                            "function return type too complex. `disk` and `redis` caches must return `Result`",
-                        )
-                    );
-                    }
-                }
+        if self.maybe_async.is_some() && input.sig.asyncness.is_some() {
+            acc.push(
+                darling::Error::custom(
+                    "`maybe_async` functions are written without `async`, which is added when its `cfg` is true",
+                )
+                .with_span(&input.sig.ident),
+            );
+        }
+
+        if self.lock_timeout.is_some() && input.sig.asyncness.is_some() {
+            acc.push(darling::Error::custom(
+                "`lock_timeout` is not supported by `async` functions",
+            ));
+        }
+
+        if self.refresh_ahead.is_some() {
+            if input.sig.receiver().is_some() {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` is not supported by methods taking `self`, since the value is refreshed in the background",
+                ));
             }
-        };
+            if input.sig.asyncness.is_some() && cfg!(not(feature = "tokio")) {
+                acc.push(darling::Error::custom(
+                    "`refresh_ahead` of `async` functions requires the `tokio` feature, to spawn the refresh",
+                ));
+            }
+        }
+
+        let generics = &input.sig.generics;
+        if self.key.is_none() {
+            let (_, without_self_types) = get_input_types(&input.sig.inputs);
+            if let Some(param) = find_generic_param(generics, quote! { #(#without_self_types)* }) {
+                acc.push(
+                    darling::Error::custom(format!(
+                        "the cache key can't use the generic parameter `{}` of the function, specify a `key` with a concrete `ty` and an `expr` converting the arguments to it",
+                        param
+                    ))
+                    .with_span(param),
+                );
+            }
+        }
+        if let Some(param) = find_generic_param(generics, quote! { #output }) {
+            acc.push(
+                darling::Error::custom(format!(
+                    "the return type of a cached function can't use the generic parameter `{}` of the function",
+                    param
+                ))
+                .with_span(param),
+            );
+        }
+
+        if self.collect && iterator_item_type(output).is_none() {
+            let error = darling::Error::custom(
+                "`collect` requires the function to return `impl Iterator<Item = T>`",
+            );
+            acc.push(match output {
+                ReturnType::Type(_, ty) => error.with_span(ty),
+                ReturnType::Default => error.with_span(&input.sig.ident),
+            });
+        }
+
+        if self.disk.is_none() && self.redis.is_none() && (self.result || self.option) {
+            self.validate_mem_value_type(output, &input.sig.ident, &mut acc);
+        }
+
+        if self.disk.is_some() || self.redis.is_some() {
+            self.validate_io_value_type(output, &input.sig.ident, &mut acc);
+        }
 
         acc.finish_with(())
     }
+
+    // the string attributes are parsed as Rust code, so a typo is reported instead of panicking in the macro
+    fn validate_code(&self, acc: &mut darling::error::Accumulator) {
+        fn parse<T: Parse>(
+            spans: &HashMap<String, (Span, Span)>,
+            attr: &str,
+            kind: &str,
+            code: Option<&String>,
+            acc: &mut darling::error::Accumulator,
+        ) {
+            let Some(code) = code else {
+                return;
+            };
+            if let Err(e) = parse_str::<T>(code) {
+                let error = darling::Error::custom(format!(
+                    "unable to parse `{}` as {}: {}",
+                    attr, kind, e
+                ));
+                acc.push(match spans.get(attr) {
+                    Some((_, value_span)) => error.with_span(&LitStr::new(code, *value_span)),
+                    None => error,
+                });
+            }
+        }
+
+        let spans = &self.attr_spans;
+
+        let expr = "an expression";
+        parse::<Expr>(spans, "size", expr, self.size.as_ref(), acc);
+        parse::<Expr>(
+            spans,
+            "initial_capacity",
+            expr,
+            self.initial_capacity.as_ref(),
+            acc,
+        );
+        parse::<Expr>(spans, "segments", expr, self.segments.as_ref(), acc);
+        parse::<Expr>(spans, "ttl", expr, self.ttl.as_ref(), acc);
+        parse::<Expr>(spans, "ttl_ms", expr, self.ttl_ms.as_ref(), acc);
+        parse::<Expr>(spans, "expiry", expr, self.expiry.as_ref(), acc);
+        parse::<Expr>(spans, "ttl_for", expr, self.ttl_for.as_ref(), acc);
+        parse::<Expr>(spans, "skip_if", expr, self.skip_if.as_ref(), acc);
+        parse::<Expr>(
+            spans,
+            "refresh_ahead",
+            expr,
+            self.refresh_ahead.as_ref(),
+            acc,
+        );
+        parse::<Expr>(spans, "display_name", expr, self.display_name.as_ref(), acc);
+        parse::<Expr>(spans, "shared_cache", expr, self.shared_cache.as_ref(), acc);
+        parse::<Visibility>(
+            spans,
+            "cache_vis",
+            "a visibility",
+            self.cache_vis.as_ref(),
+            acc,
+        );
+        if let Some(normalize) = &self.normalize {
+            if let Err(e) = Block::parse_within.parse_str(normalize) {
+                let error = darling::Error::custom(format!(
+                    "unable to parse `normalize` as statements: {}",
+                    e
+                ));
+                acc.push(match spans.get("normalize") {
+                    Some((_, value_span)) => error.with_span(&LitStr::new(normalize, *value_span)),
+                    None => error,
+                });
+            }
+        }
+        parse::<Expr>(spans, "generation", expr, self.generation.as_ref(), acc);
+        parse::<Expr>(
+            spans,
+            "schema_version",
+            expr,
+            self.schema_version.as_ref(),
+            acc,
+        );
+        parse::<Meta>(
+            spans,
+            "fallback",
+            "a cfg predicate",
+            self.fallback.as_ref(),
+            acc,
+        );
+        parse::<Meta>(
+            spans,
+            "maybe_async",
+            "a cfg predicate",
+            self.maybe_async.as_ref(),
+            acc,
+        );
+        parse::<Path>(
+            spans,
+            "key_wrapper",
+            "a path",
+            self.key_wrapper.as_ref(),
+            acc,
+        );
+        if let Some(key) = &self.key {
+            parse::<Type>(spans, "key(ty)", "a type", Some(&key.ty), acc);
+            parse::<Expr>(spans, "key(expr)", expr, key.expr.as_ref(), acc);
+            parse::<Expr>(spans, "key(try_expr)", expr, key.try_expr.as_ref(), acc);
+            parse::<ExprPath>(spans, "key(with)", "a path", key.with.as_ref(), acc);
+        }
+        if let Some(disk) = &self.disk {
+            parse::<Expr>(
+                spans,
+                "disk(connection_config)",
+                expr,
+                disk.connection_config.as_ref(),
+                acc,
+            );
+            parse::<Expr>(spans, "disk(shared_db)", expr, disk.shared_db.as_ref(), acc);
+        }
+        if let Some(redis) = &self.redis {
+            parse::<Block>(
+                spans,
+                "redis(prefix_block)",
+                "a block",
+                redis.prefix_block.as_ref(),
+                acc,
+            );
+        }
+    }
+
+    // `disk` and `redis` caches return `Result<T, E>` (`Result<Option<T>, E>` with `option`), and the value is
+    // found in its type arguments, so they must be written out, too
+    fn validate_io_value_type(
+        &self,
+        output: &ReturnType,
+        fn_ident: &syn::Ident,
+        acc: &mut darling::error::Accumulator,
+    ) {
+        let ty = match output {
+            ReturnType::Default => {
+                acc.push(
+                    darling::Error::custom(
+                        "`disk` and `redis` caches must return `Result<T, E>`, add a return type",
+                    )
+                    .with_span(fn_ident),
+                );
+                return;
+            }
+            ReturnType::Type(_, ty) => ty,
+        };
+
+        // with `cache_errors` the whole `Result` is stored, so its type arguments aren't needed
+        if self.cache_errors {
+            return;
+        }
+
+        let Some(inner_ty) = first_type_argument(ty) else {
+            acc.push(
+                darling::Error::custom(
+                    "`disk` and `redis` caches must return `Result<T, E>`, written out with its type arguments. \
+                    Type aliases without the type arguments and qualified paths aren't supported",
+                )
+                .with_span(ty),
+            );
+            return;
+        };
+        if self.option && first_type_argument(inner_ty).is_none() {
+            acc.push(
+                darling::Error::custom(
+                    "`option` requires `disk` and `redis` caches to return `Result<Option<T>, E>`, remove `option` \
+                    or return an `Option`",
+                )
+                .with_span(inner_ty),
+            );
+        }
+    }
+
+    // the value of memory caches with `result` or `option` is the first type argument of the return type,
+    // which must be written out, so it can be found
+    fn validate_mem_value_type(
+        &self,
+        output: &ReturnType,
+        fn_ident: &syn::Ident,
+        acc: &mut darling::error::Accumulator,
+    ) {
+        let (attr, expected) = match (self.result, self.option) {
+            (true, true) => ("result_option", "Result<Option<T>, E>"),
+            (true, false) => ("result", "Result<T, E>"),
+            _ => ("option", "Option<T>"),
+        };
+
+        let ty = match output {
+            ReturnType::Default => {
+                acc.push(
+                    darling::Error::custom(format!(
+                        "`{}` requires the function to return `{}`, remove `{}` or add a return type",
+                        attr, expected, attr
+                    ))
+                    .with_span(fn_ident),
+                );
+                return;
+            }
+            ReturnType::Type(_, ty) => ty,
+        };
+
+        let inner_ty = first_type_argument(ty);
+        let value_ty = if self.result && self.option {
+            inner_ty.and_then(first_type_argument)
+        } else {
+            inner_ty
+        };
+        if value_ty.is_none() {
+            acc.push(
+                darling::Error::custom(format!(
+                    "`{}` requires the return type to be written as `{}`. Type aliases without the type \
+                    arguments and qualified paths aren't supported, write out the return type or remove `{}`",
+                    attr, expected, attr
+                ))
+                .with_span(ty),
+            );
+        }
+    }
 }
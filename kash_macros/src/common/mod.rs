@@ -1,19 +1,555 @@
+pub mod disabled_fn;
+pub mod handle_fn;
 pub mod macro_args;
 pub mod no_cache_fn;
 
-use crate::common::macro_args::KeyArgs;
-use proc_macro2::{Ident, TokenStream};
+use crate::common::macro_args::{KeyArgs, KeyDigest, MacroArgs};
+use proc_macro2::{Group, Ident, Span, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
 use std::ops::Deref;
+use syn::parse::Parser;
 use syn::punctuated::Punctuated;
 use syn::token::Comma;
-use syn::{parse_str, Expr, FnArg, Pat, PatType, Type};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    parse_quote, parse_str, AngleBracketedGenericArguments, Attribute, Block, Expr, ExprPath,
+    FnArg, GenericArgument, Generics, Item, ItemFn, Macro, Pat, PatType, Path, PathArguments,
+    ReturnType, Signature, Stmt, Type, TypeParamBound, Visibility,
+};
 
 pub(super) fn gen_cache_ident(name: &Option<String>, fn_ident: &Ident) -> Ident {
     let name = name.clone().unwrap_or(fn_ident.to_string()).to_uppercase();
     Ident::new(&name, fn_ident.span())
 }
 
+// the tokens with the span of the code they're made from, e.g. a type wrapping the return type,
+// so the errors about them are reported there instead of at the attribute
+pub(super) fn respan(tokens: TokenStream, span: Span) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|mut token| {
+            if let TokenTree::Group(group) = &token {
+                let mut respanned = Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                token = TokenTree::Group(respanned);
+            } else {
+                token.set_span(span);
+            }
+            token
+        })
+        .collect()
+}
+
+// the static of the ttls given to `{fn}_with_ttl` for a memory cache
+pub(super) fn gen_ttl_hints_ident(name: &Option<String>, fn_ident: &Ident) -> Ident {
+    let cache_ident = gen_cache_ident(name, fn_ident);
+    Ident::new(&format!("{}_TTL_HINTS", cache_ident), fn_ident.span())
+}
+
+// the signature of `{fn}_with_ttl`, the function with a last `kash_ttl` argument
+pub(super) fn gen_with_ttl_sig(signature: &Signature) -> Signature {
+    let mut with_ttl_sig = signature.clone();
+    with_ttl_sig.ident = Ident::new(
+        &format!("{}_with_ttl", signature.ident),
+        signature.ident.span(),
+    );
+    with_ttl_sig
+        .inputs
+        .push(parse_quote! { kash_ttl: ::std::time::Duration });
+    with_ttl_sig
+}
+
+// emit a trace event for a cache hit or miss, the key is only formatted if the level is enabled
+pub(super) fn gen_trace(args: &MacroArgs, cache_ident: &Ident, hit: bool) -> TokenStream {
+    if !args.trace {
+        return quote! {};
+    }
+
+    let cache_name = cache_ident.to_string();
+    let key = gen_trace_key(args);
+    quote! {
+        ::kash::tracing::trace!(cache = #cache_name, key = #key, hit = #hit);
+    }
+}
+
+// the `key` field of the trace events, which never formats the key with `redact_key`
+pub(super) fn gen_trace_key(args: &MacroArgs) -> TokenStream {
+    if args.redact_key {
+        quote! { "<redacted>" }
+    } else {
+        quote! { ?kash_key }
+    }
+}
+
+// report a cache hit or miss to the global metrics recorder
+pub(super) fn gen_record(cache_ident: &Ident, hit: bool) -> TokenStream {
+    if cfg!(not(feature = "metrics")) {
+        return quote! {};
+    }
+
+    let cache_name = cache_ident.to_string();
+    if hit {
+        quote! { ::kash::metrics::recorder().record_hit(#cache_name); }
+    } else {
+        quote! { ::kash::metrics::recorder().record_miss(#cache_name); }
+    }
+}
+
+// everything to run when the cache is looked up, i.e., tracing and metrics
+pub(super) fn gen_on_lookup(args: &MacroArgs, cache_ident: &Ident, hit: bool) -> TokenStream {
+    let trace = gen_trace(args, cache_ident, hit);
+    let record = gen_record(cache_ident, hit);
+    quote! {
+        #trace
+        #record
+    }
+}
+
+// a non-`async` `{fn}_prime_cache_blocking` for `async` functions, blocking on their `{fn}_prime_cache`
+// with the current tokio runtime
+pub(super) fn gen_prime_blocking_fn(
+    input: &ItemFn,
+    prime_sig: &Signature,
+    call_prefix: &TokenStream,
+    maybe_with_self_names: &[TokenStream],
+) -> TokenStream {
+    if prime_sig.asyncness.is_none() || cfg!(not(feature = "tokio")) {
+        return quote! {};
+    }
+
+    let fn_ident = &input.sig.ident;
+    let prime_fn_ident = &prime_sig.ident;
+    let mut blocking_sig = prime_sig.clone();
+    blocking_sig.asyncness = None;
+    blocking_sig.ident = Ident::new(
+        &format!("{}_prime_cache_blocking", fn_ident),
+        fn_ident.span(),
+    );
+
+    let blocking_fn_ident_doc = format!(
+        "Primes the function [`{}`] from non-`async` code, blocking the thread until [`{}`] is done. \
+        It must be called in the context of a tokio runtime, outside of `async` code, e.g. in \
+        `tokio::task::spawn_blocking` or a thread which entered a runtime with `Handle::enter`, \
+        otherwise it panics.",
+        fn_ident, prime_fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+
+    quote! {
+        #[doc = #blocking_fn_ident_doc]
+        #[allow(dead_code)]
+        #(#attributes)*
+        #visibility #blocking_sig {
+            ::kash::async_sync::Handle::current()
+                .block_on(#call_prefix #prime_fn_ident(#(#maybe_with_self_names),*))
+        }
+    }
+}
+
+// the `{fn}_key` function, returning the key of the arguments in the cache, e.g. to remove it,
+// or `None` if the `try_expr` of the key fails, so the arguments are never cached.
+// `key_guard` is run on `kash_key` before returning it, e.g. to hash a long key of an io cache
+pub(super) fn gen_key_fn(
+    input: &ItemFn,
+    args: &MacroArgs,
+    key_ty: TokenStream,
+    key_guard: TokenStream,
+) -> TokenStream {
+    let signature = &input.sig;
+    let fn_ident = &signature.ident;
+    let key_fn_ident = Ident::new(&format!("{}_key", fn_ident), fn_ident.span());
+    let key_fn_ident_doc = format!(
+        "Returns the key of the arguments in the cache of the function [`{}`].",
+        fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+    let inputs = &signature.inputs;
+
+    let (_, without_self_types) = get_input_types(inputs);
+    let (_, without_self_names) = get_input_names(inputs);
+    let (_, key_expr) = make_cache_key_type(args, without_self_types, &without_self_names);
+    let (arc_inputs, _) = gen_key_arc_args(args, inputs);
+
+    let (key_binding, output, ret) = if args.key.as_ref().is_some_and(|key| key.try_expr.is_some())
+    {
+        (
+            gen_key_binding(&args.key, &key_expr, quote! { None }),
+            quote! { ::std::option::Option<#key_ty> },
+            quote! { Some(kash_key) },
+        )
+    } else {
+        (
+            gen_key_binding(&args.key, &key_expr, quote! {}),
+            key_ty,
+            quote! { kash_key },
+        )
+    };
+
+    let mut key_sig = signature.clone();
+    key_sig.ident = key_fn_ident;
+    key_sig.asyncness = None;
+    key_sig.output = parse_quote! { -> #output };
+
+    quote! {
+        #[doc = #key_fn_ident_doc]
+        #[allow(dead_code, unused_mut, unused_variables)]
+        #(#attributes)*
+        #visibility #key_sig {
+            #arc_inputs
+            #key_binding
+            #key_guard
+            #ret
+        }
+    }
+}
+
+// put `#[cfg(predicate)]` on each of the generated items, e.g. the ones of `fallback`
+pub(super) fn gen_cfg_items(predicate: &TokenStream, items: TokenStream) -> TokenStream {
+    let items = syn::parse2::<syn::File>(items)
+        .expect("the generated items are valid")
+        .items;
+    quote! {
+        #(
+            #[cfg(#predicate)]
+            #items
+        )*
+    }
+}
+
+// the attributes of the function forwarded to the generated functions: docs, `cfg`s, lints and
+// `must_use`, but not e.g. `#[test]`, which would register the generated functions as tests, too
+pub(super) fn gen_forwarded_attributes(attrs: &[Attribute]) -> Vec<&Attribute> {
+    const FORWARDED: [&str; 9] = [
+        "doc", "cfg", "cfg_attr", "allow", "warn", "deny", "forbid", "expect", "must_use",
+    ];
+    attrs
+        .iter()
+        .filter(|attr| FORWARDED.iter().any(|name| attr.path().is_ident(name)))
+        .collect()
+}
+
+// mark the function as `#[must_use]`, which is forwarded to all generated functions, unless it's already marked
+pub(super) fn add_must_use(input: &mut ItemFn) {
+    if !input
+        .attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("must_use"))
+    {
+        input
+            .attrs
+            .push(parse_quote! { #[must_use = "the value of a cached function should be used"] });
+    }
+}
+
+// turn a function returning `impl Future<Output = T>` or `Pin<Box<dyn Future<Output = T>>>` into
+// an `async fn` returning `T`, awaiting the original body, so it gets the async codegen.
+// Returns the original function, whose return type is given back by `resugar_future_fn`
+pub(super) fn desugar_future_fn(input: &mut ItemFn) -> Option<ItemFn> {
+    if input.sig.asyncness.is_some() {
+        return None;
+    }
+    let ReturnType::Type(_, ty) = &input.sig.output else {
+        return None;
+    };
+    let output_ty = future_output_type(ty)?;
+
+    let original = input.clone();
+    let block = &input.block;
+    input.sig.asyncness = Some(parse_quote! { async });
+    input.sig.output = parse_quote! { -> #output_ty };
+    input.block = parse_quote! {{ #block.await }};
+    Some(original)
+}
+
+// give the generated cached function and `{fn}_no_cache` of a function desugared by `desugar_future_fn`
+// the original return type back, so e.g. a `Send` boxed future can still be spawned or stored.
+// The body of the cached function is wrapped in `Box::pin(async move { .. })` (or `async move { .. }`
+// for `impl Future`), and `{fn}_no_cache` gets back the original body, building the future eagerly
+pub(super) fn resugar_future_fn(original: &ItemFn, items: TokenStream) -> TokenStream {
+    let fn_ident = &original.sig.ident;
+    let origin_idents = [
+        format!("{}_no_cache", fn_ident),
+        format!("{}_origin", fn_ident),
+    ];
+    let ReturnType::Type(_, original_ty) = &original.sig.output else {
+        return items;
+    };
+    let boxed = !matches!(original_ty.as_ref(), Type::ImplTrait(_));
+
+    let mut file = syn::parse2::<syn::File>(items).expect("the generated items are valid");
+    for item in &mut file.items {
+        let Item::Fn(item_fn) = item else {
+            continue;
+        };
+        let is_cached_fn = item_fn.sig.ident == *fn_ident;
+        let is_origin_fn = origin_idents
+            .iter()
+            .any(|ident| item_fn.sig.ident == ident.as_str());
+        if item_fn.sig.asyncness.is_none() || !(is_cached_fn || is_origin_fn) {
+            continue;
+        }
+
+        // e.g. `Arc<T>` with `arc`, in place of the `T` of the original function
+        let mut output_ty = original_ty.clone();
+        if let (ReturnType::Type(_, ty), Some(output)) =
+            (&item_fn.sig.output, future_output_type_mut(&mut output_ty))
+        {
+            *output = *ty.clone();
+        }
+        item_fn.sig.asyncness = None;
+        item_fn.sig.output = parse_quote! { -> #output_ty };
+
+        let block = &item_fn.block;
+        item_fn.block = if is_origin_fn {
+            Box::new(unawait_block(block).unwrap_or_else(|| original.block.as_ref().clone()))
+        } else if boxed {
+            parse_quote! {{ ::std::boxed::Box::pin(async move #block) }}
+        } else {
+            parse_quote! {{ async move #block }}
+        };
+    }
+    file.into_token_stream()
+}
+
+// the block `{ .. }` of a body `{ { .. }.await }` made by `desugar_future_fn`, e.g. with the recursive calls
+// rewritten by `recursive_no_cache`
+fn unawait_block(block: &Block) -> Option<Block> {
+    let [Stmt::Expr(Expr::Await(expr), None)] = block.stmts.as_slice() else {
+        return None;
+    };
+    match expr.base.as_ref() {
+        Expr::Block(expr) => Some(expr.block.clone()),
+        _ => None,
+    }
+}
+
+// with `maybe_async`, the function as it's emitted when its `cfg` is true (`async`) or false (not `async`),
+// with its `maybe_await!(expr)`s replaced by `expr.await` or `expr`
+pub(super) fn desugar_maybe_async_fn(input: &ItemFn, asyncness: bool) -> ItemFn {
+    struct MaybeAwait {
+        asyncness: bool,
+    }
+
+    impl MaybeAwait {
+        fn expand(&self, mac: &Macro) -> Option<Expr> {
+            if mac.path.segments.last()?.ident != "maybe_await" {
+                return None;
+            }
+            let expr = mac.parse_body::<Expr>().ok()?;
+            Some(if self.asyncness {
+                parse_quote! { (#expr).await }
+            } else {
+                parse_quote! { (#expr) }
+            })
+        }
+    }
+
+    impl VisitMut for MaybeAwait {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            if let Expr::Macro(mac) = expr {
+                if let Some(expanded) = self.expand(&mac.mac) {
+                    *expr = expanded;
+                }
+            }
+            visit_mut::visit_expr_mut(self, expr);
+        }
+
+        fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+            if let Stmt::Macro(mac) = stmt {
+                if let Some(expanded) = self.expand(&mac.mac) {
+                    *stmt = Stmt::Expr(expanded, mac.semi_token);
+                }
+            }
+            visit_mut::visit_stmt_mut(self, stmt);
+        }
+    }
+
+    let mut input = input.clone();
+    if asyncness {
+        input.sig.asyncness = Some(parse_quote! { async });
+    }
+    MaybeAwait { asyncness }.visit_block_mut(&mut input.block);
+    input
+}
+
+// the `Output` of `impl Future<Output = T>` or `Pin<Box<dyn Future<Output = T>>>`
+fn future_output_type(ty: &Type) -> Option<Type> {
+    future_output_type_mut(&mut ty.clone()).map(|output| output.clone())
+}
+
+fn future_output_type_mut(ty: &mut Type) -> Option<&mut Type> {
+    let bounds = match ty {
+        Type::ImplTrait(ty) => &mut ty.bounds,
+        Type::Path(ty) => {
+            let pin = ty.path.segments.last_mut()?;
+            let is_pin = pin.ident == "Pin";
+            let boxed = match single_type_argument_mut(&mut pin.arguments)? {
+                Type::Path(boxed) if is_pin => boxed.path.segments.last_mut()?,
+                _ => return None,
+            };
+            let is_box = boxed.ident == "Box";
+            match single_type_argument_mut(&mut boxed.arguments)? {
+                Type::TraitObject(ty) if is_box => &mut ty.bounds,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    bounds.iter_mut().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last_mut()?;
+        if segment.ident != "Future" {
+            return None;
+        }
+        let PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) =
+            &mut segment.arguments
+        else {
+            return None;
+        };
+        args.iter_mut().find_map(|arg| match arg {
+            GenericArgument::AssocType(arg) if arg.ident == "Output" => Some(&mut arg.ty),
+            _ => None,
+        })
+    })
+}
+
+// with `collect`, a function returning `impl Iterator<Item = T>` returns and caches a `std::vec::IntoIter<T>`,
+// collected from the iterator of the function, which is `Clone` (if `T` is) and clones the collected values
+pub(super) fn desugar_iterator_fn(input: &mut ItemFn) {
+    let Some(item_ty) = iterator_item_type(&input.sig.output) else {
+        return;
+    };
+
+    let block = &input.block;
+    input.sig.output = parse_quote! { -> ::std::vec::IntoIter<#item_ty> };
+    input.block = parse_quote! {{
+        ::std::iter::IntoIterator::into_iter(
+            ::std::iter::Iterator::collect::<::std::vec::Vec<#item_ty>>(#block)
+        )
+    }};
+}
+
+// the `Item` of `impl Iterator<Item = T>`
+pub(super) fn iterator_item_type(output: &ReturnType) -> Option<Type> {
+    let ReturnType::Type(_, ty) = output else {
+        return None;
+    };
+    let Type::ImplTrait(ty) = ty.as_ref() else {
+        return None;
+    };
+    find_assoc_type(&ty.bounds, "Iterator", "Item")
+}
+
+// the associated type `assoc` of the bound on the trait `trait_ident`, e.g. `T` of `Future<Output = T>`
+fn find_assoc_type<'a>(
+    bounds: impl IntoIterator<Item = &'a TypeParamBound>,
+    trait_ident: &str,
+    assoc: &str,
+) -> Option<Type> {
+    bounds.into_iter().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last()?;
+        if segment.ident != trait_ident {
+            return None;
+        }
+        let PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) =
+            &segment.arguments
+        else {
+            return None;
+        };
+        args.iter().find_map(|arg| match arg {
+            GenericArgument::AssocType(arg) if arg.ident == assoc => Some(arg.ty.clone()),
+            _ => None,
+        })
+    })
+}
+
+// the first generic type argument of a type, e.g. `T` of `Option<T>` or `Result<T, E>`
+pub(super) fn first_type_argument(ty: &Type) -> Option<&Type> {
+    let Type::Path(typepath) = ty else {
+        return None;
+    };
+    let PathArguments::AngleBracketed(brackets) = &typepath.path.segments.last()?.arguments else {
+        return None;
+    };
+    match brackets.args.first()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+pub(super) fn first_type_argument_mut(ty: &mut Type) -> Option<&mut Type> {
+    let Type::Path(typepath) = ty else {
+        return None;
+    };
+    let PathArguments::AngleBracketed(brackets) = &mut typepath.path.segments.last_mut()?.arguments
+    else {
+        return None;
+    };
+    match brackets.args.first_mut()? {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+fn single_type_argument_mut(arguments: &mut PathArguments) -> Option<&mut Type> {
+    match arguments {
+        PathArguments::AngleBracketed(brackets) if brackets.args.len() == 1 => {
+            match brackets.args.first_mut()? {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// the first type or const parameter of the function used by the tokens, e.g. `N` of `[u8; N]`.
+// The cache is a static, which can't use the generic parameters of its function
+pub(super) fn find_generic_param(generics: &Generics, tokens: TokenStream) -> Option<&Ident> {
+    let params = generics
+        .type_params()
+        .map(|param| &param.ident)
+        .chain(generics.const_params().map(|param| &param.ident))
+        .collect::<Vec<_>>();
+    if params.is_empty() {
+        return None;
+    }
+
+    fn find<'a>(params: &[&'a Ident], tokens: TokenStream) -> Option<&'a Ident> {
+        tokens.into_iter().find_map(|token| match token {
+            TokenTree::Ident(ident) => params.iter().copied().find(|param| **param == ident),
+            TokenTree::Group(group) => find(params, group.stream()),
+            _ => None,
+        })
+    }
+    find(&params, tokens)
+}
+
+// the bounds of the generated functions without the signature of the original one, with `in_trait`
+pub(super) fn gen_trait_bounds(args: &MacroArgs) -> TokenStream {
+    if args.in_trait {
+        quote! { where Self: Sized + 'static }
+    } else {
+        quote! {}
+    }
+}
+
+// the visibility of the generated cache static or accessor, defaults to the function's visibility
+pub(super) fn gen_cache_vis(args: &MacroArgs, input: &ItemFn) -> Visibility {
+    match &args.cache_vis {
+        Some(vis) => parse_str::<Visibility>(vis).expect("unable to parse cache_vis"),
+        None => input.vis.clone(),
+    }
+}
+
 pub(super) fn match_pattern_type(pat_type: &PatType) -> Box<Pat> {
     match &pat_type.pat.deref() {
         Pat::Ident(pat_ident) => {
@@ -29,19 +565,152 @@ pub(super) fn match_pattern_type(pat_type: &PatType) -> Box<Pat> {
     }
 }
 
+// with the `registry` feature, register a function in `kash::registry` running `clear`,
+// which should only clear the cache if it's initialized
+pub(super) fn gen_registry_entry(clear: TokenStream) -> TokenStream {
+    if cfg!(not(feature = "registry")) {
+        return quote! {};
+    }
+
+    quote! {
+        const _: () = {
+            #[::kash::linkme::distributed_slice(::kash::registry::CACHES)]
+            #[linkme(crate = ::kash::linkme)]
+            static KASH_CLEAR: fn() = || { #clear };
+        };
+    }
+}
+
 // make the block that converts the inputs into the key type
 pub(super) fn make_cache_key_type(
-    key: &Option<KeyArgs>,
+    args: &MacroArgs,
     input_tys: Vec<Type>,
     input_names: &Vec<TokenStream>,
 ) -> (TokenStream, TokenStream) {
-    if let Some(key) = key {
+    let (key_ty, key_expr) = make_inputs_key_type(args, input_tys, input_names);
+    let (key_ty, key_expr) = wrap_key(args, key_ty, key_expr);
+    let Some(generation) = &args.generation else {
+        return (key_ty, key_expr);
+    };
+
+    // the generation is evaluated on every call, so changing it makes new keys, and the old entries are missed
+    let generation = parse_str::<Expr>(generation).expect("unable to parse generation");
+    let is_io = args.disk.is_some() || args.redis.is_some();
+    let with_generation = if is_io {
+        // the keys of io caches are strings anyway, so the generation is their first segment
+        quote! { |kash_key| ::std::format!("{}:{}", kash_generation, kash_key) }
+    } else {
+        quote! { |kash_key| (kash_generation, kash_key) }
+    };
+    let key_ty = if is_io {
+        quote! { String }
+    } else {
+        quote! { (u64, #key_ty) }
+    };
+    let key_expr = if args.key.as_ref().is_some_and(|key| key.try_expr.is_some()) {
+        quote! {{
+            let kash_generation: u64 = #generation;
+            (#key_expr).map(#with_generation)
+        }}
+    } else {
+        quote! {{
+            let kash_generation: u64 = #generation;
+            (#with_generation)(#key_expr)
+        }}
+    };
+    (key_ty, key_expr)
+}
+
+// with `key_wrapper`, the key is wrapped in the given tuple struct, whose `Hash` and `Eq` tell which keys share
+// an entry, e.g. case-insensitive strings, while the wrapped key is kept as it is
+fn wrap_key(
+    args: &MacroArgs,
+    key_ty: TokenStream,
+    key_expr: TokenStream,
+) -> (TokenStream, TokenStream) {
+    let Some(key_wrapper) = &args.key_wrapper else {
+        return (key_ty, key_expr);
+    };
+
+    let wrapper_ty = parse_str::<Path>(key_wrapper).expect("unable to parse key_wrapper");
+    // the constructor of a generic wrapper needs a turbofish, e.g. `Wrapper::<String>(key)`
+    let mut wrapper = wrapper_ty.clone();
+    for segment in &mut wrapper.segments {
+        if let PathArguments::AngleBracketed(arguments) = &mut segment.arguments {
+            arguments.colon2_token = Some(Default::default());
+        }
+    }
+    let key_expr = if args.key.as_ref().is_some_and(|key| key.try_expr.is_some()) {
+        quote! { (#key_expr).map(#wrapper) }
+    } else {
+        quote! { #wrapper(#key_expr) }
+    };
+    (quote! { #wrapper_ty }, key_expr)
+}
+
+fn make_inputs_key_type(
+    args: &MacroArgs,
+    input_tys: Vec<Type>,
+    input_names: &Vec<TokenStream>,
+) -> (TokenStream, TokenStream) {
+    if let Some(key) = &args.key {
         let key_ty =
             dereference_type(parse_str::<Type>(&key.ty).expect("unable to parse a cache key type"));
 
-        let key_expr = parse_str::<Expr>(&key.expr).expect("unable to parse key expr");
+        // a function making the key takes the inputs by reference
+        if let Some(with) = &key.with {
+            let with = parse_str::<ExprPath>(with).expect("unable to parse key with");
+            return (quote! {#key_ty}, quote! {#with(#(&#input_names),*)});
+        }
+
+        let key_expr = key.expr.as_ref().or(key.try_expr.as_ref()).unwrap();
+        let key_expr = parse_str::<Expr>(key_expr).expect("unable to parse key expr");
 
         (quote! {#key_ty}, quote! {#key_expr})
+    } else if args.key_arc {
+        // the owned inputs are already moved into an `Arc` by `gen_key_arc_args`, references are cloned into one
+        let key_exprs = input_tys
+            .iter()
+            .zip(input_names)
+            .map(|(ty, name)| match ty {
+                Type::Reference(_) => quote! { ::std::sync::Arc::new(#name.clone()) },
+                _ => quote! { ::std::sync::Arc::clone(&#name) },
+            })
+            .collect::<Vec<_>>();
+        let input_tys = input_tys.into_iter().map(dereference_type);
+        (
+            quote! {(#(::std::sync::Arc<#input_tys>),*)},
+            quote! {(#(#key_exprs),*)},
+        )
+    } else if let Some(key_digest) = &args.key_digest {
+        match key_digest {
+            // only the digest is stored, the inputs are hashed by reference
+            KeyDigest::Hash => (
+                quote! { u64 },
+                quote! { ::kash::key::digest(&(#(&#input_names,)*)) },
+            ),
+            // the owned inputs are stored with their digest, so colliding digests are told apart
+            KeyDigest::Verify => {
+                let (key_tys, key_exprs): (Vec<_>, Vec<_>) = input_tys
+                    .iter()
+                    .zip(input_names)
+                    .map(|(ty, name)| match ty {
+                        Type::Reference(r) => {
+                            let elem = &r.elem;
+                            (
+                                quote! { <#elem as ::std::borrow::ToOwned>::Owned },
+                                quote! { ::std::borrow::ToOwned::to_owned(#name) },
+                            )
+                        }
+                        _ => (quote! { #ty }, quote! { #name.clone() }),
+                    })
+                    .unzip();
+                (
+                    quote! { ::kash::key::Verified<(#(#key_tys,)*)> },
+                    quote! { ::kash::key::Verified::new((#(#key_exprs,)*)) },
+                )
+            }
+        }
     } else {
         let input_tys = input_tys.into_iter().map(dereference_type);
         (
@@ -51,6 +720,95 @@ pub(super) fn make_cache_key_type(
     }
 }
 
+// the statements of `normalize`, rebinding the inputs before the key is made and the function is called
+pub(super) fn gen_normalize(args: &MacroArgs) -> TokenStream {
+    let Some(normalize) = &args.normalize else {
+        return quote! {};
+    };
+    // spanned at the attribute, so the errors in the statements are reported there, like `MacroArgs::typed_expr`
+    let stmts = match args.attr_spans.get("normalize") {
+        Some((_, value_span)) => {
+            syn::LitStr::new(normalize, *value_span).parse_with(syn::Block::parse_within)
+        }
+        None => syn::Block::parse_within.parse_str(normalize),
+    }
+    .expect("`normalize` is parsed by `validate_code`");
+    quote! { #(#stmts)* }
+}
+
+// with `key_arc`, move the owned inputs into an `Arc` shared with the key, so making the key doesn't clone them,
+// and return the inputs to call the original function with, which take the values back out of their `Arc`.
+// The inputs are normalized first, with `normalize`
+pub(super) fn gen_key_arc_args(
+    args: &MacroArgs,
+    inputs: &Punctuated<FnArg, Comma>,
+) -> (TokenStream, Vec<TokenStream>) {
+    let (maybe_with_self_names, _) = get_input_names(inputs);
+    let normalize = gen_normalize(args);
+    if !args.key_arc {
+        return (normalize, maybe_with_self_names);
+    }
+
+    let is_owned = |input: &FnArg| match input {
+        FnArg::Receiver(_) => false,
+        FnArg::Typed(pat_type) => !matches!(*pat_type.ty, Type::Reference(_)),
+    };
+    let owned_names = inputs
+        .iter()
+        .zip(&maybe_with_self_names)
+        .filter(|(input, _)| is_owned(input))
+        .map(|(_, name)| name);
+    let arc_inputs = quote! {
+        #normalize
+        #(let #owned_names = ::std::sync::Arc::new(#owned_names);)*
+    };
+    let call_names = inputs
+        .iter()
+        .zip(&maybe_with_self_names)
+        .map(|(input, name)| {
+            if is_owned(input) {
+                quote! {
+                    ::std::sync::Arc::try_unwrap(#name)
+                        .unwrap_or_else(|kash_input| ::std::clone::Clone::clone(&*kash_input))
+                }
+            } else {
+                name.clone()
+            }
+        })
+        .collect();
+    (arc_inputs, call_names)
+}
+
+// the function with the original body, called by the generated functions on a miss,
+// which is `{fn}_no_cache`, unless its recursive calls are rewritten by `recursive_no_cache`
+pub(super) fn gen_origin_fn_ident(args: &MacroArgs, fn_ident: &Ident) -> Ident {
+    let suffix = if args.recursive_no_cache {
+        "origin"
+    } else {
+        "no_cache"
+    };
+    Ident::new(&format!("{}_{}", fn_ident, suffix), fn_ident.span())
+}
+
+// bind the key to `kash_key`, with `try_expr` returning `bypass` (i.e., the result of the function
+// without the cache) if the key can't be made
+pub(super) fn gen_key_binding(
+    key: &Option<KeyArgs>,
+    key_expr: &TokenStream,
+    bypass: TokenStream,
+) -> TokenStream {
+    if key.as_ref().is_some_and(|key| key.try_expr.is_some()) {
+        quote! {
+            let kash_key = match #key_expr {
+                Ok(kash_key) => kash_key,
+                Err(_) => return #bypass,
+            };
+        }
+    } else {
+        quote! { let kash_key = #key_expr; }
+    }
+}
+
 /// Convert a type `&T` into a type `T`.
 ///
 /// If the input is a tuple, the elements are de-referenced.
@@ -1,14 +1,43 @@
+use crate::common::macro_args::MacroArgs;
+use crate::common::{gen_forwarded_attributes, gen_origin_fn_ident};
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{Ident, ItemFn};
+use syn::visit_mut::{self, VisitMut};
+use syn::{Expr, ExprCall, Ident, ItemFn};
 
 pub struct NoCacheFn<'a> {
     input: &'a ItemFn,
+    args: &'a MacroArgs,
 }
 
 impl<'a> NoCacheFn<'a> {
-    pub fn new(input: &'a ItemFn) -> Self {
-        Self { input }
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+// rewrite the calls of `fn_ident(..)` and `Self::fn_ident(..)` to call `no_cache_fn_ident` instead
+struct RecursiveCalls<'a> {
+    fn_ident: &'a Ident,
+    no_cache_fn_ident: &'a Ident,
+}
+
+impl VisitMut for RecursiveCalls<'_> {
+    fn visit_expr_call_mut(&mut self, call: &mut ExprCall) {
+        if let Expr::Path(func) = call.func.as_mut() {
+            let segments = &mut func.path.segments;
+            let is_self_call = match segments.len() {
+                1 => func.qself.is_none() && func.path.leading_colon.is_none(),
+                2 => segments[0].ident == "Self",
+                _ => false,
+            };
+            if let Some(last) = segments.last_mut() {
+                if is_self_call && last.ident == *self.fn_ident {
+                    last.ident = self.no_cache_fn_ident.clone();
+                }
+            }
+        }
+        visit_mut::visit_expr_call_mut(self, call);
     }
 }
 
@@ -19,11 +48,33 @@ impl ToTokens for NoCacheFn<'_> {
 
         let no_cache_fn_ident_doc = format!("Origin of the function [`{}`].", no_cache_fn_ident);
         let mut no_cache_fn = self.input.clone();
-        no_cache_fn.sig.ident = no_cache_fn_ident;
+        no_cache_fn.sig.ident = no_cache_fn_ident.clone();
+        no_cache_fn.attrs = gen_forwarded_attributes(&self.input.attrs)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        // keep the original function, whose recursive calls are cached, for the generated functions
+        let origin_fn = if self.args.recursive_no_cache {
+            let mut origin_fn = no_cache_fn.clone();
+            origin_fn.sig.ident = gen_origin_fn_ident(self.args, &fn_ident);
+            RecursiveCalls {
+                fn_ident: &fn_ident,
+                no_cache_fn_ident: &no_cache_fn_ident,
+            }
+            .visit_block_mut(&mut no_cache_fn.block);
+            quote! {
+                #[doc(hidden)]
+                #origin_fn
+            }
+        } else {
+            quote! {}
+        };
 
         let expanded = quote! {
             #[doc = #no_cache_fn_ident_doc]
             #no_cache_fn
+            #origin_fn
         };
 
         tokens.extend(expanded);
@@ -1,7 +1,12 @@
-use proc_macro2::{Ident, TokenStream, TokenStream as TokenStream2};
+use crate::common::macro_args::{KeyOverflow, MacroArgs};
+use crate::common::{
+    first_type_argument, gen_cache_ident, gen_forwarded_attributes, gen_key_binding, gen_normalize,
+    get_input_names, get_input_types, make_cache_key_type,
+};
+use proc_macro2::{Ident, Span, TokenStream, TokenStream as TokenStream2};
 use quote::quote;
 use syn::token::Async;
-use syn::{GenericArgument, PathArguments, ReturnType, Type};
+use syn::{parse_quote, parse_str, Expr, FnArg, ItemFn, ReturnType, Type};
 
 pub fn gen_init_and_get(
     asyncness: &Option<Async>,
@@ -24,24 +29,288 @@ pub fn gen_init_and_get(
     }
 }
 
+// set `kash_result` (or its `Ok` value) in `kash_cache`, with the `ttl` in seconds if it's given,
+// e.g. by `{fn}_with_ttl`, otherwise with the ttl of `ttl_for` if it's set
+pub fn gen_set_call(
+    args: &MacroArgs,
+    output: &ReturnType,
+    may_await: &TokenStream,
+    ttl: Option<&TokenStream>,
+) -> TokenStream {
+    if let Some(ttl) = ttl {
+        return quote! {
+            kash_cache.set_with_ttl(kash_key, kash_result.clone(), ::std::option::Option::Some(#ttl))#may_await?;
+        };
+    }
+    let Some(ttl_for) = &args.ttl_for else {
+        return quote! {
+            kash_cache.set(kash_key, kash_result.clone())#may_await?;
+        };
+    };
+
+    let ttl_for = parse_str::<Expr>(ttl_for).expect("unable to parse ttl_for");
+    let cache_value_ty = gen_cache_value_type(args.result, args.option, args.cache_errors, output);
+    // with `cache_errors` the whole `Result` is cached, otherwise `kash_result` is already a reference to the value
+    let value_ref = if args.cache_errors {
+        quote! { &kash_result }
+    } else {
+        quote! { kash_result }
+    };
+    quote! {
+        let kash_ttl_for: fn(&#cache_value_ty) -> ::std::option::Option<::std::time::Duration> = #ttl_for;
+        let kash_ttl = kash_ttl_for(#value_ref).map(|kash_ttl| kash_ttl.as_secs().max(1));
+        kash_cache.set_with_ttl(kash_key, kash_result.clone(), kash_ttl)#may_await?;
+    }
+}
+
+// with `skip_if`, only run the `set` block for the values the predicate returns `false` for,
+// i.e., the computed values which are returned but not stored
+pub fn gen_skip_if_guard(args: &MacroArgs, output: &ReturnType, set: TokenStream) -> TokenStream {
+    let Some(skip_if) = &args.skip_if else {
+        return set;
+    };
+    let cache_value_ty = gen_cache_value_type(args.result, args.option, args.cache_errors, output);
+    let skip_if = args.typed_expr("skip_if", skip_if, quote! { fn(&#cache_value_ty) -> bool });
+    // `kash_result` is a reference to the value, or the whole `Result` with `cache_errors`
+    quote! {
+        if !(#skip_if)(&kash_result) {
+            #set
+        }
+    }
+}
+
+// the `{fn}_init_cache` function of `async` functions, initializing their cache, e.g. connecting to Redis,
+// and returning the error of `cache_build`, the `Result` of building the cache, instead of panicking
+pub fn gen_init_cache_fn(
+    input: &ItemFn,
+    args: &MacroArgs,
+    build_error_ty: TokenStream2,
+    cache_build: TokenStream2,
+) -> TokenStream2 {
+    // the caches of non-`async` functions are initialized by a `Lazy`, which can't return the error
+    if input.sig.asyncness.is_none() {
+        return quote! {};
+    }
+
+    let fn_ident = &input.sig.ident;
+    let init_cache_fn_ident = Ident::new(&format!("{}_init_cache", fn_ident), fn_ident.span());
+    let init_cache_fn_ident_doc = format!(
+        "Initializes the cache of the function [`{}`], if it isn't already, so it fails here instead of on the first call.",
+        fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+    let local_cache = gen_local_cache(args, fn_ident);
+
+    quote! {
+        #[doc = #init_cache_fn_ident_doc]
+        #[allow(dead_code)]
+        #(#attributes)*
+        #visibility async fn #init_cache_fn_ident() -> ::std::result::Result<(), #build_error_ty> {
+            #local_cache.get_or_try_init(|| async { #cache_build }).await?;
+            Ok(())
+        }
+    }
+}
+
+// the `{fn}_set_cache` function, storing a given value for the arguments without evaluating the function,
+// returning the error of the store, e.g. `DiskCacheError`
+pub fn gen_set_cache_fn(
+    input: &ItemFn,
+    args: &MacroArgs,
+    error_ty: TokenStream2,
+    use_trait: TokenStream2,
+    cache_create: TokenStream2,
+    set_may_await: TokenStream2,
+) -> TokenStream2 {
+    let signature = &input.sig;
+    let fn_ident = &signature.ident;
+    let asyncness = &signature.asyncness;
+    let set_cache_fn_ident = Ident::new(&format!("{}_set_cache", fn_ident), fn_ident.span());
+    let set_cache_fn_ident_doc = format!(
+        "Stores the value in the cache of the function [`{}`] for the arguments, without evaluating the function.",
+        fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+
+    let (_, without_self_types) = get_input_types(&signature.inputs);
+    let (_, without_self_names) = get_input_names(&signature.inputs);
+    let (_, key_expr) = make_cache_key_type(args, without_self_types, &without_self_names);
+    let cache_value_ty = gen_cache_value_type(
+        args.result,
+        args.option,
+        args.cache_errors,
+        &signature.output,
+    );
+
+    // a key which can't be made, or is too long to be stored, is never cached
+    let key_binding = gen_normalized_key_binding(args, &key_expr, quote! { Ok(()) });
+    let key_len_guard = gen_key_len_guard(args, error_ty.clone(), quote! { Ok(()) });
+
+    let cache_ident = gen_cache_ident(&args.name, fn_ident);
+    let local_cache = if args.in_impl {
+        let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+        quote! { Self::#fn_cache_ident() }
+    } else {
+        quote! { &#cache_ident }
+    };
+    let (init, cache) = if asyncness.is_some() {
+        (
+            quote! { let kash_init = || async { #cache_create }; },
+            quote! { #local_cache.get_or_init(kash_init).await },
+        )
+    } else {
+        (quote! {}, local_cache)
+    };
+
+    // `gen_set_call` stores a clone of `kash_result`, which is a reference to the value, unless it's the whole `Result`
+    let value_binding = if args.cache_errors {
+        quote! { let kash_result = kash_value; }
+    } else {
+        quote! { let kash_result = &kash_value; }
+    };
+    let set_call = gen_set_call(args, &signature.output, &set_may_await, None);
+
+    let mut set_cache_sig = signature.clone();
+    set_cache_sig.ident = set_cache_fn_ident;
+    let value_input: FnArg = parse_quote! { kash_value: #cache_value_ty };
+    set_cache_sig.inputs.push(value_input);
+    set_cache_sig.output = parse_quote! { -> ::std::result::Result<(), #error_ty> };
+
+    quote! {
+        #[doc = #set_cache_fn_ident_doc]
+        #[allow(dead_code, unused_mut, unused_variables)]
+        #(#attributes)*
+        #visibility #set_cache_sig {
+            #use_trait
+            #init
+            #key_binding
+            #key_len_guard
+            #value_binding
+            let kash_cache = #cache;
+            #set_call
+            Ok(())
+        }
+    }
+}
+
+// the `{fn}_key` function, returning the key given to the store, which hashes it if it's too long
+pub fn gen_key_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream2 {
+    let (key_ty, _) = gen_cache_key_value_ty(args, input);
+    let key_guard = if hashes_long_keys(args) {
+        gen_key_len_guard(args, quote! {}, quote! {})
+    } else {
+        quote! {}
+    };
+    crate::common::gen_key_fn(input, args, quote! { #key_ty }, key_guard)
+}
+
+// bind `kash_key` to the key of the normalized inputs, or return `bypass` if the `try_expr` of the key fails
+pub fn gen_normalized_key_binding(
+    args: &MacroArgs,
+    key_expr: &TokenStream2,
+    bypass: TokenStream2,
+) -> TokenStream2 {
+    let normalize = gen_normalize(args);
+    let key_binding = gen_key_binding(&args.key, key_expr, bypass);
+    quote! {
+        #normalize
+        #key_binding
+    }
+}
+
+// whether long keys are hashed, so the key of the cache is a `String`
+pub fn hashes_long_keys(args: &MacroArgs) -> bool {
+    args.max_key_len.is_some() && args.key_overflow == KeyOverflow::Hash
+}
+
+// the key type of the cache, which is a `String` if long keys are hashed
+pub fn gen_cache_key_ty(args: &MacroArgs, cache_key_ty: TokenStream2) -> Type {
+    let cache_key_ty = match &args.key {
+        _ if hashes_long_keys(args) || args.generation.is_some() => "String".to_string(),
+        None => cache_key_ty.to_string(),
+        Some(v) => v.ty.clone(),
+    };
+    parse_str::<Type>(&cache_key_ty).expect("unable to parse a cache key type")
+}
+
+// the local cache of a sync io function, the static or its accessor with `in_impl`
+pub fn gen_local_cache(args: &MacroArgs, fn_ident: &Ident) -> TokenStream2 {
+    let cache_ident = gen_cache_ident(&args.name, fn_ident);
+    if args.in_impl {
+        let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+        quote! { Self::#fn_cache_ident() }
+    } else {
+        quote! { #cache_ident }
+    }
+}
+
+// the key and value types of the cache
+pub fn gen_cache_key_value_ty(args: &MacroArgs, input: &ItemFn) -> (Type, TokenStream2) {
+    let inputs = &input.sig.inputs;
+    let (_, without_self_types) = get_input_types(inputs);
+    let (_, without_self_names) = get_input_names(inputs);
+    let (cache_key_ty, _) = make_cache_key_type(args, without_self_types, &without_self_names);
+    let cache_value_ty = gen_cache_value_type(
+        args.result,
+        args.option,
+        args.cache_errors,
+        &input.sig.output,
+    );
+    (gen_cache_key_ty(args, cache_key_ty), cache_value_ty)
+}
+
+// check the length of `kash_key` against `max_key_len`, to avoid storing huge keys
+pub fn gen_key_len_guard(
+    args: &MacroArgs,
+    error_ty: TokenStream2,
+    no_cache_call: TokenStream2,
+) -> TokenStream2 {
+    let Some(max) = args.max_key_len else {
+        return quote! {};
+    };
+
+    match args.key_overflow {
+        KeyOverflow::Bypass => quote! {
+            if ::std::string::ToString::to_string(&kash_key).len() > #max {
+                return #no_cache_call;
+            }
+        },
+        KeyOverflow::Hash => quote! {
+            let kash_key = kash::stores::hash_long_key(::std::string::ToString::to_string(&kash_key), #max);
+        },
+        KeyOverflow::Error => quote! {
+            let kash_key_len = ::std::string::ToString::to_string(&kash_key).len();
+            if kash_key_len > #max {
+                return Err(#error_ty::KeyTooLong { len: kash_key_len, max: #max }.into());
+            }
+        },
+    }
+}
+
 pub fn gen_function_call(
     asyncness: &Option<Async>,
-    without_self_names: &[TokenStream],
+    input_names: &[TokenStream],
     call_prefix: TokenStream,
     no_cache_fn_ident: Ident,
 ) -> TokenStream {
     if asyncness.is_some() {
         quote! {
-            let kash_result = #call_prefix #no_cache_fn_ident(#(#without_self_names),*).await;
+            let kash_result = #call_prefix #no_cache_fn_ident(#(#input_names),*).await;
         }
     } else {
         quote! {
-            let kash_result = #call_prefix #no_cache_fn_ident(#(#without_self_names),*);
+            let kash_result = #call_prefix #no_cache_fn_ident(#(#input_names),*);
         }
     }
 }
 
-pub fn gen_return_cache_block(result: bool, option: bool) -> TokenStream2 {
+pub fn gen_return_cache_block(result: bool, option: bool, cache_errors: bool) -> TokenStream2 {
+    if cache_errors {
+        return quote! { return kash_result.to_owned() };
+    }
+
     match (result, option) {
         (false, false) => {
             quote! { return Ok(kash_result.to_owned()) }
@@ -77,67 +346,61 @@ pub fn gen_set_return_block(
     }
 }
 
-pub fn gen_cache_value_type(result: bool, option: bool, output: &ReturnType) -> TokenStream2 {
-    match output {
-        ReturnType::Default => panic!("Should return a Result"),
-        ReturnType::Type(_, ty) => match (result, option) {
-            (true, true) => {
-                unreachable!("All errors should be handled in the `MacroArgs` validation methods")
+// the type of the value to store, i.e., the `T` of `Result<T, E>`,
+// or of `Result<Option<T>, E>` with `option` (or `result_option`)
+pub fn gen_cache_value_type(
+    result: bool,
+    option: bool,
+    cache_errors: bool,
+    output: &ReturnType,
+) -> TokenStream2 {
+    let ReturnType::Type(_, ty) = output else {
+        unreachable!("All errors should be handled in the `MacroArgs` validation methods")
+    };
+    // the whole `Result` is stored, including the errors
+    if cache_errors {
+        return quote! {#ty};
+    }
+
+    let mut value_ty =
+        first_type_argument(ty).expect("the return type is checked by `validate_io_value_type`");
+    match (result, option) {
+        (true, true) => {
+            unreachable!("All errors should be handled in the `MacroArgs` validation methods")
+        }
+        (false, true) => {
+            value_ty = first_type_argument(value_ty)
+                .expect("the return type is checked by `validate_io_value_type`");
+        }
+        _ => {}
+    }
+    quote! {#value_ty}
+}
+
+// a `#[macro_export]` macro named after the keyspace of a cache, e.g. the `prefix` of a Redis cache, so two
+// functions of a crate with the same keyspace fail to compile, since both define the macro at the crate root,
+// instead of reading and overwriting the values of each other. The name tells what to change, as the error shows it.
+// A non-exported item, e.g. a `const`, would only collide in a module, but the functions sharing a keyspace are
+// usually in different modules, e.g. two `load` functions with disk caches, so it's only hidden from the docs
+pub fn gen_keyspace_check(keyspace: &str, advice: &str, span: Span) -> TokenStream2 {
+    let mangled: String = keyspace
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c.to_string()
+            } else {
+                format!("_{:x}_", u32::from(c))
             }
-            (false, true) => match output {
-                ReturnType::Default => {
-                    panic!("Function must return something for `result` or `option` attributes")
-                }
-                ReturnType::Type(_, ty) => {
-                    if let Type::Path(typepath) = *ty.clone() {
-                        let segments = typepath.path.segments;
-                        if let PathArguments::AngleBracketed(brackets) =
-                            &segments.last().unwrap().arguments
-                        {
-                            let inner_ty = brackets.args.first().unwrap();
-                            if let GenericArgument::Type(inner_inner_ty) = inner_ty {
-                                if let Type::Path(typepath) = inner_inner_ty.clone() {
-                                    let segments = typepath.path.segments;
-                                    if let PathArguments::AngleBracketed(brackets) =
-                                        &segments.last().unwrap().arguments
-                                    {
-                                        let inner_ty = brackets.args.first().unwrap();
-                                        quote! {#inner_ty}
-                                    } else {
-                                        panic!("Function return type has no inner type, you should remove `result` or `option`")
-                                    }
-                                } else {
-                                    panic!("Function return type is too complex")
-                                }
-                            } else {
-                                panic!("Function return type is too complex")
-                            }
-                        } else {
-                            panic!("Function return type has no inner type, you should remove `result` or `option`")
-                        }
-                    } else {
-                        panic!("Function return type is too complex")
-                    }
-                }
-            },
-            _ => match output {
-                ReturnType::Default => quote! {#ty},
-                ReturnType::Type(_, ty) => {
-                    if let Type::Path(typepath) = *ty.clone() {
-                        let segments = typepath.path.segments;
-                        if let PathArguments::AngleBracketed(brackets) =
-                            &segments.last().unwrap().arguments
-                        {
-                            let inner_ty = brackets.args.first().unwrap();
-                            quote! {#inner_ty}
-                        } else {
-                            panic!("Function return type has no inner type, you should remove `result` or `option`")
-                        }
-                    } else {
-                        panic!("Function return type is too complex")
-                    }
-                }
-            },
-        },
+        })
+        .collect();
+    let macro_ident = Ident::new(&format!("{mangled}_is_shared__{advice}"), span);
+    quote! {
+        // defined in the cache getter of a method with `in_impl`
+        #[allow(unknown_lints, non_local_definitions)]
+        #[doc(hidden)]
+        #[macro_export]
+        macro_rules! #macro_ident {
+            () => {};
+        }
     }
 }
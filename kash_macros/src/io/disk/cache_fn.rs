@@ -1,9 +1,13 @@
 use crate::common::macro_args::MacroArgs;
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
+use crate::common::{
+    gen_cache_ident, gen_forwarded_attributes, gen_on_lookup, gen_origin_fn_ident,
+    gen_with_ttl_sig, get_input_names, get_input_types, make_cache_key_type,
+};
 use crate::io::common::{
-    gen_function_call, gen_init_and_get, gen_return_cache_block, gen_set_return_block,
+    gen_function_call, gen_init_and_get, gen_key_len_guard, gen_normalized_key_binding,
+    gen_return_cache_block, gen_set_return_block,
 };
-use crate::io::disk::{gen_cache_create, gen_set_cache_block, gen_use_trait};
+use crate::io::disk::{gen_cache_create, gen_record_stats, gen_set_cache_block, gen_use_trait};
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use syn::{Ident, ItemFn};
@@ -12,11 +16,25 @@ use syn::{Ident, ItemFn};
 pub struct CacheFn<'a> {
     input: &'a ItemFn,
     args: &'a MacroArgs,
+    // generate `{fn}_with_ttl`, storing the computed value with its `kash_ttl` argument
+    with_ttl: bool,
 }
 
 impl<'a> CacheFn<'a> {
     pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
-        Self { input, args }
+        Self {
+            input,
+            args,
+            with_ttl: false,
+        }
+    }
+
+    pub fn with_ttl(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self {
+            input,
+            args,
+            with_ttl: true,
+        }
     }
 }
 
@@ -25,13 +43,34 @@ impl ToTokens for CacheFn<'_> {
         let signature = &self.input.sig;
         let fn_ident = &signature.ident;
         let asyncness = &signature.asyncness;
-        let cache_fn_ident_doc = format!("Caches the function [`{}`].", fn_ident);
-        let attributes = &self.input.attrs;
+        let (cache_fn_ident_doc, attributes, cache_sig, ttl) = if self.with_ttl {
+            let attributes = gen_forwarded_attributes(&self.input.attrs);
+            (
+                format!(
+                    "Caches the function [`{}`], keeping a computed value for `kash_ttl` instead of the ttl of the cache.",
+                    fn_ident
+                ),
+                quote! {
+                    #[allow(dead_code)]
+                    #(#attributes)*
+                },
+                gen_with_ttl_sig(signature),
+                Some(quote! { kash_ttl.as_secs().max(1) }),
+            )
+        } else {
+            let attributes = &self.input.attrs;
+            (
+                format!("Caches the function [`{}`].", fn_ident),
+                quote! { #(#attributes)* },
+                signature.clone(),
+                None,
+            )
+        };
         let visibility = &self.input.vis;
         let inputs = &self.input.sig.inputs;
 
         let (_, without_self_types) = get_input_types(inputs);
-        let (_, without_self_names) = get_input_names(inputs);
+        let (maybe_with_self_names, without_self_names) = get_input_names(inputs);
 
         let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
         let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
@@ -41,7 +80,7 @@ impl ToTokens for CacheFn<'_> {
         } else {
             quote! {}
         };
-        let no_cache_fn_ident = Ident::new(&format!("{}_no_cache", fn_ident), fn_ident.span());
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
 
         let init_cache_ident = if self.args.in_impl {
             quote! {
@@ -53,12 +92,31 @@ impl ToTokens for CacheFn<'_> {
             }
         };
 
-        let (_, key_expr) =
-            make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let may_await = if asyncness.is_some() {
+            quote! { .await }
+        } else {
+            quote! {}
+        };
+        let no_cache_call =
+            quote! { #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await };
+        let key_binding = gen_normalized_key_binding(self.args, &key_expr, no_cache_call.clone());
+        let key_len_guard =
+            gen_key_len_guard(self.args, quote! { kash::DiskCacheError }, no_cache_call);
         let cache_name = cache_ident.to_string();
 
-        let set_cache_block = gen_set_cache_block(self.args.result, self.args.option);
-        let return_cache_block = gen_return_cache_block(self.args.result, self.args.option);
+        let set_cache_block = gen_set_cache_block(self.args, &self.input.sig.output, ttl.as_ref());
+        let return_cache_block =
+            gen_return_cache_block(self.args.result, self.args.option, self.args.cache_errors);
+        let on_hit = gen_on_lookup(self.args, &cache_ident, true);
+        let on_miss = gen_on_lookup(self.args, &cache_ident, false);
+        let record_hit = gen_record_stats(self.args, fn_ident, true);
+        let record_miss = gen_record_stats(self.args, fn_ident, false);
+        let return_cache_block = quote! {
+            #on_hit
+            #record_hit
+            #return_cache_block
+        };
 
         let cache_create = gen_cache_create(self.args, cache_name);
 
@@ -85,7 +143,7 @@ impl ToTokens for CacheFn<'_> {
         };
         let function_call = gen_function_call(
             asyncness,
-            &without_self_names,
+            &maybe_with_self_names,
             call_prefix,
             no_cache_fn_ident,
         );
@@ -99,14 +157,17 @@ impl ToTokens for CacheFn<'_> {
 
         let expanded = quote! {
             #[doc = #cache_fn_ident_doc]
-            #(#attributes)*
-             #visibility #signature {
+            #attributes
+             #visibility #cache_sig {
                  #init
                  #use_trait
-                 let kash_key = #key_expr;
+                 #key_binding
+                 #key_len_guard
                  {
                      #init_and_get
                  }
+                 #on_miss
+                 #record_miss
                  #do_set_return_block
              }
         };
@@ -1,11 +1,21 @@
+use crate::common::disabled_fn::gen_disabled_fns;
+use crate::common::handle_fn::{HandleFn, HandleOps, HandleTypes};
 use crate::common::macro_args::MacroArgs;
 use crate::common::no_cache_fn::NoCacheFn;
+use crate::common::{
+    gen_cache_ident, gen_forwarded_attributes, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::io::common::{
+    gen_cache_key_ty, gen_cache_key_value_ty, gen_init_cache_fn, gen_key_fn, gen_key_len_guard,
+    gen_local_cache, gen_normalized_key_binding, gen_set_cache_fn, gen_set_call, gen_skip_if_guard,
+};
 use cache_fn::CacheFn;
 use prime_fn::PrimeFn;
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use syn::{parse_str, Expr, ItemFn, Type};
+use syn::{parse_quote, parse_str, Expr, Ident, ItemFn, ReturnType};
 use ty::CacheType;
 
 pub mod cache_fn;
@@ -13,22 +23,187 @@ pub mod prime_fn;
 pub mod ty;
 
 pub(crate) fn kash(input: &ItemFn, args: &MacroArgs) -> TokenStream {
-    let no_cache_fn = NoCacheFn::new(input);
+    let no_cache_fn = NoCacheFn::new(input, args);
     let prime_fn = PrimeFn::new(input, args);
     let cache_fn = CacheFn::new(input, args);
+    let with_ttl_fn = args.with_ttl.then(|| CacheFn::with_ttl(input, args));
     let cache_type = CacheType::new(input, args);
+    let handle_fn = gen_handle_fn(input, args);
+    let key_fn = gen_key_fn(input, args);
+    let cache_name = gen_cache_ident(&args.name, &input.sig.ident).to_string();
+    let stats_fn = gen_stats_fn(input, args);
+    let entry_age_fn = gen_entry_age_fn(input, args, gen_cache_create(args, cache_name.clone()));
+    let init_cache_fn = gen_init_cache_fn(
+        input,
+        args,
+        quote! { kash::stores::DiskCacheBuildError },
+        gen_cache_build(args, cache_name.clone()),
+    );
+    let set_cache_fn = gen_set_cache_fn(
+        input,
+        args,
+        quote! { kash::DiskCacheError },
+        gen_use_trait(),
+        gen_cache_create(args, cache_name),
+        quote! {},
+    );
 
-    quote! {
+    let items = quote! {
         #cache_type
+        #init_cache_fn
         #no_cache_fn
         #prime_fn
+        #set_cache_fn
+        #key_fn
+        #entry_age_fn
+        #stats_fn
         #cache_fn
+        #with_ttl_fn
+        #handle_fn
+    };
+    if args.disabled {
+        gen_disabled_fns(input, args, items, |_, value| value).into()
+    } else {
+        items.into()
+    }
+}
+
+fn gen_handle_fn<'a>(input: &'a ItemFn, args: &'a MacroArgs) -> HandleFn<'a> {
+    let (key, value) = gen_cache_key_value_ty(args, input);
+    let local_cache = gen_local_cache(args, &input.sig.ident);
+    let types = HandleTypes {
+        key: quote! { #key },
+        value,
+        error: quote! { kash::DiskCacheError },
+        stored_key: quote! { String },
+    };
+    let ops = HandleOps {
+        // the records which `iter` skips, e.g. expired ones, or of another version, aren't cached entries
+        len: quote! {
+            || #local_cache.iter().try_fold(0, |kash_len, kash_entry| kash_entry.map(|_| kash_len + 1))
+        },
+        clear: quote! { || Ok(#local_cache.tree().clear()?) },
+        remove: quote! {
+            |kash_key| {
+                use kash::IOKash;
+                #local_cache.remove(kash_key)
+            }
+        },
+        contains: quote! {
+            |kash_key| {
+                use kash::IOKash;
+                Ok(#local_cache.get(kash_key)?.is_some())
+            }
+        },
+        iter: quote! { || #local_cache.iter().collect() },
+    };
+    HandleFn::new(input, args, types, ops)
+}
+
+// the `{fn}_cache_stats` function of `stats`, returning the hit and miss counters of the cache
+fn gen_stats_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream2 {
+    if !args.stats {
+        return quote! {};
+    }
+
+    let fn_ident = &input.sig.ident;
+    let stats_fn_ident = Ident::new(&format!("{}_cache_stats", fn_ident), fn_ident.span());
+    let stats_fn_ident_doc = format!(
+        "Returns the hit and miss counters of the cache of the function [`{}`].",
+        fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+
+    quote! {
+        #[doc = #stats_fn_ident_doc]
+        #[allow(dead_code)]
+        #(#attributes)*
+        #visibility fn #stats_fn_ident() -> &'static ::kash::CacheStats {
+            static KASH_STATS: ::kash::CacheStats = ::kash::CacheStats::new();
+            &KASH_STATS
+        }
+    }
+}
+
+// count a hit or a miss of the cache with `stats`
+fn gen_record_stats(args: &MacroArgs, fn_ident: &Ident, hit: bool) -> TokenStream2 {
+    if !args.stats {
+        return quote! {};
+    }
+
+    let call_prefix = if args.in_impl {
+        quote! { Self:: }
+    } else {
+        quote! {}
+    };
+    let stats_fn_ident = Ident::new(&format!("{}_cache_stats", fn_ident), fn_ident.span());
+    if hit {
+        quote! { #call_prefix #stats_fn_ident().record_hit(); }
+    } else {
+        quote! { #call_prefix #stats_fn_ident().record_miss(); }
+    }
+}
+
+// the `{fn}_entry_age` function, returning how long ago the value of the arguments was cached
+fn gen_entry_age_fn(input: &ItemFn, args: &MacroArgs, cache_create: TokenStream2) -> TokenStream2 {
+    let signature = &input.sig;
+    let fn_ident = &signature.ident;
+    let entry_age_fn_ident = Ident::new(&format!("{}_entry_age", fn_ident), fn_ident.span());
+    let entry_age_fn_ident_doc = format!(
+        "Returns how long ago the value of the arguments was stored in the cache of the function [`{}`], without refreshing it.",
+        fn_ident
+    );
+    let attributes = gen_forwarded_attributes(&input.attrs);
+    let visibility = &input.vis;
+
+    let (_, without_self_types) = get_input_types(&signature.inputs);
+    let (_, without_self_names) = get_input_names(&signature.inputs);
+    let (_, key_expr) = make_cache_key_type(args, without_self_types, &without_self_names);
+
+    // a key which can't be made, or is too long to be stored, is never cached
+    let key_binding = gen_normalized_key_binding(args, &key_expr, quote! { Ok(None) });
+    let key_len_guard =
+        gen_key_len_guard(args, quote! { kash::DiskCacheError }, quote! { Ok(None) });
+
+    let local_cache = gen_local_cache(args, fn_ident);
+    let cache = if signature.asyncness.is_some() {
+        quote! {
+            #local_cache.get_or_init(|| async { #cache_create }).await
+        }
+    } else {
+        local_cache
+    };
+
+    let mut entry_age_sig = signature.clone();
+    entry_age_sig.ident = entry_age_fn_ident;
+    entry_age_sig.output = parse_quote! {
+        -> ::std::result::Result<::std::option::Option<::std::time::Duration>, kash::DiskCacheError>
+    };
+
+    quote! {
+        #[doc = #entry_age_fn_ident_doc]
+        #[allow(dead_code, unused_mut, unused_variables)]
+        #(#attributes)*
+        #visibility #entry_age_sig {
+            #key_binding
+            #key_len_guard
+            #cache.entry_age(&kash_key)
+        }
     }
-    .into()
 }
 
-fn gen_set_cache_block(result: bool, option: bool) -> TokenStream2 {
-    let cache_let = match (result, option) {
+fn gen_set_cache_block(
+    args: &MacroArgs,
+    output: &ReturnType,
+    ttl: Option<&TokenStream2>,
+) -> TokenStream2 {
+    let set = gen_skip_if_guard(args, output, gen_set_call(args, output, &quote! {}, ttl));
+    if args.cache_errors {
+        return set;
+    }
+
+    let cache_let = match (args.result, args.option) {
         (false, false) => {
             quote! { if let Ok(kash_result) = &kash_result  }
         }
@@ -47,7 +222,7 @@ fn gen_set_cache_block(result: bool, option: bool) -> TokenStream2 {
 
     quote! {
         #cache_let {
-            kash_cache.set(kash_key, kash_result.clone())?;
+            #set
         }
     }
 }
@@ -57,18 +232,31 @@ fn gen_cache_ty(
     cache_value_ty: TokenStream2,
     cache_key_ty: TokenStream2,
 ) -> TokenStream2 {
-    let cache_key_ty = match &args.key {
-        None => cache_key_ty.to_string(),
-        Some(v) => v.ty.clone(),
-    };
-    let cache_key_ty = parse_str::<Type>(&cache_key_ty).expect("unable to parse a cache key type");
+    let cache_key_ty = gen_cache_key_ty(args, cache_key_ty);
 
     // https://github.com/spacejam/sled?tab=readme-ov-file#interaction-with-async
     quote! { kash::DiskCache<#cache_key_ty, #cache_value_ty> }
 }
 
 fn gen_cache_create(args: &MacroArgs, cache_name: String) -> TokenStream2 {
-    let ttl = &args.ttl;
+    let cache_build = gen_cache_build(args, cache_name);
+    quote! { #cache_build.expect("error constructing DiskCache in #[kash] macro") }
+}
+
+// the `Result` of building the cache
+fn gen_cache_build(args: &MacroArgs, cache_name: String) -> TokenStream2 {
+    let ttl = args
+        .ttl
+        .as_ref()
+        .map(|ttl| args.typed_expr("ttl", ttl, quote! { u64 }));
+    let ttl_ms = args
+        .ttl_ms
+        .as_ref()
+        .map(|ttl_ms| args.typed_expr("ttl_ms", ttl_ms, quote! { u64 }));
+    let schema_version = args
+        .schema_version
+        .as_ref()
+        .map(|schema_version| args.typed_expr("schema_version", schema_version, quote! { u64 }));
     let args = args.disk.as_ref().expect("We are in the disk section");
 
     let connection_config = match &args.connection_config {
@@ -85,20 +273,55 @@ fn gen_cache_create(args: &MacroArgs, cache_name: String) -> TokenStream2 {
             .set_sync_to_disk_on_cache_change(#sync_to_disk_on_cache_change)
     };
     if let Some(ttl) = ttl {
-        let ttl = parse_str::<Expr>(ttl).expect("Unable to parse ttl");
         create = quote! {
             (#create).set_ttl(#ttl)
         };
     };
+    if let Some(ttl_ms) = ttl_ms {
+        create = quote! {
+            (#create).set_ttl_duration(::std::time::Duration::from_millis(#ttl_ms))
+        };
+    };
+    if let Some(schema_version) = schema_version {
+        create = quote! { (#create).set_schema_version(#schema_version) };
+    }
     if let Some(connection_config) = connection_config {
         create = quote! {
             (#create).set_connection_config(#connection_config)
         };
     };
+    if let Some(shared_db) = &args.shared_db {
+        let shared_db = parse_str::<Expr>(shared_db).expect("unable to parse shared_db block");
+        create = quote! { (#create).set_shared_db(&(#shared_db)) };
+    }
     if let Some(dir) = &args.dir {
         create = quote! { (#create).set_disk_directory(#dir) };
     }
-    quote! { (#create).build().expect("error constructing DiskCache in #[kash] macro") }
+    if args.flush_on_exit {
+        create = quote! { (#create).set_flush_on_exit(true) };
+    }
+    if args.use_compression {
+        create = quote! { (#create).set_use_compression(true) };
+    }
+    if let Some(compression_factor) = args.compression_factor {
+        create = quote! { (#create).set_compression_factor(#compression_factor) };
+    }
+    quote! { (#create).build() }
+}
+
+// clear the cache in the `OnceCell` (`Lazy` if not async) at `cache`, unless it's not initialized,
+// ignoring the errors
+pub(crate) fn gen_clear_cache(asyncness: bool, cache: TokenStream2) -> TokenStream2 {
+    let cache = if asyncness {
+        quote! { (#cache).get() }
+    } else {
+        quote! { ::kash::once_cell::sync::Lazy::get(#cache) }
+    };
+    quote! {
+        if let Some(kash_cache) = #cache {
+            let _ = kash_cache.tree().clear();
+        }
+    }
 }
 
 fn gen_use_trait() -> TokenStream2 {
@@ -1,6 +1,9 @@
 use crate::common::macro_args::MacroArgs;
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
-use crate::io::common::gen_set_return_block;
+use crate::common::{
+    gen_cache_ident, gen_forwarded_attributes, gen_origin_fn_ident, gen_prime_blocking_fn,
+    get_input_names, get_input_types, make_cache_key_type,
+};
+use crate::io::common::{gen_key_len_guard, gen_normalized_key_binding, gen_set_return_block};
 use crate::io::disk::{gen_cache_create, gen_set_cache_block, gen_use_trait};
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
@@ -29,7 +32,7 @@ impl ToTokens for PrimeFn<'_> {
         prime_sig.ident = prime_fn_ident;
 
         let prime_fn_indent_doc = format!("Primes the function [`{}`].", fn_ident);
-        let attributes = &self.input.attrs;
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
         let visibility = &self.input.vis;
         let inputs = &self.input.sig.inputs;
 
@@ -44,7 +47,7 @@ impl ToTokens for PrimeFn<'_> {
         } else {
             quote! {}
         };
-        let no_cache_fn_ident = Ident::new(&format!("{}_no_cache", fn_ident), fn_ident.span());
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
 
         let may_await = if asyncness.is_some() {
             quote! {.await}
@@ -66,11 +69,15 @@ impl ToTokens for PrimeFn<'_> {
             let kash_result = #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await;
         };
 
-        let (_, key_expr) =
-            make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let no_cache_call =
+            quote! { #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await };
+        let key_binding = gen_normalized_key_binding(self.args, &key_expr, no_cache_call.clone());
+        let key_len_guard =
+            gen_key_len_guard(self.args, quote! { kash::DiskCacheError }, no_cache_call);
         let cache_name = cache_ident.to_string();
 
-        let set_cache_block = gen_set_cache_block(self.args.result, self.args.option);
+        let set_cache_block = gen_set_cache_block(self.args, &self.input.sig.output, None);
 
         let cache_create = gen_cache_create(self.args, cache_name);
 
@@ -91,6 +98,9 @@ impl ToTokens for PrimeFn<'_> {
             set_cache_and_return,
         );
 
+        let prime_blocking_fn =
+            gen_prime_blocking_fn(self.input, &prime_sig, &call_prefix, &maybe_with_self_names);
+
         let expanded = quote! {
             #[doc = #prime_fn_indent_doc]
             #[allow(dead_code)]
@@ -98,9 +108,11 @@ impl ToTokens for PrimeFn<'_> {
             #visibility #prime_sig {
                 #use_trait
                 #init
-                let kash_key = #key_expr;
+                #key_binding
+                #key_len_guard
                 #do_set_return_block
             }
+            #prime_blocking_fn
         };
 
         tokens.extend(expanded);
@@ -1,7 +1,10 @@
 use crate::common::macro_args::MacroArgs;
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
-use crate::io::common::gen_cache_value_type;
-use crate::io::disk::{gen_cache_create, gen_cache_ty};
+use crate::common::{
+    gen_cache_ident, gen_cache_vis, gen_registry_entry, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::io::common::{gen_cache_value_type, gen_keyspace_check};
+use crate::io::disk::{gen_cache_create, gen_cache_ty, gen_clear_cache};
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use syn::{Ident, ItemFn};
@@ -21,7 +24,7 @@ impl<'a> CacheType<'a> {
 
 impl ToTokens for CacheType<'_> {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        let visibility = &self.input.vis;
+        let visibility = gen_cache_vis(self.args, self.input);
         let signature = &self.input.sig;
         let asyncness = &signature.asyncness;
         let fn_ident = &signature.ident;
@@ -35,35 +38,69 @@ impl ToTokens for CacheType<'_> {
         let (_, without_self_types) = get_input_types(inputs);
         let (_, without_self_names) = get_input_names(inputs);
 
-        let cache_value_ty = gen_cache_value_type(self.args.result, self.args.option, output);
+        let cache_value_ty = gen_cache_value_type(
+            self.args.result,
+            self.args.option,
+            self.args.cache_errors,
+            output,
+        );
 
         let (cache_key_ty, _) =
-            make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
+            make_cache_key_type(self.args, without_self_types, &without_self_names);
 
         let cache_ty = gen_cache_ty(self.args, cache_value_ty, cache_key_ty);
-        let cache_create = gen_cache_create(self.args, cache_name);
+        let cache_create = gen_cache_create(self.args, cache_name.clone());
+        let disk = self.args.disk.as_ref().expect("We are in the disk section");
+        // the records of a shared database are in a tree named after the cache, whatever its `dir`
+        let keyspace_check = if disk.shared_db.is_some() {
+            gen_keyspace_check(
+                &format!("kash_disk_shared_db_{cache_name}"),
+                "set_another_name",
+                fn_ident.span(),
+            )
+        } else {
+            let dir = disk.dir.as_deref().unwrap_or_default();
+            gen_keyspace_check(
+                &format!("kash_disk_cache_{dir}_{cache_name}"),
+                "set_another_name_or_dir",
+                fn_ident.span(),
+            )
+        };
 
         let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
 
+        let registry_entry = gen_registry_entry(gen_clear_cache(
+            asyncness.is_some(),
+            quote! { &#cache_ident },
+        ));
+
         let key = match (asyncness.is_some(), self.args.in_impl) {
             (true, true) => quote! {
                 #visibility fn #fn_cache_ident() -> &'static ::kash::async_sync::OnceCell<#cache_ty> {
                     static #cache_ident: ::kash::async_sync::OnceCell<#cache_ty> = ::kash::async_sync::OnceCell::const_new();
+                    #registry_entry
+                    #keyspace_check
                     &#cache_ident
                 }
             },
             (true, false) => quote! {
                 #visibility static #cache_ident: ::kash::async_sync::OnceCell<#cache_ty> = ::kash::async_sync::OnceCell::const_new();
+                #registry_entry
+                #keyspace_check
             },
 
             (false, true) => quote! {
                 #visibility fn #fn_cache_ident() -> &'static ::kash::once_cell::sync::Lazy<#cache_ty> {
                     static #cache_ident: ::kash::once_cell::sync::Lazy<#cache_ty> = ::kash::once_cell::sync::Lazy::new(|| #cache_create);
+                    #registry_entry
+                    #keyspace_check
                     &#cache_ident
                 }
             },
             (false, false) => quote! {
                 #visibility static #cache_ident: ::kash::once_cell::sync::Lazy<#cache_ty> = ::kash::once_cell::sync::Lazy::new(|| #cache_create);
+                #registry_entry
+                #keyspace_check
             },
         };
 
@@ -1,35 +1,138 @@
+use crate::common::disabled_fn::gen_disabled_fns;
+use crate::common::gen_cache_ident;
+use crate::common::handle_fn::{HandleFn, HandleOps, HandleTypes};
 use crate::common::macro_args::MacroArgs;
 use crate::common::no_cache_fn::NoCacheFn;
+use crate::io::common::{
+    gen_cache_key_ty, gen_cache_key_value_ty, gen_init_cache_fn, gen_key_fn, gen_local_cache,
+    gen_set_cache_fn, gen_set_call, gen_skip_if_guard,
+};
 use cache_fn::CacheFn;
 use prime_fn::PrimeFn;
 use proc_macro::TokenStream;
 use proc_macro2::Ident;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
+use refresh_fn::RefreshFn;
 use syn::token::Async;
-use syn::{parse_str, Block, Expr, ItemFn, Type};
+use syn::{parse_str, Block, ItemFn, ReturnType};
 use ty::CacheType;
 
 pub mod cache_fn;
 pub mod prime_fn;
+pub mod refresh_fn;
 pub mod ty;
 
 pub(crate) fn kash(input: &ItemFn, args: &MacroArgs) -> TokenStream {
-    let no_cache_fn = NoCacheFn::new(input);
+    let no_cache_fn = NoCacheFn::new(input, args);
     let prime_fn = PrimeFn::new(input, args);
     let cache_fn = CacheFn::new(input, args);
+    let with_ttl_fn = args.with_ttl.then(|| CacheFn::with_ttl(input, args));
+    let refresh_fn = RefreshFn::new(input, args);
     let cache_type = CacheType::new(input, args);
+    let handle_fn = gen_handle_fn(input, args);
+    let key_fn = gen_key_fn(input, args);
+    let asyncness = &input.sig.asyncness;
+    let cache_ident = gen_cache_ident(&args.name, &input.sig.ident);
+    let set_may_await = if asyncness.is_some() {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    let init_cache_fn = gen_init_cache_fn(
+        input,
+        args,
+        quote! { kash::stores::RedisCacheBuildError },
+        gen_cache_build(args, asyncness, &cache_ident),
+    );
+    let set_cache_fn = gen_set_cache_fn(
+        input,
+        args,
+        quote! { kash::RedisCacheError },
+        gen_use_trait(asyncness),
+        gen_cache_create(args, asyncness, &cache_ident),
+        set_may_await,
+    );
 
-    quote! {
+    let items = quote! {
         #cache_type
+        #init_cache_fn
         #no_cache_fn
         #prime_fn
+        #refresh_fn
+        #set_cache_fn
+        #key_fn
         #cache_fn
+        #with_ttl_fn
+        #handle_fn
+    };
+    if args.disabled {
+        gen_disabled_fns(input, args, items, |_, value| value).into()
+    } else {
+        items.into()
     }
-    .into()
 }
 
-fn gen_set_cache_block(result: bool, option: bool, asyncness: &Option<Async>) -> TokenStream2 {
+fn gen_handle_fn<'a>(input: &'a ItemFn, args: &'a MacroArgs) -> HandleFn<'a> {
+    let (key, value) = gen_cache_key_value_ty(args, input);
+    let local_cache = gen_local_cache(args, &input.sig.ident);
+    let types = HandleTypes {
+        key: quote! { #key },
+        value,
+        error: quote! { kash::RedisCacheError },
+        stored_key: quote! { String },
+    };
+    let ops = HandleOps {
+        len: quote! { || #local_cache.len() },
+        clear: quote! { || #local_cache.clear() },
+        remove: quote! {
+            |kash_key| {
+                use kash::IOKash;
+                #local_cache.remove(kash_key)
+            }
+        },
+        contains: quote! {
+            |kash_key| {
+                use kash::IOKash;
+                Ok(#local_cache.get(kash_key)?.is_some())
+            }
+        },
+        iter: quote! { || #local_cache.iter() },
+    };
+    HandleFn::new(input, args, types, ops)
+}
+
+fn gen_set_cache_block(
+    args: &MacroArgs,
+    output: &ReturnType,
+    asyncness: &Option<Async>,
+    ttl: Option<&TokenStream2>,
+) -> TokenStream2 {
+    let may_await = if asyncness.is_some() {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+
+    gen_guarded_set(
+        args.result,
+        args.option,
+        args.cache_errors,
+        gen_skip_if_guard(args, output, gen_set_call(args, output, &may_await, ttl)),
+    )
+}
+
+// only run the `set` block for the values that should be cached
+fn gen_guarded_set(
+    result: bool,
+    option: bool,
+    cache_errors: bool,
+    set: TokenStream2,
+) -> TokenStream2 {
+    if cache_errors {
+        return set;
+    }
+
     let cache_let = match (result, option) {
         (false, false) => {
             quote! { if let Ok(kash_result) = &kash_result  }
@@ -47,23 +150,9 @@ fn gen_set_cache_block(result: bool, option: bool, asyncness: &Option<Async>) ->
         _ => unreachable!("All errors should be handled in the `MacroArgs` validation methods"),
     };
 
-    if asyncness.is_some() {
-        let may_await = if asyncness.is_some() {
-            quote! { .await }
-        } else {
-            quote! {}
-        };
-
-        quote! {
-            #cache_let {
-                kash_cache.set(kash_key, kash_result.clone())#may_await?;
-            }
-        }
-    } else {
-        quote! {
-            #cache_let {
-                kash_cache.set(kash_key, kash_result.clone())?;
-            }
+    quote! {
+        #cache_let {
+            #set
         }
     }
 }
@@ -74,11 +163,7 @@ fn gen_cache_ty(
     cache_value_ty: TokenStream2,
     cache_key_ty: TokenStream2,
 ) -> TokenStream2 {
-    let cache_key_ty = match &args.key {
-        None => cache_key_ty.to_string(),
-        Some(v) => v.ty.clone(),
-    };
-    let cache_key_ty = parse_str::<Type>(&cache_key_ty).expect("unable to parse a cache key type");
+    let cache_key_ty = gen_cache_key_ty(args, cache_key_ty);
 
     if asyncness.is_some() {
         quote! { kash::AsyncRedisCache<#cache_key_ty, #cache_value_ty> }
@@ -92,31 +177,79 @@ fn gen_cache_create(
     asyncness: &Option<Async>,
     cache_ident: &Ident,
 ) -> TokenStream2 {
-    let ttl = &args.ttl;
+    let cache_build = gen_cache_build(args, asyncness, cache_ident);
+    if asyncness.is_some() {
+        quote! { #cache_build.expect("error constructing AsyncRedisCache in #[kash] macro") }
+    } else {
+        quote! { #cache_build.expect("error constructing RedisCache in #[kash] macro") }
+    }
+}
+
+// the `Result` of building the cache
+fn gen_cache_build(
+    args: &MacroArgs,
+    asyncness: &Option<Async>,
+    cache_ident: &Ident,
+) -> TokenStream2 {
+    let ttl = args
+        .ttl
+        .as_ref()
+        .map(|ttl| args.typed_expr("ttl", ttl, quote! { u64 }));
+    let set_ttl_ms = args.ttl_ms.as_ref().map(|ttl_ms| {
+        let ttl_ms = args.typed_expr("ttl_ms", ttl_ms, quote! { u64 });
+        let ttl = quote! { ::std::time::Duration::from_millis(#ttl_ms) };
+        if asyncness.is_some() {
+            quote! { .set_ttl_duration(Some(#ttl)) }
+        } else {
+            quote! { .set_ttl_duration(#ttl) }
+        }
+    });
+    let set_schema_version = args.schema_version.as_ref().map(|schema_version| {
+        let schema_version = args.typed_expr("schema_version", schema_version, quote! { u64 });
+        quote! { .set_schema_version(#schema_version) }
+    });
     let args = args.redis.as_ref().expect("We are in the redis section");
 
     let ttl = match ttl {
-        Some(ttl) => {
-            let ttl = parse_str::<Expr>(ttl).expect("Unable to parse ttl");
-            quote! { Some(#ttl) }
-        }
+        Some(ttl) => quote! { Some(#ttl) },
         None => quote! { None },
     };
 
-    let cache_prefix = if let Some(cp) = &args.prefix_block {
-        cp.to_string()
+    let cache_prefix = if let Some(prefix) = &args.prefix {
+        quote! { #prefix }
+    } else if let Some(cp) = &args.prefix_block {
+        let cp = parse_str::<Block>(cp).expect("unable to parse prefix_block");
+        quote! { #cp }
     } else {
-        format!(" {{ \"{}\" }}", cache_ident)
+        // the module path of the function makes the prefix unique across modules and crates
+        let prefix = format!("::{}:", cache_ident);
+        quote! { ::std::concat!(::std::module_path!(), #prefix) }
     };
-    let cache_prefix = parse_str::<Block>(&cache_prefix).expect("unable to parse prefix_block");
+
+    let set_namespace = args
+        .namespace
+        .as_ref()
+        .map(|namespace| quote! { .set_namespace(#namespace) });
 
     if asyncness.is_some() {
-        quote! { kash::AsyncRedisCache::new(#cache_prefix, #ttl).build().await.expect("error constructing AsyncRedisCache in #[kash] macro") }
+        quote! { kash::AsyncRedisCache::new(#cache_prefix, #ttl)#set_ttl_ms #set_namespace #set_schema_version.build().await }
     } else {
-        quote! {
-            kash::RedisCache::new(#cache_prefix, #ttl).build().expect("error constructing RedisCache in #[kash] macro")
-        }
+        quote! { kash::RedisCache::new(#cache_prefix, #ttl)#set_ttl_ms #set_namespace #set_schema_version.build() }
+    }
+}
+
+// clear the cache in the `Lazy` at `cache`, unless it's never been used, ignoring the errors;
+// the async cache can only be cleared in an async context
+pub(crate) fn gen_clear_cache(asyncness: bool, cache: TokenStream2) -> Option<TokenStream2> {
+    if asyncness {
+        return None;
     }
+
+    Some(quote! {
+        if let Some(kash_cache) = ::kash::once_cell::sync::Lazy::get(#cache) {
+            let _ = kash_cache.clear();
+        }
+    })
 }
 
 fn gen_use_trait(asyncness: &Option<Async>) -> TokenStream2 {
@@ -0,0 +1,119 @@
+use crate::common::macro_args::MacroArgs;
+use crate::common::{
+    gen_cache_ident, gen_forwarded_attributes, gen_origin_fn_ident, get_input_names,
+    get_input_types, make_cache_key_type,
+};
+use crate::io::common::{gen_key_len_guard, gen_normalized_key_binding, gen_skip_if_guard};
+use crate::io::redis::{gen_cache_create, gen_guarded_set};
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{Ident, ItemFn};
+
+// struct for the function recomputing a value and only storing it if it's not changed meanwhile
+#[derive(Debug, Clone)]
+pub struct RefreshFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> RefreshFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for RefreshFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let asyncness = &signature.asyncness;
+        let refresh_fn_ident =
+            Ident::new(&format!("{}_refresh_if_stale", &fn_ident), fn_ident.span());
+        let mut refresh_sig = signature.clone();
+        refresh_sig.ident = refresh_fn_ident;
+
+        let refresh_fn_ident_doc = format!(
+            "Recomputes the function [`{}`], but only caches the result if the cached value is not changed meanwhile.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+        let inputs = &self.input.sig.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (maybe_with_self_names, without_self_names) = get_input_names(inputs);
+
+        let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+        let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
+
+        let call_prefix = if self.args.in_impl {
+            quote! { Self:: }
+        } else {
+            quote! {}
+        };
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
+
+        let may_await = if asyncness.is_some() {
+            quote! {.await}
+        } else {
+            quote! {}
+        };
+
+        let init_cache_ident = if self.args.in_impl {
+            quote! {
+                &#call_prefix #fn_cache_ident()
+            }
+        } else {
+            quote! {
+                &#call_prefix #cache_ident
+            }
+        };
+
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let no_cache_call =
+            quote! { #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await };
+        let key_binding = gen_normalized_key_binding(self.args, &key_expr, no_cache_call.clone());
+        let key_len_guard =
+            gen_key_len_guard(self.args, quote! { kash::RedisCacheError }, no_cache_call);
+
+        let set_cache_block = gen_guarded_set(
+            self.args.result,
+            self.args.option,
+            self.args.cache_errors,
+            gen_skip_if_guard(
+                self.args,
+                &self.input.sig.output,
+                quote! {
+                    kash_cache.set_if_unchanged(kash_key, kash_stale.as_deref(), kash_result.clone())#may_await?;
+                },
+            ),
+        );
+
+        let get_cache = if asyncness.is_some() {
+            let cache_create = gen_cache_create(self.args, asyncness, &cache_ident);
+            quote! {
+                let kash_init = || async { #cache_create };
+                let kash_cache = #init_cache_ident.get_or_init(kash_init).await;
+            }
+        } else {
+            quote! { let kash_cache = #init_cache_ident; }
+        };
+
+        let expanded = quote! {
+            #[doc = #refresh_fn_ident_doc]
+            #[allow(dead_code)]
+            #(#attributes)*
+            #visibility #refresh_sig {
+                #key_binding
+                #key_len_guard
+                #get_cache
+                let kash_stale = kash_cache.get_raw(&kash_key)#may_await?;
+                let kash_result = #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await;
+                #set_cache_block
+                kash_result
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::common::desugar_future_fn;
+use crate::common::macro_args::MacroArgs;
+use crate::io::{disk, redis};
+use crate::mem;
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_quote, parse_str, Attribute, Ident, ImplItem, ImplItemFn, ItemFn, ItemImpl, Meta};
+
+pub(crate) fn kash_impl(mut input: ItemImpl) -> TokenStream {
+    if let Some((_, path, _)) = &input.trait_ {
+        return syn::Error::new(
+            path.span(),
+            "`kash_impl` adds an associated function to the type, so it must be on an inherent `impl` block",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let mut clears = Vec::new();
+    let mut errors = TokenStream2::new();
+    for item in &input.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let Some(attr) = method.attrs.iter().find(|attr| is_kash_attr(attr)) else {
+            continue;
+        };
+        // invalid arguments are reported by `#[kash]` itself
+        let Some(args) = parse_kash_args(attr) else {
+            continue;
+        };
+        match gen_clear_method_cache(method, &args) {
+            Ok(clear) => clears.push(clear),
+            Err(e) => errors.extend(e.to_compile_error()),
+        }
+    }
+
+    let self_ty = &input.self_ty;
+    let clear_all_doc = format!(
+        "Clears the caches of the memoized methods of [`{}`].",
+        quote! { #self_ty }.to_string().replace(' ', "")
+    );
+    input.items.push(parse_quote! {
+        #[doc = #clear_all_doc]
+        #[allow(dead_code)]
+        pub fn kash_clear_all() {
+            #(#clears)*
+        }
+    });
+
+    quote! {
+        #input
+        #errors
+    }
+    .into()
+}
+
+// `#[kash]` or `#[kash::kash]`
+fn is_kash_attr(attr: &Attribute) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "kash")
+}
+
+fn parse_kash_args(attr: &Attribute) -> Option<MacroArgs> {
+    let tokens = match &attr.meta {
+        Meta::Path(_) => TokenStream2::new(),
+        Meta::List(list) => list.tokens.clone(),
+        Meta::NameValue(_) => return None,
+    };
+    MacroArgs::try_from(tokens.into()).ok()
+}
+
+// the statement clearing the cache of a method, found by the accessor `#[kash(in_impl)]` generates
+fn gen_clear_method_cache(method: &ImplItemFn, args: &MacroArgs) -> syn::Result<TokenStream2> {
+    // without a cache, or with a cache owned by the caller, there's nothing to clear
+    if args.shared_cache.is_some() {
+        return Ok(quote! {});
+    }
+
+    let fn_ident = &method.sig.ident;
+    if !args.in_impl {
+        return Err(syn::Error::new(
+            fn_ident.span(),
+            "the methods of a `kash_impl` block must set `in_impl`",
+        ));
+    }
+    let mut input = ItemFn {
+        attrs: Vec::new(),
+        vis: method.vis.clone(),
+        sig: method.sig.clone(),
+        block: Box::new(method.block.clone()),
+    };
+    let _ = desugar_future_fn(&mut input);
+    let asyncness = input.sig.asyncness.is_some();
+
+    let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+    let cache = quote! { Self::#fn_cache_ident() };
+    let gen_clear = |asyncness: bool| -> syn::Result<TokenStream2> {
+        let clear = if args.redis.is_some() {
+            redis::gen_clear_cache(asyncness, cache.clone()).unwrap_or_default()
+        } else if args.disk.is_some() {
+            disk::gen_clear_cache(asyncness, cache.clone())
+        } else {
+            mem::gen_clear_cache(cache.clone())
+        };
+        Ok(match &args.fallback {
+            Some(fallback) => {
+                let predicate = parse_predicate(fn_ident, "fallback", fallback)?;
+                let mem_clear = mem::gen_clear_cache(cache.clone());
+                quote! {
+                    #[cfg(#predicate)]
+                    { #clear }
+                    #[cfg(not(#predicate))]
+                    { #mem_clear }
+                }
+            }
+            None => clear,
+        })
+    };
+    let clear = match &args.maybe_async {
+        Some(maybe_async) => {
+            let predicate = parse_predicate(fn_ident, "maybe_async", maybe_async)?;
+            let async_clear = gen_clear(true)?;
+            let sync_clear = gen_clear(false)?;
+            quote! {
+                #[cfg(#predicate)]
+                { #async_clear }
+                #[cfg(not(#predicate))]
+                { #sync_clear }
+            }
+        }
+        None => gen_clear(asyncness)?,
+    };
+
+    // a method compiled out by a `cfg` has no cache
+    let cfgs = method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"));
+    Ok(quote! {
+        #(#cfgs)*
+        { #clear }
+    })
+}
+
+fn parse_predicate(fn_ident: &Ident, attr: &str, predicate: &str) -> syn::Result<Meta> {
+    parse_str::<Meta>(predicate).map_err(|e| {
+        syn::Error::new(
+            fn_ident.span(),
+            format!("unable to parse `{}` of `{}`: {}", attr, fn_ident, e),
+        )
+    })
+}
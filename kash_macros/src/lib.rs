@@ -1,44 +1,346 @@
 mod common;
 mod io;
+mod kash_impl;
 mod mem;
 
 use crate::common::macro_args::MacroArgs;
 use io::{disk, redis};
 use proc_macro::TokenStream;
-use syn::{parse_macro_input, ItemFn};
+use quote::quote;
+use syn::{parse_macro_input, parse_str, ItemFn, ItemImpl, Meta};
 
 /// Define a memoized function
 ///
 /// By default, it keeps the cache in memory unless you define `disk` or `redis`.
+/// Concurrent calls of a memory cached function with the same key (sync or async) evaluate the function only once,
+/// the other callers wait for and share its result. Only the callers of the same key wait, no lock is held across
+/// keys while computing, so different keys are computed concurrently. If the caller computing the value of an `async` function is
+/// cancelled (e.g. its task is aborted), nothing is cached and one of the waiting callers computes the value instead.
+/// With `result`, an `Err` is shared with the callers waiting for it, but isn't cached, so the next call computes
+/// the value again.
 ///
 /// In the attribute list below, `size`, `eviction_policy` are possible just if it's a memory cache.
 ///
+/// Besides the cached function, memory caches also generate a `{fn}_get_or_compute` function, taking the same
+/// arguments plus a closure (or a future for `async` functions), which returns the cached value for the
+/// arguments, or computes it with the closure on a miss. They also generate a non-`async` `{fn}_is_cached`
+/// function, taking the same arguments, which returns whether a value is cached for them (and not expired),
+/// without computing or cloning it.
+///
+/// All caches generate a `{fn}_set_cache` function, taking the same arguments plus the value to store for them,
+/// which is the type of the cached value, e.g. `T` for a function returning `Result<T, E>` with `result`
+/// (or `Arc<T>` with `arc`), so a value can be injected without evaluating the function, e.g. in tests or to warm
+/// the cache from external data. It's `async` for `async` functions, and the functions of `disk` and `redis` caches
+/// return the `Result<(), DiskCacheError>` (or `RedisCacheError`) of the store.
+///
+/// All caches also generate a non-`async` `{fn}_key` function, taking the same arguments, which returns their key in
+/// the cache, e.g. to remove it with the cache or the `{fn}_cache()` handle, so the keys don't have to be rebuilt
+/// by hand. It returns an `Option` of the key with a `try_expr` key, which is `None` if the arguments are never
+/// cached. The key of `disk` and `redis` caches is hashed with `key_overflow = "hash"`, like the one they store,
+/// but the namespace and the prefix of a Redis key aren't included.
+///
+/// `moka` caches are eventually consistent: evictions (e.g. of `size`) are applied and `entry_count` is updated by
+/// maintenance tasks running in the background, so the cache can briefly hold more entries than its `size`.
+/// Memory caches generate a `{fn}_sync` function (`async` for `async` functions) running the pending tasks,
+/// e.g. in tests or when exact counts matter.
+///
+/// The memory cache of a non-`async` function without arguments (besides `self`) is a `kash::SlotCache`, holding its
+/// only value without the hash map of a `moka` cache, with the same methods to invalidate it, e.g. `CONFIG.invalidate_all()`.
+/// It keeps a `moka` cache with a `key`, `generation`, `key_digest`, `key_wrapper`, `size`, `initial_capacity`, `segments`,
+/// `shared_cache`, `in_trait`, `expiry`, `ttl_for`, `skip_if`, `with_ttl`, `invalidate_if` or `display_name`.
+///
+/// Functions returning `impl Future<Output = T>` or `Pin<Box<dyn Future<Output = T>>>` are cached like an
+/// `async fn` returning `T`, so the generated functions are `async fn`s, too. The cached function and
+/// `{fn}_no_cache` keep the return type of the function (e.g. a `Send` boxed future can still be spawned),
+/// the value is looked up in the cache when the returned future is polled.
+///
+/// The attributes of the function are kept on the cached function, but only its docs, `cfg`, `cfg_attr`,
+/// lint (e.g. `allow`) and `must_use` attributes are forwarded to the generated functions, so e.g. a
+/// `#[test]` or `#[inline]` below `#[kash]` only applies to the cached function.
+///
+/// The `unsafe` and `extern "ABI"` qualifiers of the function are kept on the cached function and the generated
+/// functions evaluating it, e.g. `{fn}_no_cache` and `{fn}_prime_cache`. A `const fn` can't be cached, since its
+/// cache is created and used at runtime.
+///
+/// Functions with type or `const` generic parameters share one cache between all their instantiations,
+/// so they need a `key` of a concrete type, e.g. `key(ty = "Vec<u8>", expr = "data.to_vec()")` for
+/// `fn checksum<const N: usize>(data: [u8; N]) -> u64`, and a return type not using the generic parameters.
+/// The `where` clause of the function is kept on the generated functions taking its arguments, while the
+/// accessor of the cache of a method with `in_impl` (or `in_trait`) has no generic parameters, since the
+/// cache can't depend on them.
+///
+/// Non-`async` functions also generate a `{fn}_cache()` function, returning a `kash::KashHandle` to inspect and
+/// manage the cache (`len`, `clear`, `remove`, `contains` and `iter`) the same way for memory, `disk` and `redis` caches.
+///
+/// The `disk` and `redis` caches of `async` functions are built on the first call, so these functions also generate a
+/// `{fn}_init_cache()` function, building the cache (e.g. connecting to Redis) if it isn't already, and returning the
+/// `DiskCacheBuildError` or `RedisCacheBuildError` instead of panicking, e.g. to fail fast at the startup of a server.
+///
+/// The `kash_*_is_shared__*` names in the errors of the `disk` and `redis` caches sharing a keyspace are macros defined
+/// at the root of the crate, the only place where two modules can't define the same item, so they're exported to the
+/// dependents of the crate too, but hidden from its docs.
+///
+/// With the `tokio` feature, `async` functions also generate a non-`async` `{fn}_prime_cache_blocking` function,
+/// which blocks the thread on `{fn}_prime_cache` with `tokio::runtime::Handle::current()`, e.g. to prime the cache
+/// from a background thread. It must be called in the context of a tokio runtime but outside of `async` code,
+/// e.g. in `tokio::task::spawn_blocking`, or in a thread which entered a runtime with `Handle::enter`, otherwise it panics.
+///
 /// # Attributes
 /// - `name`: (optional, string) Specify the name for the generated cache. Defaults to CONSTANT_CASE name of the function
-/// - `size`: (optional, string) Specify to keep the number of entries in the cache. Default to unbounded.
+/// - `display_name`: (optional, string expr) Specify an expression for the name of the underlying `moka` cache,
+///   e.g. to label its metrics with build metadata, like `display_name = r#"concat!("fib-", env!("GIT_HASH"))"#`.
+///   It can be a `&str` or a `String`, and is evaluated when the cache is first used. Defaults to `name`.
+///   Only supported by memory caches.
+/// - `cache_vis`: (optional, string) Specify the visibility of the generated cache static (or accessor for `in_impl`),
+///   e.g. `cache_vis = "pub(crate)"`. Defaults to the visibility of the function.
+/// - `shared_cache`: (optional, string expr) Specify a static of your own to use as the cache, instead of generating one,
+///   e.g. `shared_cache = "USERS"` for `static USERS: Lazy<moka::sync::Cache<u64, User>>`, so several functions
+///   with the same key and value types share one cache and bound its total size. It must dereference to a
+///   `moka::sync::Cache<K, V>` (a `moka::future::Cache<K, V>` for `async` functions), where `K` is the key type
+///   and `V` is the stored value. A `{fn}_get_cache_ident()` function returning it is generated instead of the static.
+///   The functions share the keys, too, so give them a `key` telling them apart if they compute different values.
+///   The options of the cache (`size`, `ttl`, ...) are set where it's created, so they can't be combined with it.
+///   Only supported by memory caches, and not by `in_trait`.
+/// - `size`: (optional, string expr) Specify to keep the number of entries in the cache. Defaults to
+///   `kash::defaults::set_default_size`, or unbounded.
 /// - `eviction_policy`: (optional, string) Specify the eviction policy, valid options are "lfu" (Least Frequently Used) and "lru" (Least Recently Used). Defaults to "lfu" and it's the most suitable policy for most cases.
-/// - `ttl`: (optional, string) Specify a cache TTL in seconds. Defaults to unlimited amount of time.
+/// - `initial_capacity`: (optional, string expr) Specify the number of entries the memory cache allocates room for when
+///   it's created, so it doesn't grow while it's first filled. It must be a `usize`.
+/// - `segments`: (optional, string expr) Split the memory cache of a non-`async` function into this number of
+///   `moka::sync::SegmentedCache` segments, each with its own lock for its writes, to reduce the contention of many
+///   threads inserting hot keys. It must be a non-zero `usize`, and is rounded up to a power of two. The `size` is
+///   split between the segments, each evicting its entries on its own. The cache static (or the cache returned by the
+///   accessor of `in_impl`) is then a `SegmentedCache`. `moka` 0.12 has no segmented cache for `async` functions, so
+///   it's ignored for them.
+///
+///   Both only apply to memory caches, and can't be used with `shared_cache`, which is configured where it's created.
+/// - `ttl`: (optional, string expr) Specify a cache TTL in seconds. Defaults to `kash::defaults::set_default_ttl`
+///   for memory caches without `expiry` or `ttl_for`, or unlimited amount of time.
+///
+///   Both `size` and `ttl` are evaluated once, when the cache is first used, so they can be read at runtime,
+///   e.g. `size = r#"{ std::env::var("FIB_CACHE_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(100) }"#`.
+///   They must be `u64`s (like `ttl_ms`), and a value of another type, e.g. a `const TTL: &str`, is reported at the attribute.
+///   The `size` of a created cache can't be changed, since `moka` 0.12 doesn't expose resizing a cache in place, so
+///   to shed memory under pressure, clear or remove entries with the `{fn}_cache()` handle instead.
+/// - `ttl_ms`: (optional, string expr) Specify a cache TTL in milliseconds instead of seconds, e.g. `ttl_ms = "500"`.
+///   Can't be used with `ttl`.
+/// - `expiry`: (optional, string expr) Specify an expression which returns a `moka::Expiry<K, V>`, to compute
+///   the TTL of each entry, e.g. from a `max_age` field of the value. `K` is the key type and `V` is the stored
+///   value, i.e., the inner type with `result` or `option` (the `Ok` or `Some` value) and an `Arc` with `arc`
+///   (a `kash::Cached` with `guard`).
+///   It can be combined with `ttl`, then the shorter one applies. Only supported by memory caches.
+/// - `ttl_for`: (optional, string expr) Specify a closure (or function) taking a reference to the cached value and
+///   returning its TTL as an `Option<std::time::Duration>`, e.g. to keep `200` responses longer than `404`s:
+///   `ttl_for = r#"|res| if res.status == 200 { Some(Duration::from_secs(300)) } else { Some(Duration::from_secs(10)) }"#`.
+///   It's evaluated whenever a value is cached, and its TTL replaces the `ttl` of the cache, which only applies to
+///   `None`, so it can be longer. The value is the same as the one of `expiry` for memory caches, and the `Ok` (or
+///   `Some`) value for `disk` and `redis` caches (the whole `Result` with `cache_errors`), which store the TTL in
+///   seconds, at least one. It can't be combined with `expiry`, and
+///   `{fn}_refresh_if_stale` of `redis` caches uses the `ttl` of the cache.
+/// - `skip_if`: (optional, string expr) Specify a closure (or function) taking a reference to a computed value and
+///   returning `true` if it shouldn't be cached, e.g. `skip_if = "|v| v.is_empty()"` to compute an empty result
+///   again on the next call. The value is still returned. It takes the same value as `ttl_for`, and applies to the
+///   values computed by the function, `{fn}_prime_cache` and `{fn}_with_ttl`, not to the ones of `{fn}_set_cache`.
+///   The entry API of memory caches inserts a value before it's returned, so a skipped value is invalidated right
+///   after, and the concurrent callers waiting for it share it. Can't be used with `refresh_ahead`.
+/// - `refresh_ahead`: (optional, string expr) Specify the fraction of the `ttl` (or `ttl_ms`) before the expiry
+///   of a value during which a hit refreshes it, e.g. `refresh_ahead = "0.25"` for the last quarter. The hit returns
+///   the cached value, while the function is called again in the background, with clones of the arguments, on a
+///   thread (a tokio task for `async` functions, which requires the `tokio` feature), and its value replaces the
+///   entry, so the callers of a busy key don't miss when it expires. Only one refresh of a key runs at a time, and
+///   a value that shouldn't be cached (an `Err` or `None`) keeps the entry, to retry on the next hit. The arguments
+///   must be `Clone + Send + 'static`, so it isn't supported by methods taking `self`. Only the computed values
+///   are refreshed, not the ones of `{fn}_prime_cache` or `{fn}_set_cache`. Only supported by memory caches, and
+///   can't be used with `expiry`, `ttl_for`, `lock_timeout`, `key_arc` or `in_trait`.
+/// - `with_ttl`: (optional) Also generate a `{fn}_with_ttl(args.., kash_ttl: std::time::Duration)` function, which
+///   returns the cached value like the function, and caches a computed value for `kash_ttl`, e.g. the `max-age`
+///   of a response known by the caller. It overrides the `ttl` of the cache, shorter or longer, for this entry only,
+///   until it's replaced. `disk` and `redis` caches store it in seconds, at least one. For memory caches, the `ttl`
+///   is an expiry of each entry, and it can't be used with `expiry`, `ttl_for`, `shared_cache`, `in_impl`,
+///   `in_trait` or `refresh_ahead`.
 /// - `key`: (optional, string) Specify a specific key to use. You need to define the following attributes for a custom `key`, e.g., `key(ty = "String", expr = r#"{ format!("{}:{}", arg1, arg2) }"#)`. By default, use all the arguments of the function as the key.
-///   - `ty`: (string) Specify type of the key. E.g, `ty = "String"`
+///   - `ty`: (string) Specify type of the key. E.g, `ty = "String"`. Any type can be used, e.g. `smartstring::alias::String`
+///     or your own struct, as long as it's `Hash + Eq + Clone + Send + Sync + 'static` for memory caches,
+///     and `Display` (or `Serialize`, see `RedisCacheBuilder::set_key_serialization`) for `disk` and `redis` caches.
 ///   - `expr`: (string expr) Specify an expression used to generate a cache key.
 ///     E.g., `expr = r#"{ format!("{}:{}", arg1, arg2) }"#`.
+///     With `in_impl`, the expression can also reference `self`, e.g. `expr = r#"{ format!("{}:{}", self.tenant, id) }"#`.
+///     Arguments which aren't `Hash + Eq`, like floats, or whose order doesn't matter, like sets, can be converted
+///     with the helpers of `kash::key`, e.g. `key(ty = "(u64, Vec<String>)", expr = "(kash::key::canon_f64(x), kash::key::sorted(&tags))")`.
+///   - `try_expr`: (string expr) Instead of `expr`, specify an expression returning a `Result` of the key.
+///     On `Err`, the cache is skipped and the function is evaluated, e.g. `try_expr = "raw.parse::<u32>()"`.
+///   - `with`: (string path) Instead of `expr`, specify a function making the key, which is called with a reference
+///     to each argument (except `self`), e.g. `with = "user_key"` for `fn user_key(id: &u64, name: &str) -> String`,
+///     to share the key logic between functions and test it on its own.
+/// - `generation`: (optional, string expr) Specify a `u64` expression evaluated on every call and added to the key,
+///   e.g. `generation = "GENERATION.load(Ordering::Relaxed)"`, so incrementing it invalidates the whole cache in O(1):
+///   the entries of the previous generations are missed, and evicted by the `size` or the `ttl` of the cache.
+///   The key of memory caches is `(u64, K)`, and the key of `disk` and `redis` caches is the `String`
+///   `"{generation}:{key}"`. For a generation shared by processes, e.g. of a Redis cache, read it from a shared store.
+/// - `schema_version`: (optional, string expr) Specify the `u64` version of the layout of the cached value, e.g. a
+///   `const`, for `disk` and `redis` caches, which outlive a deploy. Bump it when the value type changes, so the values
+///   stored with the old layout are missed instead of deserialized as the new one. It's stored with each record of
+///   `disk` caches (see `DiskCacheBuilder::set_schema_version`), and added to the prefix of `redis` caches as
+///   `v{schema_version}` (see `RedisCacheBuilder::set_schema_version`).
+/// - `normalize`: (optional, string) Specify statements rebinding the arguments before the key is made and the
+///   function is called, e.g. to fill the default of an `Option` argument, so `f(None)` and `f(Some(1))` share a
+///   cached value with `normalize = "let page = Some(page.unwrap_or(1));"`. The arguments must keep their types.
+///   They're normalized in all the generated functions, but not in `{fn}_no_cache`.
 /// - `result`: (optional) If your function returns a `Result`, only cache `Ok` values returned by the function.
 /// - `option`: (optional) If your function returns an `Option`, only cache `Some` values returned by the function.
-/// - `in_impl`: (optional) Set it if your function is defined in an `impl` block, otherwise not.
+///   For `disk` and `redis` caches, which always return a `Result`, the function returns `Result<Option<T>, E>`.
+/// - `result_option`: (optional) If your function returns a `Result<Option<T>, E>`, e.g. a database lookup, only cache
+///   `Ok(Some)` values, and re-run the function on `Ok(None)` and `Err`. For `disk` and `redis` caches, it's the same as `option`.
+/// - `cache_errors`: (optional) If your function returns a `Result`, cache both `Ok` and `Err` values, so deterministic
+///   errors aren't recomputed. The error type must be `Clone` (and `Serialize`/`Deserialize` for `disk` and `redis`).
+///   Memory caches already do this without `result`, but `disk` and `redis` only cache `Ok` values by default.
+/// - `trace`: (optional) Emit a `tracing` event on every cache hit and miss, with the cache name and the key.
+///   The key type must implement `Debug`. Requires the `trace` feature.
+/// - `redact_key`: (optional) Never format the key in the generated code, e.g. for keys of emails or tokens, the `key`
+///   of the `trace` events is `"<redacted>"` instead (and the key type doesn't need `Debug`). The keys stored by `disk`
+///   and `redis` caches are still derived from the arguments, so use a `key` expression hashing them to keep them
+///   out of the store.
+/// - `refresh_cache`: (optional) Also generate a `{fn}_refresh_cache` function, which primes the cache like
+///   `{fn}_prime_cache`, but returns a tuple of the result and whether an existing value was replaced.
+///   Only supported by memory caches.
+/// - `invalidate_if`: (optional) Also generate a `{fn}_invalidate_if(predicate)` function, which invalidates the
+///   entries for which the predicate, taking a reference to the key and the stored value (see `expiry`), returns
+///   `true`, e.g. `{fn}_invalidate_if(|_, config| config.version < 3)`. `moka` applies it lazily in the background,
+///   call `{fn}_sync` after it to apply it right away, e.g. in tests. It enables the invalidation closures of
+///   `moka`, which have a small overhead. Only supported by memory caches, and not by `shared_cache`.
+/// - `arc`: (optional) Store the value in an `Arc` and return it, so a cache hit doesn't clone the whole value.
+///   The cached function (and `{fn}_prime_cache`) returns `Arc<T>` instead of `T`, e.g. `Result<Arc<T>, E>` with `result`,
+///   while `{fn}_no_cache` keeps the original return type. Only supported by memory caches.
+///   It caches a boxed trait object, e.g. `Box<dyn Trait + Send + Sync>`, returned as an `Arc<Box<dyn ..>>`, and
+///   a function can return an `Arc<dyn Trait + Send + Sync>` itself, without `arc`. A cached value must be
+///   `Send + Sync`, as `moka` shares it between threads, which is reported at the return type otherwise.
+/// - `guard`: (optional) Like `arc`, but store and return the value in a `kash::Cached<T>`, which dereferences to `T`,
+///   e.g. `Result<kash::Cached<Vec<u8>>, E>` with `result`. It refers to the value as it was cached, not to the
+///   entry, so it doesn't lock the cache and stays valid after the entry is evicted or replaced.
+///   Can't be combined with `arc`. Only supported by memory caches.
+/// - `collect`: (optional) Cache a function returning `impl Iterator<Item = T>`, whose iterator can't be stored, by
+///   collecting it into a `Vec<T>`. The cached function (and `{fn}_no_cache`) returns the `std::vec::IntoIter<T>` of
+///   the collected values instead, which is also the type of the value given to `{fn}_set_cache`, so a hit iterates
+///   a clone of the cached values. The body must end with the iterator, a `return` of the iterator doesn't compile.
+///   Can't be used with `result` or `option`, and only supported by memory caches. See the `iterator` example.
+/// - `key_arc`: (optional) Move each owned argument into an `Arc` shared with the key, instead of cloning it into the key,
+///   so a cache hit doesn't clone e.g. a large `String` argument. The key type becomes a tuple of `Arc`s, e.g.
+///   `Arc<String>` for `fn f(s: String)` or `(Arc<String>, Arc<u32>)` for `fn f(s: String, n: u32)`. On a miss, the
+///   arguments are cloned out of their `Arc`s to call the function. Reference arguments are still cloned into the key.
+///   Can't be used with `key`, and only supported by memory caches.
+/// - `key_digest`: (optional, string) Make the key of a digest of the arguments, hashed by reference, instead of
+///   cloning them into the key, e.g. for `fn render(template: &[Token]) -> String` with a large slice. Valid options are
+///   "hash" (the key is the `u64` of `kash::key::digest`) and "verify" (the key is a `kash::key::Verified` holding the
+///   digest and an owned copy of the arguments, hashed with the digest and compared by value). With "hash", arguments
+///   with the same digest share the cached value, which is unlikely but possible, see `kash::key::digest`.
+///   The arguments must implement `Hash`. Can't be used with `key` or `key_arc`, and only supported by memory caches.
+/// - `key_wrapper`: (optional, string) Specify a tuple struct the key is wrapped in before it's stored, whose `Hash`
+///   and `Eq` tell which keys share an entry, e.g. `key_wrapper = "CaseInsensitive"` with a
+///   `struct CaseInsensitive(String)` comparing its strings ignoring their case, so `"A"` and `"a"` share a value,
+///   while the key keeps the original string, unlike a `normalize` lowercasing it. Its field is the key, i.e., the
+///   `ty` of `key` or the tuple of the arguments, and it must be `Hash + Eq + Clone + Send + Sync + 'static`.
+///   The `{fn}_key` function returns the wrapped key. Can't be used with `key_digest`, and only supported by memory caches.
+/// - `persist`: (optional) Also generate `{fn}_dump_cache(path)` and `{fn}_load_cache(path)` functions, which write the
+///   cached entries to a file with `rmp_serde` and insert them back, e.g. to warm the cache on startup. The key and the
+///   cached value must be `Serialize + DeserializeOwned`, and the ttl of the loaded entries starts again.
+///   `{fn}_load_cache` is `async` for `async` functions. Requires the `mem_persist` feature, and only supported by memory caches.
+/// - `must_use`: (optional) Mark the cached function and its generated `{fn}_no_cache`, `{fn}_prime_cache`
+///   (and other) functions as `#[must_use]`, so a dropped `Result` of a cached call is a warning.
+///   A `#[must_use]` put on the original function is forwarded to them anyway.
+/// - `lock_timeout`: (optional, int) The milliseconds concurrent callers wait for the caller computing the same key,
+///   before computing it themselves, so a stuck computation doesn't block them forever. With `trace`, a warning is
+///   emitted on a timeout. Only supported by memory caches of non-`async` functions.
+/// - `recursive_no_cache`: (optional) Make the recursive calls of the function in `{fn}_no_cache` call
+///   `{fn}_no_cache` too, instead of the cached function, so it's a really uncached reference implementation,
+///   e.g. to benchmark a memoized recursive function. Only the calls like `fib(n - 1)` and `Self::fib(n - 1)` are replaced.
+/// - `stats`: (optional) Count the hits and misses of the cache, returned by a generated `{fn}_cache_stats()`
+///   function as a `&'static kash::CacheStats`, e.g. to measure the effectiveness of an expensive disk cache.
+///   Only supported by `disk` caches, the `metrics` feature reports the hits and misses of all the caches.
+/// - `max_key_len`: (optional, int) Limit the length of the key (as a string) stored in `disk` and `redis` caches,
+///   so a huge user input can't become a huge key. Only supported by `disk` and `redis` caches.
+/// - `key_overflow`: (optional, string) What to do with keys longer than `max_key_len`, valid options are
+///   "bypass" (evaluate the function without the cache), "hash" (store the SHA-256 digest of the key instead)
+///   and "error" (return a `KeyTooLong` error of `DiskCacheError` or `RedisCacheError`). Defaults to "bypass".
+///   With "hash", the key type of the cache is `String`, and a long key is stored as `#sha256:{hex digest}`,
+///   72 characters long, so `max_key_len` must be at least 72. The keys starting with `#sha256:` are hashed too,
+///   so a hashed key can't collide with one which is not.
+/// - `in_impl`: (optional) Set it if your function is defined in an `impl` block, otherwise not. Methods can be `async`, too.
+///   The receiver (`self`) isn't part of the default key, so all the instances share the cached values, unless the
+///   `key` references `self`. Associated functions without `self` are cached the same way. The cache of a function
+///   in the `impl` of a generic type is shared by all the type arguments, so its key and value can't use them.
+///   The cache is still a single static shared across all instances, so if the result depends on
+///   `self`, include the instance identity in a custom `key`.
+/// - `in_trait`: (optional) Set it if your function is a default method of a trait, instead of `in_impl`.
+///   Each type implementing the trait gets its own cache, found by its `TypeId`, so the cached and generated functions
+///   require `Self: Sized + 'static`, i.e., they can't be called on trait objects. Only supported by memory caches.
 /// - `redis`: (optional) Store cached values in Redis.
+///   Redis caches also generate a `{fn}_refresh_if_stale` function, which recomputes the value, but only
+///   stores it if the cached value is not changed meanwhile (e.g. by a concurrent refresh), using
+///   `RedisCache::set_if_unchanged`.
+///   - `namespace`: (optional, string) specify the namespace of all cache keys of this function, e.g.
+///     `namespace = "my_service"`. Defaults to `kash`, see `RedisCacheBuilder::set_namespace`.
+///   - `prefix`: (optional, string) specify the prefix of all cache keys of this function, e.g. `prefix = "users"`.
+///     Can't be used with `prefix_block`. Two functions of a crate with the same namespace and `prefix` fail to
+///     compile, with an error that the name `kash_redis_prefix_{prefix}_is_shared__set_another_prefix_or_prefix_block`
+///     is defined multiple times, since they would read and overwrite the values of each other.
 ///   - `prefix_block`: (optional, string expr) specify an expression used to create the string used as a
-///      prefix for all cache keys of this function, e.g. `prefix_block = r#"{ "my_prefix:" }"#`.
-///      When not specified, the cache prefix will be constructed from the name of the function. This
-///      could result in unexpected conflicts between kash-functions of the same name, be sure to specify a
-///     `prefix_block` if you have multiple kash-functions with the same name. And consider using a unique
-///      separator at the end of the prefix, like ":" in the example above.
+///     prefix for all cache keys of this function, e.g. `prefix_block = r#"{ "my_prefix" }"#`.
+///     When neither `prefix` nor `prefix_block` is specified, the cache prefix is the module path and the cache
+///     name of the function, e.g. `my_crate::users::FIB`, so functions of the same name in different modules or
+///     crates don't share keys. Methods of the same name in one module still do, specify a `prefix` for them, or
+///     to keep the keys when the function is moved. The namespace, the prefix
+///     and the key are always separated by ":", see `RedisCacheBuilder::set_delimiter`.
 /// - `disk`: (optional) Store cached values on disk.
-///   - `dir`: (optional, string) Specify directory of `disk` cache
+///   Disk caches also generate a `{fn}_entry_age` function, which returns how long ago the value of the arguments
+///   was cached, or `None` if it isn't cached, see `DiskCache::entry_age`.
+///   A disk cache can only be opened by one process at a time, so the cache creation panics with
+///   "error constructing DiskCache in #[kash] macro" if another process (e.g. a concurrent run of the same CLI)
+///   holds it, like on any other error building the cache. Only `async` functions can get the
+///   `DiskCacheBuildError::Locked` instead, from their `{fn}_init_cache()`. Use
+///   `kash::stores::DiskCacheBuilder::set_lock_retries` directly to wait for the lock instead.
+///   - `dir`: (optional, string) Specify directory of `disk` cache.
+///     The file of a cache is named after its `name`, so two functions of a crate with the same name and `dir`,
+///     e.g. in different modules, fail to compile, with an error that the name
+///     `kash_disk_cache_{dir}_{name}_is_shared__set_another_name_or_dir` is defined multiple times.
 ///   - `sync_to_disk_on_cache_change`: (optional) Specify whether to synchronize the cache to disk each
 ///     time the cache changes.
+///   - `flush_on_exit`: (optional) Register the cache to be flushed by `kash::stores::flush_disk_caches`, e.g. when a
+///     `kash::stores::DiskFlushGuard` held by `main` is dropped. The cache is in a static, which is never dropped, so
+///     without it the changes since the last periodic flush of `sled` are lost when the process exits.
 ///   - `connection_config`: (optional, string expr) Specify an expression which returns a `sled::Config`
 ///     to give more control over the connection to the `disk` cache, i.e., useful for controlling the rate at which the cache syncs to disk.
 ///     See the docs of `kash::stores::DiskCacheBuilder::connection_config` for more info.
+///   - `use_compression`: (optional) Compress the `sled` database with zstd. Requires the `disk_compression` feature.
+///     A database can't be opened with another setting than it was created with, so changing it needs a new `name`
+///     or `dir`.
+///   - `compression_factor`: (optional, int) Specify the zstd compression level of `use_compression`, from 1 up to 22.
+///     Defaults to 5.
+///   - `shared_db`: (optional, string expr) Specify an expression which returns a `sled::Db` (or a reference to it),
+///     e.g. a static, to store the values in a tree of it named after the `name`, instead of opening a database for
+///     the function, so many functions share one file and flush thread. Can't be used with `dir`, `connection_config`
+///     or `use_compression`, which are the ones of the shared database. Two functions of a crate with the same name
+///     and a `shared_db` fail to compile, with an error that the name
+///     `kash_disk_shared_db_{name}_is_shared__set_another_name` is defined multiple times.
+/// - `disabled`: (optional) Don't cache the function: the function (and its `{fn}_prime_cache`, `{fn}_with_ttl`,
+///   `{fn}_refresh_cache` and `{fn}_refresh_if_stale` functions) evaluates it on every call, `{fn}_get_or_compute`
+///   evaluates the given computation, `{fn}_set_cache` stores nothing and `{fn}_is_cached` returns `false`.
+///   The other generated items, e.g. the cache static, `{fn}_key` and `{fn}_cache()`, are generated like without
+///   `disabled`, with the same signatures, so the call sites compile unchanged, and see an empty cache.
+///   Meant to be set with `cfg_attr`, e.g. `#[cfg_attr(debug_assertions, kash(size = "100", disabled))]` and
+///   `#[cfg_attr(not(debug_assertions), kash(size = "100"))]`.
+///   The value is still returned in an `Arc` with `arc` (or a `kash::Cached` with `guard`).
+/// - `fallback`: (optional, string) Specify a `cfg` predicate for the `disk` or `redis` cache, and use a memory cache
+///   when it's false, e.g. `fallback = r#"feature = "redis""#` to run the tests of a crate without a Redis server.
+///   The memory cache caches the `Ok` values (the whole `Result` with `cache_errors`) and uses the memory attributes,
+///   e.g. `size`. Only the cached function and its `{fn}_no_cache` and `{fn}_prime_cache` functions have the same
+///   signatures for both caches, and the error of the function must be `Clone`.
+///   Requires the `mem_store` feature.
+/// - `maybe_async`: (optional, string) Specify a `cfg` predicate, e.g. `maybe_async = r#"feature = "async""#`, for which
+///   the function is `async`, and not `async` otherwise, so a library can expose both from one definition. The function
+///   is written without `async` and its body must compile in both modes: `maybe_await!(expr)` is replaced with
+///   `expr.await` when it's `async`, and `expr` otherwise, e.g. to call another `maybe_async` function. All the generated
+///   functions follow, e.g. `{fn}_prime_cache`. The `async` cache requires the `async` feature of `kash` when the
+///   predicate is true, e.g. with an `async = ["kash/async"]` feature of the library.
 ///
 #[proc_macro_attribute]
 pub fn kash(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -49,18 +351,84 @@ pub fn kash(args: TokenStream, input: TokenStream) -> TokenStream {
         }
     };
 
-    let input = parse_macro_input!(input as ItemFn);
+    let mut input = parse_macro_input!(input as ItemFn);
+    let future_fn = common::desugar_future_fn(&mut input);
 
     match args.validate(&input).map_err(|e| e.write_errors()) {
         Ok(_) => {}
         Err(e) => return e.into(),
     }
 
-    if args.redis.is_some() {
-        redis::kash(&input, &args)
+    if args.collect {
+        common::desugar_iterator_fn(&mut input);
+    }
+
+    if args.must_use {
+        common::add_must_use(&mut input);
+    }
+
+    if let Some(maybe_async) = &args.maybe_async {
+        let predicate = parse_str::<Meta>(maybe_async).expect("unable to parse maybe_async");
+        let async_input = common::desugar_maybe_async_fn(&input, true);
+        // e.g. `lock_timeout` isn't supported by `async` functions
+        let async_args = MacroArgs {
+            maybe_async: None,
+            ..args.clone()
+        };
+        if let Err(e) = async_args.validate(&async_input) {
+            return e.write_errors().into();
+        }
+        let sync_input = common::desugar_maybe_async_fn(&input, false);
+        let async_items =
+            common::gen_cfg_items(&quote! { #predicate }, kash_fn(&async_input, &args).into());
+        let sync_items = common::gen_cfg_items(
+            &quote! { not(#predicate) },
+            kash_fn(&sync_input, &args).into(),
+        );
+        quote! { #async_items #sync_items }.into()
+    } else if let Some(future_fn) = &future_fn {
+        common::resugar_future_fn(future_fn, kash_fn(&input, &args).into()).into()
+    } else {
+        kash_fn(&input, &args)
+    }
+}
+
+// the items of a cached function, for the cache selected by the arguments
+fn kash_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream {
+    if let Some(fallback) = &args.fallback {
+        let predicate = parse_str::<Meta>(fallback).expect("unable to parse fallback");
+        let io = if args.redis.is_some() {
+            redis::kash(input, args)
+        } else {
+            disk::kash(input, args)
+        };
+        let mem = mem::kash(input, &args.memory_fallback());
+        let io = common::gen_cfg_items(&quote! { #predicate }, io.into());
+        let mem = common::gen_cfg_items(&quote! { not(#predicate) }, mem.into());
+        quote! { #io #mem }.into()
+    } else if args.redis.is_some() {
+        redis::kash(input, args)
     } else if args.disk.is_some() {
-        disk::kash(&input, &args)
+        disk::kash(input, args)
     } else {
-        mem::kash(&input, &args)
+        mem::kash(input, args)
     }
 }
+
+/// Generate a `kash_clear_all()` associated function for an inherent `impl` block, clearing the caches of all
+/// its `#[kash(in_impl)]` methods at once, e.g. when the data of the type changes, or between tests.
+///
+/// The caches of `async` `redis` methods can only be cleared in an async context, so they aren't cleared, like
+/// the `shared_cache`s, which belong to their owner.
+/// See the `in_impl` example.
+#[proc_macro_attribute]
+pub fn kash_impl(args: TokenStream, input: TokenStream) -> TokenStream {
+    if let Some(token) = proc_macro2::TokenStream::from(args).into_iter().next() {
+        return syn::Error::new(token.span(), "`kash_impl` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let input = parse_macro_input!(input as ItemImpl);
+    kash_impl::kash_impl(input)
+}
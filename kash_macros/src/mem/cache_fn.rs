@@ -1,10 +1,16 @@
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{Ident, ItemFn};
+use syn::{ItemFn, Type};
 
 use crate::common::macro_args::MacroArgs;
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
-use crate::mem::gen_local_cache;
+use crate::common::{
+    gen_cache_ident, gen_key_arc_args, gen_key_binding, gen_on_lookup, gen_origin_fn_ident,
+    gen_trace_key, get_input_names, get_input_types, make_cache_key_type,
+};
+use crate::mem::{
+    gen_entry_insert, gen_local_cache, gen_output, gen_refresh_ahead, gen_set_cache_block,
+    gen_skip_if, gen_try_optional, gen_wrap_arc, gen_wrap_value,
+};
 
 #[derive(Debug, Clone)]
 pub struct CacheFn<'a> {
@@ -29,54 +35,162 @@ impl ToTokens for CacheFn<'_> {
         let inputs = &self.input.sig.inputs;
 
         let (_, without_self_types) = get_input_types(inputs);
-        let (maybe_with_self_names, without_self_names) = get_input_names(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+        let (arc_inputs, call_names) = gen_key_arc_args(self.args, inputs);
 
-        let (_, key_expr) =
-            make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
-        let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+        let (key_ty, key_expr) =
+            make_cache_key_type(self.args, without_self_types.clone(), &without_self_names);
         let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
-        let local_cache = gen_local_cache(self.args.in_impl, fn_cache_ident, cache_ident);
+        let on_hit = gen_on_lookup(self.args, &cache_ident, true);
+        let on_miss = gen_on_lookup(self.args, &cache_ident, false);
+        let local_cache = gen_local_cache(self.args, fn_ident);
         let call_prefix = if self.args.in_impl {
             quote! { Self:: }
         } else {
             quote! {}
         };
-        let no_cache_fn_ident = Ident::new(&format!("{}_no_cache", fn_ident), fn_ident.span());
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
         let may_await = if self.input.sig.asyncness.is_some() {
             quote! { .await }
         } else {
             quote! {}
         };
         let mut function_call = quote! {
-            #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*)
+            #call_prefix #no_cache_fn_ident(#(#call_names),*)
         };
+        let function_value = gen_wrap_arc(self.args, quote! { #function_call #may_await });
+        let (refresh_due, refresh_start) = gen_refresh_ahead(
+            self.args,
+            &key_ty,
+            &local_cache,
+            &function_value,
+            &call_names,
+            self.input.sig.asyncness.is_some(),
+        );
+        let key_binding_without_arc_inputs =
+            gen_key_binding(&self.args.key, &key_expr, function_value.clone());
+        let key_binding = quote! {
+            #arc_inputs
+            #key_binding_without_arc_inputs
+        };
+        let borrowed_lookup = gen_borrowed_lookup(
+            self.args,
+            &without_self_types,
+            &without_self_names,
+            &local_cache,
+            &on_hit,
+            &gen_wrap_value(self.args, quote! { kash_value }),
+            &may_await,
+        );
 
+        let may_miss = |value: TokenStream| quote! { #on_miss #value };
         if self.input.sig.asyncness.is_none() {
+            let function_call_arc = may_miss(gen_try_optional(
+                self.args,
+                gen_wrap_arc(self.args, function_call),
+            ));
+            function_call = quote! {
+                || { #function_call_arc }
+            }
+        } else if !on_miss.is_empty()
+            || self.args.arc
+            || self.args.key_arc
+            || (self.args.result && self.args.option)
+        {
+            // with `key_arc`, the inputs must only be taken out of their `Arc` on a miss
+            let function_call_arc = may_miss(gen_try_optional(
+                self.args,
+                gen_wrap_arc(self.args, quote! { #function_call.await }),
+            ));
             function_call = quote! {
-                || #function_call
+                async { #function_call_arc }
             }
         }
 
-        let (insert, may_return_early, may_wrap) = match (self.args.result, self.args.option) {
-            (false, false) => (quote!(.or_insert_with(#function_call)), quote!(), quote!()),
-            (true, false) => (
-                quote!(.or_try_insert_with(#function_call)),
-                quote!(.map_err(|e| e.deref().clone())?),
-                quote!(Ok),
-            ),
-            (false, true) => (
-                quote!(.or_optionally_insert_with(#function_call) ),
-                quote!(?),
-                quote!(Some),
-            ),
-            _ => unreachable!("All errors should be handled in the `MacroArgs` validation methods"),
-        };
+        let (insert, may_return_early) = gen_entry_insert(self.args, &function_call);
+        let cached_value = gen_wrap_value(self.args, quote! { kash_value });
+        let skip_if = gen_skip_if(self.args, &signature.output);
 
-        let do_set_return_block = quote! {
-            use std::ops::Deref;
-            #may_wrap (#local_cache.entry_by_ref(&#key_expr) #insert #may_await #may_return_early .into_value() .clone())
+        let do_set_return_block = if let Some(timeout) = self.args.lock_timeout {
+            // moka's entry API waits for the leader forever, so it's replaced with `InFlight`
+            let set_cache_block = gen_set_cache_block(
+                &local_cache,
+                self.args.result,
+                self.args.option,
+                &skip_if,
+                &may_await,
+            );
+            let on_timeout = if self.args.trace {
+                let cache_name = gen_cache_ident(&self.args.name, fn_ident).to_string();
+                let key = gen_trace_key(self.args);
+                quote! {
+                    ::kash::tracing::warn!(cache = #cache_name, key = #key, "timed out waiting for the value, computing it");
+                }
+            } else {
+                quote! {}
+            };
+            quote! {
+                static KASH_IN_FLIGHT: ::kash::once_cell::sync::Lazy<::kash::flight::InFlight<#key_ty>> =
+                    ::kash::once_cell::sync::Lazy::new(::kash::flight::InFlight::new);
+                #key_binding
+                if let Some(kash_value) = #local_cache.get(&kash_key) {
+                    #on_hit
+                    return #cached_value;
+                }
+                let kash_leader = KASH_IN_FLIGHT.lead_or_wait(&kash_key, ::std::time::Duration::from_millis(#timeout));
+                // the value may be cached while waiting, or by a previous leader before leading
+                if let Some(kash_value) = #local_cache.get(&kash_key) {
+                    #on_hit
+                    return #cached_value;
+                }
+                if kash_leader.is_none() {
+                    #on_timeout
+                }
+                #on_miss
+                let kash_result = #function_value;
+                #set_cache_block
+                kash_result
+            }
+        } else {
+            let on_hit = if on_hit.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    if !kash_entry.is_fresh() {
+                        #on_hit
+                    }
+                }
+            };
+            // the entry API inserts the value before it's returned, so a fresh value matching `skip_if` is
+            // invalidated right away; the callers waiting for the same key still share it
+            let skip_if = if skip_if.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    if kash_entry.is_fresh() && (#skip_if)(kash_entry.value()) {
+                        #local_cache.invalidate(&kash_key) #may_await;
+                    }
+                }
+            };
+            quote! {
+                use std::ops::Deref;
+                #arc_inputs
+                #borrowed_lookup
+                #key_binding_without_arc_inputs
+                #refresh_due
+                let kash_entry = #local_cache.entry_by_ref(&kash_key) #insert #may_await;
+                #may_return_early
+                #refresh_start
+                #skip_if
+                #on_hit
+                let kash_value = kash_entry.into_value();
+                #cached_value
+            }
         };
 
+        let mut signature = signature.clone();
+        signature.output = gen_output(self.args, &signature.output);
+
         let expanded = quote! {
             #[doc = #cache_fn_ident_doc]
             #(#attributes)*
@@ -88,3 +202,47 @@ impl ToTokens for CacheFn<'_> {
         tokens.extend(expanded);
     }
 }
+
+// look the value up by a reference to the only argument, if it's the key, so a hit doesn't build the owned key,
+// e.g. clone a `String`; the key is only built on a miss, for `entry_by_ref`
+fn gen_borrowed_lookup(
+    args: &MacroArgs,
+    input_tys: &[Type],
+    input_names: &[TokenStream],
+    local_cache: &TokenStream,
+    on_hit: &TokenStream,
+    cached_value: &TokenStream,
+    may_await: &TokenStream,
+) -> TokenStream {
+    let ([input_ty], [input_name]) = (input_tys, input_names) else {
+        return quote! {};
+    };
+    // the key is made of the argument, but isn't the argument itself
+    if args.key.is_some()
+        || args.key_arc
+        || args.key_digest.is_some()
+        || args.key_wrapper.is_some()
+        || args.generation.is_some()
+        || args.refresh_ahead.is_some()
+    {
+        return quote! {};
+    }
+
+    let key_ref = match input_ty {
+        Type::Reference(_) => quote! { &*#input_name },
+        _ => quote! { &#input_name },
+    };
+    // the trace event of the hit formats `kash_key`
+    let trace_key = if args.trace {
+        quote! { let kash_key = #key_ref; }
+    } else {
+        quote! {}
+    };
+    quote! {
+        if let Some(kash_value) = #local_cache.get(#key_ref) #may_await {
+            #trace_key
+            #on_hit
+            return #cached_value;
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_quote, FnArg, Ident, ItemFn};
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{
+    gen_forwarded_attributes, gen_key_arc_args, gen_key_binding, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::mem::{gen_cache_value_type, gen_local_cache};
+
+// struct for the function computing a missing value with a given closure
+#[derive(Debug, Clone)]
+pub struct GetOrComputeFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> GetOrComputeFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for GetOrComputeFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let get_or_compute_fn_ident =
+            Ident::new(&format!("{}_get_or_compute", fn_ident), fn_ident.span());
+
+        let get_or_compute_fn_ident_doc = format!(
+            "Returns the cached value of the function [`{}`], or caches and returns the given computation on a miss.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+        let inputs = &signature.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let local_cache = gen_local_cache(self.args, fn_ident);
+
+        let cache_value_ty = gen_cache_value_type(self.args, &signature.output);
+
+        let mut get_or_compute_sig = signature.clone();
+        get_or_compute_sig.ident = get_or_compute_fn_ident;
+        get_or_compute_sig.output = parse_quote! { -> #cache_value_ty };
+
+        let (compute_arg, may_await): (FnArg, _) = if signature.asyncness.is_some() {
+            (
+                parse_quote! { kash_compute: impl ::std::future::Future<Output = #cache_value_ty> },
+                quote! { .await },
+            )
+        } else {
+            (
+                parse_quote! { kash_compute: impl FnOnce() -> #cache_value_ty },
+                quote! {},
+            )
+        };
+        get_or_compute_sig.inputs.push(compute_arg);
+        let compute_call = if signature.asyncness.is_some() {
+            quote! { kash_compute.await }
+        } else {
+            quote! { kash_compute() }
+        };
+        let key_binding = gen_key_binding(&self.args.key, &key_expr, compute_call);
+        let (arc_inputs, _) = gen_key_arc_args(self.args, inputs);
+        let key_binding = quote! {
+            #arc_inputs
+            #key_binding
+        };
+
+        let expanded = quote! {
+            #[doc = #get_or_compute_fn_ident_doc]
+            #[allow(dead_code, unused_mut, unused_variables)]
+            #(#attributes)*
+            #visibility #get_or_compute_sig {
+                #key_binding
+                #local_cache.get_with_by_ref(&kash_key, kash_compute) #may_await
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
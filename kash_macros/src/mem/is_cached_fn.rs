@@ -0,0 +1,67 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_quote, Ident, ItemFn};
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{
+    gen_forwarded_attributes, gen_key_arc_args, gen_key_binding, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::mem::gen_local_cache;
+
+// struct for the function checking if a value is cached, without computing or cloning it
+#[derive(Debug, Clone)]
+pub struct IsCachedFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> IsCachedFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for IsCachedFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let is_cached_fn_ident = Ident::new(&format!("{}_is_cached", fn_ident), fn_ident.span());
+
+        let is_cached_fn_ident_doc = format!(
+            "Returns whether the function [`{}`] has a cached value for the arguments, without computing it.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+        let inputs = &signature.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let local_cache = gen_local_cache(self.args, fn_ident);
+
+        // a key which can't be made is never cached
+        let key_binding = gen_key_binding(&self.args.key, &key_expr, quote! { false });
+        let (arc_inputs, _) = gen_key_arc_args(self.args, inputs);
+
+        let mut is_cached_sig = signature.clone();
+        is_cached_sig.ident = is_cached_fn_ident;
+        is_cached_sig.asyncness = None;
+        is_cached_sig.output = parse_quote! { -> bool };
+
+        let expanded = quote! {
+            #[doc = #is_cached_fn_ident_doc]
+            #[allow(dead_code, unused_mut, unused_variables)]
+            #(#attributes)*
+            #visibility #is_cached_sig {
+                #arc_inputs
+                #key_binding
+                #local_cache.contains_key(&kash_key)
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
@@ -1,57 +1,400 @@
+use crate::common::disabled_fn::gen_disabled_fns;
+use crate::common::handle_fn::{HandleFn, HandleOps, HandleTypes};
 use crate::common::macro_args::MacroArgs;
 use crate::common::no_cache_fn::NoCacheFn;
+use crate::common::{
+    first_type_argument, first_type_argument_mut, gen_cache_ident, gen_trait_bounds,
+    get_input_names, get_input_types, make_cache_key_type,
+};
 use crate::mem::cache_fn::CacheFn;
+use crate::mem::get_or_compute_fn::GetOrComputeFn;
+use crate::mem::is_cached_fn::IsCachedFn;
+use crate::mem::persist_fn::PersistFn;
 use crate::mem::prime_fn::PrimeFn;
+use crate::mem::set_cache_fn::SetCacheFn;
 use crate::mem::ty::CacheType;
+use crate::mem::with_ttl_fn::WithTtlFn;
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, TokenStream as TokenStream2};
 use quote::quote;
-use syn::{ItemFn, PathArguments, ReturnType, Type};
+use syn::{parse_quote, ItemFn, ReturnType};
 
 pub mod cache_fn;
+pub mod get_or_compute_fn;
+pub mod is_cached_fn;
+pub mod persist_fn;
 pub mod prime_fn;
+pub mod set_cache_fn;
 pub mod ty;
+pub mod with_ttl_fn;
 
 pub(super) fn kash(input: &ItemFn, args: &MacroArgs) -> TokenStream {
-    let no_cache_fn = NoCacheFn::new(input);
+    // the cache of a trait method is found by the `TypeId` of `Self`, so all the functions using it need the bounds
+    let mut trait_input;
+    let input = if args.in_trait {
+        trait_input = input.clone();
+        trait_input
+            .sig
+            .generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote! { Self: Sized + 'static });
+        &trait_input
+    } else {
+        input
+    };
+
+    let no_cache_fn = NoCacheFn::new(input, args);
     let prime_fn = PrimeFn::new(input, args);
     let cache_fn = CacheFn::new(input, args);
+    let get_or_compute_fn = GetOrComputeFn::new(input, args);
+    let is_cached_fn = IsCachedFn::new(input, args);
+    let set_cache_fn = SetCacheFn::new(input, args);
+    let with_ttl_fn = WithTtlFn::new(input, args);
     let cache_type = CacheType::new(input, args);
+    let handle_fn = gen_handle_fn(input, args);
+    let persist_fn = PersistFn::new(input, args);
+    let sync_fn = gen_sync_fn(input, args);
+    let invalidate_if_fn = gen_invalidate_if_fn(input, args);
+    let key_fn = gen_key_fn(input, args);
 
-    quote! {
+    let items = quote! {
         #cache_type
         #no_cache_fn
         #prime_fn
+        #get_or_compute_fn
+        #is_cached_fn
+        #set_cache_fn
+        #with_ttl_fn
+        #key_fn
         #cache_fn
+        #handle_fn
+        #persist_fn
+        #sync_fn
+        #invalidate_if_fn
+    };
+    if args.disabled {
+        gen_disabled_fns(input, args, items, gen_wrap_arc).into()
+    } else {
+        items.into()
     }
-    .into()
+}
+
+// whether the cache of a function is a `SlotCache`, i.e., its key is always `()`, since it only takes `self`
+// or nothing, and it uses none of the `moka` options which don't apply to a single value
+fn is_single_slot(input: &ItemFn, args: &MacroArgs) -> bool {
+    let (_, without_self_types) = get_input_types(&input.sig.inputs);
+    without_self_types.is_empty()
+        && input.sig.asyncness.is_none()
+        && args.key.is_none()
+        && args.generation.is_none()
+        && args.key_digest.is_none()
+        && args.key_wrapper.is_none()
+        && args.size.is_none()
+        && args.initial_capacity.is_none()
+        && args.segments.is_none()
+        && args.shared_cache.is_none()
+        && !args.in_trait
+        && args.expiry.is_none()
+        && args.ttl_for.is_none()
+        && args.skip_if.is_none()
+        && !args.with_ttl
+        && !args.invalidate_if
+        && args.display_name.is_none()
+}
+
+fn gen_key_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream2 {
+    let (_, without_self_types) = get_input_types(&input.sig.inputs);
+    let (_, without_self_names) = get_input_names(&input.sig.inputs);
+    let (key_ty, _) = make_cache_key_type(args, without_self_types, &without_self_names);
+    crate::common::gen_key_fn(input, args, key_ty, quote! {})
+}
+
+// `moka` applies evictions and updates `entry_count` in the background, so `{fn}_sync` runs its pending tasks,
+// e.g. to see the `size` of the cache enforced right away
+fn gen_sync_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream2 {
+    let fn_ident = &input.sig.ident;
+    let sync_fn_ident = Ident::new(&format!("{}_sync", fn_ident), fn_ident.span());
+    let sync_fn_ident_doc = format!(
+        "Runs the pending maintenance tasks of the cache of the function [`{}`], so its evictions are applied and its `entry_count` is exact.",
+        fn_ident
+    );
+    let visibility = &input.vis;
+    let where_clause = gen_trait_bounds(args);
+    let local_cache = gen_local_cache(args, fn_ident);
+    let (asyncness, may_await) = if input.sig.asyncness.is_some() {
+        (quote! { async }, quote! { .await })
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    quote! {
+        #[doc = #sync_fn_ident_doc]
+        #[allow(dead_code)]
+        #visibility #asyncness fn #sync_fn_ident() #where_clause {
+            #local_cache.run_pending_tasks() #may_await;
+        }
+    }
+}
+
+// `invalidate_if` enables the invalidation closures of `moka`, to remove the entries matching a predicate
+fn gen_invalidate_if_fn(input: &ItemFn, args: &MacroArgs) -> TokenStream2 {
+    if !args.invalidate_if {
+        return quote! {};
+    }
+
+    let fn_ident = &input.sig.ident;
+    let invalidate_if_fn_ident =
+        Ident::new(&format!("{}_invalidate_if", fn_ident), fn_ident.span());
+    let invalidate_if_fn_ident_doc = format!(
+        "Invalidates the cached entries of the function [`{}`] for which the predicate returns `true`. \
+        The predicate is applied lazily, to the entries which exist when this is called, see [`{}`] to apply it right away.",
+        fn_ident,
+        Ident::new(&format!("{}_sync", fn_ident), fn_ident.span())
+    );
+    let visibility = &input.vis;
+    let where_clause = gen_trait_bounds(args);
+    let inputs = &input.sig.inputs;
+    let (_, without_self_types) = get_input_types(inputs);
+    let (_, without_self_names) = get_input_names(inputs);
+    let (key, _) = make_cache_key_type(args, without_self_types, &without_self_names);
+    let value = gen_cache_value_type(args, &input.sig.output);
+    let local_cache = gen_local_cache(args, fn_ident);
+
+    quote! {
+        #[doc = #invalidate_if_fn_ident_doc]
+        #[allow(dead_code)]
+        #visibility fn #invalidate_if_fn_ident(
+            predicate: impl Fn(&#key, &#value) -> bool + Send + Sync + 'static,
+        ) #where_clause {
+            #local_cache
+                .invalidate_entries_if(predicate)
+                .expect("the invalidation closures are enabled by `invalidate_if`");
+        }
+    }
+}
+
+fn gen_handle_fn<'a>(input: &'a ItemFn, args: &'a MacroArgs) -> HandleFn<'a> {
+    let fn_ident = &input.sig.ident;
+    let inputs = &input.sig.inputs;
+    let (_, without_self_types) = get_input_types(inputs);
+    let (_, without_self_names) = get_input_names(inputs);
+    let (key, _) = make_cache_key_type(args, without_self_types, &without_self_names);
+    let value = gen_cache_value_type(args, &input.sig.output);
+
+    let local_cache = gen_local_cache(args, fn_ident);
+
+    let types = HandleTypes {
+        stored_key: quote! { ::std::sync::Arc<#key> },
+        key,
+        value,
+        error: quote! { ::std::convert::Infallible },
+    };
+    let ops = HandleOps {
+        len: quote! {
+            || {
+                #local_cache.run_pending_tasks();
+                Ok(#local_cache.entry_count() as usize)
+            }
+        },
+        clear: quote! {
+            || {
+                #local_cache.invalidate_all();
+                #local_cache.run_pending_tasks();
+                Ok(())
+            }
+        },
+        remove: quote! { |kash_key| Ok(#local_cache.remove(kash_key)) },
+        contains: quote! { |kash_key| Ok(#local_cache.contains_key(kash_key)) },
+        iter: quote! { || Ok(#local_cache.iter().collect()) },
+    };
+    HandleFn::new(input, args, types, ops)
 }
 
 fn gen_set_cache_block(
     local_cache: &TokenStream2,
     result: bool,
     option: bool,
+    skip_if: &TokenStream2,
     may_await: &TokenStream2,
 ) -> TokenStream2 {
-    match (result, option) {
-        (false, false) => {
-            quote! { #local_cache.insert(kash_key, kash_result.clone())#may_await; }
+    gen_cache_guard(
+        result,
+        option,
+        skip_if,
+        quote! { #local_cache.insert(kash_key, kash_result.clone())#may_await; },
+    )
+}
+
+// with `skip_if`, the predicate of the values which are returned but not inserted, typed as a `fn` of the stored value
+fn gen_skip_if(args: &MacroArgs, output: &ReturnType) -> TokenStream2 {
+    let Some(skip_if) = &args.skip_if else {
+        return quote! {};
+    };
+    let value_ty = gen_cache_value_type(args, output);
+    args.typed_expr("skip_if", skip_if, quote! { fn(&#value_ty) -> bool })
+}
+
+// with `refresh_ahead`, the block cloning the arguments if the value of `kash_key` is due to be refreshed,
+// with the static tracking when the values were computed, and the block run after the lookup, which records
+// a computed value or starts the refresh of a hit in the background. A miss is never refreshed, e.g. after
+// the value was evicted, since it's computed by the caller. The refresh calls the function with the clones
+// of the arguments, and inserts the value it should cache
+fn gen_refresh_ahead(
+    args: &MacroArgs,
+    key_ty: &TokenStream2,
+    local_cache: &TokenStream2,
+    function_value: &TokenStream2,
+    names: &[TokenStream2],
+    is_async: bool,
+) -> (TokenStream2, TokenStream2) {
+    let Some(fraction) = &args.refresh_ahead else {
+        return (quote! {}, quote! {});
+    };
+    let fraction = args.typed_expr("refresh_ahead", fraction, quote! { f64 });
+    let ttl = if let Some(ttl) = &args.ttl {
+        let ttl = args.typed_expr("ttl", ttl, quote! { u64 });
+        quote! { ::std::time::Duration::from_secs(#ttl) }
+    } else {
+        let ttl_ms = args.ttl_ms.as_ref().expect("refresh_ahead requires a ttl");
+        let ttl_ms = args.typed_expr("ttl_ms", ttl_ms, quote! { u64 });
+        quote! { ::std::time::Duration::from_millis(#ttl_ms) }
+    };
+
+    let may_await = if is_async {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+    let insert = gen_cache_guard(
+        args.result,
+        args.option,
+        &quote! {},
+        quote! {
+            #local_cache.insert(::std::clone::Clone::clone(&kash_key), kash_result.clone())#may_await;
+            kash_refreshed = true;
+        },
+    );
+    // the guard unmarks the key if the refresh panics, so the next hit retries it
+    let refresh = quote! {
+        let kash_result = #function_value;
+        let mut kash_refreshed = false;
+        #insert
+        kash_refreshing.finish(kash_refreshed);
+    };
+    let spawn = if is_async {
+        quote! { ::kash::async_sync::spawn(async move { #refresh }); }
+    } else {
+        quote! { ::std::thread::spawn(move || { #refresh }); }
+    };
+    let due = quote! {
+        static KASH_REFRESH: ::kash::once_cell::sync::Lazy<::kash::refresh::RefreshAhead<#key_ty>> =
+            ::kash::once_cell::sync::Lazy::new(|| ::kash::refresh::RefreshAhead::new(#ttl, #fraction));
+        let kash_refresh_args = if KASH_REFRESH.is_due(&kash_key) {
+            Some((#(::std::clone::Clone::clone(&#names),)*))
+        } else {
+            None
+        };
+    };
+    let start = quote! {
+        if kash_entry.is_fresh() {
+            KASH_REFRESH.computed(::std::clone::Clone::clone(&kash_key));
+        } else if let Some((#(#names,)*)) = kash_refresh_args {
+            if let Some(kash_refreshing) = KASH_REFRESH.start(&kash_key) {
+                let kash_key = ::std::clone::Clone::clone(&kash_key);
+                #spawn
+            }
         }
+    };
+    (due, start)
+}
+
+// with `result_option`, only `Ok(Some(_))` is inserted, so `Ok(None)` becomes the `Err(None)` of `or_try_insert_with`
+fn gen_try_optional(args: &MacroArgs, value: TokenStream2) -> TokenStream2 {
+    if args.result && args.option {
+        quote! {
+            match #value {
+                Ok(Some(kash_value)) => Ok(kash_value),
+                Ok(None) => Err(None),
+                Err(e) => Err(Some(e)),
+            }
+        }
+    } else {
+        value
+    }
+}
+
+// the method of the entry API inserting the value computed by `function_call` on a miss, which moka only runs
+// once for the callers waiting for the same key, and the block returning early from the function if the value
+// isn't inserted, e.g. with the error of `result`
+fn gen_entry_insert(
+    args: &MacroArgs,
+    function_call: &TokenStream2,
+) -> (TokenStream2, TokenStream2) {
+    match (args.result, args.option) {
+        (false, false) => (quote!(.or_insert_with(#function_call)), quote!()),
+        (true, false) => (
+            quote!(.or_try_insert_with(#function_call)),
+            quote! { let kash_entry = kash_entry.map_err(|e| e.deref().clone())?; },
+        ),
+        (false, true) => (
+            quote!(.or_optionally_insert_with(#function_call) ),
+            quote! { let kash_entry = kash_entry?; },
+        ),
+        (true, true) => (
+            quote!(.or_try_insert_with(#function_call)),
+            quote! {
+                let kash_entry = match kash_entry.map_err(|e| e.deref().clone()) {
+                    Ok(kash_entry) => kash_entry,
+                    Err(None) => return Ok(None),
+                    Err(Some(e)) => return Err(e),
+                };
+            },
+        ),
+    }
+}
+
+// only run the `insert` block for the values that should be cached,
+// i.e., the `Ok` of `result` and the `Some` of `option` functions, unless `skip_if` returns `true` for them
+fn gen_cache_guard(
+    result: bool,
+    option: bool,
+    skip_if: &TokenStream2,
+    insert: TokenStream2,
+) -> TokenStream2 {
+    // `kash_result` is the value, or a reference to it, which the argument of the predicate derefs to
+    let insert = if skip_if.is_empty() {
+        insert
+    } else {
+        quote! {
+            if !(#skip_if)(&kash_result) {
+                #insert
+            }
+        }
+    };
+    match (result, option) {
+        (false, false) => insert,
         (true, false) => {
             quote! {
                 if let Ok(kash_result) = &kash_result {
-                    #local_cache.insert(kash_key, kash_result.clone())#may_await;
+                    #insert
                 }
             }
         }
         (false, true) => {
             quote! {
                 if let Some(kash_result) = &kash_result {
-                    #local_cache.insert(kash_key, kash_result.clone())#may_await;
+                    #insert
+                }
+            }
+        }
+        (true, true) => {
+            quote! {
+                if let Ok(Some(kash_result)) = &kash_result {
+                    #insert
                 }
             }
         }
-        _ => unreachable!("All errors should be handled in the `MacroArgs` validation methods"),
     }
 }
 
@@ -59,46 +402,116 @@ fn gen_set_cache_block(
 // Normally it's the same as the return type of the functions, but
 // for Options and Results it's the (first) inner type. So for
 // Option<u32>, store u32, for Result<i32, String>, store i32, etc.
-fn gen_cache_value_type(result: bool, option: bool, output: &ReturnType) -> TokenStream2 {
+// With `result_option`, i.e., both `result` and `option`, it's the inner type of both,
+// so for Result<Option<u32>, String>, store u32.
+// With `arc`, it's wrapped in an `Arc`, e.g. Arc<i32>.
+fn gen_cache_value_type(args: &MacroArgs, output: &ReturnType) -> TokenStream2 {
+    let value_ty = gen_inner_value_type(args.result, args.option, output);
+    if args.arc {
+        let value_ptr = gen_value_ptr(args);
+        quote! { #value_ptr<#value_ty> }
+    } else {
+        value_ty
+    }
+}
+
+// the pointer sharing the values with `arc`, which is a `kash::Cached` with `guard`
+fn gen_value_ptr(args: &MacroArgs) -> TokenStream2 {
+    if args.guard {
+        quote! { ::kash::Cached }
+    } else {
+        quote! { ::std::sync::Arc }
+    }
+}
+
+fn gen_inner_value_type(result: bool, option: bool, output: &ReturnType) -> TokenStream2 {
     match (result, option) {
         (false, false) => match &output {
             ReturnType::Default => quote! {()},
             ReturnType::Type(_, key) => quote! {#key},
         },
-        (true, true) => {
-            unreachable!("All errors should be handled in the `MacroArgs` validation methods")
-        }
-        _ => match output.clone() {
+        _ => match output {
             ReturnType::Default => {
-                panic!("Function must return something for `result` or `option` attributes")
+                unreachable!("All errors should be handled in the `MacroArgs` validation methods")
             }
             ReturnType::Type(_, ty) => {
-                if let Type::Path(typepath) = *ty {
-                    let segments = typepath.path.segments;
-                    if let PathArguments::AngleBracketed(brackets) =
-                        &segments.last().unwrap().arguments
-                    {
-                        let inner_ty = brackets.args.first().unwrap();
-                        quote! {#inner_ty}
-                    } else {
-                        panic!("Function return type has no inner type, you should remove `result` or `option`")
-                    }
-                } else {
-                    panic!("Function return type is too complex")
+                let mut inner_ty = first_type_argument(ty)
+                    .expect("All errors should be handled in the `MacroArgs` validation methods");
+                if result && option {
+                    inner_ty = first_type_argument(inner_ty).expect(
+                        "All errors should be handled in the `MacroArgs` validation methods",
+                    );
                 }
+                quote! {#inner_ty}
             }
         },
     }
 }
 
-fn gen_local_cache(
-    in_impl: bool,
-    fn_cache_ident: Ident,
-    cache_ident: Ident,
-) -> proc_macro2::TokenStream {
-    if in_impl {
+// The return type of the generated functions, which is the return type of the function,
+// but with `arc` the value is wrapped in an `Arc`, e.g. Result<Arc<i32>, String>
+fn gen_output(args: &MacroArgs, output: &ReturnType) -> ReturnType {
+    if !args.arc {
+        return output.clone();
+    }
+
+    let value_ty = gen_cache_value_type(args, output);
+    match (output, args.result || args.option) {
+        (ReturnType::Type(_, ty), true) => {
+            let mut ty = ty.as_ref().clone();
+            let mut inner_ty = first_type_argument_mut(&mut ty);
+            if args.result && args.option {
+                inner_ty = inner_ty.and_then(first_type_argument_mut);
+            }
+            if let Some(inner_ty) = inner_ty {
+                *inner_ty = parse_quote! { #value_ty };
+            }
+            parse_quote! { -> #ty }
+        }
+        _ => parse_quote! { -> #value_ty },
+    }
+}
+
+// With `arc`, wrap the returned value of the original function in an `Arc` (or a `kash::Cached` with `guard`)
+fn gen_wrap_arc(args: &MacroArgs, value: TokenStream2) -> TokenStream2 {
+    let value_ptr = gen_value_ptr(args);
+    match (args.arc, args.result || args.option) {
+        (false, _) => value,
+        (true, false) => quote! { #value_ptr::new(#value) },
+        (true, true) if args.result && args.option => {
+            quote! { (#value).map(|kash_value| kash_value.map(#value_ptr::new)) }
+        }
+        (true, true) => quote! { (#value).map(#value_ptr::new) },
+    }
+}
+
+// wrap a cached value in the return type of the function, e.g. `Ok(value)` for `result`
+fn gen_wrap_value(args: &MacroArgs, value: TokenStream2) -> TokenStream2 {
+    match (args.result, args.option) {
+        (false, false) => value,
+        (true, false) => quote! { Ok(#value) },
+        (false, true) => quote! { Some(#value) },
+        (true, true) => quote! { Ok(Some(#value)) },
+    }
+}
+
+fn gen_local_cache(args: &MacroArgs, fn_ident: &Ident) -> proc_macro2::TokenStream {
+    let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
+    if args.in_impl {
         quote! {Self:: #fn_cache_ident()}
+    } else if args.shared_cache.is_some() {
+        quote! {#fn_cache_ident()}
     } else {
+        let cache_ident = gen_cache_ident(&args.name, fn_ident);
         quote! {#cache_ident}
     }
 }
+
+// clear the cache in the `Lazy` at `cache`, unless it's never been used
+pub(crate) fn gen_clear_cache(cache: TokenStream2) -> TokenStream2 {
+    quote! {
+        if let Some(kash_cache) = ::kash::once_cell::sync::Lazy::get(#cache) {
+            kash_cache.invalidate_all();
+        }
+    }
+}
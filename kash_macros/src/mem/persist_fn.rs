@@ -0,0 +1,83 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{Ident, ItemFn};
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{gen_trait_bounds, get_input_names, get_input_types, make_cache_key_type};
+use crate::mem::{gen_cache_value_type, gen_local_cache};
+
+// struct for the functions dumping the cache to a file and loading it back
+#[derive(Debug, Clone)]
+pub struct PersistFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> PersistFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for PersistFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        if !self.args.persist {
+            return;
+        }
+
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let dump_fn_ident = Ident::new(&format!("{}_dump_cache", fn_ident), fn_ident.span());
+        let load_fn_ident = Ident::new(&format!("{}_load_cache", fn_ident), fn_ident.span());
+
+        let dump_fn_ident_doc = format!(
+            "Writes the cached entries of the function [`{}`] to a file, and returns their number.",
+            fn_ident
+        );
+        let load_fn_ident_doc = format!(
+            "Inserts the entries written by [`{}`] into the cache of the function [`{}`], and returns their number.",
+            dump_fn_ident, fn_ident
+        );
+        let visibility = &self.input.vis;
+        let where_clause = gen_trait_bounds(self.args);
+        let inputs = &signature.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+        let (key_ty, _) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let cache_value_ty = gen_cache_value_type(self.args, &signature.output);
+
+        let local_cache = gen_local_cache(self.args, fn_ident);
+
+        let (asyncness, may_await) = if signature.asyncness.is_some() {
+            (quote! { async }, quote! { .await })
+        } else {
+            (quote! {}, quote! {})
+        };
+
+        let expanded = quote! {
+            #[doc = #dump_fn_ident_doc]
+            #[allow(dead_code)]
+            #visibility fn #dump_fn_ident(
+                path: impl ::std::convert::AsRef<::std::path::Path>,
+            ) -> ::std::result::Result<usize, ::kash::persist::PersistError> #where_clause {
+                ::kash::persist::dump_cache(#local_cache.iter(), path)
+            }
+
+            #[doc = #load_fn_ident_doc]
+            #[allow(dead_code)]
+            #visibility #asyncness fn #load_fn_ident(
+                path: impl ::std::convert::AsRef<::std::path::Path>,
+            ) -> ::std::result::Result<usize, ::kash::persist::PersistError> #where_clause {
+                let kash_entries = ::kash::persist::load_cache::<#key_ty, #cache_value_ty>(path)?;
+                let kash_len = kash_entries.len();
+                for (kash_key, kash_value) in kash_entries {
+                    #local_cache.insert(kash_key, kash_value) #may_await;
+                }
+                ::std::result::Result::Ok(kash_len)
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
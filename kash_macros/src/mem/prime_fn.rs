@@ -1,10 +1,15 @@
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{Ident, ItemFn};
+use syn::{parse_quote, Ident, ItemFn, ReturnType};
 
 use crate::common::macro_args::MacroArgs;
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
-use crate::mem::{gen_local_cache, gen_set_cache_block};
+use crate::common::{
+    gen_forwarded_attributes, gen_key_arc_args, gen_key_binding, gen_origin_fn_ident,
+    gen_prime_blocking_fn, get_input_names, get_input_types, make_cache_key_type,
+};
+use crate::mem::{
+    gen_cache_guard, gen_local_cache, gen_output, gen_set_cache_block, gen_skip_if, gen_wrap_arc,
+};
 
 // struct for prime function
 #[derive(Debug, Clone)]
@@ -19,6 +24,66 @@ impl<'a> PrimeFn<'a> {
     }
 }
 
+impl PrimeFn<'_> {
+    // like the prime function, but also returns whether an existing value was replaced
+    fn gen_refresh_fn(
+        &self,
+        local_cache: &TokenStream,
+        function_call: &TokenStream,
+        key_binding: &TokenStream,
+        may_await: &TokenStream,
+    ) -> TokenStream {
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let refresh_fn_ident = Ident::new(&format!("{}_refresh_cache", fn_ident), fn_ident.span());
+        let mut refresh_sig = signature.clone();
+        refresh_sig.ident = refresh_fn_ident;
+        let output_ty = match gen_output(self.args, &signature.output) {
+            ReturnType::Default => quote! {()},
+            ReturnType::Type(_, ty) => quote! {#ty},
+        };
+        refresh_sig.output = parse_quote! { -> (#output_ty, bool) };
+
+        let refresh_fn_ident_doc = format!(
+            "Primes the function [`{}`] and returns whether an existing value was replaced.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+
+        let new_value = if self.input.sig.asyncness.is_some() {
+            quote! { |_| ::std::future::ready(kash_result.clone()) }
+        } else {
+            quote! { |_| kash_result.clone() }
+        };
+        let upsert_block = gen_cache_guard(
+            self.args.result,
+            self.args.option,
+            &gen_skip_if(self.args, &signature.output),
+            quote! {
+                kash_replaced = #local_cache
+                    .entry_by_ref(&kash_key)
+                    .and_upsert_with(#new_value)
+                    #may_await
+                    .is_old_value_replaced();
+            },
+        );
+
+        quote! {
+            #[doc = #refresh_fn_ident_doc]
+            #[allow(dead_code)]
+            #(#attributes)*
+            #visibility #refresh_sig {
+                #key_binding
+                #function_call
+                let mut kash_replaced = false;
+                #upsert_block
+                (kash_result, kash_replaced)
+            }
+        }
+    }
+}
+
 impl ToTokens for PrimeFn<'_> {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let signature = &self.input.sig;
@@ -26,27 +91,25 @@ impl ToTokens for PrimeFn<'_> {
         let prime_fn_ident = Ident::new(&format!("{}_prime_cache", fn_ident), fn_ident.span());
         let mut prime_sig = signature.clone();
         prime_sig.ident = prime_fn_ident;
+        prime_sig.output = gen_output(self.args, &signature.output);
 
         let prime_fn_indent_doc = format!("Primes the function [`{}`].", fn_ident);
-        let attributes = &self.input.attrs;
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
         let visibility = &self.input.vis;
         let inputs = &self.input.sig.inputs;
 
         let (_, without_self_types) = get_input_types(inputs);
         let (maybe_with_self_names, without_self_names) = get_input_names(inputs);
 
-        let (_, key_expr) =
-            make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
-        let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
-        let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
 
-        let local_cache = gen_local_cache(self.args.in_impl, fn_cache_ident, cache_ident);
+        let local_cache = gen_local_cache(self.args, fn_ident);
         let call_prefix = if self.args.in_impl {
             quote! { Self:: }
         } else {
             quote! {}
         };
-        let no_cache_fn_ident = Ident::new(&format!("{}_no_cache", fn_ident), fn_ident.span());
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
 
         let may_await = if self.input.sig.asyncness.is_some() {
             quote! {.await}
@@ -54,11 +117,40 @@ impl ToTokens for PrimeFn<'_> {
             quote! {}
         };
 
+        let (arc_inputs, call_names) = gen_key_arc_args(self.args, inputs);
+        let function_value = gen_wrap_arc(
+            self.args,
+            quote! { #call_prefix #no_cache_fn_ident(#(#call_names),*) #may_await },
+        );
+        let key_binding = gen_key_binding(&self.args.key, &key_expr, function_value.clone());
+        let key_binding = quote! {
+            #arc_inputs
+            #key_binding
+        };
         let function_call = quote! {
-            let kash_result = #call_prefix #no_cache_fn_ident(#(#maybe_with_self_names),*) #may_await;
+            let kash_result = #function_value;
         };
-        let set_cache_block =
-            gen_set_cache_block(&local_cache, self.args.result, self.args.option, &may_await);
+        let refresh_fn = if self.args.refresh_cache {
+            let key_binding = gen_key_binding(
+                &self.args.key,
+                &key_expr,
+                quote! { (#function_value, false) },
+            );
+            let key_binding = quote! {
+                #arc_inputs
+                #key_binding
+            };
+            self.gen_refresh_fn(&local_cache, &function_call, &key_binding, &may_await)
+        } else {
+            quote! {}
+        };
+        let set_cache_block = gen_set_cache_block(
+            &local_cache,
+            self.args.result,
+            self.args.option,
+            &gen_skip_if(self.args, &signature.output),
+            &may_await,
+        );
         let set_cache_and_return = quote! {
             #set_cache_block
             kash_result
@@ -69,14 +161,19 @@ impl ToTokens for PrimeFn<'_> {
             #set_cache_and_return
         };
 
+        let prime_blocking_fn =
+            gen_prime_blocking_fn(self.input, &prime_sig, &call_prefix, &maybe_with_self_names);
+
         let expanded = quote! {
             #[doc = #prime_fn_indent_doc]
             #[allow(dead_code)]
             #(#attributes)*
             #visibility #prime_sig {
-                let kash_key = #key_expr;
+                #key_binding
                 #prime_do_set_return_block
             }
+            #refresh_fn
+            #prime_blocking_fn
         };
 
         tokens.extend(expanded);
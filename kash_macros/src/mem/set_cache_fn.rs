@@ -0,0 +1,75 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_quote, FnArg, Ident, ItemFn, ReturnType};
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{
+    gen_forwarded_attributes, gen_key_arc_args, gen_key_binding, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::mem::{gen_cache_value_type, gen_local_cache};
+
+// struct for the function storing a given value for the arguments, without evaluating the function
+#[derive(Debug, Clone)]
+pub struct SetCacheFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> SetCacheFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for SetCacheFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let set_cache_fn_ident = Ident::new(&format!("{}_set_cache", fn_ident), fn_ident.span());
+
+        let set_cache_fn_ident_doc = format!(
+            "Stores the value in the cache of the function [`{}`] for the arguments, without evaluating the function.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+        let inputs = &signature.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+        let cache_value_ty = gen_cache_value_type(self.args, &signature.output);
+        let local_cache = gen_local_cache(self.args, fn_ident);
+
+        // a key which can't be made is never cached
+        let key_binding = gen_key_binding(&self.args.key, &key_expr, quote! { () });
+        let (arc_inputs, _) = gen_key_arc_args(self.args, inputs);
+
+        let may_await = if signature.asyncness.is_some() {
+            quote! { .await }
+        } else {
+            quote! {}
+        };
+
+        let mut set_cache_sig = signature.clone();
+        set_cache_sig.ident = set_cache_fn_ident;
+        let value_input: FnArg = parse_quote! { kash_value: #cache_value_ty };
+        set_cache_sig.inputs.push(value_input);
+        set_cache_sig.output = ReturnType::Default;
+
+        let expanded = quote! {
+            #[doc = #set_cache_fn_ident_doc]
+            #[allow(dead_code, unused_mut, unused_variables)]
+            #(#attributes)*
+            #visibility #set_cache_sig {
+                #arc_inputs
+                #key_binding
+                #local_cache.insert(kash_key, kash_value) #may_await;
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
@@ -1,10 +1,27 @@
 use proc_macro2::TokenStream;
-use quote::{quote, ToTokens};
-use syn::{parse_str, Expr, Ident, ItemFn};
+use quote::{quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
+use syn::{parse_str, Expr, Ident, ItemFn, ReturnType};
 
 use crate::common::macro_args::{EvictionPolicy, MacroArgs};
-use crate::common::{gen_cache_ident, get_input_names, get_input_types, make_cache_key_type};
-use crate::mem::gen_cache_value_type;
+use crate::common::{
+    gen_cache_ident, gen_cache_vis, gen_registry_entry, gen_trait_bounds, gen_ttl_hints_ident,
+    get_input_names, get_input_types, make_cache_key_type, respan,
+};
+use crate::mem::{gen_cache_value_type, gen_clear_cache, is_single_slot};
+
+// the `Option<Duration>` of the `ttl` or `ttl_ms` of the cache, or of the process default
+fn gen_ttl_duration(args: &MacroArgs) -> TokenStream {
+    if let Some(ref ttl) = args.ttl {
+        let ttl = args.typed_expr("ttl", ttl, quote! { u64 });
+        quote! { Some(core::time::Duration::from_secs(#ttl)) }
+    } else if let Some(ref ttl_ms) = args.ttl_ms {
+        let ttl_ms = args.typed_expr("ttl_ms", ttl_ms, quote! { u64 });
+        quote! { Some(core::time::Duration::from_millis(#ttl_ms)) }
+    } else {
+        quote! { ::kash::defaults::runtime_defaults().ttl_duration() }
+    }
+}
 
 // struct for cache function
 #[derive(Debug, Clone)]
@@ -21,68 +38,262 @@ impl<'a> CacheType<'a> {
 
 impl ToTokens for CacheType<'_> {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        let visibility = &self.input.vis;
+        let visibility = gen_cache_vis(self.args, self.input);
         let signature = &self.input.sig;
         let fn_ident = &signature.ident;
         let inputs = &signature.inputs;
-        let generics = &signature.generics;
         let output = &signature.output;
 
         let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
-        let moka_ty = if self.input.sig.asyncness.is_some() {
+        let asyncness = self.input.sig.asyncness.is_some();
+        let moka_ty = if asyncness {
             quote! {::kash::moka::future::Cache}
         } else {
             quote! {::kash::moka::sync::Cache}
         };
+        // the `moka` cache of `async` functions isn't segmented, so `segments` is ignored for them
+        let segments = match &self.args.segments {
+            Some(segments) if !asyncness => {
+                Some(self.args.typed_expr("segments", segments, quote! { usize }))
+            }
+            _ => None,
+        };
+        let cache_moka_ty = if segments.is_some() {
+            quote! {::kash::moka::sync::SegmentedCache}
+        } else {
+            moka_ty.clone()
+        };
         let (_, without_self_types) = get_input_types(inputs);
         let (_, without_self_names) = get_input_names(inputs);
 
-        let (key, _) = make_cache_key_type(&self.args.key, without_self_types, &without_self_names);
+        let (key, _) = make_cache_key_type(self.args, without_self_types, &without_self_names);
 
-        let cache_value_ty = gen_cache_value_type(self.args.result, self.args.option, output);
+        let cache_value_ty = gen_cache_value_type(self.args, output);
+        // the value type is checked by `MemValue`, spanned at the return type, so a trait object which isn't
+        // `Send + Sync` is reported there, once, and the uses of the cache aren't reported
+        let return_span = match output {
+            ReturnType::Default => fn_ident.span(),
+            ReturnType::Type(_, ty) => ty.span(),
+        };
+        let checked_value_ty = respan(cache_value_ty.clone(), return_span);
+        let checked_value_ty = quote_spanned! { return_span=>
+            <#checked_value_ty as ::kash::MemValue>::Value
+        };
 
-        let cache_ty = quote! {#moka_ty<#key, #cache_value_ty>};
+        let single_slot = is_single_slot(self.input, self.args);
+        let cache_ty = if single_slot {
+            quote! {::kash::SlotCache<#checked_value_ty>}
+        } else {
+            quote! {#cache_moka_ty<#key, #checked_value_ty>}
+        };
+
+        let segments = match segments {
+            Some(segments) => quote! { .segments(#segments) },
+            None => quote! {},
+        };
+        let initial_capacity = if let Some(ref initial_capacity) = self.args.initial_capacity {
+            let initial_capacity =
+                self.args
+                    .typed_expr("initial_capacity", initial_capacity, quote! { usize });
+            quote! { .initial_capacity(#initial_capacity) }
+        } else {
+            quote! {}
+        };
 
         let size = if let Some(ref size) = self.args.size {
-            let size = parse_str::<Expr>(size).expect("Unable to parse size");
+            let size = self.args.typed_expr("size", size, quote! { u64 });
             quote! { .max_capacity(#size) }
         } else {
             quote! {}
         };
 
-        let ttl = if let Some(ref ttl) = self.args.ttl {
-            let ttl = parse_str::<Expr>(ttl).expect("Unable to parse ttl");
+        let ttl_hints_ident = gen_ttl_hints_ident(&self.args.name, fn_ident);
+        let ttl = if self.args.with_ttl {
+            // the ttl of the cache only applies to the entries without a ttl of `{fn}_with_ttl`, so it's not a `time_to_live`
+            let default_ttl = gen_ttl_duration(self.args);
+            quote! { .expire_after(::kash::expiry::WithTtl::new(&#ttl_hints_ident, #default_ttl)) }
+        } else if self.args.ttl_for.is_some() {
+            // the ttl of the cache is the fallback of `ttl_for`, which moka would cap with a `time_to_live`
+            quote! {}
+        } else if let Some(ref ttl) = self.args.ttl {
+            let ttl = self.args.typed_expr("ttl", ttl, quote! { u64 });
             quote! { .time_to_live(core::time::Duration::from_secs(#ttl)) }
+        } else if let Some(ref ttl_ms) = self.args.ttl_ms {
+            let ttl_ms = self.args.typed_expr("ttl_ms", ttl_ms, quote! { u64 });
+            quote! { .time_to_live(core::time::Duration::from_millis(#ttl_ms)) }
         } else {
             quote! {}
         };
 
-        let name = if let Some(ref name) = self.args.name {
+        let expiry = if let Some(ref expiry) = self.args.expiry {
+            let expiry = parse_str::<Expr>(expiry).expect("Unable to parse expiry");
+            quote! { .expire_after(#expiry) }
+        } else if let Some(ref ttl_for) = self.args.ttl_for {
+            let ttl_for = parse_str::<Expr>(ttl_for).expect("Unable to parse ttl_for");
+            let fallback_ttl = if self.args.ttl.is_some() || self.args.ttl_ms.is_some() {
+                gen_ttl_duration(self.args)
+            } else {
+                quote! { None }
+            };
+            quote! { .expire_after(::kash::expiry::TtlFor::<#cache_value_ty>::new(#ttl_for, #fallback_ttl)) }
+        } else {
+            quote! {}
+        };
+
+        let name = if let Some(ref display_name) = self.args.display_name {
+            let display_name =
+                parse_str::<Expr>(display_name).expect("Unable to parse display_name");
+            quote! { .name(::std::convert::AsRef::<str>::as_ref(&(#display_name))) }
+        } else if let Some(ref name) = self.args.name {
             quote! { .name(#name) }
         } else {
             quote! {}
         };
 
+        let eviction_listener = if cfg!(feature = "metrics") {
+            let cache_name = cache_ident.to_string();
+            quote! {
+                .eviction_listener(|_, _, cause| {
+                    if cause.was_evicted() {
+                        ::kash::metrics::recorder().record_evict(#cache_name);
+                    }
+                })
+            }
+        } else {
+            quote! {}
+        };
+
+        let invalidation_closures = if self.args.invalidate_if {
+            quote! { .support_invalidation_closures() }
+        } else {
+            quote! {}
+        };
+
         let policy = match self.args.eviction_policy {
             EvictionPolicy::Lfu => quote! { tiny_lfu },
             EvictionPolicy::Lru => quote! { lru },
         };
 
-        let cache_init = quote! {
-            static #cache_ident: ::kash::once_cell::sync::Lazy<#cache_ty> = ::kash::once_cell::sync::Lazy::new(|| {
+        // without `size` or `ttl`, the process defaults are read when the cache is created
+        let default_size = if self.args.size.is_none() {
+            quote! {
+                let kash_builder = match kash_defaults.size {
+                    Some(kash_size) => kash_builder.max_capacity(kash_size),
+                    None => kash_builder,
+                };
+            }
+        } else {
+            quote! {}
+        };
+        let default_ttl = if self.args.ttl.is_none()
+            && self.args.ttl_ms.is_none()
+            && !self.args.with_ttl
+            && self.args.expiry.is_none()
+            && self.args.ttl_for.is_none()
+        {
+            quote! {
+                let kash_builder = match kash_defaults.ttl_duration() {
+                    Some(kash_ttl) => kash_builder.time_to_live(kash_ttl),
+                    None => kash_builder,
+                };
+            }
+        } else {
+            quote! {}
+        };
+        let cache_build = if default_size.is_empty() && default_ttl.is_empty() {
+            quote! {
                 #moka_ty::builder()
+                    #segments
+                    #initial_capacity
                     #size
                     #ttl
+                    #expiry
                     #name
+                    #eviction_listener
+                    #invalidation_closures
                     .eviction_policy(::kash::moka::policy::EvictionPolicy::#policy())
                     .build()
+            }
+        } else {
+            quote! {{
+                let kash_defaults = ::kash::defaults::runtime_defaults();
+                let kash_builder = #moka_ty::builder()
+                    #segments
+                    #initial_capacity
+                    #size
+                    #ttl
+                    #expiry
+                    #name
+                    #eviction_listener
+                    #invalidation_closures
+                    .eviction_policy(::kash::moka::policy::EvictionPolicy::#policy());
+                #default_size
+                #default_ttl
+                kash_builder.build()
+            }}
+        };
+        let cache_build = if single_slot {
+            let slot_ttl = gen_ttl_duration(self.args);
+            quote! { ::kash::SlotCache::new(#slot_ttl) }
+        } else {
+            cache_build
+        };
+        let registry_entry = gen_registry_entry(gen_clear_cache(quote! { &#cache_ident }));
+        let ttl_hints = if self.args.with_ttl {
+            quote! {
+                static #ttl_hints_ident: ::kash::once_cell::sync::Lazy<::kash::expiry::TtlHints<#key>> =
+                    ::kash::once_cell::sync::Lazy::new(::kash::expiry::TtlHints::new);
+            }
+        } else {
+            quote! {}
+        };
+        let static_ty = respan(
+            quote! { ::kash::once_cell::sync::Lazy<#cache_ty> },
+            return_span,
+        );
+        let cache_init = quote! {
+            static #cache_ident: #static_ty = ::kash::once_cell::sync::Lazy::new(|| {
+                #cache_build
             });
+            const _: () = ::kash::assert_mem_key::<#key>();
+            #registry_entry
+            #ttl_hints
         };
         let fn_cache_ident = Ident::new(&format!("{}_get_cache_ident", fn_ident), fn_ident.span());
 
-        let cache_ty = if self.args.in_impl {
+        let trait_bounds = gen_trait_bounds(self.args);
+        let cache_ty = if let Some(ref shared_cache) = self.args.shared_cache {
+            // the shared static is defined by the user, its type is checked by the return type of the accessor
+            let shared_cache =
+                parse_str::<Expr>(shared_cache).expect("Unable to parse shared_cache");
+            quote! {
+                #[allow(dead_code)]
+                #visibility fn #fn_cache_ident() -> &'static #cache_ty {
+                    const _: () = ::kash::assert_mem_key::<#key>();
+                    &#shared_cache
+                }
+            }
+        } else if self.args.in_trait {
+            // a static in a default method is shared by all the implementors, so it holds a cache per type
+            let registry_entry = gen_registry_entry(quote! {
+                if let Some(kash_caches) = ::kash::once_cell::sync::Lazy::get(&#cache_ident) {
+                    for kash_cache in kash_caches.caches() {
+                        kash_cache.invalidate_all();
+                    }
+                }
+            });
+            quote! {
+                fn #fn_cache_ident() -> &'static #cache_ty #trait_bounds {
+                    static #cache_ident: ::kash::once_cell::sync::Lazy<::kash::type_map::TypeMap<#cache_ty>> =
+                        ::kash::once_cell::sync::Lazy::new(::kash::type_map::TypeMap::new);
+                    const _: () = ::kash::assert_mem_key::<#key>();
+                    #registry_entry
+                    #cache_ident.get_or_init::<Self>(|| #cache_build)
+                }
+            }
+        } else if self.args.in_impl {
             quote! {
-                #visibility fn #fn_cache_ident #generics () -> &'static ::kash::once_cell::sync::Lazy<#cache_ty> {
+                #visibility fn #fn_cache_ident() -> &'static ::kash::once_cell::sync::Lazy<#cache_ty> {
                     #cache_init
                     &#cache_ident
                 }
@@ -96,6 +307,7 @@ impl ToTokens for CacheType<'_> {
 
         let cache_ty = quote! {
             #[doc = #cache_ident_doc]
+            #[allow(clippy::type_complexity)]
             #cache_ty
         };
         tokens.extend(cache_ty);
@@ -0,0 +1,130 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::ItemFn;
+
+use crate::common::macro_args::MacroArgs;
+use crate::common::{
+    gen_cache_ident, gen_forwarded_attributes, gen_key_arc_args, gen_key_binding, gen_on_lookup,
+    gen_origin_fn_ident, gen_ttl_hints_ident, gen_with_ttl_sig, get_input_names, get_input_types,
+    make_cache_key_type,
+};
+use crate::mem::{
+    gen_cache_guard, gen_entry_insert, gen_local_cache, gen_output, gen_skip_if, gen_try_optional,
+    gen_wrap_arc, gen_wrap_value,
+};
+
+// struct for the function caching a computed value with the ttl given by the caller
+#[derive(Debug, Clone)]
+pub struct WithTtlFn<'a> {
+    input: &'a ItemFn,
+    args: &'a MacroArgs,
+}
+
+impl<'a> WithTtlFn<'a> {
+    pub fn new(input: &'a ItemFn, args: &'a MacroArgs) -> Self {
+        Self { input, args }
+    }
+}
+
+impl ToTokens for WithTtlFn<'_> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        if !self.args.with_ttl {
+            return;
+        }
+
+        let signature = &self.input.sig;
+        let fn_ident = &signature.ident;
+        let with_ttl_fn_ident_doc = format!(
+            "Caches the function [`{}`], keeping a computed value for `kash_ttl` instead of the ttl of the cache.",
+            fn_ident
+        );
+        let attributes = gen_forwarded_attributes(&self.input.attrs);
+        let visibility = &self.input.vis;
+        let inputs = &signature.inputs;
+
+        let (_, without_self_types) = get_input_types(inputs);
+        let (_, without_self_names) = get_input_names(inputs);
+        let (arc_inputs, call_names) = gen_key_arc_args(self.args, inputs);
+        let (_, key_expr) = make_cache_key_type(self.args, without_self_types, &without_self_names);
+
+        let cache_ident = gen_cache_ident(&self.args.name, fn_ident);
+        let ttl_hints_ident = gen_ttl_hints_ident(&self.args.name, fn_ident);
+        let on_hit = gen_on_lookup(self.args, &cache_ident, true);
+        let on_miss = gen_on_lookup(self.args, &cache_ident, false);
+        let local_cache = gen_local_cache(self.args, fn_ident);
+        let no_cache_fn_ident = gen_origin_fn_ident(self.args, fn_ident);
+        let may_await = if signature.asyncness.is_some() {
+            quote! { .await }
+        } else {
+            quote! {}
+        };
+        let function_value = gen_wrap_arc(
+            self.args,
+            quote! { #no_cache_fn_ident(#(#call_names),*) #may_await },
+        );
+        let key_binding = gen_key_binding(&self.args.key, &key_expr, function_value.clone());
+        let cached_value = gen_wrap_value(self.args, quote! { kash_value });
+        // the hint is only given by the caller computing the value, right before it's inserted, so the callers
+        // waiting for the same key share it, and other entries of the cache don't take it
+        let set_hint = gen_cache_guard(
+            self.args.result,
+            self.args.option,
+            &quote! {},
+            quote! { #ttl_hints_ident.set(::std::clone::Clone::clone(&kash_key), kash_ttl); },
+        );
+        let compute = gen_try_optional(self.args, quote! { kash_result });
+        let compute = quote! {
+            #on_miss
+            let kash_result = #function_value;
+            #set_hint
+            #compute
+        };
+        let function_call = if signature.asyncness.is_some() {
+            quote! { async { #compute } }
+        } else {
+            quote! { || { #compute } }
+        };
+        let (insert, may_return_early) = gen_entry_insert(self.args, &function_call);
+        let skip_if = gen_skip_if(self.args, &signature.output);
+        let skip_if = if skip_if.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                if kash_entry.is_fresh() && (#skip_if)(kash_entry.value()) {
+                    #local_cache.invalidate(&kash_key) #may_await;
+                }
+            }
+        };
+        let on_hit = if on_hit.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                if !kash_entry.is_fresh() {
+                    #on_hit
+                }
+            }
+        };
+
+        let mut with_ttl_sig = gen_with_ttl_sig(signature);
+        with_ttl_sig.output = gen_output(self.args, &signature.output);
+
+        let expanded = quote! {
+            #[doc = #with_ttl_fn_ident_doc]
+            #[allow(dead_code)]
+            #(#attributes)*
+            #visibility #with_ttl_sig {
+                use std::ops::Deref;
+                #arc_inputs
+                #key_binding
+                let kash_entry = #local_cache.entry_by_ref(&kash_key) #insert #may_await;
+                #may_return_early
+                #skip_if
+                #on_hit
+                let kash_value = kash_entry.into_value();
+                #cached_value
+            }
+        };
+
+        tokens.extend(expanded);
+    }
+}
@@ -0,0 +1,131 @@
+//! A shared reference to a cached value, returned by functions with the `guard` attribute.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A cached value, shared with the cache instead of being cloned.
+///
+/// It holds a strong reference to the value as it was cached, not to the cache entry: it doesn't lock
+/// the cache, so it never blocks other callers, and it stays valid after the entry is evicted, invalidated
+/// or replaced, while the cache returns the new value to the next callers. The value is dropped when
+/// neither the cache nor any `Cached` refer to it anymore.
+///
+/// The value can't be mutated through it, since it's shared. Use [`Cached::into_arc`] and
+/// [`Arc::try_unwrap`] (or [`Arc::unwrap_or_clone`]) to take it out.
+///
+/// ```rust
+/// use kash::{kash, Cached};
+///
+/// #[kash(guard)]
+/// fn primes(limit: u32) -> Vec<u32> {
+///     (2..limit).filter(|n| (2..*n).all(|d| n % d != 0)).collect()
+/// }
+///
+/// let first: Cached<Vec<u32>> = primes(10_000);
+/// let second = primes(10_000);
+/// // both point to the vector in the cache, nothing is cloned
+/// assert!(Cached::ptr_eq(&first, &second));
+/// assert_eq!(2, first[0]);
+///
+/// // the value outlives its cache entry
+/// PRIMES.invalidate_all();
+/// assert_eq!(1229, first.len());
+/// ```
+#[cfg_attr(
+    feature = "mem_persist",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Cached<V: ?Sized>(Arc<V>);
+
+impl<V> Cached<V> {
+    /// Wrap a value, e.g. to return it from a function cached with `guard` without the cache
+    #[must_use]
+    pub fn new(value: V) -> Self {
+        Self(Arc::new(value))
+    }
+}
+
+impl<V: ?Sized> Cached<V> {
+    /// Return whether both point to the same value, e.g. the one in the cache
+    #[must_use]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// Return the `Arc` of the value
+    #[must_use]
+    pub fn into_arc(this: Self) -> Arc<V> {
+        this.0
+    }
+}
+
+impl<V: ?Sized> Clone for Cached<V> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<V: ?Sized> Deref for Cached<V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.0
+    }
+}
+
+impl<V: ?Sized> AsRef<V> for Cached<V> {
+    fn as_ref(&self) -> &V {
+        &self.0
+    }
+}
+
+impl<V: ?Sized> From<Arc<V>> for Cached<V> {
+    fn from(value: Arc<V>) -> Self {
+        Self(value)
+    }
+}
+
+impl<V> From<V> for Cached<V> {
+    fn from(value: V) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<V: ?Sized + fmt::Debug> fmt::Debug for Cached<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl<V: ?Sized + fmt::Display> fmt::Display for Cached<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl<V: ?Sized + PartialEq> PartialEq for Cached<V> {
+    fn eq(&self, other: &Self) -> bool {
+        *self.0 == *other.0
+    }
+}
+
+impl<V: ?Sized + Eq> Eq for Cached<V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_value() {
+        let value = Cached::new(vec![1, 2, 3]);
+        let clone = value.clone();
+        assert!(Cached::ptr_eq(&value, &clone));
+        assert_eq!(value, Cached::new(vec![1, 2, 3]));
+        assert!(!Cached::ptr_eq(&value, &Cached::new(vec![1, 2, 3])));
+
+        drop(value);
+        assert_eq!(Ok(vec![1, 2, 3]), Arc::try_unwrap(Cached::into_arc(clone)));
+    }
+}
@@ -0,0 +1,92 @@
+//! Process-wide defaults for the `size` and `ttl` of memory caches.
+//!
+//! Memory caches without a `size` (or `ttl`) attribute read the defaults when they're first used,
+//! so set them at startup, before calling any cached function. The precedence is the attribute,
+//! then the default, then unbounded (or unlimited), and caches with `expiry` or `ttl_for` don't
+//! use the default ttl.
+//!
+//! ```rust
+//! use kash::kash;
+//!
+//! #[kash]
+//! fn square(n: u64) -> u64 {
+//!     n * n
+//! }
+//!
+//! kash::defaults::set_default_size(Some(1_000));
+//! kash::defaults::set_default_ttl(Some(60));
+//!
+//! square(3);
+//! assert_eq!(Some(1_000), SQUARE.policy().max_capacity());
+//! ```
+
+use std::sync::{PoisonError, RwLock};
+use std::time::Duration;
+
+/// The defaults of memory caches without a `size` or `ttl` attribute
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Defaults {
+    /// The max number of entries
+    pub size: Option<u64>,
+    /// The ttl in seconds
+    pub ttl: Option<u64>,
+}
+
+impl Defaults {
+    /// The ttl as a `Duration`
+    #[must_use]
+    pub fn ttl_duration(&self) -> Option<Duration> {
+        self.ttl.map(Duration::from_secs)
+    }
+}
+
+static DEFAULTS: RwLock<Defaults> = RwLock::new(Defaults {
+    size: None,
+    ttl: None,
+});
+
+/// Set the default max number of entries of memory caches without a `size` attribute.
+/// `None` keeps them unbounded.
+pub fn set_default_size(size: Option<u64>) {
+    DEFAULTS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .size = size;
+}
+
+/// Set the default ttl in seconds of memory caches without a `ttl`, `expiry` or `ttl_for` attribute.
+/// `None` keeps their entries until they're evicted.
+pub fn set_default_ttl(seconds: Option<u64>) {
+    DEFAULTS.write().unwrap_or_else(PoisonError::into_inner).ttl = seconds;
+}
+
+/// Return the current defaults, read by the memory caches when they're first used
+pub fn runtime_defaults() -> Defaults {
+    *DEFAULTS.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_read_defaults() {
+        set_default_size(Some(10));
+        set_default_ttl(Some(5));
+        assert_eq!(
+            Defaults {
+                size: Some(10),
+                ttl: Some(5)
+            },
+            runtime_defaults()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            runtime_defaults().ttl_duration()
+        );
+
+        set_default_size(None);
+        set_default_ttl(None);
+        assert_eq!(Defaults::default(), runtime_defaults());
+    }
+}
@@ -0,0 +1,101 @@
+//! The `moka::Expiry`s of the memory caches with the `ttl_for` and `with_ttl` attributes.
+//!
+//! They compute the ttl of each entry when it's inserted or replaced, from its value with `ttl_for`,
+//! or from the ttl given to `{fn}_with_ttl` for its key with `with_ttl`, falling back to the `ttl` of the cache.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The `moka::Expiry` of the `ttl_for` attribute, computing the ttl of each entry from its value
+/// when it's inserted or replaced. `None` expires the entry after the `ttl` of the cache, if any.
+pub struct TtlFor<V> {
+    ttl_for: fn(&V) -> Option<Duration>,
+    ttl: Option<Duration>,
+}
+
+impl<V> TtlFor<V> {
+    #[must_use]
+    pub fn new(ttl_for: fn(&V) -> Option<Duration>, ttl: Option<Duration>) -> Self {
+        Self { ttl_for, ttl }
+    }
+}
+
+/// The ttls given to the `{fn}_with_ttl` functions of the memory caches with the `with_ttl` attribute,
+/// by key, until the [`WithTtl`] of the cache applies them to the inserted entries.
+pub struct TtlHints<K>(Mutex<HashMap<K, Duration>>);
+
+impl<K: Hash + Eq> TtlHints<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+
+    /// Give the ttl to the next entry inserted for the key
+    pub fn set(&self, key: K, ttl: Duration) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, ttl);
+    }
+
+    fn take(&self, key: &K) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(key)
+    }
+}
+
+impl<K: Hash + Eq> Default for TtlHints<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The `moka::Expiry` of the `with_ttl` attribute, expiring the entries inserted with a [`TtlHints`] after
+/// its ttl, and the others after the `ttl` of the cache, if any, when they're inserted or replaced.
+pub struct WithTtl<K: 'static> {
+    hints: &'static TtlHints<K>,
+    ttl: Option<Duration>,
+}
+
+impl<K> WithTtl<K> {
+    #[must_use]
+    pub fn new(hints: &'static TtlHints<K>, ttl: Option<Duration>) -> Self {
+        Self { hints, ttl }
+    }
+}
+
+impl<K: Hash + Eq, V> moka::Expiry<K, V> for WithTtl<K> {
+    fn expire_after_create(&self, key: &K, _value: &V, _created_at: Instant) -> Option<Duration> {
+        self.hints.take(key).or(self.ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        key: &K,
+        _value: &V,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.hints.take(key).or(self.ttl)
+    }
+}
+
+impl<K, V> moka::Expiry<K, V> for TtlFor<V> {
+    fn expire_after_create(&self, _key: &K, value: &V, _created_at: Instant) -> Option<Duration> {
+        (self.ttl_for)(value).or(self.ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &K,
+        value: &V,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        (self.ttl_for)(value).or(self.ttl)
+    }
+}
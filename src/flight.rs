@@ -0,0 +1,125 @@
+//! Single-flight with a timeout, for the memory caches with `lock_timeout`.
+//!
+//! The first caller of a key leads the computation, the others wait for it, but only up to
+//! the timeout, so a stuck leader can't block them forever. The map of the computations is only locked
+//! to find or add the call of a key, never while computing, so different keys are computed concurrently.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Call {
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+/// The in-flight computations of a cache, by key
+pub struct InFlight<K> {
+    calls: Mutex<HashMap<K, Arc<Call>>>,
+}
+
+impl<K: Hash + Eq + Clone> InFlight<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return a guard if the caller leads the computation of the key. Otherwise, wait for the leader
+    /// to finish, or for the timeout, and return `None`.
+    /// The leader finishes when the guard is dropped, so the value should be cached before that.
+    pub fn lead_or_wait(&self, key: &K, timeout: Duration) -> Option<InFlightGuard<'_, K>> {
+        let call = {
+            let mut calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+            match calls.get(key) {
+                Some(call) => call.clone(),
+                None => {
+                    let call = Arc::new(Call::default());
+                    calls.insert(key.clone(), call.clone());
+                    return Some(InFlightGuard {
+                        flight: self,
+                        key: key.clone(),
+                        call,
+                    });
+                }
+            }
+        };
+
+        let done = call.done.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = call
+            .finished
+            .wait_timeout_while(done, timeout, |done| !*done)
+            .unwrap_or_else(PoisonError::into_inner);
+        None
+    }
+}
+
+impl<K: Hash + Eq + Clone> Default for InFlight<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finishes the computation of the leader when dropped, even if it panics
+pub struct InFlightGuard<'a, K: Hash + Eq> {
+    flight: &'a InFlight<K>,
+    key: K,
+    call: Arc<Call>,
+}
+
+impl<K: Hash + Eq> Drop for InFlightGuard<'_, K> {
+    fn drop(&mut self) {
+        self.flight
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+        *self
+            .call
+            .done
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = true;
+        self.call.finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn waiters_are_released_when_the_leader_finishes() {
+        let flight = InFlight::new();
+        let guard = flight.lead_or_wait(&1, Duration::from_secs(10));
+        assert!(guard.is_some());
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let started = Instant::now();
+                assert!(flight.lead_or_wait(&1, Duration::from_secs(10)).is_none());
+                started.elapsed()
+            });
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+            assert!(waiter.join().unwrap() < Duration::from_secs(10));
+        });
+
+        assert!(flight.lead_or_wait(&1, Duration::from_secs(10)).is_some());
+    }
+
+    #[test]
+    fn waiters_give_up_after_the_timeout() {
+        let flight = InFlight::new();
+        let _guard = flight.lead_or_wait(&1, Duration::from_secs(10));
+
+        let started = Instant::now();
+        assert!(flight.lead_or_wait(&1, Duration::from_millis(50)).is_none());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(flight.lead_or_wait(&2, Duration::from_millis(50)).is_some());
+    }
+}
@@ -0,0 +1,165 @@
+//! Helpers to make cache keys of arguments which aren't `Hash + Eq`, or whose order doesn't matter.
+//!
+//! Floats don't implement `Hash` or `Eq`, so a function taking an `f64` needs a `key` converting it,
+//! e.g. with [`canon_f64`], and a set-like argument (e.g. a `HashSet` or a `Vec` of tags) can be
+//! sorted with [`sorted`], so the same items make the same key in any order.
+//!
+//! [`digest`] and [`Verified`] make the keys of functions with the `key_digest` attribute.
+//!
+//! ```rust
+//! use kash::kash;
+//! use std::collections::HashSet;
+//!
+//! #[kash(key(ty = "(u64, Vec<String>)", expr = "(kash::key::canon_f64(x), kash::key::sorted(&tags))"))]
+//! fn score(x: f64, tags: HashSet<String>) -> f64 {
+//!     x * tags.len() as f64
+//! }
+//!
+//! let tags = HashSet::from(["a".to_string(), "b".to_string()]);
+//! assert_eq!(3.0, score(1.5, tags.clone()));
+//! assert!(SCORE.contains_key(&(kash::key::canon_f64(1.5), vec!["a".to_string(), "b".to_string()])));
+//! ```
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Return the bits of a float as a hashable key.
+///
+/// `-0.0` and `0.0` make the same key, since they're equal, and so do all the `NaN`s,
+/// which aren't equal to anything, but are the same argument for a cache.
+#[must_use]
+pub fn canon_f64(x: f64) -> u64 {
+    if x == 0.0 {
+        0.0f64.to_bits()
+    } else if x.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        x.to_bits()
+    }
+}
+
+/// Return the bits of a float as a hashable key, like [`canon_f64`]
+#[must_use]
+pub fn canon_f32(x: f32) -> u32 {
+    if x == 0.0 {
+        0.0f32.to_bits()
+    } else if x.is_nan() {
+        f32::NAN.to_bits()
+    } else {
+        x.to_bits()
+    }
+}
+
+/// Return the items sorted, so the key of a set-like argument doesn't depend on their order
+#[must_use]
+pub fn sorted<'a, T: Ord + Clone + 'a>(items: impl IntoIterator<Item = &'a T>) -> Vec<T> {
+    let mut items = items.into_iter().cloned().collect::<Vec<_>>();
+    items.sort();
+    items
+}
+
+/// Return a 64-bit digest of a value, the key of a function with `key_digest = "hash"`.
+///
+/// It's the `DefaultHasher` of the standard library, created with `DefaultHasher::new`, so the digest is the same
+/// for the same value in a program, but may change between Rust versions. Different values can have the same
+/// digest, with a probability of about `n² / 2^65` for `n` different values, e.g. about one in 37 million for a
+/// million of them, in which case a function returns the value cached for the other arguments.
+#[must_use]
+pub fn digest<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A key hashed with its [`digest`], computed once, and compared by value, the key of a function with
+/// `key_digest = "verify"`.
+///
+/// The cache only hashes the digest, but it stores the key, so values with the same digest are still different keys.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "mem_persist", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verified<K> {
+    digest: u64,
+    key: K,
+}
+
+impl<K: Hash> Verified<K> {
+    #[must_use]
+    pub fn new(key: K) -> Self {
+        Self {
+            digest: digest(&key),
+            key,
+        }
+    }
+}
+
+impl<K> Verified<K> {
+    /// Return the digest of the key
+    #[must_use]
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    /// Return the key
+    #[must_use]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K> Hash for Verified<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.digest);
+    }
+}
+
+impl<K: PartialEq> PartialEq for Verified<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.digest == other.digest && self.key == other.key
+    }
+}
+
+impl<K: Eq> Eq for Verified<K> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn equal_floats_make_the_same_key() {
+        assert_eq!(canon_f64(0.0), canon_f64(-0.0));
+        assert_eq!(canon_f64(f64::NAN), canon_f64(-f64::NAN));
+        assert_eq!(
+            canon_f64(f64::NAN),
+            canon_f64(f64::INFINITY - f64::INFINITY)
+        );
+        assert_ne!(canon_f64(1.0), canon_f64(-1.0));
+        assert_ne!(canon_f64(f64::INFINITY), canon_f64(f64::NAN));
+
+        assert_eq!(canon_f32(0.0), canon_f32(-0.0));
+        assert_eq!(canon_f32(f32::NAN), canon_f32(-f32::NAN));
+        assert_ne!(canon_f32(0.5), canon_f32(0.25));
+    }
+
+    #[test]
+    fn sorted_items_ignore_the_order() {
+        let set = HashSet::from([3, 1, 2]);
+        assert_eq!(vec![1, 2, 3], sorted(&set));
+        assert_eq!(sorted(&[2, 1, 3]), sorted(&vec![3, 2, 1]));
+    }
+
+    #[test]
+    fn verified_keys_compare_the_values() {
+        assert_eq!(digest(&[1, 2, 3][..]), digest(&vec![1, 2, 3]));
+        assert_eq!(Verified::new("a"), Verified::new("a"));
+        assert_ne!(Verified::new("a"), Verified::new("b"));
+
+        // a colliding digest doesn't make the keys equal
+        let collision = Verified {
+            digest: digest("a"),
+            key: "b",
+        };
+        assert_eq!(Verified::new("a").digest(), collision.digest());
+        assert_ne!(Verified::new("a"), collision);
+    }
+}
@@ -35,15 +35,27 @@ fn fib(n: u64) -> u64 {
 
 ## Features
 
-- `default`: Includes `ahash` feature.
+- `default`: Includes `mem_store` feature.
+- `mem_store`: Include memory caches, backed by `moka`. Disable the default features to only use `disk` or `redis`
+  caches without depending on `moka`.
+- `mem_persist`: Enable the `persist` attribute, to dump memory caches to a file and load them back, implies `mem_store`.
 - `ahash`: Enable `ahash` hasher as default hashing algorithm.
 - `async`: Include support for async functions.
+- `tokio`: Generate a `{fn}_prime_cache_blocking` function for `async` functions, to prime them from non-`async` code
+  running in a `tokio` runtime context, e.g. `tokio::task::spawn_blocking`. Implied by `redis_tokio`.
 - `redis_store`: Include Redis cache store.
 - `redis_tokio`: Include async Redis support using `tokio` and `tokio` tls support, implies `redis_store` and `async`.
 - `redis_connection_manager`: Enable the optional `connection-manager` feature of `redis`. Any async redis caches created
-                              will use a connection manager instead of a `MultiplexedConnection`.
+  will use a connection manager instead of a `MultiplexedConnection`.
+- `redis_bb8`: Any async redis caches created will use a `bb8` connection pool instead of a `MultiplexedConnection`,
+  implies `redis_tokio`. Takes precedence over `redis_connection_manager`.
 - `redis_ahash`: Enable the optional `ahash` feature of `redis`.
 - `disk_store`: Include disk cache store.
+- `disk_compression`: Enable the zstd compression of the `sled` database of disk caches, see the `use_compression`
+  attribute, implies `disk_store`.
+- `trace`: Enable the `trace` attribute, emitting `tracing` events on cache hits and misses.
+- `metrics`: Report cache hits, misses and evictions to a global recorder, see `kash::metrics`.
+- `registry`: Register the generated caches, so `kash::clear_all()` clears all of them, e.g. between tests.
 
 ----
 
@@ -68,6 +80,8 @@ fn keyed(a: &str, b: &str) -> usize {
 ----
 
 ```rust
+# #[cfg(feature = "redis_tokio")]
+# mod doctest {
 use kash::{kash, RedisCacheError};
 use kash::AsyncRedisCache;
 use thiserror::Error;
@@ -91,11 +105,14 @@ async fn async_kash_sleep_secs(secs: u64) -> Result<String, ExampleError> {
     std::thread::sleep(std::time::Duration::from_secs(secs));
     Ok(secs.to_string())
 }
+# }
 ```
 
 ----
 
 ```rust
+# #[cfg(feature = "disk_store")]
+# mod doctest {
 use kash::{kash, DiskCacheError};
 use kash::DiskCache;
 use thiserror::Error;
@@ -120,6 +137,7 @@ fn kash_sleep_secs(secs: u64) -> Result<String, ExampleError> {
     std::thread::sleep(std::time::Duration::from_secs(secs));
     Ok(secs.to_string())
 }
+# }
 ```
 
 Functions defined via macros will have their result, cached using the
@@ -133,20 +151,41 @@ See [`examples`](https://github.com/omid/kash/tree/master/examples) directory fo
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+#[cfg(feature = "mem_store")]
 #[doc(hidden)]
 pub use moka;
 #[doc(hidden)]
 pub use once_cell;
+#[cfg(feature = "trace")]
+#[doc(hidden)]
+pub use tracing;
 
+/// The attribute to implement [`IOKashAsync`] with
 #[cfg(feature = "async")]
-use async_trait::async_trait;
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub use async_trait::async_trait;
 
 #[doc(inline)]
 pub use kash_macros::kash;
-
+#[doc(inline)]
+pub use kash_macros::kash_impl;
+
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub use metrics::set_metrics_recorder;
+#[cfg(feature = "registry")]
+#[cfg_attr(docsrs, doc(cfg(feature = "registry")))]
+pub use registry::clear_all;
+pub use stats::CacheStats;
 #[cfg(feature = "redis_tokio")]
 #[cfg_attr(docsrs, doc(cfg(feature = "redis_tokio")))]
 pub use stores::AsyncRedisCache;
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub use stores::MemoryCache;
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub use stores::SlotCache;
 #[cfg(feature = "disk_store")]
 #[cfg_attr(docsrs, doc(cfg(feature = "disk_store")))]
 pub use stores::{DiskCache, DiskCacheError};
@@ -154,13 +193,67 @@ pub use stores::{DiskCache, DiskCacheError};
 #[cfg_attr(docsrs, doc(cfg(feature = "redis_store")))]
 pub use stores::{RedisCache, RedisCacheError};
 
+#[cfg(feature = "mem_store")]
+mod cached;
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub use cached::Cached;
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub mod defaults;
+#[cfg(feature = "mem_store")]
+#[doc(hidden)]
+pub mod expiry;
+#[doc(hidden)]
+pub mod flight;
+pub mod key;
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
+#[cfg(feature = "mem_persist")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_persist")))]
+pub mod persist;
+#[cfg(feature = "mem_store")]
+#[doc(hidden)]
+pub mod refresh;
+#[cfg(feature = "registry")]
+#[cfg_attr(docsrs, doc(cfg(feature = "registry")))]
+pub mod registry;
+pub mod stats;
 pub mod stores;
+#[cfg(feature = "mem_store")]
+#[doc(hidden)]
+pub mod type_map;
 #[doc(hidden)]
 pub use instant;
+#[cfg(feature = "registry")]
+#[doc(hidden)]
+pub use linkme;
+
+/// Fails to compile if `K` can't be the key of a memory cache, with an error naming the missing bound
+#[cfg(feature = "mem_store")]
+#[doc(hidden)]
+pub const fn assert_mem_key<K: std::hash::Hash + Eq + Clone + Send + Sync + 'static>() {}
+
+/// The value of a memory cache, whose type is `<V as MemValue>::Value`, i.e., `V`. It fails to compile if `V`
+/// can't be the value of a memory cache, e.g. a `Box<dyn Trait>` which isn't `Clone` or a trait object which
+/// isn't `Send + Sync`, with an error naming the missing bound, which isn't repeated by every use of the cache
+#[cfg(feature = "mem_store")]
+#[doc(hidden)]
+pub trait MemValue {
+    type Value;
+}
+
+#[cfg(feature = "mem_store")]
+impl<V: Clone + Send + Sync + 'static> MemValue for V {
+    type Value = V;
+}
 
 #[cfg(feature = "tokio")]
 #[doc(hidden)]
 pub mod async_sync {
+    pub use tokio::runtime::Handle;
+    pub use tokio::spawn;
     pub use tokio::sync::Mutex;
     pub use tokio::sync::OnceCell;
     pub use tokio::sync::RwLock;
@@ -210,6 +303,44 @@ pub trait IOKash<K, V> {
     }
 }
 
+/// Cache operations on an io-connected store, for `async` code
+///
+/// It's object safe, so stores with the same key, value and error types can be used behind a
+/// `Box<dyn IOKashAsync<K, V, Error = E>>`, e.g. to choose between a memory and a redis store at runtime.
+/// Implement it with [`async_trait`].
+///
+/// ```rust
+/// use kash::{async_trait, IOKashAsync};
+/// use std::collections::HashMap;
+/// use std::sync::Mutex;
+///
+/// #[derive(Default)]
+/// struct Memory(Mutex<HashMap<String, u32>>);
+///
+/// #[async_trait]
+/// impl IOKashAsync<String, u32> for Memory {
+///     type Error = std::convert::Infallible;
+///
+///     async fn get(&self, k: &String) -> Result<Option<u32>, Self::Error> {
+///         Ok(self.0.lock().unwrap().get(k).copied())
+///     }
+///
+///     async fn set(&self, k: String, v: u32) -> Result<Option<u32>, Self::Error> {
+///         Ok(self.0.lock().unwrap().insert(k, v))
+///     }
+///
+///     async fn remove(&self, k: &String) -> Result<Option<u32>, Self::Error> {
+///         Ok(self.0.lock().unwrap().remove(k))
+///     }
+/// }
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let store: Box<dyn IOKashAsync<String, u32, Error = std::convert::Infallible> + Send + Sync> =
+///     Box::new(Memory::default());
+/// store.set("a".to_string(), 1).await.unwrap();
+/// assert_eq!(Some(1), store.get(&"a".to_string()).await.unwrap());
+/// # });
+/// ```
 #[cfg(feature = "async")]
 #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
 #[async_trait]
@@ -240,3 +371,93 @@ pub trait IOKashAsync<K, V> {
         None
     }
 }
+
+/// A handle to the cache of a `#[kash]` function, returned by the generated `{fn}_cache()` function
+/// of non-`async` functions.
+///
+/// It works the same, whether the cache is in memory, on disk or in redis.
+/// `K` and `V` are the key and value types of the cache, and `E` is the error of the store, which is
+/// [`std::convert::Infallible`] for memory caches. `S` is the key as it's stored, returned by
+/// [`KashHandle::iter`], which is `Arc<K>` for memory caches and the string of the key for `disk` and `redis`.
+pub struct KashHandle<K: 'static, V: 'static, E: 'static, S: 'static> {
+    len: fn() -> Result<usize, E>,
+    clear: fn() -> Result<(), E>,
+    remove: fn(&K) -> Result<Option<V>, E>,
+    contains: fn(&K) -> Result<bool, E>,
+    iter: IterFn<S, V, E>,
+}
+
+type IterFn<S, V, E> = fn() -> Result<Vec<(S, V)>, E>;
+
+impl<K, V, E, S> KashHandle<K, V, E, S> {
+    #[doc(hidden)]
+    pub const fn new(
+        len: fn() -> Result<usize, E>,
+        clear: fn() -> Result<(), E>,
+        remove: fn(&K) -> Result<Option<V>, E>,
+        contains: fn(&K) -> Result<bool, E>,
+        iter: IterFn<S, V, E>,
+    ) -> Self {
+        Self {
+            len,
+            clear,
+            remove,
+            contains,
+            iter,
+        }
+    }
+
+    /// Return the number of cached entries
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn len(&self) -> Result<usize, E> {
+        (self.len)()
+    }
+
+    /// Return whether there is no cached entry
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn is_empty(&self) -> Result<bool, E> {
+        Ok((self.len)()? == 0)
+    }
+
+    /// Remove all the cached entries
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn clear(&self) -> Result<(), E> {
+        (self.clear)()
+    }
+
+    /// Remove a cached entry and return its value
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn remove(&self, key: &K) -> Result<Option<V>, E> {
+        (self.remove)(key)
+    }
+
+    /// Return whether a key is cached
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn contains(&self, key: &K) -> Result<bool, E> {
+        (self.contains)(key)
+    }
+
+    /// Return all the cached entries
+    ///
+    /// # Errors
+    ///
+    /// Will return `E` if the store fails
+    pub fn iter(&self) -> Result<Vec<(S, V)>, E> {
+        (self.iter)()
+    }
+}
@@ -0,0 +1,71 @@
+//! Push-based metrics hooks for `#[kash]` generated caches.
+//!
+//! Install a [`Recorder`] once at startup with [`set_metrics_recorder`] and every generated function
+//! reports its hits, misses and evictions to it, labeled by the cache name (e.g. `FIB`).
+//! Until a recorder is set, a no-op recorder is used.
+//!
+//! ```rust
+//! use kash::metrics::{set_metrics_recorder, Recorder};
+//!
+//! struct Stdout;
+//!
+//! impl Recorder for Stdout {
+//!     fn record_hit(&self, cache_name: &'static str) {
+//!         println!("{cache_name} hit");
+//!     }
+//!
+//!     fn record_miss(&self, cache_name: &'static str) {
+//!         println!("{cache_name} miss");
+//!     }
+//! }
+//!
+//! set_metrics_recorder(Stdout).unwrap();
+//! ```
+
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+/// Receives cache events from generated functions
+pub trait Recorder: Send + Sync {
+    /// Called when a value is returned from the cache
+    fn record_hit(&self, cache_name: &'static str);
+
+    /// Called when a value is not found in the cache and the function is evaluated
+    fn record_miss(&self, cache_name: &'static str);
+
+    /// Called when a memory cache evicts an entry because of its size or ttl
+    fn record_evict(&self, _cache_name: &'static str) {}
+}
+
+struct NoopRecorder;
+
+impl Recorder for NoopRecorder {
+    fn record_hit(&self, _cache_name: &'static str) {}
+
+    fn record_miss(&self, _cache_name: &'static str) {}
+}
+
+static RECORDER: OnceCell<Box<dyn Recorder>> = OnceCell::new();
+
+#[derive(Error, Debug)]
+#[error("a metrics recorder is already set")]
+pub struct SetRecorderError;
+
+/// Set the global metrics recorder. It can only be set once.
+///
+/// # Errors
+///
+/// Will return `SetRecorderError` if a recorder is already set
+pub fn set_metrics_recorder<R: Recorder + 'static>(recorder: R) -> Result<(), SetRecorderError> {
+    RECORDER
+        .set(Box::new(recorder))
+        .map_err(|_| SetRecorderError)
+}
+
+/// Return the global metrics recorder, or a no-op one if it's not set
+pub fn recorder() -> &'static dyn Recorder {
+    match RECORDER.get() {
+        Some(recorder) => recorder.as_ref(),
+        None => &NoopRecorder,
+    }
+}
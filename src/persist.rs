@@ -0,0 +1,130 @@
+//! Dump memory caches to a file and load them back, e.g. to warm a cache on startup.
+//!
+//! Functions with the `persist` attribute generate `{fn}_dump_cache(path)` and `{fn}_load_cache(path)`,
+//! which use [`dump_cache`] and [`load_cache`] with the entries of their cache.
+//!
+//! ```rust
+//! use kash::kash;
+//!
+//! #[kash(persist)]
+//! fn square(n: u64) -> u64 {
+//!     n * n
+//! }
+//!
+//! let path = std::env::temp_dir().join("kash-doc-square.cache");
+//! square(3);
+//! assert_eq!(1, square_dump_cache(&path).unwrap());
+//!
+//! SQUARE.invalidate_all();
+//! assert_eq!(1, square_load_cache(&path).unwrap());
+//! assert!(SQUARE.contains_key(&3));
+//! ```
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PersistError {
+    #[error("Error accessing the cache file")]
+    IoError(#[from] std::io::Error),
+    #[error("Error deserializing cached entries")]
+    CacheDeserializationError(#[from] rmp_serde::decode::Error),
+    #[error("Error serializing cached entries")]
+    CacheSerializationError(#[from] rmp_serde::encode::Error),
+}
+
+/// Write the entries to the file at `path`, replacing it, and return the number of written entries.
+///
+/// The entries are written to a temporary file next to `path` first, so a failed dump doesn't leave
+/// a truncated file behind.
+///
+/// # Errors
+///
+/// Will return a `PersistError` if the file can't be written, or an entry can't be serialized
+pub fn dump_cache<K, V>(
+    entries: impl IntoIterator<Item = (Arc<K>, V)>,
+    path: impl AsRef<Path>,
+) -> Result<usize, PersistError>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    let path = path.as_ref();
+    let entries = entries.into_iter().collect::<Vec<_>>();
+    let entries_ref = entries
+        .iter()
+        .map(|(key, value)| (key.as_ref(), value))
+        .collect::<Vec<_>>();
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    rmp_serde::encode::write(&mut writer, &entries_ref)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)?;
+
+    Ok(entries.len())
+}
+
+/// Read the entries written by [`dump_cache`] from the file at `path`.
+///
+/// # Errors
+///
+/// Will return a `PersistError` if the file can't be read, or its entries can't be deserialized,
+/// e.g. because the key or value type is changed
+pub fn load_cache<K, V>(path: impl AsRef<Path>) -> Result<Vec<(K, V)>, PersistError>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let reader = BufReader::new(File::open(path)?);
+    Ok(rmp_serde::decode::from_read(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entries");
+        let entries = vec![
+            (Arc::new(1u32), "one".to_string()),
+            (Arc::new(2), "two".to_string()),
+        ];
+
+        assert_eq!(2, dump_cache(entries, &path).unwrap());
+        let mut loaded = load_cache::<u32, String>(&path).unwrap();
+        loaded.sort();
+        assert_eq!(vec![(1, "one".to_string()), (2, "two".to_string())], loaded);
+
+        // the file is replaced
+        assert_eq!(
+            0,
+            dump_cache(Vec::<(Arc<u32>, String)>::new(), &path).unwrap()
+        );
+        assert!(load_cache::<u32, String>(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entries");
+        assert!(matches!(
+            load_cache::<u32, String>(&path),
+            Err(PersistError::IoError(_))
+        ));
+
+        dump_cache(vec![(Arc::new(1u32), 1u32)], &path).unwrap();
+        assert!(matches!(
+            load_cache::<u32, String>(&path),
+            Err(PersistError::CacheDeserializationError(_))
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+//! Refresh-ahead, for the memory caches with the `refresh_ahead` attribute.
+//!
+//! It keeps when the value of each key was computed, so a hit on a value about to expire starts
+//! recomputing it in the background, while the callers keep getting the cached value. Only one refresh of
+//! a key runs at a time, and a refresh which panics is retried by the next hit.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// When the values of a cache were computed, and the keys being refreshed
+pub struct RefreshAhead<K> {
+    computed_at: moka::sync::Cache<K, Instant>,
+    refreshing: Mutex<HashSet<K>>,
+    // the age from which a value is refreshed
+    window: Duration,
+}
+
+impl<K: Hash + Eq + Clone + Send + Sync + 'static> RefreshAhead<K> {
+    /// Refresh the values during the last `fraction` of their `ttl`, e.g. the last quarter with `0.25`
+    #[must_use]
+    pub fn new(ttl: Duration, fraction: f64) -> Self {
+        Self {
+            computed_at: moka::sync::Cache::builder().time_to_live(ttl).build(),
+            refreshing: Mutex::new(HashSet::new()),
+            window: ttl.mul_f64(1.0 - fraction.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// Record that the value of the key is computed now
+    pub fn computed(&self, key: K) {
+        self.computed_at.insert(key, Instant::now());
+    }
+
+    /// Return whether the value of the key is in the refresh window, without marking it as being refreshed,
+    /// e.g. to only clone the arguments of the function if it may be refreshed
+    pub fn is_due(&self, key: &K) -> bool {
+        self.computed_at
+            .get(key)
+            .is_some_and(|computed_at| computed_at.elapsed() >= self.window)
+    }
+
+    /// Return a guard if the value of the key should be refreshed, i.e., it's in the refresh window
+    /// and isn't being refreshed, in which case it's marked as being refreshed until the guard is
+    /// finished or dropped, e.g. if the refresh panics
+    pub fn start(&self, key: &K) -> Option<Refreshing<'_, K>> {
+        if !self.is_due(key) {
+            return None;
+        }
+        let started = self
+            .refreshing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.clone());
+        started.then(|| Refreshing {
+            refresh: self,
+            key: key.clone(),
+        })
+    }
+}
+
+/// The refresh of a key started by [`RefreshAhead::start`], which is unmarked when it's dropped
+pub struct Refreshing<'a, K: Hash + Eq + Clone + Send + Sync + 'static> {
+    refresh: &'a RefreshAhead<K>,
+    key: K,
+}
+
+impl<K: Hash + Eq + Clone + Send + Sync + 'static> Refreshing<'_, K> {
+    /// Finish the refresh, recording the new value if it's `refreshed`, otherwise the next hit retries it
+    pub fn finish(self, refreshed: bool) {
+        if refreshed {
+            self.refresh.computed(self.key.clone());
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + Send + Sync + 'static> Drop for Refreshing<'_, K> {
+    fn drop(&mut self) {
+        self.refresh
+            .refreshing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_refreshed_once_in_the_window() {
+        let refresh = RefreshAhead::new(Duration::from_millis(200), 0.5);
+        assert!(refresh.start(&1).is_none(), "unknown keys aren't refreshed");
+
+        refresh.computed(1);
+        assert!(!refresh.is_due(&1));
+        assert!(refresh.start(&1).is_none());
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(refresh.is_due(&1));
+        let refreshing = refresh.start(&1).unwrap();
+        assert!(
+            refresh.start(&1).is_none(),
+            "a key is refreshed once at a time"
+        );
+
+        refreshing.finish(false);
+        let refreshing = refresh.start(&1).expect("a failed refresh is retried");
+        refreshing.finish(true);
+        assert!(refresh.start(&1).is_none());
+    }
+
+    #[test]
+    fn panicking_refreshes_are_retried() {
+        let refresh = RefreshAhead::new(Duration::from_millis(100), 1.0);
+        refresh.computed(1);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _refreshing = refresh.start(&1).unwrap();
+            panic!("the refresh failed");
+        }));
+        assert!(panicked.is_err());
+        assert!(refresh.start(&1).is_some(), "the key isn't left refreshing");
+    }
+}
@@ -0,0 +1,35 @@
+//! A registry of the generated caches, to clear all of them at once, e.g. between the tests of a binary
+//! sharing the caches of a process.
+//!
+//! With the `registry` feature, each cache generated by `#[kash]` registers itself at link time, so
+//! [`clear_all`] clears the memory caches, and the `disk` and `redis` caches which are initialized.
+//! The caches of `async` `redis` functions and of `shared_cache` aren't registered, since clearing them
+//! needs a runtime or belongs to their owner.
+//!
+//! ```rust
+//! use kash::kash;
+//!
+//! #[kash]
+//! fn double(n: u32) -> u32 {
+//!     n * 2
+//! }
+//!
+//! double(1);
+//! kash::clear_all();
+//! DOUBLE.run_pending_tasks();
+//! assert_eq!(0, DOUBLE.entry_count());
+//! ```
+
+#[doc(hidden)]
+#[linkme::distributed_slice]
+pub static CACHES: [fn()];
+
+/// Clear all the registered caches.
+///
+/// The errors of clearing `disk` and `redis` caches are ignored, so a cache which can't be
+/// reached doesn't keep the others from being cleared.
+pub fn clear_all() {
+    for clear in CACHES {
+        clear();
+    }
+}
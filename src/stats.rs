@@ -0,0 +1,103 @@
+//! Hit and miss counters of a cache, for the `disk` caches with the `stats` attribute.
+//!
+//! A function with `stats` generates a `{fn}_cache_stats()` function returning its counters:
+//!
+//! ```rust
+//! # #[cfg(feature = "disk_store")]
+//! # fn main() -> Result<(), kash::DiskCacheError> {
+//! use kash::kash;
+//!
+//! #[kash(disk, stats)]
+//! fn lookup(n: u32) -> Result<u32, kash::DiskCacheError> {
+//!     Ok(n * 2)
+//! }
+//!
+//! # use kash::IOKash;
+//! # LOOKUP.remove(&1)?;
+//! lookup(1)?;
+//! lookup(1)?;
+//! assert_eq!(1, lookup_cache_stats().hits());
+//! assert_eq!(Some(0.5), lookup_cache_stats().hit_rate());
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "disk_store"))]
+//! # fn main() {}
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The numbers of hits and misses of a cache since it's created or reset
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a value returned from the cache
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a value not found in the cache, for which the function is evaluated
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Return the number of hits
+    #[must_use]
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of misses
+    #[must_use]
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Return the ratio of the hits to all the lookups, or `None` before the first lookup
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_rate(&self) -> Option<f64> {
+        let hits = self.hits();
+        let lookups = hits + self.misses();
+        (lookups > 0).then(|| hits as f64 / lookups as f64)
+    }
+
+    /// Set both counters to zero
+    pub fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let stats = CacheStats::new();
+        assert_eq!(None, stats.hit_rate());
+
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        assert_eq!(3, stats.hits());
+        assert_eq!(1, stats.misses());
+        assert_eq!(Some(0.75), stats.hit_rate());
+
+        stats.reset();
+        assert_eq!((0, 0), (stats.hits(), stats.misses()));
+    }
+}
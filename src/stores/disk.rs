@@ -1,20 +1,35 @@
+use crate::stores::ttl_secs;
 use crate::IOKash;
 use directories::BaseDirs;
 use instant::Duration;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use sled::Db;
+use sled::{Db, Tree};
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
 use std::{path::PathBuf, time::SystemTime};
 use thiserror::Error;
 
 pub struct DiskCacheBuilder<K, V> {
-    seconds: Option<u64>,
+    ttl: Option<Duration>,
     sync_to_disk_on_cache_change: bool,
     dir: Option<PathBuf>,
     cache_name: String,
     connection_config: Option<sled::Config>,
+    shared_db: Option<Db>,
+    schema_version: u64,
+    lock_retries: u32,
+    lock_backoff: Duration,
+    background_flush: Option<Duration>,
+    flush_on_exit: bool,
+    read_only: bool,
+    #[cfg(feature = "disk_compression")]
+    use_compression: Option<bool>,
+    #[cfg(feature = "disk_compression")]
+    compression_factor: Option<i32>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -22,6 +37,8 @@ pub struct DiskCacheBuilder<K, V> {
 pub enum DiskCacheBuildError {
     #[error("Storage connection error")]
     ConnectionError(#[from] sled::Error),
+    #[error("Disk cache {path:?} is locked by another process")]
+    Locked { path: PathBuf },
     #[error("Connection string not specified or invalid in env var {env_key:?}: {error:?}")]
     MissingPath {
         env_key: String,
@@ -31,6 +48,8 @@ pub enum DiskCacheBuildError {
 
 static DISK_FILE_PREFIX: &str = "kash_disk_cache";
 const DISK_FILE_VERSION: u64 = 1;
+// the number of records a `DiskCache::sweep` visits at a time
+const DISK_SWEEP_BATCH: usize = 1000;
 
 impl<K, V> DiskCacheBuilder<K, V>
 where
@@ -40,11 +59,22 @@ where
     /// Initialize a `DiskCacheBuilder`
     pub fn new<S: ToString>(cache_name: S) -> Self {
         Self {
-            seconds: None,
+            ttl: None,
             sync_to_disk_on_cache_change: false,
             dir: None,
             cache_name: cache_name.to_string(),
             connection_config: None,
+            shared_db: None,
+            schema_version: 0,
+            lock_retries: 0,
+            lock_backoff: Duration::from_millis(50),
+            background_flush: None,
+            flush_on_exit: false,
+            read_only: false,
+            #[cfg(feature = "disk_compression")]
+            use_compression: None,
+            #[cfg(feature = "disk_compression")]
+            compression_factor: None,
             _phantom: Default::default(),
         }
     }
@@ -52,7 +82,14 @@ where
     /// Specify the cache ttl in seconds
     #[must_use]
     pub fn set_ttl(mut self, seconds: u64) -> Self {
-        self.seconds = Some(seconds);
+        self.ttl = Some(Duration::from_secs(seconds));
+        self
+    }
+
+    /// Specify the cache ttl as a `Duration`, e.g. for a ttl shorter than a second
+    #[must_use]
+    pub fn set_ttl_duration(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
         self
     }
 
@@ -74,6 +111,30 @@ where
         self
     }
 
+    /// Flush the changes to disk every `interval` in a background thread owned by the cache, instead of on each
+    /// change like [`DiskCacheBuilder::set_sync_to_disk_on_cache_change`], so the changes in between are flushed
+    /// together, and at most the changes of one `interval` are lost on a crash.
+    ///
+    /// The thread is stopped when the cache is dropped, which flushes once more. It's besides the periodic flush
+    /// of [sled] itself, which can be turned off with [`sled::Config::flush_every_ms`].
+    #[must_use]
+    pub fn set_background_flush(mut self, interval: Duration) -> Self {
+        self.background_flush = Some(interval);
+        self
+    }
+
+    /// Register the cache to be flushed by [`flush_disk_caches`], e.g. when a [`DiskFlushGuard`] held by `main`
+    /// is dropped, until the cache is dropped.
+    ///
+    /// [sled] flushes a database when it's dropped, but a cache in a `static`, like the one of `#[kash(disk)]`,
+    /// is never dropped, so the changes since the last periodic flush are lost when the process exits.
+    /// A read-only cache (see [`DiskCacheBuilder::set_read_only`]) isn't registered.
+    #[must_use]
+    pub fn set_flush_on_exit(mut self, flush_on_exit: bool) -> Self {
+        self.flush_on_exit = flush_on_exit;
+        self
+    }
+
     /// Specify the [`sled::Config`] to use for the connection to the disk cache.
     ///
     /// ### Note
@@ -101,6 +162,89 @@ where
         self
     }
 
+    /// Specify whether [sled] compresses the database with zstd, see [`sled::Config::use_compression`].
+    ///
+    /// It overrides the value of [`DiskCacheBuilder::set_connection_config`]. A database can't be opened with another
+    /// setting than it was created with, so changing it needs a new `cache_name` or directory.
+    #[cfg(feature = "disk_compression")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "disk_compression")))]
+    #[must_use]
+    pub fn set_use_compression(mut self, use_compression: bool) -> Self {
+        self.use_compression = Some(use_compression);
+        self
+    }
+
+    /// Specify the zstd compression level of [`DiskCacheBuilder::set_use_compression`], from 1 up to 22,
+    /// see [`sled::Config::compression_factor`]. Defaults to 5.
+    ///
+    /// It overrides the value of [`DiskCacheBuilder::set_connection_config`], and a level out of the range
+    /// fails the `build` with a [`DiskCacheBuildError::ConnectionError`].
+    #[cfg(feature = "disk_compression")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "disk_compression")))]
+    #[must_use]
+    pub fn set_compression_factor(mut self, compression_factor: i32) -> Self {
+        self.compression_factor = Some(compression_factor);
+        self
+    }
+
+    /// Store the records in a tree of an open database, named after the `cache_name`, instead of opening a
+    /// database of the cache, so the caches of many functions share one [sled] database, with its file and
+    /// its flush thread. The trees scope the keys of each cache, like the prefix of a Redis cache.
+    ///
+    /// The directory, connection config and compression of the builder aren't used, they're the ones of `db`.
+    /// ```rust
+    /// use kash::stores::DiskCache;
+    /// use kash::IOKash;
+    ///
+    /// let db = sled::Config::new().temporary(true).open().unwrap();
+    /// let users: DiskCache<u32, String> = DiskCache::new("users").set_shared_db(&db).build().unwrap();
+    /// let orders: DiskCache<u32, String> = DiskCache::new("orders").set_shared_db(&db).build().unwrap();
+    /// users.set(1, "user".to_string()).unwrap();
+    ///
+    /// assert_eq!(None, orders.get(&1).unwrap());
+    /// assert_eq!(1, users.tree().len());
+    /// ```
+    #[must_use]
+    pub fn set_shared_db(mut self, db: &Db) -> Self {
+        self.shared_db = Some(db.clone());
+        self
+    }
+
+    /// Set the version of the schema of the values, stored with each record, so the records of another
+    /// version are treated as absent (and removed by [`DiskCache::remove_expired_entries`]).
+    /// Bump it when the layout of the value type changes, so the old records aren't deserialized as the new
+    /// type. Defaults to `0`, the version of the records written before it was stored.
+    #[must_use]
+    pub fn set_schema_version(mut self, schema_version: u64) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+
+    /// Open the cache read-only, so [`IOKash::set`], [`IOKash::remove`] and the other methods writing to it
+    /// return [`DiskCacheError::ReadOnly`], e.g. for a tool inspecting a cache copied from production.
+    ///
+    /// sled can't open a database without locking it (see [`DiskCache`]), so the cache directory is copied
+    /// to a temporary directory, which is opened instead and removed when the cache is dropped. So it can be
+    /// opened while another process holds the cache, and it reads the values which were flushed to disk
+    /// when it's built. With [`DiskCacheBuilder::set_shared_db`], the database is used as is.
+    #[must_use]
+    pub fn set_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Retry opening the cache when it's locked by another process, instead of failing right away
+    /// with [`DiskCacheBuildError::Locked`]. The backoff is doubled after each retry.
+    ///
+    /// A disk cache can only be opened by a single process at a time (see [`DiskCache`]),
+    /// so this is useful for e.g. a CLI which may run concurrently and only holds the cache briefly.
+    #[must_use]
+    pub fn set_lock_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.lock_retries = retries;
+        self.lock_backoff = backoff;
+        self
+    }
+
     fn default_disk_dir() -> PathBuf {
         BaseDirs::new().map_or_else(
             || std::env::current_dir().expect("disk cache unable to determine current directory"),
@@ -118,34 +262,268 @@ where
         )
     }
 
-    pub fn build(self) -> Result<DiskCache<K, V>, DiskCacheBuildError> {
-        let dir = self.dir.unwrap_or_else(|| Self::default_disk_dir());
+    /// The last step in building a `DiskCache` is to call `build()`
+    ///
+    /// # Errors
+    ///
+    /// Will return `DiskCacheBuildError::Locked` if the cache is opened by another process
+    /// (after the retries of [`DiskCacheBuilder::set_lock_retries`]), or another `DiskCacheBuildError`
+    /// depending on the error
+    pub fn build(mut self) -> Result<DiskCache<K, V>, DiskCacheBuildError> {
+        if let Some(connection) = self.shared_db.take() {
+            let tree = connection.open_tree(&self.cache_name)?;
+            return Ok(self.build_with(connection, tree, PathBuf::new()));
+        }
+
+        let dir = self.dir.take().unwrap_or_else(|| Self::default_disk_dir());
         let path = dir.join(format!("{}_v{}", self.cache_name, DISK_FILE_VERSION));
-        let connection = match self.connection_config {
-            Some(config) => config.path(path.clone()).open()?,
-            None => sled::open(path.clone())?,
+        let config = self.connection_config.take().unwrap_or_default();
+        let config = if self.read_only {
+            static SNAPSHOTS: AtomicU64 = AtomicU64::new(0);
+            let snapshot = std::env::temp_dir().join(format!(
+                "{}_v{}_read_only_{}_{}",
+                self.cache_name,
+                DISK_FILE_VERSION,
+                std::process::id(),
+                SNAPSHOTS.fetch_add(1, Ordering::Relaxed)
+            ));
+            if let Err(error) = copy_dir(&path, &snapshot) {
+                // a partial copy isn't a database yet, so sled wouldn't remove it
+                let _ = std::fs::remove_dir_all(&snapshot);
+                return Err(sled::Error::from(error).into());
+            }
+            // the copy is removed when the database is dropped
+            config.path(snapshot).temporary(true)
+        } else {
+            config.path(path.clone())
+        };
+        #[cfg(feature = "disk_compression")]
+        let config = match self.use_compression {
+            Some(use_compression) => config.use_compression(use_compression),
+            None => config,
+        };
+        #[cfg(feature = "disk_compression")]
+        let config = match self.compression_factor {
+            Some(compression_factor) => config.compression_factor(compression_factor),
+            None => config,
         };
 
-        Ok(DiskCache {
-            seconds: self.seconds,
+        let mut backoff = self.lock_backoff;
+        let mut retries = self.lock_retries;
+        let connection = loop {
+            match config.open() {
+                Ok(connection) => break connection,
+                // the snapshot of a read-only cache is its own, so it's never locked
+                Err(e) if !self.read_only && is_locked(&e, &path) => {
+                    if retries == 0 {
+                        return Err(DiskCacheBuildError::Locked { path });
+                    }
+                    retries -= 1;
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        // the records of a database of its own are in its default tree
+        let tree = Tree::clone(&connection);
+        Ok(self.build_with(connection, tree, path))
+    }
+
+    fn build_with(self, connection: Db, tree: Tree, path: PathBuf) -> DiskCache<K, V> {
+        let background_flush = self
+            .background_flush
+            .map(|interval| BackgroundFlush::spawn(connection.clone(), interval));
+
+        let flush_on_exit = (self.flush_on_exit && !self.read_only)
+            .then(|| FlushOnExit::register(connection.clone()));
+
+        DiskCache {
+            ttl: self.ttl,
             sync_to_disk_on_cache_change: self.sync_to_disk_on_cache_change,
             version: DISK_FILE_VERSION,
+            schema_version: self.schema_version,
+            read_only: self.read_only,
             path,
             connection,
+            tree,
+            _background_flush: background_flush,
+            _flush_on_exit: flush_on_exit,
             _phantom: self._phantom,
-        })
+        }
+    }
+}
+
+// a thread flushing the database every interval, until it's dropped
+struct BackgroundFlush {
+    connection: Db,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundFlush {
+    fn spawn(connection: Db, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread_connection = connection.clone();
+        let thread = std::thread::spawn(move || {
+            // the sender is only dropped, which disconnects the channel
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // a failed flush is retried on the next interval
+                let _ = thread_connection.flush();
+            }
+        });
+        Self {
+            connection,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
     }
 }
 
+impl Drop for BackgroundFlush {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = self.connection.flush();
+    }
+}
+
+// the databases registered by `set_flush_on_exit`, with the ids of their registrations
+static FLUSH_ON_EXIT: Mutex<Vec<(u64, Db)>> = Mutex::new(Vec::new());
+static FLUSH_ON_EXIT_ID: AtomicU64 = AtomicU64::new(0);
+
+// the registration of a database in `FLUSH_ON_EXIT`, removed when it's dropped, so the database isn't kept open
+struct FlushOnExit {
+    id: u64,
+}
+
+impl FlushOnExit {
+    fn register(connection: Db) -> Self {
+        let id = FLUSH_ON_EXIT_ID.fetch_add(1, Ordering::Relaxed);
+        lock_flush_on_exit().push((id, connection));
+        Self { id }
+    }
+}
+
+impl Drop for FlushOnExit {
+    fn drop(&mut self) {
+        lock_flush_on_exit().retain(|(id, _)| *id != self.id);
+    }
+}
+
+// a panic while the registrations are locked doesn't corrupt them
+fn lock_flush_on_exit() -> std::sync::MutexGuard<'static, Vec<(u64, Db)>> {
+    FLUSH_ON_EXIT
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Flush the disk caches built with [`DiskCacheBuilder::set_flush_on_exit`], e.g. in a shutdown hook or the handler
+/// of a signal, before the process exits. All the caches are flushed, and the first error is returned.
+///
+/// See [`DiskFlushGuard`] to flush them when `main` returns.
+pub fn flush_disk_caches() -> Result<(), DiskCacheError> {
+    let connections: Vec<Db> = lock_flush_on_exit()
+        .iter()
+        .map(|(_, connection)| connection.clone())
+        .collect();
+    let mut result = Ok(());
+    for connection in connections {
+        if let (Err(e), Ok(())) = (connection.flush(), &result) {
+            result = Err(e.into());
+        }
+    }
+    result
+}
+
+/// Flushes the disk caches built with [`DiskCacheBuilder::set_flush_on_exit`] when it's dropped, see
+/// [`flush_disk_caches`].
+///
+/// The caches of `#[kash(disk)]` are in `static`s, which are never dropped, so hold a guard in `main` to flush
+/// them when it returns. [`std::process::exit`] and the signals terminating the process skip the destructors,
+/// so they don't flush, call [`flush_disk_caches`] before them instead.
+/// ```rust
+/// // at the start of `main`
+/// let _flush = kash::stores::DiskFlushGuard::new();
+/// // ... use the disk caches, flushed when `_flush` is dropped at the end of `main`
+/// ```
+#[must_use = "the caches are flushed when the guard is dropped"]
+#[derive(Debug, Default)]
+pub struct DiskFlushGuard {
+    _private: (),
+}
+
+impl DiskFlushGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Drop for DiskFlushGuard {
+    fn drop(&mut self) {
+        // there's no one to report the error to
+        let _ = flush_disk_caches();
+    }
+}
+
+// copy the files of a database, which is opened read-only from the copy
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else {
+            std::fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
+// sled reports a lock held by another process as an IO error of kind `Other`, with the error of the lock only
+// in its message, so the lock of the data file of the database at `path` is tried again to check its error
+fn is_locked(e: &sled::Error, path: &Path) -> bool {
+    let sled::Error::Io(e) = e else {
+        return false;
+    };
+    if e.kind() != std::io::ErrorKind::Other {
+        return false;
+    }
+    let Ok(file) = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("db"))
+    else {
+        return false;
+    };
+    // the lock is released when the file is closed, if it's acquired
+    fs2::FileExt::try_lock_exclusive(&file).is_err_and(|e| {
+        e.raw_os_error().is_some() && e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+    })
+}
+
 /// Cache store backed by disk
+///
+/// The underlying [sled] database takes an exclusive lock on its directory, so a cache can only
+/// be opened by a single process at a time. Opening it from another process fails with
+/// [`DiskCacheBuildError::Locked`], see [`DiskCacheBuilder::set_lock_retries`] to wait for it instead.
 pub struct DiskCache<K, V> {
-    pub(super) seconds: Option<u64>,
+    pub(super) ttl: Option<Duration>,
     sync_to_disk_on_cache_change: bool,
-    #[allow(unused)]
     version: u64,
+    schema_version: u64,
+    read_only: bool,
+    // empty for a shared database
     #[allow(unused)]
     path: PathBuf,
     connection: Db,
+    tree: Tree,
+    _background_flush: Option<BackgroundFlush>,
+    _flush_on_exit: Option<FlushOnExit>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -161,56 +539,276 @@ where
         DiskCacheBuilder::new(cache_name)
     }
 
+    /// Remove the expired records, see [`DiskCache::sweep`] to also remove the corrupt ones and count them.
     pub fn remove_expired_entries(&self) -> Result<(), DiskCacheError> {
+        self.sweep(false).map(|_| ())
+    }
+
+    /// Remove the expired records, and with `purge_corrupt` the records which can't be deserialized,
+    /// returning the numbers of removed records.
+    ///
+    /// The records are visited in batches of 1000, see [`DiskCache::sweep_batch`] to spread a sweep
+    /// of a large cache over time instead.
+    pub fn sweep(&self, purge_corrupt: bool) -> Result<DiskSweep, DiskCacheError> {
+        let mut total = DiskSweep::default();
+        let mut from = None;
+        loop {
+            let batch = self.sweep_batch(from.as_deref(), DISK_SWEEP_BATCH, purge_corrupt)?;
+            total.removed_expired += batch.removed_expired;
+            total.removed_corrupt += batch.removed_corrupt;
+            from = batch.next_key;
+            if from.is_none() {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Sweep at most `limit` records, starting at the key `from` (or the first key), like [`DiskCache::sweep`].
+    ///
+    /// The returned [`DiskSweep::next_key`] is the key to resume from, or `None` once the whole cache was visited,
+    /// so a periodic task can sweep a large cache a batch at a time:
+    /// ```rust
+    /// use kash::stores::DiskCache;
+    ///
+    /// let cache: DiskCache<u32, String> = DiskCache::new("my-swept-cache")
+    ///     .set_disk_directory(std::env::temp_dir().join("kash-sweep-doc"))
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let mut from = None;
+    /// loop {
+    ///     let batch = cache.sweep_batch(from.as_deref(), 100, true).unwrap();
+    ///     from = batch.next_key;
+    ///     if from.is_none() {
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    pub fn sweep_batch(
+        &self,
+        from: Option<&[u8]>,
+        limit: usize,
+        purge_corrupt: bool,
+    ) -> Result<DiskSweep, DiskCacheError> {
+        self.check_writable()?;
         let now = SystemTime::now();
+        let mut sweep = DiskSweep::default();
+        let mut records = match from {
+            Some(from) => self.tree.range(from..),
+            None => self.tree.iter(),
+        };
 
-        for (key, value) in self.connection.iter().flatten() {
-            if let Ok(kash) = rmp_serde::from_slice::<KashDiskValue<V>>(&value) {
-                if let Some(lifetime_seconds) = self.seconds {
-                    if now
-                        .duration_since(kash.created_at)
-                        .unwrap_or(Duration::from_secs(0))
-                        >= Duration::from_secs(lifetime_seconds)
-                    {
-                        self.connection.remove(key)?;
+        for record in records.by_ref().take(limit) {
+            let (key, value) = record?;
+            match rmp_serde::from_slice::<KashDiskValue<V>>(&value) {
+                Ok(kash) => {
+                    if !self.is_current(&kash) || kash.is_expired(self.ttl, now) {
+                        self.tree.remove(key)?;
+                        sweep.removed_expired += 1;
                     }
                 }
+                Err(_) if purge_corrupt => {
+                    self.tree.remove(key)?;
+                    sweep.removed_corrupt += 1;
+                }
+                Err(_) => {}
             }
         }
+        sweep.next_key = records.next().transpose()?.map(|(key, _)| key.to_vec());
 
         if self.sync_to_disk_on_cache_change {
             self.connection.flush()?;
         }
-        Ok(())
+        Ok(sweep)
+    }
+
+    /// Iterate the cached entries, with the string of their keys.
+    /// Expired records and the records which can't be deserialized are skipped.
+    ///
+    /// Keys are stored as their `to_string()`, so if `K` implements [`std::str::FromStr`]
+    /// it can be recovered by parsing it, e.g. for offline analysis or migration of a cache:
+    /// ```rust
+    /// use kash::stores::DiskCache;
+    /// use kash::IOKash;
+    ///
+    /// let cache: DiskCache<u32, String> = DiskCache::new("my-iterated-cache")
+    ///     .set_disk_directory(std::env::temp_dir().join("kash-iter-doc"))
+    ///     .build()
+    ///     .unwrap();
+    /// cache.set(1, "one".to_string()).unwrap();
+    ///
+    /// for entry in cache.iter() {
+    ///     let (key, value) = entry.unwrap();
+    ///     let key: u32 = key.parse().unwrap();
+    ///     println!("{key}: {value}");
+    /// }
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, V), DiskCacheError>> + '_ {
+        self.tree.iter().filter_map(|record| {
+            let (key, value) = match record {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let kash = rmp_serde::from_slice::<KashDiskValue<V>>(&value).ok()?;
+            let value = self.check_expiration(kash)?;
+            Some(Ok((String::from_utf8_lossy(&key).into_owned(), value)))
+        })
+    }
+
+    /// Remove all the records which can't be deserialized, e.g. because of a partial write
+    /// or a change of the value type, and return the number of removed records.
+    ///
+    /// Such records are already treated as absent, so this only reclaims their space.
+    pub fn repair(&self) -> Result<usize, DiskCacheError> {
+        self.check_writable()?;
+        let mut removed = 0;
+        for (key, value) in self.tree.iter().flatten() {
+            if rmp_serde::from_slice::<KashDiskValue<V>>(&value).is_err() {
+                self.tree.remove(key)?;
+                removed += 1;
+            }
+        }
+
+        if self.sync_to_disk_on_cache_change {
+            self.connection.flush()?;
+        }
+        Ok(removed)
+    }
+
+    /// Return when the value of a key was cached, without refreshing or removing it.
+    /// Expired values and the values which can't be deserialized are absent, like with [`IOKash::get`].
+    ///
+    /// # Errors
+    ///
+    /// Will return a `DiskCacheError` if the record can't be read
+    pub fn created_at(&self, key: &K) -> Result<Option<SystemTime>, DiskCacheError> {
+        let Some(data) = self.tree.get(key.to_string())? else {
+            return Ok(None);
+        };
+        let Ok(kash) = rmp_serde::from_slice::<KashDiskValue<V>>(&data) else {
+            return Ok(None);
+        };
+        let created_at = kash.created_at;
+        Ok(self.check_expiration(kash).map(|_| created_at))
+    }
+
+    /// Return how long ago the value of a key was cached, e.g. to monitor the freshness of a cache,
+    /// like [`DiskCache::created_at`].
+    ///
+    /// ```rust
+    /// use kash::stores::DiskCache;
+    /// use kash::IOKash;
+    ///
+    /// let cache: DiskCache<u32, String> = DiskCache::new("my-aged-cache")
+    ///     .set_disk_directory(std::env::temp_dir().join("kash-entry-age-doc"))
+    ///     .build()
+    ///     .unwrap();
+    /// cache.set(1, "one".to_string()).unwrap();
+    ///
+    /// assert!(cache.entry_age(&1).unwrap().is_some());
+    /// assert_eq!(None, cache.entry_age(&2).unwrap());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Will return a `DiskCacheError` if the record can't be read
+    pub fn entry_age(&self, key: &K) -> Result<Option<Duration>, DiskCacheError> {
+        Ok(self.created_at(key)?.map(|created_at| {
+            SystemTime::now()
+                .duration_since(created_at)
+                .unwrap_or(Duration::from_secs(0))
+        }))
     }
 
     /// Provide access to the underlying [Db] connection
     /// This is useful for i.e., manually flushing the cache to disk.
+    ///
+    /// It may be shared with other caches, see [`DiskCacheBuilder::set_shared_db`], in which case
+    /// the records of this cache are only the ones of [`DiskCache::tree`].
     #[must_use]
     pub fn connection(&self) -> &Db {
         &self.connection
     }
 
+    /// Provide access to the [Tree] of the records of this cache, e.g. to count or clear them,
+    /// which is the default tree of its [`DiskCache::connection`], unless the database is shared.
+    #[must_use]
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
     /// Provide mutable access to the underlying [Db] connection
     pub fn connection_mut(&mut self) -> &mut Db {
         &mut self.connection
     }
 
+    // the error of the methods writing to a read-only cache
+    fn check_writable(&self) -> Result<(), DiskCacheError> {
+        if self.read_only {
+            Err(DiskCacheError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    // whether the record was written by this version of the crate and of the values
+    fn is_current(&self, kash: &KashDiskValue<V>) -> bool {
+        kash.version == self.version && kash.schema_version == self.schema_version
+    }
+
     fn check_expiration(&self, kash: KashDiskValue<V>) -> Option<V> {
-        if let Some(ttl) = self.seconds {
-            if SystemTime::now()
-                .duration_since(kash.created_at)
-                .unwrap_or(Duration::from_secs(0))
-                < Duration::from_secs(ttl)
-            {
-                Some(kash.value)
-            } else {
-                None
-            }
+        if !self.is_current(&kash) {
+            return None;
+        }
+
+        if kash.is_expired(self.ttl, SystemTime::now()) {
+            None
         } else {
             Some(kash.value)
         }
     }
+
+    /// Set a value with its own ttl in seconds, instead of the ttl of the cache.
+    /// With `None`, the ttl of the cache is used, like [`IOKash::set`].
+    ///
+    /// # Errors
+    ///
+    /// Will return a `DiskCacheError` if the value can't be serialized or stored
+    pub fn set_with_ttl(
+        &self,
+        key: K,
+        value: V,
+        seconds: Option<u64>,
+    ) -> Result<Option<V>, DiskCacheError> {
+        self.check_writable()?;
+        let key = key.to_string();
+        let value = rmp_serde::to_vec(&KashDiskValue::new(value, seconds, self.schema_version))?;
+
+        let result = if let Some(data) = self.tree.insert(key, value)? {
+            rmp_serde::from_slice::<KashDiskValue<V>>(&data)
+                .ok()
+                .and_then(|kash| self.check_expiration(kash))
+        } else {
+            None
+        };
+
+        if self.sync_to_disk_on_cache_change {
+            self.connection.flush()?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// The records removed by [`DiskCache::sweep`] or [`DiskCache::sweep_batch`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiskSweep {
+    /// The records which expired, or were written with another version of the record or of its value
+    pub removed_expired: usize,
+    /// The records which couldn't be deserialized, only removed with `purge_corrupt`
+    pub removed_corrupt: usize,
+    /// The key [`DiskCache::sweep_batch`] resumes from, `None` once the whole cache was visited
+    pub next_key: Option<Vec<u8>>,
 }
 
 #[derive(Error, Debug)]
@@ -221,21 +819,46 @@ pub enum DiskCacheError {
     CacheDeserializationError(#[from] rmp_serde::decode::Error),
     #[error("Error serializing cached value")]
     CacheSerializationError(#[from] rmp_serde::encode::Error),
+    #[error("Cache key of {len} bytes is longer than max_key_len {max}")]
+    KeyTooLong { len: usize, max: usize },
+    #[error("Disk cache is read-only")]
+    ReadOnly,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct KashDiskValue<V> {
     pub(crate) value: V,
     pub(crate) created_at: SystemTime,
+    /// Records written before the version was stored default to `0`, so they're treated as a miss
+    #[serde(default)]
+    pub(crate) version: u64,
+    /// The version of the schema of the value, see [`DiskCacheBuilder::set_schema_version`]
+    #[serde(default)]
+    pub(crate) schema_version: u64,
+    /// The ttl of this record, overriding the ttl of the cache
+    #[serde(default)]
+    pub(crate) seconds: Option<u64>,
 }
 
 impl<V> KashDiskValue<V> {
-    fn new(value: V) -> Self {
+    fn new(value: V, seconds: Option<u64>, schema_version: u64) -> Self {
         Self {
             value,
             created_at: SystemTime::now(),
+            version: DISK_FILE_VERSION,
+            schema_version,
+            seconds,
         }
     }
+
+    fn is_expired(&self, cache_ttl: Option<Duration>, now: SystemTime) -> bool {
+        let Some(ttl) = self.seconds.map(Duration::from_secs).or(cache_ttl) else {
+            return false;
+        };
+        now.duration_since(self.created_at)
+            .unwrap_or(Duration::from_secs(0))
+            >= ttl
+    }
 }
 
 impl<K, V> IOKash<K, V> for DiskCache<K, V>
@@ -247,32 +870,37 @@ where
 
     fn get(&self, key: &K) -> Result<Option<V>, DiskCacheError> {
         let key = key.to_string();
-        let seconds = self.seconds;
+        if self.read_only {
+            // the expired records aren't removed from a read-only cache
+            let Some(data) = self.tree.get(key)? else {
+                return Ok(None);
+            };
+            return Ok(rmp_serde::from_slice::<KashDiskValue<V>>(&data)
+                .ok()
+                .and_then(|kash| self.check_expiration(kash)));
+        }
+        let ttl = self.ttl;
         let update = |old: Option<&[u8]>| -> Option<Vec<u8>> {
             let old = old?;
-            if seconds.is_none() {
-                return Some(old.to_vec());
-            }
-            let seconds = seconds.unwrap();
             let Ok(kash) = rmp_serde::from_slice::<KashDiskValue<V>>(old) else {
                 // unable to deserialize, treat it as not existing
                 return None;
             };
-            if SystemTime::now()
-                .duration_since(kash.created_at)
-                .unwrap_or(Duration::from_secs(0))
-                < Duration::from_secs(seconds)
-            {
-                let cache_val =
-                    rmp_serde::to_vec(&kash).expect("error serializing kash disk value");
-                Some(cache_val)
-            } else {
+            if kash.is_expired(ttl, SystemTime::now()) {
                 None
+            } else {
+                Some(old.to_vec())
             }
         };
 
-        if let Some(data) = self.connection.update_and_fetch(key, update)? {
-            let kash = rmp_serde::from_slice::<KashDiskValue<V>>(&data)?;
+        if let Some(data) = self.tree.update_and_fetch(key, update)? {
+            // a corrupt record is treated as a miss, and is overwritten by the next `set`
+            let Ok(kash) = rmp_serde::from_slice::<KashDiskValue<V>>(&data) else {
+                return Ok(None);
+            };
+            if !self.is_current(&kash) {
+                return Ok(None);
+            }
             Ok(Some(kash.value))
         } else {
             Ok(None)
@@ -280,30 +908,16 @@ where
     }
 
     fn set(&self, key: K, value: V) -> Result<Option<V>, DiskCacheError> {
-        let key = key.to_string();
-        let value = rmp_serde::to_vec(&KashDiskValue::new(value))?;
-
-        let result = if let Some(data) = self.connection.insert(key, value)? {
-            let kash = rmp_serde::from_slice::<KashDiskValue<V>>(&data)?;
-
-            self.check_expiration(kash)
-        } else {
-            None
-        };
-
-        if self.sync_to_disk_on_cache_change {
-            self.connection.flush()?;
-        }
-
-        Ok(result)
+        self.set_with_ttl(key, value, None)
     }
 
     fn remove(&self, key: &K) -> Result<Option<V>, DiskCacheError> {
+        self.check_writable()?;
         let key = key.to_string();
-        let result = if let Some(data) = self.connection.remove(key)? {
-            let kash = rmp_serde::from_slice::<KashDiskValue<V>>(&data)?;
-
-            self.check_expiration(kash)
+        let result = if let Some(data) = self.tree.remove(key)? {
+            rmp_serde::from_slice::<KashDiskValue<V>>(&data)
+                .ok()
+                .and_then(|kash| self.check_expiration(kash))
         } else {
             None
         };
@@ -316,17 +930,17 @@ where
     }
 
     fn ttl(&self) -> Option<u64> {
-        self.seconds
+        self.ttl.map(ttl_secs)
     }
 
     fn set_ttl(&mut self, seconds: u64) -> Option<u64> {
-        let old = self.seconds;
-        self.seconds = Some(seconds);
+        let old = self.ttl();
+        self.ttl = Some(Duration::from_secs(seconds));
         old
     }
 
     fn unset_ttl(&mut self) -> Option<u64> {
-        self.seconds.take()
+        self.ttl.take().map(ttl_secs)
     }
 }
 
@@ -373,6 +987,126 @@ mod test_DiskCache {
     const LIFE_SPAN_2_SECS: u64 = 2;
     const LIFE_SPAN_1_SEC: u64 = 1;
 
+    #[googletest::test]
+    fn caches_sharing_a_db_keep_their_own_records() {
+        let tmp_dir = temp_dir!();
+        let db = sled::open(tmp_dir.path()).unwrap();
+        let first: DiskCache<u32, u32> =
+            DiskCache::new("first").set_shared_db(&db).build().unwrap();
+        let second: DiskCache<u32, u32> =
+            DiskCache::new("second").set_shared_db(&db).build().unwrap();
+
+        first.set(TEST_KEY, TEST_VAL).unwrap();
+        second.set(TEST_KEY, TEST_VAL_1).unwrap();
+        second.set(TEST_KEY_1, TEST_VAL_1).unwrap();
+        assert_that!(first.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        assert_that!(second.get(&TEST_KEY), ok(some(eq(&TEST_VAL_1))));
+        assert_eq!(1, first.iter().count());
+        assert_eq!(2, second.iter().count());
+
+        assert_that!(first.remove(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        assert_that!(second.get(&TEST_KEY), ok(some(eq(&TEST_VAL_1))));
+        assert!(db.is_empty());
+    }
+
+    #[googletest::test]
+    fn opening_a_locked_cache_returns_locked() {
+        let tmp_dir = temp_dir!();
+        let _cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+
+        let locked = DiskCache::<u32, u32>::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build();
+        assert!(matches!(locked, Err(DiskCacheBuildError::Locked { .. })));
+    }
+
+    #[googletest::test]
+    fn read_only_cache_reads_a_cache_held_by_its_writer() {
+        let tmp_dir = temp_dir!();
+        let writer: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_sync_to_disk_on_cache_change(true)
+            .build()
+            .unwrap();
+        writer.set(TEST_KEY, TEST_VAL).unwrap();
+
+        let reader: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_read_only(true)
+            .build()
+            .unwrap();
+        assert_that!(reader.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        assert_that!(reader.get(&TEST_KEY_1), ok(none()));
+        assert!(matches!(
+            reader.set(TEST_KEY_1, TEST_VAL_1),
+            Err(DiskCacheError::ReadOnly)
+        ));
+        assert!(matches!(
+            reader.remove(&TEST_KEY),
+            Err(DiskCacheError::ReadOnly)
+        ));
+
+        // the writer still holds the cache
+        writer.set(TEST_KEY_1, TEST_VAL_1).unwrap();
+        assert_that!(writer.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        assert_that!(reader.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+    }
+
+    #[googletest::test]
+    fn read_only_cache_of_a_missing_cache_leaves_no_snapshot() {
+        let tmp_dir = temp_dir!();
+        let reader = DiskCache::<u32, u32>::new("missing-read-only-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_read_only(true)
+            .build();
+        assert!(reader.is_err());
+
+        let snapshot_prefix = format!(
+            "missing-read-only-cache_v{}_read_only_{}_",
+            DISK_FILE_VERSION,
+            std::process::id()
+        );
+        let snapshots = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&snapshot_prefix)
+            })
+            .count();
+        assert_eq!(snapshots, 0);
+    }
+
+    #[googletest::test]
+    fn opening_a_locked_cache_retries_until_released() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        cache.connection().flush().unwrap();
+
+        let holder = std::thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            drop(cache);
+        });
+
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_lock_retries(10, Duration::from_millis(20))
+            .build()
+            .unwrap();
+        holder.join().unwrap();
+
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+    }
+
     #[googletest::test]
     fn cache_get_after_cache_remove_returns_none() {
         let tmp_dir = temp_dir!();
@@ -418,6 +1152,402 @@ mod test_DiskCache {
         drop(cache);
     }
 
+    #[googletest::test]
+    fn values_with_another_schema_version_are_absent() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        drop(cache);
+
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_schema_version(2)
+            .build()
+            .unwrap();
+        assert_that!(cache.get(&TEST_KEY), ok(none()));
+        cache.set(TEST_KEY, TEST_VAL_1).unwrap();
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL_1))));
+        drop(cache);
+
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+        assert_that!(cache.get(&TEST_KEY), ok(none()));
+        cache.remove_expired_entries().unwrap();
+        assert!(cache.tree().is_empty());
+    }
+
+    #[googletest::test]
+    fn values_with_a_different_version_return_none() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+
+        let mut kash = KashDiskValue::new(TEST_VAL, None, 0);
+        kash.version = DISK_FILE_VERSION + 1;
+        cache
+            .connection
+            .insert(TEST_KEY.to_string(), rmp_serde::to_vec(&kash).unwrap())
+            .unwrap();
+
+        assert_that!(
+            cache.get(&TEST_KEY),
+            ok(none()),
+            "Getting a value with a different version should return None"
+        );
+        assert_that!(
+            cache.set(TEST_KEY, TEST_VAL_1),
+            ok(none()),
+            "Setting over a value with a different version should return None"
+        );
+        assert_that!(
+            cache.get(&TEST_KEY),
+            ok(some(eq(&TEST_VAL_1))),
+            "Getting an overwritten value should return the new value"
+        );
+    }
+
+    #[googletest::test]
+    fn corrupt_values_are_treated_as_absent() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+
+        cache
+            .connection
+            .insert(TEST_KEY.to_string(), b"garbage".to_vec())
+            .unwrap();
+
+        assert_that!(
+            cache.get(&TEST_KEY),
+            ok(none()),
+            "Getting a corrupt value should return None"
+        );
+        assert_that!(
+            cache.set(TEST_KEY, TEST_VAL),
+            ok(none()),
+            "Setting over a corrupt value should return None"
+        );
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+
+        cache
+            .connection
+            .insert(TEST_KEY.to_string(), b"garbage".to_vec())
+            .unwrap();
+        assert_that!(
+            cache.remove(&TEST_KEY),
+            ok(none()),
+            "Removing a corrupt value should return None"
+        );
+    }
+
+    #[googletest::test]
+    fn repair_removes_corrupt_values() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        cache
+            .connection
+            .insert(TEST_KEY_1.to_string(), b"garbage".to_vec())
+            .unwrap();
+
+        assert_that!(cache.repair(), ok(eq(&1)));
+        assert_that!(cache.connection.len(), eq(1));
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+    }
+
+    #[googletest::test]
+    fn sweep_removes_expired_and_corrupt_values() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_1_SEC)
+            .build()
+            .unwrap();
+
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        sleep(Duration::from_millis(1100));
+        cache.set(TEST_KEY_1, TEST_VAL_1).unwrap();
+        cache.connection.insert("3", b"garbage".to_vec()).unwrap();
+
+        // corrupt records are kept unless they're purged
+        assert_that!(
+            cache.sweep(false),
+            ok(eq(&DiskSweep {
+                removed_expired: 1,
+                removed_corrupt: 0,
+                next_key: None,
+            }))
+        );
+        assert_that!(cache.connection.len(), eq(2));
+
+        assert_that!(
+            cache.sweep(true),
+            ok(eq(&DiskSweep {
+                removed_expired: 0,
+                removed_corrupt: 1,
+                next_key: None,
+            }))
+        );
+        assert_that!(cache.connection.len(), eq(1));
+        assert_that!(cache.get(&TEST_KEY_1), ok(some(eq(&TEST_VAL_1))));
+    }
+
+    #[googletest::test]
+    fn sweep_batch_resumes_from_the_next_key() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_1_SEC)
+            .build()
+            .unwrap();
+
+        for key in 1..=4 {
+            cache.set(key, key).unwrap();
+        }
+        sleep(Duration::from_millis(1100));
+        cache.set(5, 5).unwrap();
+        cache.connection.insert("6", b"garbage".to_vec()).unwrap();
+
+        let first = cache.sweep_batch(None, 3, true).unwrap();
+        assert_that!(first.removed_expired, eq(3));
+        assert_that!(first.next_key.as_deref(), some(eq(b"4".as_slice())));
+
+        let second = cache
+            .sweep_batch(first.next_key.as_deref(), 3, true)
+            .unwrap();
+        assert_that!(
+            second,
+            eq(&DiskSweep {
+                removed_expired: 1,
+                removed_corrupt: 1,
+                next_key: None,
+            })
+        );
+        assert_that!(cache.connection.len(), eq(1));
+    }
+
+    #[googletest::test]
+    fn entry_age_grows_until_the_value_expires() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_1_SEC)
+            .build()
+            .unwrap();
+
+        assert_that!(cache.entry_age(&TEST_KEY), ok(none()));
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        let created_at = cache.created_at(&TEST_KEY).unwrap().unwrap();
+
+        sleep(Duration::from_millis(200));
+        let age = cache.entry_age(&TEST_KEY).unwrap().unwrap();
+        assert!(age >= Duration::from_millis(200));
+        assert!(age < Duration::from_secs(LIFE_SPAN_1_SEC));
+        // reading the age doesn't touch the value
+        assert_that!(cache.created_at(&TEST_KEY), ok(some(eq(&created_at))));
+
+        sleep(Duration::from_secs(LIFE_SPAN_1_SEC));
+        assert_that!(cache.entry_age(&TEST_KEY), ok(none()));
+    }
+
+    #[googletest::test]
+    fn iter_skips_expired_and_corrupt_values() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_1_SEC)
+            .build()
+            .unwrap();
+
+        cache.set(TEST_KEY, TEST_VAL).unwrap();
+        sleep(Duration::from_millis(1100));
+        cache.set(TEST_KEY_1, TEST_VAL_1).unwrap();
+        cache.connection.insert("3", b"garbage".to_vec()).unwrap();
+
+        let entries = cache.iter().collect::<Result<Vec<_>, _>>();
+        assert_that!(entries, ok(eq(&vec![(TEST_KEY_1.to_string(), TEST_VAL_1)])));
+    }
+
+    #[googletest::test]
+    fn values_with_their_own_ttl_expire_independently() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_2_SECS)
+            .build()
+            .unwrap();
+
+        cache
+            .set_with_ttl(TEST_KEY, TEST_VAL, Some(LIFE_SPAN_1_SEC))
+            .unwrap();
+        cache.set_with_ttl(TEST_KEY_1, TEST_VAL_1, None).unwrap();
+        sleep(Duration::from_millis(1100));
+
+        assert_that!(cache.get(&TEST_KEY), ok(none()));
+        assert_that!(cache.get(&TEST_KEY_1), ok(some(eq(&TEST_VAL_1))));
+    }
+
+    #[cfg(feature = "disk_compression")]
+    #[googletest::test]
+    fn compressed_cache() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, String> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_use_compression(true)
+            .set_compression_factor(10)
+            .build()
+            .unwrap();
+        assert_that!(cache.set(TEST_KEY, "a".repeat(1000)), ok(none()));
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&"a".repeat(1000)))));
+        drop(cache);
+
+        // the database was created with compression
+        let uncompressed = DiskCache::<u32, String>::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build();
+        assert!(matches!(
+            uncompressed,
+            Err(DiskCacheBuildError::ConnectionError(_))
+        ));
+
+        let invalid_factor = DiskCache::<u32, String>::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_use_compression(true)
+            .set_compression_factor(23)
+            .build();
+        assert!(matches!(
+            invalid_factor,
+            Err(DiskCacheBuildError::ConnectionError(_))
+        ));
+    }
+
+    #[googletest::test]
+    fn background_flush() {
+        let tmp_dir = temp_dir!();
+        // without the periodic flush of sled, so only the background thread writes the value to disk
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_connection_config(sled::Config::new().flush_every_ms(None))
+            .set_background_flush(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        assert_that!(cache.set(TEST_KEY, TEST_VAL), ok(none()));
+        let read_flushed = || {
+            let reader: DiskCache<u32, u32> = DiskCache::new("test-cache")
+                .set_disk_directory(tmp_dir.path())
+                .set_read_only(true)
+                .build()
+                .unwrap();
+            reader.get(&TEST_KEY).unwrap()
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while read_flushed().is_none() && std::time::Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert_that!(read_flushed(), some(eq(TEST_VAL)));
+        drop(cache);
+
+        // the thread is stopped, so it doesn't hold the lock of the database anymore
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+    }
+
+    #[googletest::test]
+    fn flush_on_exit() {
+        let tmp_dir = temp_dir!();
+        // without the periodic flush of sled, like a process exiting before it runs
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_connection_config(sled::Config::new().flush_every_ms(None))
+            .set_flush_on_exit(true)
+            .build()
+            .unwrap();
+        assert_that!(cache.set(TEST_KEY, TEST_VAL), ok(none()));
+        let read_flushed = || {
+            let reader: DiskCache<u32, u32> = DiskCache::new("test-cache")
+                .set_disk_directory(tmp_dir.path())
+                .set_read_only(true)
+                .build()
+                .unwrap();
+            reader.get(&TEST_KEY).unwrap()
+        };
+        assert_that!(read_flushed(), none());
+
+        // the cache is never dropped, like the one in a static, but the guard at the end of `main` flushes it
+        drop(DiskFlushGuard::new());
+        assert_that!(read_flushed(), some(eq(TEST_VAL)));
+
+        // a dropped cache isn't kept open by its registration
+        drop(cache);
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .build()
+            .unwrap();
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+    }
+
+    #[googletest::test]
+    fn sub_second_ttl() {
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl_duration(Duration::from_millis(300))
+            .build()
+            .unwrap();
+
+        assert_that!(cache.set(TEST_KEY, TEST_VAL), ok(none()));
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        sleep(Duration::from_millis(400));
+        assert_that!(cache.get(&TEST_KEY), ok(none()));
+    }
+
+    #[googletest::test]
+    fn values_without_their_own_ttl_use_the_cache_ttl() {
+        #[derive(serde::Serialize)]
+        struct KashDiskValueWithoutTtl {
+            value: u32,
+            created_at: SystemTime,
+            version: u64,
+        }
+
+        let tmp_dir = temp_dir!();
+        let cache: DiskCache<u32, u32> = DiskCache::new("test-cache")
+            .set_disk_directory(tmp_dir.path())
+            .set_ttl(LIFE_SPAN_1_SEC)
+            .build()
+            .unwrap();
+
+        let kash = KashDiskValueWithoutTtl {
+            value: TEST_VAL,
+            created_at: SystemTime::now(),
+            version: DISK_FILE_VERSION,
+        };
+        cache
+            .connection
+            .insert(TEST_KEY.to_string(), rmp_serde::to_vec(&kash).unwrap())
+            .unwrap();
+
+        assert_that!(cache.get(&TEST_KEY), ok(some(eq(&TEST_VAL))));
+        sleep(Duration::from_millis(1100));
+        assert_that!(cache.get(&TEST_KEY), ok(none()));
+    }
+
     #[googletest::test]
     fn values_expire_when_lifespan_elapses_returning_none() {
         let tmp_dir = temp_dir!();
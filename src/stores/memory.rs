@@ -0,0 +1,82 @@
+use crate::stores::ttl_secs;
+use crate::IOKash;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hash};
+
+/// The `moka` cache of the memory caches of non-`async` functions.
+///
+/// It implements [`IOKash`], which never fails, so a function generic over an `IOKash` accepts a memory cache,
+/// as well as a `DiskCache` or a `RedisCache`, e.g. to write backend-agnostic helpers or tiered caches.
+///
+/// ```rust
+/// use kash::{IOKash, MemoryCache};
+///
+/// fn get_or_double<C: IOKash<u32, u32>>(cache: &C, n: u32) -> Result<u32, C::Error> {
+///     if let Some(doubled) = cache.get(&n)? {
+///         return Ok(doubled);
+///     }
+///     cache.set(n, n * 2)?;
+///     Ok(n * 2)
+/// }
+///
+/// let cache = MemoryCache::new(100);
+/// assert_eq!(Ok(4), get_or_double(&cache, 2));
+/// assert_eq!(Some(4), cache.get(&2));
+/// ```
+pub type MemoryCache<K, V> = moka::sync::Cache<K, V>;
+
+impl<K, V, S> IOKash<K, V> for moka::sync::Cache<K, V, S>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    type Error = Infallible;
+
+    fn get(&self, k: &K) -> Result<Option<V>, Infallible> {
+        Ok(moka::sync::Cache::get(self, k))
+    }
+
+    fn set(&self, k: K, v: V) -> Result<Option<V>, Infallible> {
+        // replace the value in the entry, to return the old one atomically
+        let mut old = None;
+        self.entry(k).and_upsert_with(|entry| {
+            old = entry.map(moka::Entry::into_value);
+            v
+        });
+        Ok(old)
+    }
+
+    fn remove(&self, k: &K) -> Result<Option<V>, Infallible> {
+        Ok(moka::sync::Cache::remove(self, k))
+    }
+
+    /// Return the ttl of the cache, rounded up to whole seconds, `moka` can't change it once the cache is built
+    fn ttl(&self) -> Option<u64> {
+        self.policy().time_to_live().map(ttl_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn memory_cache_is_io_kash() {
+        let cache = MemoryCache::builder()
+            .time_to_live(Duration::from_secs(10))
+            .build();
+        assert_eq!(Ok(None), IOKash::set(&cache, 1, 10));
+        assert_eq!(Ok(Some(10)), IOKash::set(&cache, 1, 11));
+        assert_eq!(Ok(Some(11)), IOKash::get(&cache, &1));
+        assert_eq!(Ok(Some(11)), IOKash::remove(&cache, &1));
+        assert_eq!(Ok(None), IOKash::get(&cache, &1));
+        assert_eq!(Some(10), IOKash::ttl(&cache));
+
+        let cache: MemoryCache<u32, u32> = MemoryCache::builder()
+            .time_to_live(Duration::from_millis(200))
+            .build();
+        assert_eq!(Some(1), IOKash::ttl(&cache));
+    }
+}
@@ -1,16 +1,91 @@
 #[cfg(feature = "disk_store")]
 mod disk;
+#[cfg(feature = "mem_store")]
+mod memory;
 #[cfg(feature = "redis_store")]
 mod redis;
+#[cfg(feature = "mem_store")]
+mod slot;
 
 #[cfg(feature = "disk_store")]
-pub use crate::stores::disk::{DiskCache, DiskCacheBuildError, DiskCacheBuilder, DiskCacheError};
+pub use crate::stores::disk::{
+    flush_disk_caches, DiskCache, DiskCacheBuildError, DiskCacheBuilder, DiskCacheError,
+    DiskFlushGuard, DiskSweep,
+};
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub use crate::stores::memory::MemoryCache;
 #[cfg(feature = "redis_store")]
 #[cfg_attr(docsrs, doc(cfg(feature = "redis_store")))]
 pub use crate::stores::redis::{
     RedisCache, RedisCacheBuildError, RedisCacheBuilder, RedisCacheError,
 };
-// pub use memory::MemoryCache;
+#[cfg(feature = "mem_store")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mem_store")))]
+pub use crate::stores::slot::{SlotCache, SlotEntry, SlotEntryRef};
+
+// the ttl in the whole seconds of `IOKash::ttl`, rounded up, so a sub-second ttl, e.g. of `ttl_ms`, isn't reported as 0
+#[cfg(any(feature = "mem_store", feature = "disk_store", feature = "redis_store"))]
+pub(crate) fn ttl_secs(ttl: std::time::Duration) -> u64 {
+    ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0)
+}
+
+/// The prefix of the keys hashed by [`hash_long_key`], which a key that is not hashed can't start with
+#[cfg(any(feature = "disk_store", feature = "redis_store"))]
+#[doc(hidden)]
+pub const HASHED_KEY_PREFIX: &str = "#sha256:";
+
+/// Replace a key longer than `max_len` with its SHA-256 digest, used by `max_key_len` with `key_overflow = "hash"`.
+/// The hashed key is always 72 characters long, the hex digest after [`HASHED_KEY_PREFIX`], so it can't collide
+/// with a key which is not hashed: the keys starting with the prefix are hashed, too.
+#[cfg(any(feature = "disk_store", feature = "redis_store"))]
+#[doc(hidden)]
+#[must_use]
+pub fn hash_long_key(key: String, max_len: usize) -> String {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    if key.len() <= max_len && !key.starts_with(HASHED_KEY_PREFIX) {
+        return key;
+    }
+
+    let mut hashed = String::with_capacity(HASHED_KEY_PREFIX.len() + 64);
+    hashed.push_str(HASHED_KEY_PREFIX);
+    for b in Sha256::digest(key.as_bytes()) {
+        let _ = write!(hashed, "{b:02x}");
+    }
+    hashed
+}
+
+#[cfg(all(test, any(feature = "disk_store", feature = "redis_store")))]
+mod tests {
+    use super::{hash_long_key, ttl_secs, HASHED_KEY_PREFIX};
+    use std::time::Duration;
+
+    #[test]
+    fn sub_second_ttls_are_rounded_up() {
+        assert_eq!(0, ttl_secs(Duration::ZERO));
+        assert_eq!(1, ttl_secs(Duration::from_millis(200)));
+        assert_eq!(1, ttl_secs(Duration::from_secs(1)));
+        assert_eq!(2, ttl_secs(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn long_keys_are_hashed_to_a_fixed_length() {
+        assert_eq!("short", hash_long_key("short".to_string(), 8));
+
+        let hashed = hash_long_key("a".repeat(1000), 8);
+        assert_eq!(72, hashed.len());
+        assert!(hashed.starts_with(HASHED_KEY_PREFIX));
+        assert_eq!(hashed, hash_long_key("a".repeat(1000), 8));
+        assert_ne!(hashed, hash_long_key("b".repeat(1000), 8));
+        assert_eq!(hashed, hash_long_key("a".repeat(1000), 100));
+
+        // a key looking like a hashed one is hashed, too
+        assert_ne!(hashed, hash_long_key(hashed.clone(), 100));
+        assert_eq!(72, hash_long_key(hashed.clone(), 100).len());
+    }
+}
 
 #[cfg(all(feature = "async", feature = "redis_store", feature = "redis_tokio"))]
 #[cfg_attr(
@@ -1,25 +1,52 @@
+use crate::stores::ttl_secs;
 use crate::IOKash;
+use once_cell::sync::Lazy;
 use redis::Pipeline;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt::Display;
 use std::marker::PhantomData;
+use std::time::Duration;
 use thiserror::Error;
 
 pub struct RedisCacheBuilder<K, V> {
-    seconds: Option<u64>,
-    namespace: String,
+    ttl: Option<Duration>,
+    namespace: Option<String>,
     prefix: String,
+    delimiter: String,
+    schema_version: Option<u64>,
     connection_string: Option<String>,
     pool_max_size: Option<u32>,
     pool_min_idle: Option<u32>,
     pool_max_lifetime: Option<std::time::Duration>,
     pool_idle_timeout: Option<std::time::Duration>,
+    refresh: bool,
+    key_fn: KeyFn<K>,
     _phantom: PhantomData<(K, V)>,
 }
 
 const ENV_KEY: &str = "KASH_REDIS_CONNECTION_STRING";
-const DEFAULT_NAMESPACE: &str = "kash:";
+const DEFAULT_NAMESPACE: &str = "kash";
+// the default namespace without a delimiter, i.e., of the keys of the versions without delimiters
+const LEGACY_NAMESPACE: &str = "kash:";
+const DEFAULT_DELIMITER: &str = ":";
+
+type KeyFn<K> = fn(&K) -> Result<String, rmp_serde::encode::Error>;
+
+fn display_key<K: Display>(key: &K) -> Result<String, rmp_serde::encode::Error> {
+    Ok(key.to_string())
+}
+
+fn serialized_key<K: Serialize>(key: &K) -> Result<String, rmp_serde::encode::Error> {
+    use std::fmt::Write;
+
+    let bytes = rmp_serde::to_vec(key)?;
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(hex, "{b:02x}");
+    }
+    Ok(hex)
+}
 
 #[derive(Error, Debug)]
 pub enum RedisCacheBuildError {
@@ -41,15 +68,52 @@ where
 {
     /// Initialize a `RedisCacheBuilder`
     pub fn new<S: ToString>(prefix: S, seconds: Option<u64>) -> RedisCacheBuilder<K, V> {
+        Self::with_key_fn(prefix, seconds, display_key)
+    }
+}
+
+impl<K, V> RedisCacheBuilder<K, V>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+{
+    /// Initialize a `RedisCacheBuilder` for keys which don't implement `Display`.
+    /// See [`RedisCacheBuilder::set_key_serialization`]
+    pub fn new_serialized<S: ToString>(prefix: S, seconds: Option<u64>) -> RedisCacheBuilder<K, V> {
+        Self::with_key_fn(prefix, seconds, serialized_key)
+    }
+
+    /// Serialize keys with msgpack and store them as hex strings, instead of using their `Display` value.
+    /// The namespace and prefix are still prepended: `{namespace}:{prefix}:{hex}`, see [`Self::set_delimiter`].
+    ///
+    /// Serialized keys can be decoded back to `K`, but they take twice the size of their msgpack
+    /// encoding. Redis keys are limited to 512 MB, and long keys are slower to look up,
+    /// so prefer small key types.
+    #[must_use]
+    pub fn set_key_serialization(mut self) -> Self {
+        self.key_fn = serialized_key;
+        self
+    }
+}
+
+impl<K, V> RedisCacheBuilder<K, V>
+where
+    V: Serialize + DeserializeOwned,
+{
+    fn with_key_fn<S: ToString>(prefix: S, seconds: Option<u64>, key_fn: KeyFn<K>) -> Self {
         Self {
-            seconds,
-            namespace: DEFAULT_NAMESPACE.to_string(),
+            ttl: seconds.map(Duration::from_secs),
+            namespace: None,
             prefix: prefix.to_string(),
+            delimiter: DEFAULT_DELIMITER.to_string(),
+            schema_version: None,
             connection_string: None,
             pool_max_size: None,
             pool_min_idle: None,
             pool_max_lifetime: None,
             pool_idle_timeout: None,
+            refresh: false,
+            key_fn,
             _phantom: PhantomData,
         }
     }
@@ -57,30 +121,64 @@ where
     /// Specify the cache ttl in seconds
     #[must_use]
     pub fn set_ttl(mut self, seconds: u64) -> Self {
-        self.seconds = Some(seconds);
+        self.ttl = Some(Duration::from_secs(seconds));
+        self
+    }
+
+    /// Specify the cache ttl as a `Duration`, with a precision of milliseconds
+    #[must_use]
+    pub fn set_ttl_duration(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Specify whether a cache hit resets the ttl of the value, like memory caches do,
+    /// so frequently read values don't expire
+    #[must_use]
+    pub fn set_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
         self
     }
 
-    /// Set the namespace for cache keys. Defaults to `kash:`.
-    /// Used to generate keys formatted as: `{namespace}{prefix}{key}`
-    /// Note that no delimiters are implicitly added, so you may pass
-    /// an empty string if you want there to be no namespace on keys.
+    /// Set the namespace for cache keys. Defaults to `kash` (`kash:` without a delimiter).
+    /// Used to generate keys formatted as: `{namespace}:{prefix}:{key}`, see [`Self::set_delimiter`].
+    /// You may pass an empty string if you want there to be no namespace on keys.
     #[must_use]
     pub fn set_namespace<S: ToString>(mut self, namespace: S) -> Self {
-        self.namespace = namespace.to_string();
+        self.namespace = Some(namespace.to_string());
         self
     }
 
     /// Set the prefix for cache keys.
-    /// Used to generate keys formatted as: `{namespace}{prefix}{key}`
-    /// Note that no delimiters are implicitly added, so you may pass
-    /// an empty string if you want there to be no prefix on keys.
+    /// Used to generate keys formatted as: `{namespace}:{prefix}:{key}`, see [`Self::set_delimiter`].
+    /// You may pass an empty string if you want there to be no prefix on keys.
     #[must_use]
     pub fn set_prefix<S: ToString>(mut self, prefix: S) -> Self {
         self.prefix = prefix.to_string();
         self
     }
 
+    /// Set the delimiter added after the namespace and the prefix of cache keys, unless they're empty,
+    /// so a prefix and a key can't collide with another prefix and key.
+    /// Defaults to `:`. Pass an empty string for no delimiter, i.e., keys formatted as `{namespace}{prefix}{key}`.
+    /// Without a delimiter, the default namespace is `kash:`, so the keys are formatted as `kash:{prefix}{key}`,
+    /// like the keys stored by the versions without delimiters.
+    #[must_use]
+    pub fn set_delimiter<S: ToString>(mut self, delimiter: S) -> Self {
+        self.delimiter = delimiter.to_string();
+        self
+    }
+
+    /// Set the version of the schema of the values, added to the prefix of the keys as `v{schema_version}`,
+    /// i.e., keys formatted as `{namespace}:{prefix}:v{schema_version}:{key}`. Bump it when the layout of
+    /// the value type changes, so the values of the old layout are missed instead of deserialized as the new
+    /// one, and expire with their ttl.
+    #[must_use]
+    pub fn set_schema_version(mut self, schema_version: u64) -> Self {
+        self.schema_version = Some(schema_version);
+        self
+    }
+
     /// Set the connection string for redis
     #[must_use]
     pub fn set_connection_string(mut self, cs: &str) -> Self {
@@ -165,6 +263,15 @@ where
         Ok(pool)
     }
 
+    fn key_prefix(&self) -> String {
+        key_prefix(
+            namespace_or_default(self.namespace.as_deref(), &self.delimiter),
+            &self.prefix,
+            self.schema_version,
+            &self.delimiter,
+        )
+    }
+
     /// The last step in building a `RedisCache` is to call `build()`
     ///
     /// # Errors
@@ -172,11 +279,12 @@ where
     /// Will return a `RedisCacheBuildError`, depending on the error
     pub fn build(self) -> Result<RedisCache<K, V>, RedisCacheBuildError> {
         Ok(RedisCache {
-            seconds: self.seconds,
+            ttl: self.ttl,
             connection_string: self.connection_string()?,
             pool: self.create_pool()?,
-            namespace: self.namespace,
-            prefix: self.prefix,
+            key_prefix: self.key_prefix(),
+            refresh: self.refresh,
+            key_fn: self.key_fn,
             _phantom: PhantomData,
         })
     }
@@ -187,11 +295,12 @@ where
 /// Values have a ttl applied and enforced by redis.
 /// Uses an r2d2 connection pool under the hood.
 pub struct RedisCache<K, V> {
-    pub(super) seconds: Option<u64>,
-    pub(super) namespace: String,
-    pub(super) prefix: String,
+    pub(super) ttl: Option<Duration>,
+    key_prefix: String,
     connection_string: String,
     pool: r2d2::Pool<redis::Client>,
+    refresh: bool,
+    key_fn: KeyFn<K>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -205,9 +314,153 @@ where
     pub fn new<S: ToString>(prefix: S, seconds: Option<u64>) -> RedisCacheBuilder<K, V> {
         RedisCacheBuilder::new(prefix, seconds)
     }
+}
 
-    fn generate_key(&self, key: &K) -> String {
-        format!("{}{}{}", self.namespace, self.prefix, key)
+impl<K, V> RedisCache<K, V>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+{
+    #[allow(clippy::new_ret_no_self)]
+    /// Initialize a `RedisCacheBuilder` for keys which don't implement `Display`
+    pub fn new_serialized<S: ToString>(prefix: S, seconds: Option<u64>) -> RedisCacheBuilder<K, V> {
+        RedisCacheBuilder::new_serialized(prefix, seconds)
+    }
+}
+
+impl<K, V> RedisCache<K, V>
+where
+    V: Serialize + DeserializeOwned,
+{
+    fn generate_key(&self, key: &K) -> Result<String, rmp_serde::encode::Error> {
+        Ok(format!("{}{}", self.key_prefix, (self.key_fn)(key)?))
+    }
+
+    // all the redis keys of this cache, i.e., starting with the key prefix
+    fn scan_keys(
+        &self,
+        conn: &mut r2d2::PooledConnection<redis::Client>,
+    ) -> Result<Vec<String>, RedisCacheError> {
+        use redis::Commands;
+
+        let pattern = format!("{}*", escape_glob(&self.key_prefix));
+        let keys = conn.scan_match::<_, String>(pattern)?.collect();
+        Ok(keys)
+    }
+
+    /// Return the number of cached entries, by scanning the keys starting with `{namespace}:{prefix}:`
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn len(&self) -> Result<usize, RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        Ok(self.scan_keys(&mut conn)?.len())
+    }
+
+    /// Return whether there is no cached entry
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn is_empty(&self) -> Result<bool, RedisCacheError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Remove all the cached entries, i.e., the keys starting with `{namespace}:{prefix}:`
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn clear(&self) -> Result<(), RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        let keys = self.scan_keys(&mut conn)?;
+        if !keys.is_empty() {
+            redis::cmd("DEL").arg(keys).query::<()>(&mut *conn)?;
+        }
+        Ok(())
+    }
+
+    /// Return all the cached entries, with the keys without `{namespace}:{prefix}:`
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn iter(&self) -> Result<Vec<(String, V)>, RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        let key_prefix_len = self.key_prefix.len();
+        let mut entries = vec![];
+        for key in self.scan_keys(&mut conn)? {
+            let value: Option<Vec<u8>> = redis::cmd("GET").arg(&key).query(&mut *conn)?;
+            // the key may be expired since the scan
+            if let Some(value) = value {
+                entries.push((
+                    key[key_prefix_len..].to_string(),
+                    rmp_serde::from_slice(&value)?,
+                ));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Set a value with its own ttl in seconds, instead of the ttl of the cache.
+    /// With `None`, the ttl of the cache is used, like [`IOKash::set`].
+    /// Note that with [`RedisCacheBuilder::set_refresh`], reading the value resets it to the ttl of the cache.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn set_with_ttl(
+        &self,
+        key: K,
+        val: V,
+        seconds: Option<u64>,
+    ) -> Result<Option<V>, RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        let mut pipe = redis::pipe();
+        let key = self.generate_key(&key)?;
+
+        pipe.get(&key);
+        let val = rmp_serde::to_vec(&val)?;
+        set_val(
+            seconds.map(Duration::from_secs).or(self.ttl),
+            &mut pipe,
+            key,
+            &val,
+        );
+
+        let res: (Option<Vec<u8>>,) = pipe.query(&mut *conn)?;
+        check_and_get_result(res)
+    }
+
+    /// Return the serialized value stored for the key, to compare it with [`RedisCache::set_if_unchanged`]
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn get_raw(&self, key: &K) -> Result<Option<Vec<u8>>, RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        let key = self.generate_key(key)?;
+        Ok(redis::cmd("GET").arg(key).query(&mut *conn)?)
+    }
+
+    /// Set a cached value atomically, only if the stored value is still `expected`,
+    /// i.e., the bytes returned by [`RedisCache::get_raw`], or `None` if it was absent.
+    /// Returns whether the value was set, so a newer value is never overwritten by an older computation.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `RedisCacheError`, depending on the error
+    pub fn set_if_unchanged(
+        &self,
+        key: K,
+        expected: Option<&[u8]>,
+        val: V,
+    ) -> Result<bool, RedisCacheError> {
+        let mut conn = self.pool.get()?;
+        let key = self.generate_key(&key)?;
+        let val = rmp_serde::to_vec(&val)?;
+        Ok(cas_invocation(&key, expected, &val, self.ttl).invoke(&mut *conn)?)
     }
 
     /// Return the redis connection string used
@@ -227,11 +480,15 @@ pub enum RedisCacheError {
     CacheDeserializationError(#[from] rmp_serde::decode::Error),
     #[error("Error serializing cached value")]
     CacheSerializationError(#[from] rmp_serde::encode::Error),
+    #[error("Cache key of {len} bytes is longer than max_key_len {max}")]
+    KeyTooLong { len: usize, max: usize },
+    #[cfg(feature = "redis_bb8")]
+    #[error("redis async pool error")]
+    AsyncPoolError(#[from] bb8::RunError<redis::RedisError>),
 }
 
 impl<K, V> IOKash<K, V> for RedisCache<K, V>
 where
-    K: Display,
     V: Serialize + DeserializeOwned,
 {
     type Error = RedisCacheError;
@@ -239,31 +496,22 @@ where
     fn get(&self, key: &K) -> Result<Option<V>, RedisCacheError> {
         let mut conn = self.pool.get()?;
         let mut pipe = redis::pipe();
-        let key = self.generate_key(key);
+        let key = self.generate_key(key)?;
 
-        pipe.get(&key);
+        get_val(self.refresh, self.ttl, &mut pipe, &key);
         // ugh: https://github.com/mitsuhiko/redis-rs/pull/388#issuecomment-910919137
         let res: (Option<Vec<u8>>,) = pipe.query(&mut *conn)?;
         check_and_get_result(res)
     }
 
     fn set(&self, key: K, val: V) -> Result<Option<V>, RedisCacheError> {
-        let mut conn = self.pool.get()?;
-        let mut pipe = redis::pipe();
-        let key = self.generate_key(&key);
-
-        pipe.get(&key);
-        let val = rmp_serde::to_vec(&val)?;
-        set_val(self.seconds, &mut pipe, key, &val);
-
-        let res: (Option<Vec<u8>>,) = pipe.query(&mut *conn)?;
-        check_and_get_result(res)
+        self.set_with_ttl(key, val, None)
     }
 
     fn remove(&self, key: &K) -> Result<Option<V>, RedisCacheError> {
         let mut conn = self.pool.get()?;
         let mut pipe = redis::pipe();
-        let key = self.generate_key(key);
+        let key = self.generate_key(key)?;
 
         pipe.get(&key);
         pipe.del(key).ignore();
@@ -272,12 +520,12 @@ where
     }
 
     fn ttl(&self) -> Option<u64> {
-        self.seconds
+        self.ttl.map(ttl_secs)
     }
 
     fn set_ttl(&mut self, seconds: u64) -> Option<u64> {
-        let old = self.seconds;
-        self.seconds = Some(seconds);
+        let old = self.ttl();
+        self.ttl = Some(Duration::from_secs(seconds));
         old
     }
 }
@@ -285,16 +533,30 @@ where
 #[cfg(all(feature = "async", feature = "redis_tokio"))]
 mod async_redis {
     use super::{
-        check_and_get_result, set_val, DeserializeOwned, Display, PhantomData,
-        RedisCacheBuildError, RedisCacheError, Serialize, DEFAULT_NAMESPACE, ENV_KEY,
+        cas_invocation, check_and_get_result, display_key, get_val, key_prefix,
+        namespace_or_default, serialized_key, set_val, ttl_secs, DeserializeOwned, Display,
+        Duration, KeyFn, PhantomData, RedisCacheBuildError, RedisCacheError, Serialize,
+        DEFAULT_DELIMITER, ENV_KEY,
     };
     use crate::IOKashAsync;
 
     pub struct AsyncRedisCacheBuilder<K, V> {
-        seconds: Option<u64>,
-        namespace: String,
+        ttl: Option<Duration>,
+        namespace: Option<String>,
         prefix: String,
+        delimiter: String,
+        schema_version: Option<u64>,
         connection_string: Option<String>,
+        #[cfg(feature = "redis_bb8")]
+        pool_max_size: Option<u32>,
+        #[cfg(feature = "redis_bb8")]
+        pool_min_idle: Option<u32>,
+        #[cfg(feature = "redis_bb8")]
+        pool_max_lifetime: Option<std::time::Duration>,
+        #[cfg(feature = "redis_bb8")]
+        pool_idle_timeout: Option<std::time::Duration>,
+        refresh: bool,
+        key_fn: KeyFn<K>,
         _phantom: PhantomData<(K, V)>,
     }
 
@@ -305,11 +567,59 @@ mod async_redis {
     {
         /// Initialize a `RedisCacheBuilder`
         pub fn new<S: ToString>(prefix: S, seconds: Option<u64>) -> AsyncRedisCacheBuilder<K, V> {
+            Self::with_key_fn(prefix, seconds, display_key)
+        }
+    }
+
+    impl<K, V> AsyncRedisCacheBuilder<K, V>
+    where
+        K: Serialize,
+        V: Serialize + DeserializeOwned,
+    {
+        /// Initialize an `AsyncRedisCacheBuilder` for keys which don't implement `Display`.
+        /// See [`AsyncRedisCacheBuilder::set_key_serialization`]
+        pub fn new_serialized<S: ToString>(
+            prefix: S,
+            seconds: Option<u64>,
+        ) -> AsyncRedisCacheBuilder<K, V> {
+            Self::with_key_fn(prefix, seconds, serialized_key)
+        }
+
+        /// Serialize keys with msgpack and store them as hex strings, instead of using their `Display` value.
+        /// The namespace and prefix are still prepended: `{namespace}:{prefix}:{hex}`, see [`Self::set_delimiter`].
+        ///
+        /// Serialized keys can be decoded back to `K`, but they take twice the size of their msgpack
+        /// encoding. Redis keys are limited to 512 MB, and long keys are slower to look up,
+        /// so prefer small key types.
+        #[must_use]
+        pub fn set_key_serialization(mut self) -> Self {
+            self.key_fn = serialized_key;
+            self
+        }
+    }
+
+    impl<K, V> AsyncRedisCacheBuilder<K, V>
+    where
+        V: Serialize + DeserializeOwned,
+    {
+        fn with_key_fn<S: ToString>(prefix: S, seconds: Option<u64>, key_fn: KeyFn<K>) -> Self {
             Self {
-                seconds,
-                namespace: DEFAULT_NAMESPACE.to_string(),
+                ttl: seconds.map(Duration::from_secs),
+                namespace: None,
                 prefix: prefix.to_string(),
+                delimiter: DEFAULT_DELIMITER.to_string(),
+                schema_version: None,
                 connection_string: None,
+                #[cfg(feature = "redis_bb8")]
+                pool_max_size: None,
+                #[cfg(feature = "redis_bb8")]
+                pool_min_idle: None,
+                #[cfg(feature = "redis_bb8")]
+                pool_max_lifetime: None,
+                #[cfg(feature = "redis_bb8")]
+                pool_idle_timeout: None,
+                refresh: false,
+                key_fn,
                 _phantom: PhantomData,
             }
         }
@@ -317,30 +627,62 @@ mod async_redis {
         /// Specify the cache ttl in seconds
         #[must_use]
         pub fn set_ttl(mut self, seconds: Option<u64>) -> Self {
-            self.seconds = seconds;
+            self.ttl = seconds.map(Duration::from_secs);
+            self
+        }
+
+        /// Specify the cache ttl as a `Duration`, with a precision of milliseconds
+        #[must_use]
+        pub fn set_ttl_duration(mut self, ttl: Option<Duration>) -> Self {
+            self.ttl = ttl;
+            self
+        }
+
+        /// Specify whether a cache hit resets the ttl of the value, like memory caches do,
+        /// so frequently read values don't expire
+        #[must_use]
+        pub fn set_refresh(mut self, refresh: bool) -> Self {
+            self.refresh = refresh;
             self
         }
 
-        /// Set the namespace for cache keys. Defaults to `kash:`.
-        /// Used to generate keys formatted as: `{namespace}{prefix}{key}`
-        /// Note that no delimiters are implicitly added, so you may pass
-        /// an empty string if you want there to be no namespace on keys.
+        /// Set the namespace for cache keys. Defaults to `kash` (`kash:` without a delimiter).
+        /// Used to generate keys formatted as: `{namespace}:{prefix}:{key}`, see [`Self::set_delimiter`].
+        /// You may pass an empty string if you want there to be no namespace on keys.
         #[must_use]
         pub fn set_namespace<S: ToString>(mut self, namespace: S) -> Self {
-            self.namespace = namespace.to_string();
+            self.namespace = Some(namespace.to_string());
             self
         }
 
-        /// Set the prefix for cache keys
-        /// Used to generate keys formatted as: `{namespace}{prefix}{key}`
-        /// Note that no delimiters are implicitly added, so you may pass
-        /// an empty string if you want there to be no prefix on keys.
+        /// Set the prefix for cache keys.
+        /// Used to generate keys formatted as: `{namespace}:{prefix}:{key}`, see [`Self::set_delimiter`].
+        /// You may pass an empty string if you want there to be no prefix on keys.
         #[must_use]
         pub fn set_prefix<S: ToString>(mut self, prefix: S) -> Self {
             self.prefix = prefix.to_string();
             self
         }
 
+        /// Set the delimiter added after the namespace and the prefix of cache keys, unless they're empty,
+        /// so a prefix and a key can't collide with another prefix and key.
+        /// Defaults to `:`. Pass an empty string for no delimiter, i.e., keys formatted as `{namespace}{prefix}{key}`.
+        /// Without a delimiter, the default namespace is `kash:`, so the keys are formatted as `kash:{prefix}{key}`,
+        /// like the keys stored by the versions without delimiters.
+        #[must_use]
+        pub fn set_delimiter<S: ToString>(mut self, delimiter: S) -> Self {
+            self.delimiter = delimiter.to_string();
+            self
+        }
+
+        /// Set the version of the schema of the values, added to the prefix of the keys as `v{schema_version}`,
+        /// see [`RedisCacheBuilder::set_schema_version`].
+        #[must_use]
+        pub fn set_schema_version(mut self, schema_version: u64) -> Self {
+            self.schema_version = Some(schema_version);
+            self
+        }
+
         /// Set the connection string for redis
         #[must_use]
         pub fn set_connection_string(mut self, cs: &str) -> Self {
@@ -348,6 +690,45 @@ mod async_redis {
             self
         }
 
+        /// Set the max size of the underlying redis connection pool
+        #[cfg(feature = "redis_bb8")]
+        #[must_use]
+        pub fn set_connection_pool_max_size(mut self, max_size: u32) -> Self {
+            self.pool_max_size = Some(max_size);
+            self
+        }
+
+        /// Set the minimum number of idle redis connections that should be maintained by the
+        /// underlying redis connection pool
+        #[cfg(feature = "redis_bb8")]
+        #[must_use]
+        pub fn set_connection_pool_min_idle(mut self, min_idle: u32) -> Self {
+            self.pool_min_idle = Some(min_idle);
+            self
+        }
+
+        /// Set the max lifetime of connections used by the underlying redis connection pool
+        #[cfg(feature = "redis_bb8")]
+        #[must_use]
+        pub fn set_connection_pool_max_lifetime(
+            mut self,
+            max_lifetime: std::time::Duration,
+        ) -> Self {
+            self.pool_max_lifetime = Some(max_lifetime);
+            self
+        }
+
+        /// Set the max lifetime of idle connections maintained by the underlying redis connection pool
+        #[cfg(feature = "redis_bb8")]
+        #[must_use]
+        pub fn set_connection_pool_idle_timeout(
+            mut self,
+            idle_timeout: std::time::Duration,
+        ) -> Self {
+            self.pool_idle_timeout = Some(idle_timeout);
+            self
+        }
+
         /// Return the current connection string or load from the env var: `KASH_REDIS_CONNECTION_STRING`
         ///
         /// # Errors
@@ -367,7 +748,7 @@ mod async_redis {
 
         /// Create a multiplexed redis connection. This is a single connection that can
         /// be used asynchronously by multiple futures.
-        #[cfg(not(feature = "redis_connection_manager"))]
+        #[cfg(not(any(feature = "redis_connection_manager", feature = "redis_bb8")))]
         async fn create_multiplexed_connection(
             &self,
         ) -> Result<redis::aio::MultiplexedConnection, RedisCacheBuildError> {
@@ -380,7 +761,7 @@ mod async_redis {
         /// Create a multiplexed connection wrapped in a manager. The manager provides access
         /// to a multiplexed connection and will automatically reconnect to the server when
         /// necessary.
-        #[cfg(feature = "redis_connection_manager")]
+        #[cfg(all(feature = "redis_connection_manager", not(feature = "redis_bb8")))]
         async fn create_connection_manager(
             &self,
         ) -> Result<redis::aio::ConnectionManager, RedisCacheBuildError> {
@@ -390,6 +771,49 @@ mod async_redis {
             Ok(conn)
         }
 
+        /// Create a pool of multiplexed connections, which are checked out by each operation.
+        #[cfg(feature = "redis_bb8")]
+        async fn create_pool(
+            &self,
+        ) -> Result<bb8::Pool<bb8_redis::RedisConnectionManager>, RedisCacheBuildError> {
+            let s = self.connection_string()?;
+            let manager = bb8_redis::RedisConnectionManager::new(s)?;
+            // like the sync pool, only overwrite the defaults of the pool builder with given values
+            let pool_builder = bb8::Pool::builder();
+            let pool_builder = if let Some(max_size) = self.pool_max_size {
+                pool_builder.max_size(max_size)
+            } else {
+                pool_builder
+            };
+            let pool_builder = if let Some(min_idle) = self.pool_min_idle {
+                pool_builder.min_idle(Some(min_idle))
+            } else {
+                pool_builder
+            };
+            let pool_builder = if let Some(max_lifetime) = self.pool_max_lifetime {
+                pool_builder.max_lifetime(Some(max_lifetime))
+            } else {
+                pool_builder
+            };
+            let pool_builder = if let Some(idle_timeout) = self.pool_idle_timeout {
+                pool_builder.idle_timeout(Some(idle_timeout))
+            } else {
+                pool_builder
+            };
+
+            let pool = pool_builder.build(manager).await?;
+            Ok(pool)
+        }
+
+        fn key_prefix(&self) -> String {
+            key_prefix(
+                namespace_or_default(self.namespace.as_deref(), &self.delimiter),
+                &self.prefix,
+                self.schema_version,
+                &self.delimiter,
+            )
+        }
+
         /// The last step in building a `RedisCache` is to call `build()`
         ///
         /// # Errors
@@ -397,14 +821,17 @@ mod async_redis {
         /// Will return a `RedisCacheBuildError`, depending on the error
         pub async fn build(self) -> Result<AsyncRedisCache<K, V>, RedisCacheBuildError> {
             Ok(AsyncRedisCache {
-                seconds: self.seconds,
+                ttl: self.ttl,
                 connection_string: self.connection_string()?,
-                #[cfg(not(feature = "redis_connection_manager"))]
+                #[cfg(not(any(feature = "redis_connection_manager", feature = "redis_bb8")))]
                 connection: self.create_multiplexed_connection().await?,
-                #[cfg(feature = "redis_connection_manager")]
+                #[cfg(all(feature = "redis_connection_manager", not(feature = "redis_bb8")))]
                 connection: self.create_connection_manager().await?,
-                namespace: self.namespace,
-                prefix: self.prefix,
+                #[cfg(feature = "redis_bb8")]
+                pool: self.create_pool().await?,
+                key_prefix: self.key_prefix(),
+                refresh: self.refresh,
+                key_fn: self.key_fn,
                 _phantom: PhantomData,
             })
         }
@@ -413,20 +840,68 @@ mod async_redis {
     /// Cache store backed by redis
     ///
     /// Values have a ttl applied and enforced by redis.
-    /// Uses a `redis::aio::MultiplexedConnection` or `redis::aio::ConnectionManager`
-    /// under the hood depending on if feature `redis_connection_manager` is used or not.
+    /// Uses a `redis::aio::MultiplexedConnection` under the hood, a `redis::aio::ConnectionManager`
+    /// with the feature `redis_connection_manager`, or a `bb8` pool of multiplexed connections
+    /// with the feature `redis_bb8`.
     pub struct AsyncRedisCache<K, V> {
-        pub(super) seconds: Option<u64>,
-        pub(super) namespace: String,
-        pub(super) prefix: String,
+        pub(super) ttl: Option<Duration>,
+        key_prefix: String,
         connection_string: String,
-        #[cfg(not(feature = "redis_connection_manager"))]
+        #[cfg(not(any(feature = "redis_connection_manager", feature = "redis_bb8")))]
         connection: redis::aio::MultiplexedConnection,
-        #[cfg(feature = "redis_connection_manager")]
+        #[cfg(all(feature = "redis_connection_manager", not(feature = "redis_bb8")))]
         connection: redis::aio::ConnectionManager,
+        #[cfg(feature = "redis_bb8")]
+        pool: bb8::Pool<bb8_redis::RedisConnectionManager>,
+        refresh: bool,
+        key_fn: KeyFn<K>,
         _phantom: PhantomData<(K, V)>,
     }
 
+    /// A connection checked out of the pool, returned to it when dropped
+    #[cfg(feature = "redis_bb8")]
+    struct PooledConnection<'a>(bb8::PooledConnection<'a, bb8_redis::RedisConnectionManager>);
+
+    #[cfg(feature = "redis_bb8")]
+    impl redis::aio::ConnectionLike for PooledConnection<'_> {
+        fn req_packed_command<'a>(
+            &'a mut self,
+            cmd: &'a redis::Cmd,
+        ) -> redis::RedisFuture<'a, redis::Value> {
+            (*self.0).req_packed_command(cmd)
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a redis::Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            (*self.0).req_packed_commands(cmd, offset, count)
+        }
+
+        fn get_db(&self) -> i64 {
+            (*self.0).get_db()
+        }
+    }
+
+    impl<K, V> AsyncRedisCache<K, V> {
+        #[cfg(not(any(feature = "redis_connection_manager", feature = "redis_bb8")))]
+        async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, RedisCacheError> {
+            Ok(self.connection.clone())
+        }
+
+        #[cfg(all(feature = "redis_connection_manager", not(feature = "redis_bb8")))]
+        async fn connection(&self) -> Result<redis::aio::ConnectionManager, RedisCacheError> {
+            Ok(self.connection.clone())
+        }
+
+        #[cfg(feature = "redis_bb8")]
+        async fn connection(&self) -> Result<PooledConnection<'_>, RedisCacheError> {
+            Ok(PooledConnection(self.pool.get().await?))
+        }
+    }
+
     impl<K, V> AsyncRedisCache<K, V>
     where
         K: Display + Send + Sync,
@@ -437,9 +912,92 @@ mod async_redis {
         pub fn new<S: ToString>(prefix: S, seconds: Option<u64>) -> AsyncRedisCacheBuilder<K, V> {
             AsyncRedisCacheBuilder::new(prefix, seconds)
         }
+    }
+
+    impl<K, V> AsyncRedisCache<K, V>
+    where
+        K: Serialize + Send + Sync,
+        V: Serialize + DeserializeOwned + Send + Sync,
+    {
+        #[allow(clippy::new_ret_no_self)]
+        /// Initialize an `AsyncRedisCacheBuilder` for keys which don't implement `Display`
+        pub fn new_serialized<S: ToString>(
+            prefix: S,
+            seconds: Option<u64>,
+        ) -> AsyncRedisCacheBuilder<K, V> {
+            AsyncRedisCacheBuilder::new_serialized(prefix, seconds)
+        }
+    }
+
+    impl<K, V> AsyncRedisCache<K, V>
+    where
+        K: Send + Sync,
+        V: Serialize + DeserializeOwned + Send + Sync,
+    {
+        fn generate_key(&self, key: &K) -> Result<String, rmp_serde::encode::Error> {
+            Ok(format!("{}{}", self.key_prefix, (self.key_fn)(key)?))
+        }
+
+        /// Set a value with its own ttl in seconds, instead of the ttl of the cache.
+        /// With `None`, the ttl of the cache is used, like [`IOKashAsync::set`].
+        /// Note that with [`AsyncRedisCacheBuilder::set_refresh`], reading the value resets it to the ttl of the cache.
+        ///
+        /// # Errors
+        ///
+        /// Will return a `RedisCacheError`, depending on the error
+        pub async fn set_with_ttl(
+            &self,
+            key: K,
+            val: V,
+            seconds: Option<u64>,
+        ) -> Result<Option<V>, RedisCacheError> {
+            let mut conn = self.connection().await?;
+            let mut pipe = redis::pipe();
+            let key = self.generate_key(&key)?;
+
+            pipe.get(&key);
+            let val = rmp_serde::to_vec(&val)?;
+            set_val(
+                seconds.map(Duration::from_secs).or(self.ttl),
+                &mut pipe,
+                key,
+                &val,
+            );
+
+            let res: (Option<Vec<u8>>,) = pipe.query_async(&mut conn).await?;
+            check_and_get_result(res)
+        }
 
-        fn generate_key(&self, key: &K) -> String {
-            format!("{}{}{}", self.namespace, self.prefix, key)
+        /// Return the serialized value stored for the key, to compare it with [`AsyncRedisCache::set_if_unchanged`]
+        ///
+        /// # Errors
+        ///
+        /// Will return a `RedisCacheError`, depending on the error
+        pub async fn get_raw(&self, key: &K) -> Result<Option<Vec<u8>>, RedisCacheError> {
+            let mut conn = self.connection().await?;
+            let key = self.generate_key(key)?;
+            Ok(redis::cmd("GET").arg(key).query_async(&mut conn).await?)
+        }
+
+        /// Set a cached value atomically, only if the stored value is still `expected`,
+        /// i.e., the bytes returned by [`AsyncRedisCache::get_raw`], or `None` if it was absent.
+        /// Returns whether the value was set, so a newer value is never overwritten by an older computation.
+        ///
+        /// # Errors
+        ///
+        /// Will return a `RedisCacheError`, depending on the error
+        pub async fn set_if_unchanged(
+            &self,
+            key: K,
+            expected: Option<&[u8]>,
+            val: V,
+        ) -> Result<bool, RedisCacheError> {
+            let mut conn = self.connection().await?;
+            let key = self.generate_key(&key)?;
+            let val = rmp_serde::to_vec(&val)?;
+            Ok(cas_invocation(&key, expected, &val, self.ttl)
+                .invoke_async(&mut conn)
+                .await?)
         }
 
         /// Return the redis connection string used
@@ -452,41 +1010,32 @@ mod async_redis {
     #[async_trait::async_trait]
     impl<K, V> IOKashAsync<K, V> for AsyncRedisCache<K, V>
     where
-        K: Display + Send + Sync,
+        K: Send + Sync,
         V: Serialize + DeserializeOwned + Send + Sync,
     {
         type Error = RedisCacheError;
 
         /// Get a cached value
         async fn get(&self, key: &K) -> Result<Option<V>, Self::Error> {
-            let mut conn = self.connection.clone();
+            let mut conn = self.connection().await?;
             let mut pipe = redis::pipe();
-            let key = self.generate_key(key);
+            let key = self.generate_key(key)?;
 
-            pipe.get(&key);
+            get_val(self.refresh, self.ttl, &mut pipe, &key);
             let res: (Option<Vec<u8>>,) = pipe.query_async(&mut conn).await?;
             check_and_get_result(res)
         }
 
         /// Set a cached value
         async fn set(&self, key: K, val: V) -> Result<Option<V>, Self::Error> {
-            let mut conn = self.connection.clone();
-            let mut pipe = redis::pipe();
-            let key = self.generate_key(&key);
-
-            pipe.get(&key);
-            let val = rmp_serde::to_vec(&val)?;
-            set_val(self.seconds, &mut pipe, key, &val);
-
-            let res: (Option<Vec<u8>>,) = pipe.query_async(&mut conn).await?;
-            check_and_get_result(res)
+            self.set_with_ttl(key, val, None).await
         }
 
         /// Remove a cached value
         async fn remove(&self, key: &K) -> Result<Option<V>, Self::Error> {
-            let mut conn = self.connection.clone();
+            let mut conn = self.connection().await?;
             let mut pipe = redis::pipe();
-            let key = self.generate_key(key);
+            let key = self.generate_key(key)?;
 
             pipe.get(&key);
             pipe.del(&key).ignore();
@@ -496,13 +1045,13 @@ mod async_redis {
 
         /// Return the ttl of cached values (time to eviction)
         fn ttl(&self) -> Option<u64> {
-            self.seconds
+            self.ttl.map(ttl_secs)
         }
 
         /// Set the ttl of cached values, returns the old value
         fn set_ttl(&mut self, seconds: u64) -> Option<u64> {
-            let old = self.seconds;
-            self.seconds = Some(seconds);
+            let old = self.ttl();
+            self.ttl = Some(Duration::from_secs(seconds));
             old
         }
     }
@@ -550,7 +1099,75 @@ mod async_redis {
             assert_eq!(c.get(&1).await.unwrap().unwrap(), 100);
             assert_eq!(c.get(&1).await.unwrap().unwrap(), 100);
         }
+
+        #[cfg(feature = "redis_bb8")]
+        #[tokio::test]
+        async fn test_async_redis_cache_pool() {
+            let c: AsyncRedisCache<u32, u32> =
+                AsyncRedisCache::new(format!("{}:async-redis-pool-test", now_millis()), Some(10))
+                    .set_connection_pool_max_size(2)
+                    .build()
+                    .await
+                    .unwrap();
+
+            // more concurrent operations than pooled connections wait for a connection
+            let (one, two, three) = tokio::join!(c.set(1, 10), c.set(2, 20), c.set(3, 30));
+            assert!(one.unwrap().is_none());
+            assert!(two.unwrap().is_none());
+            assert!(three.unwrap().is_none());
+            for i in 1..=3 {
+                assert_eq!(Some(i * 10), c.get(&i).await.unwrap());
+            }
+            assert_eq!(Some(10), c.remove(&1).await.unwrap());
+            assert!(c.get(&1).await.unwrap().is_none());
+        }
+    }
+}
+
+// the start of all the keys, i.e., `{namespace}{delimiter}{prefix}{delimiter}`,
+// without the delimiter after the empty parts. It's added even after the parts already ending with it,
+// otherwise the prefixes `foo:` and `foo` would collide
+fn key_prefix(
+    namespace: &str,
+    prefix: &str,
+    schema_version: Option<u64>,
+    delimiter: &str,
+) -> String {
+    let schema_version = schema_version
+        .map(|schema_version| format!("v{schema_version}"))
+        .unwrap_or_default();
+    let mut key_prefix = String::with_capacity(
+        namespace.len() + prefix.len() + schema_version.len() + 3 * delimiter.len(),
+    );
+    for part in [namespace, prefix, &schema_version] {
+        if part.is_empty() {
+            continue;
+        }
+        key_prefix.push_str(part);
+        key_prefix.push_str(delimiter);
+    }
+    key_prefix
+}
+
+// the namespace, or the default one, which ends with `:` without a delimiter, like the keys of the versions without delimiters
+fn namespace_or_default<'a>(namespace: Option<&'a str>, delimiter: &str) -> &'a str {
+    match namespace {
+        Some(namespace) => namespace,
+        None if delimiter.is_empty() => LEGACY_NAMESPACE,
+        None => DEFAULT_NAMESPACE,
+    }
+}
+
+// escape the special characters of a redis glob pattern
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
 }
 
 fn check_and_get_result<V>(res: (Option<Vec<u8>>,)) -> Result<Option<V>, RedisCacheError>
@@ -566,9 +1183,61 @@ where
     }
 }
 
-fn set_val(seconds: Option<u64>, pipe: &mut Pipeline, key: String, val: &[u8]) {
-    if let Some(seconds) = seconds {
-        pipe.set_ex(key, val, seconds).ignore();
+// compare the stored value with ARGV[2] (or its absence, if ARGV[1] is 0) and only then set it,
+// in a script, so no other client can write in between
+static CAS_SCRIPT: Lazy<redis::Script> = Lazy::new(|| {
+    redis::Script::new(
+        r"
+        local current = redis.call('GET', KEYS[1])
+        if ARGV[1] == '1' then
+            if current ~= ARGV[2] then
+                return 0
+            end
+        elseif current then
+            return 0
+        end
+        if ARGV[4] == '0' then
+            redis.call('SET', KEYS[1], ARGV[3])
+        else
+            redis.call('SET', KEYS[1], ARGV[3], 'PX', ARGV[4])
+        end
+        return 1
+        ",
+    )
+});
+
+fn cas_invocation(
+    key: &str,
+    expected: Option<&[u8]>,
+    val: &[u8],
+    ttl: Option<Duration>,
+) -> redis::ScriptInvocation<'static> {
+    let mut invocation = CAS_SCRIPT.prepare_invoke();
+    invocation
+        .key(key)
+        .arg(u8::from(expected.is_some()))
+        .arg(expected.unwrap_or_default())
+        .arg(val)
+        .arg(ttl.map_or(0, ttl_millis));
+    invocation
+}
+
+// the ttl in milliseconds for redis, which rejects a zero ttl
+fn ttl_millis(ttl: Duration) -> u64 {
+    u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1)
+}
+
+// get the value, and with `refresh`, reset its ttl in the same pipeline
+fn get_val(refresh: bool, ttl: Option<Duration>, pipe: &mut Pipeline, key: &str) {
+    pipe.get(key);
+    if let (true, Some(ttl)) = (refresh, ttl) {
+        pipe.pexpire(key, ttl_millis(ttl) as i64).ignore();
+    }
+}
+
+fn set_val(ttl: Option<Duration>, pipe: &mut Pipeline, key: String, val: &[u8]) {
+    if let Some(ttl) = ttl {
+        pipe.pset_ex(key, val, ttl_millis(ttl)).ignore();
     } else {
         pipe.set(key, val).ignore();
     }
@@ -597,7 +1266,7 @@ mod tests {
     fn redis_cache() {
         let mut c: RedisCache<u32, u32> =
             RedisCache::new(format!("{}:redis-cache-test", now_millis()), Some(2))
-                .set_namespace("in-tests:")
+                .set_namespace("in-tests")
                 .build()
                 .unwrap();
 
@@ -624,6 +1293,48 @@ mod tests {
         assert_eq!(c.get(&1).unwrap().unwrap(), 100);
     }
 
+    #[test]
+    fn refresh_on_read() {
+        let c: RedisCache<u32, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-refresh:", now_millis()),
+            Some(2),
+        )
+        .set_refresh(true)
+        .build()
+        .unwrap();
+        let not_refreshed: RedisCache<u32, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-not-refreshed:", now_millis()),
+            Some(2),
+        )
+        .build()
+        .unwrap();
+
+        assert!(c.set(1, 100).unwrap().is_none());
+        assert!(not_refreshed.set(1, 100).unwrap().is_none());
+        for _ in 0..3 {
+            sleep(Duration::from_millis(1500));
+            assert_eq!(100, c.get(&1).unwrap().unwrap());
+        }
+        // 4.5 seconds have passed, more than the 2 seconds ttl
+        assert!(not_refreshed.get(&1).unwrap().is_none());
+    }
+
+    #[test]
+    fn set_with_ttl() {
+        let c: RedisCache<u32, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-set-with-ttl", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+
+        assert!(c.set_with_ttl(1, 100, Some(1)).unwrap().is_none());
+        assert!(c.set_with_ttl(2, 200, None).unwrap().is_none());
+        sleep(Duration::from_millis(1100));
+        assert!(c.get(&1).unwrap().is_none());
+        assert_eq!(200, c.get(&2).unwrap().unwrap());
+    }
+
     #[test]
     fn remove() {
         let c: RedisCache<u32, u32> = RedisCache::new(
@@ -639,4 +1350,170 @@ mod tests {
 
         assert_eq!(100, c.remove(&1).unwrap().unwrap());
     }
+
+    #[test]
+    fn key_prefixes_are_delimited() {
+        assert_eq!("kash:foo:", key_prefix("kash", "foo", None, ":"));
+        assert_eq!("kash:foo::", key_prefix("kash", "foo:", None, ":"));
+        assert_ne!(
+            key_prefix("kash", "foo:", None, ":"),
+            key_prefix("kash", "foo", None, ":")
+        );
+        assert_eq!("foo:", key_prefix("", "foo", None, ":"));
+        assert_eq!("kash:", key_prefix("kash", "", None, ":"));
+        assert_eq!("kashfoo", key_prefix("kash", "foo", None, ""));
+        assert_eq!("kash/foo/", key_prefix("kash", "foo", None, "/"));
+        assert_eq!("kash:foo:v2:", key_prefix("kash", "foo", Some(2), ":"));
+        assert_eq!("kash:v0:", key_prefix("kash", "", Some(0), ":"));
+    }
+
+    #[test]
+    fn legacy_keys_without_a_delimiter() {
+        let builder = RedisCache::<u32, u32>::new("foo", None).set_delimiter("");
+        assert_eq!("kash:foo1", format!("{}{}", builder.key_prefix(), 1));
+        let builder = builder.set_namespace("kash");
+        assert_eq!("kashfoo1", format!("{}{}", builder.key_prefix(), 1));
+        let builder = RedisCache::<u32, u32>::new("foo", None);
+        assert_eq!("kash:foo:1", format!("{}{}", builder.key_prefix(), 1));
+    }
+
+    #[test]
+    fn delimited_prefixes_do_not_collide() {
+        let foo: RedisCache<String, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-delimiter-foo", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+        let foob: RedisCache<String, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-delimiter-foob", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+
+        // without a delimiter, both would be stored as `...-foobar`
+        assert!(foo.set("bar".to_string(), 1).unwrap().is_none());
+        assert!(foob.set("ar".to_string(), 2).unwrap().is_none());
+        assert_eq!(1, foo.get(&"bar".to_string()).unwrap().unwrap());
+        assert_eq!(2, foob.get(&"ar".to_string()).unwrap().unwrap());
+    }
+
+    #[test]
+    fn glob_patterns_are_escaped() {
+        assert_eq!(r"kash:a\*b\?\[c\]\\", escape_glob(r"kash:a*b?[c]\"));
+    }
+
+    #[test]
+    fn clear_and_iter() {
+        let c: RedisCache<u32, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-clear-and-iter:", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+
+        assert!(c.set(1, 100).unwrap().is_none());
+        assert!(c.set(2, 200).unwrap().is_none());
+        assert_eq!(2, c.len().unwrap());
+
+        let mut entries = c.iter().unwrap();
+        entries.sort();
+        assert_eq!(
+            vec![("1".to_string(), 100), ("2".to_string(), 200)],
+            entries
+        );
+
+        c.clear().unwrap();
+        assert!(c.is_empty().unwrap());
+    }
+
+    #[test]
+    fn set_if_unchanged() {
+        let c: RedisCache<u32, u32> = RedisCache::new(
+            format!("{}:redis-cache-test-set-if-unchanged:", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+
+        assert!(c.get_raw(&1).unwrap().is_none());
+        assert!(c.set_if_unchanged(1, None, 100).unwrap());
+        assert!(!c.set_if_unchanged(1, None, 200).unwrap());
+
+        let stale = c.get_raw(&1).unwrap();
+        assert!(c.set_if_unchanged(1, stale.as_deref(), 300).unwrap());
+        assert!(!c.set_if_unchanged(1, stale.as_deref(), 400).unwrap());
+        assert_eq!(300, c.get(&1).unwrap().unwrap());
+    }
+
+    #[test]
+    fn set_if_unchanged_concurrently() {
+        let c: RedisCache<u32, u32> = RedisCache::new(
+            format!(
+                "{}:redis-cache-test-set-if-unchanged-concurrently:",
+                now_millis()
+            ),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+        c.set(1, 0).unwrap();
+
+        // all the threads read the same value, then race to replace it
+        let barrier = std::sync::Barrier::new(8);
+        let written = std::thread::scope(|s| {
+            let handles = (1..=8)
+                .map(|n| {
+                    let (c, barrier) = (&c, &barrier);
+                    s.spawn(move || {
+                        let stale = c.get_raw(&1).unwrap();
+                        barrier.wait();
+                        c.set_if_unchanged(1, stale.as_deref(), n).unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|written| *written)
+                .count()
+        });
+
+        assert_eq!(1, written);
+        assert_ne!(0, c.get(&1).unwrap().unwrap());
+    }
+
+    #[test]
+    fn serialized_keys_round_trip() {
+        #[derive(Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Pair(u8, u8);
+
+        let key = serialized_key(&Pair(1, 2)).unwrap();
+        assert_eq!("920102", key);
+
+        let bytes: Vec<u8> = (0..key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&key[i..i + 2], 16).unwrap())
+            .collect();
+        let decoded: Pair = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(Pair(1, 2), decoded);
+    }
+
+    #[test]
+    fn serialized_keys_without_display() {
+        #[derive(Serialize)]
+        struct UserId(u64);
+
+        let c: RedisCache<UserId, u32> = RedisCache::new_serialized(
+            format!("{}:redis-cache-test-serialized", now_millis()),
+            Some(3600),
+        )
+        .build()
+        .unwrap();
+
+        assert!(c.set(UserId(1), 100).unwrap().is_none());
+        assert_eq!(100, c.get(&UserId(1)).unwrap().unwrap());
+        assert!(c.get(&UserId(2)).unwrap().is_none());
+    }
 }
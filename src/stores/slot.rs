@@ -0,0 +1,344 @@
+use std::any::Any;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// The cache of the memory caches of non-`async` functions without arguments, holding their only value.
+///
+/// Its key is always `()`, and it has the subset of the methods of a `moka` cache used by the generated
+/// functions and the ones to invalidate it, e.g. `CONFIG.invalidate_all()`, without the hash map of a cache.
+/// Concurrent callers wait for the caller computing the value, like the entry API of `moka`, and share its
+/// error (or `None`) if it fails, instead of computing it again.
+///
+/// ```rust
+/// use kash::{kash, SlotCache};
+///
+/// #[kash(ttl = "60")]
+/// fn config() -> String {
+///     "loaded".to_string()
+/// }
+///
+/// assert_eq!("loaded", config());
+/// let cache: &SlotCache<String> = &CONFIG;
+/// assert!(cache.contains_key(&()));
+/// CONFIG.invalidate_all();
+/// assert_eq!(0, CONFIG.entry_count());
+/// ```
+pub struct SlotCache<V> {
+    slot: RwLock<Option<Slot<V>>>,
+    ttl: Option<Duration>,
+    // held while computing the value, so it's computed once for the concurrent callers,
+    // with the error of the last computation, which the callers waiting for it return
+    computing: Mutex<Option<Arc<dyn Any + Send + Sync>>>,
+    // the number of finished computations, to tell whether one finished while waiting for the lock
+    computed: AtomicU64,
+}
+
+struct Slot<V> {
+    value: V,
+    expires_at: Option<Instant>,
+}
+
+// the error of `or_optionally_insert_with`, private so it's never shared with the callers of
+// `or_try_insert_with`, e.g. of a `result` function whose error is `()`
+struct NoValue;
+
+/// The value returned by the entry API of a [`SlotCache`], like a `moka::Entry`
+pub struct SlotEntry<V> {
+    value: V,
+    is_fresh: bool,
+}
+
+impl<V> SlotEntry<V> {
+    /// Return whether the value was just computed, instead of being cached
+    #[must_use]
+    pub fn is_fresh(&self) -> bool {
+        self.is_fresh
+    }
+
+    #[must_use]
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    #[must_use]
+    pub fn into_value(self) -> V {
+        self.value
+    }
+}
+
+/// The entry of the value of a [`SlotCache`], to get it or compute it, like a `moka` `RefKeyEntrySelector`
+pub struct SlotEntryRef<'a, V> {
+    cache: &'a SlotCache<V>,
+}
+
+impl<V: Clone> SlotCache<V> {
+    /// Create an empty cache, whose value expires after the `ttl`, if any
+    #[must_use]
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            slot: RwLock::new(None),
+            ttl,
+            computing: Mutex::new(None),
+            computed: AtomicU64::new(0),
+        }
+    }
+
+    /// Return the value, if it's cached and not expired
+    pub fn get(&self, _key: &()) -> Option<V> {
+        let slot = self.slot.read().unwrap_or_else(PoisonError::into_inner);
+        slot.as_ref()
+            .filter(|slot| slot.expires_at.map_or(true, |at| Instant::now() < at))
+            .map(|slot| slot.value.clone())
+    }
+
+    /// Cache the value, replacing the previous one
+    pub fn insert(&self, _key: (), value: V) {
+        let expires_at = self.ttl.map(|ttl| Instant::now() + ttl);
+        *self.slot.write().unwrap_or_else(PoisonError::into_inner) =
+            Some(Slot { value, expires_at });
+    }
+
+    /// Remove the value, returning it if it's not expired
+    pub fn remove(&self, key: &()) -> Option<V> {
+        let value = self.get(key);
+        self.invalidate_all();
+        value
+    }
+
+    /// Remove the value
+    pub fn invalidate(&self, _key: &()) {
+        self.invalidate_all();
+    }
+
+    /// Remove the value
+    pub fn invalidate_all(&self) {
+        *self.slot.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Return whether the value is cached and not expired
+    pub fn contains_key(&self, key: &()) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Return the number of cached values, one or zero
+    pub fn entry_count(&self) -> u64 {
+        u64::from(self.get(&()).is_some())
+    }
+
+    /// Do nothing, the value is removed as soon as it's invalidated or expired
+    pub fn run_pending_tasks(&self) {}
+
+    /// Return the cached value, with its `()` key, like the iterator of a `moka` cache
+    pub fn iter(&self) -> impl Iterator<Item = (Arc<()>, V)> {
+        self.get(&()).map(|value| (Arc::new(()), value)).into_iter()
+    }
+
+    /// Return the cached value, or compute and cache it with `init`
+    pub fn get_with_by_ref(&self, key: &(), init: impl FnOnce() -> V) -> V {
+        self.entry_by_ref(key).or_insert_with(init).into_value()
+    }
+
+    /// Return the entry of the value, to compute it if it's not cached
+    pub fn entry_by_ref(&self, _key: &()) -> SlotEntryRef<'_, V> {
+        SlotEntryRef { cache: self }
+    }
+
+    // return the cached value, or the one computed by `init`, which is cached if it's `Ok`,
+    // in the caller holding the `computing` lock, so the others wait for it, and return its error if it fails
+    fn get_or_try_insert<E: Send + Sync + 'static>(
+        &self,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<SlotEntry<V>, Arc<E>> {
+        let cached = |value| SlotEntry {
+            value,
+            is_fresh: false,
+        };
+        if let Some(value) = self.get(&()) {
+            return Ok(cached(value));
+        }
+        let computed = self.computed.load(Ordering::Acquire);
+        let mut error = self
+            .computing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(value) = self.get(&()) {
+            return Ok(cached(value));
+        }
+        if self.computed.load(Ordering::Acquire) != computed {
+            if let Some(error) = error.clone().and_then(|error| error.downcast::<E>().ok()) {
+                return Err(error);
+            }
+        }
+
+        let value = init();
+        self.computed.fetch_add(1, Ordering::Release);
+        match value {
+            Ok(value) => {
+                *error = None;
+                self.insert((), value.clone());
+                Ok(SlotEntry {
+                    value,
+                    is_fresh: true,
+                })
+            }
+            Err(e) => {
+                let e = Arc::new(e);
+                *error = Some(e.clone());
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<V: Clone> SlotEntryRef<'_, V> {
+    pub fn or_insert_with(self, init: impl FnOnce() -> V) -> SlotEntry<V> {
+        // no error can be of type `Infallible`, so the value is computed if the others failed
+        match self.cache.get_or_try_insert(|| Ok::<_, Infallible>(init())) {
+            Ok(entry) => entry,
+            Err(never) => match *never {},
+        }
+    }
+
+    /// Compute the value with `init`, only caching the `Ok` values
+    ///
+    /// # Errors
+    ///
+    /// Will return the error of `init`, like `moka`, in an `Arc`, which is shared with the callers waiting for it
+    pub fn or_try_insert_with<E: Send + Sync + 'static>(
+        self,
+        init: impl FnOnce() -> Result<V, E>,
+    ) -> Result<SlotEntry<V>, Arc<E>> {
+        self.cache.get_or_try_insert(init)
+    }
+
+    /// Compute the value with `init`, only caching the `Some` values
+    pub fn or_optionally_insert_with(
+        self,
+        init: impl FnOnce() -> Option<V>,
+    ) -> Option<SlotEntry<V>> {
+        self.cache.get_or_try_insert(|| init().ok_or(NoValue)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_value_is_computed_once() {
+        let cache = SlotCache::new(None);
+        let entry = cache.entry_by_ref(&()).or_insert_with(|| 1);
+        assert!(entry.is_fresh());
+        let entry = cache.entry_by_ref(&()).or_insert_with(|| unreachable!());
+        assert!(!entry.is_fresh());
+        assert_eq!(1, entry.into_value());
+
+        assert!(cache
+            .entry_by_ref(&())
+            .or_optionally_insert_with(|| None)
+            .is_some());
+        cache.invalidate_all();
+        assert!(cache
+            .entry_by_ref(&())
+            .or_optionally_insert_with(|| None)
+            .is_none());
+        assert!(cache
+            .entry_by_ref(&())
+            .or_try_insert_with(|| Err::<u32, _>("failed"))
+            .is_err());
+        assert_eq!(0, cache.entry_count());
+    }
+
+    #[test]
+    fn the_waiting_callers_share_the_error() {
+        let cache = Arc::new(SlotCache::<u32>::new(None));
+        let (started, leading) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let leader = std::thread::spawn({
+            let cache = cache.clone();
+            move || {
+                cache.entry_by_ref(&()).or_try_insert_with(|| {
+                    started.send(()).unwrap();
+                    released.recv().unwrap();
+                    Err::<u32, _>("failed")
+                })
+            }
+        });
+        leading.recv().unwrap();
+        let waiter = std::thread::spawn({
+            let cache = cache.clone();
+            move || {
+                cache
+                    .entry_by_ref(&())
+                    .or_try_insert_with(|| Ok::<_, &str>(1))
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+
+        let error = leader.join().unwrap().err().unwrap();
+        let shared = waiter.join().unwrap().err().unwrap();
+        assert!(Arc::ptr_eq(&error, &shared));
+        assert_eq!(0, cache.entry_count());
+
+        // the error isn't cached, so the next caller computes the value
+        let entry = cache
+            .entry_by_ref(&())
+            .or_try_insert_with(|| Ok::<_, &str>(1))
+            .unwrap();
+        assert!(entry.is_fresh());
+    }
+
+    #[test]
+    fn the_errors_are_shared_by_the_callers_of_the_same_method() {
+        let cache = Arc::new(SlotCache::<u32>::new(None));
+        let (started, leading) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let leader = std::thread::spawn({
+            let cache = cache.clone();
+            move || {
+                cache.entry_by_ref(&()).or_optionally_insert_with(|| {
+                    started.send(()).unwrap();
+                    released.recv().unwrap();
+                    None
+                })
+            }
+        });
+        leading.recv().unwrap();
+        let waiters = [
+            std::thread::spawn({
+                let cache = cache.clone();
+                move || cache.entry_by_ref(&()).or_insert_with(|| 1).into_value()
+            }),
+            std::thread::spawn({
+                let cache = cache.clone();
+                move || {
+                    cache
+                        .entry_by_ref(&())
+                        .or_try_insert_with(|| Ok::<_, ()>(1))
+                        .unwrap()
+                        .into_value()
+                }
+            }),
+        ];
+        std::thread::sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+
+        assert!(leader.join().unwrap().is_none());
+        for waiter in waiters {
+            assert_eq!(1, waiter.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn the_value_expires_after_the_ttl() {
+        let cache = SlotCache::new(Some(Duration::from_millis(50)));
+        cache.insert((), 1);
+        assert_eq!(Some(1), cache.get(&()));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(None, cache.get(&()));
+        assert_eq!(2, cache.get_with_by_ref(&(), || 2));
+    }
+}
@@ -0,0 +1,108 @@
+//! A cache per type, for the memory caches of trait methods with `in_trait`.
+//!
+//! The static of a default method is shared by all the implementors of the trait, so it maps the
+//! `TypeId` of each implementor to its own cache, which lives as long as the program, like a static.
+
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+/// The caches of a trait method, by the type implementing the trait
+pub struct TypeMap<C: 'static> {
+    caches: RwLock<HashMap<TypeId, &'static C>>,
+}
+
+impl<C: Send + Sync> TypeMap<C> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            caches: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Return the cache of the type `T`, creating it with `init` on the first call
+    pub fn get_or_init<T: ?Sized + 'static>(&self, init: impl FnOnce() -> C) -> &'static C {
+        let type_id = TypeId::of::<T>();
+        if let Some(cache) = self
+            .caches
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&type_id)
+        {
+            return cache;
+        }
+
+        let mut caches = self.caches.write().unwrap_or_else(PoisonError::into_inner);
+        caches
+            .entry(type_id)
+            .or_insert_with(|| Box::leak(Box::new(init())))
+    }
+}
+
+impl<C> TypeMap<C> {
+    /// Return the caches of all the types
+    pub fn caches(&self) -> Vec<&'static C> {
+        self.caches
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .copied()
+            .collect()
+    }
+}
+
+impl<C: Send + Sync> Default for TypeMap<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn types_get_their_own_cache() {
+        let caches = TypeMap::<AtomicUsize>::new();
+        caches
+            .get_or_init::<u32>(AtomicUsize::default)
+            .fetch_add(1, Ordering::SeqCst);
+        caches
+            .get_or_init::<u32>(AtomicUsize::default)
+            .fetch_add(1, Ordering::SeqCst);
+        caches
+            .get_or_init::<str>(AtomicUsize::default)
+            .fetch_add(1, Ordering::SeqCst);
+
+        assert_eq!(
+            2,
+            caches
+                .get_or_init::<u32>(|| unreachable!())
+                .load(Ordering::SeqCst)
+        );
+        assert_eq!(
+            1,
+            caches
+                .get_or_init::<str>(|| unreachable!())
+                .load(Ordering::SeqCst)
+        );
+    }
+
+    #[test]
+    fn init_is_called_once_per_type() {
+        let inits = AtomicUsize::new(0);
+        let caches = TypeMap::<u32>::new();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    caches.get_or_init::<u32>(|| {
+                        inits.fetch_add(1, Ordering::SeqCst);
+                        1
+                    })
+                });
+            }
+        });
+        assert_eq!(1, inits.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "mem_store")]
+
+use kash::kash;
+use std::time::Duration;
+
+#[kash]
+fn default_sized(n: u32) -> u32 {
+    n
+}
+
+#[kash(size = "5", ttl = "10")]
+fn explicitly_sized(n: u32) -> u32 {
+    n
+}
+
+#[kash(expiry = "kash::expiry::TtlFor::<u32>::new(|_| None, None)")]
+fn with_expiry(n: u32) -> u32 {
+    n
+}
+
+// the defaults are process-wide, so they're tested in their own binary
+#[test]
+fn test_runtime_defaults() {
+    kash::defaults::set_default_size(Some(100));
+    kash::defaults::set_default_ttl(Some(30));
+
+    default_sized(1);
+    explicitly_sized(1);
+    with_expiry(1);
+
+    assert_eq!(Some(100), DEFAULT_SIZED.policy().max_capacity());
+    assert_eq!(
+        Some(Duration::from_secs(30)),
+        DEFAULT_SIZED.policy().time_to_live()
+    );
+
+    // the attributes take precedence
+    assert_eq!(Some(5), EXPLICITLY_SIZED.policy().max_capacity());
+    assert_eq!(
+        Some(Duration::from_secs(10)),
+        EXPLICITLY_SIZED.policy().time_to_live()
+    );
+
+    // `expiry` computes the ttl of the entries instead
+    assert_eq!(Some(100), WITH_EXPIRY.policy().max_capacity());
+    assert_eq!(None, WITH_EXPIRY.policy().time_to_live());
+}
@@ -17,6 +17,182 @@ impl From<DiskCacheError> for TestError {
     }
 }
 
+#[derive(Error, Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+enum SerializableError {
+    #[error("error with disk cache `{0}`")]
+    DiskError(String),
+    #[error("count `{0}`")]
+    Count(u32),
+}
+
+impl From<DiskCacheError> for SerializableError {
+    fn from(e: DiskCacheError) -> Self {
+        SerializableError::DiskError(format!("{e:?}"))
+    }
+}
+
+static CACHE_ERRORS_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, cache_errors)]
+fn kash_disk_cache_errors(n: u32) -> Result<u32, SerializableError> {
+    CACHE_ERRORS_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(SerializableError::Count(n))
+    }
+}
+
+#[test]
+fn test_kash_disk_cache_errors() {
+    KASH_DISK_CACHE_ERRORS.connection().clear().unwrap();
+    assert_eq!(kash_disk_cache_errors(1), Ok(1));
+    assert_eq!(kash_disk_cache_errors(5), Err(SerializableError::Count(5)));
+    assert_eq!(kash_disk_cache_errors(5), Err(SerializableError::Count(5)));
+    assert_eq!(kash_disk_cache_errors(1), Ok(1));
+    assert_eq!(
+        CACHE_ERRORS_CALLS.load(std::sync::atomic::Ordering::SeqCst),
+        2
+    );
+}
+
+static TRY_KEY_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, key(ty = "u32", try_expr = "raw.parse::<u32>()"))]
+fn kash_disk_try_key(raw: String) -> Result<usize, TestError> {
+    TRY_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok(raw.len())
+}
+
+#[test]
+fn test_kash_disk_try_key() {
+    KASH_DISK_TRY_KEY.connection().clear().unwrap();
+    assert_eq!(kash_disk_try_key("12".to_string()), Ok(2));
+    assert_eq!(kash_disk_try_key("12".to_string()), Ok(2));
+    assert_eq!(kash_disk_try_key("x".to_string()), Ok(1));
+    assert_eq!(kash_disk_try_key("x".to_string()), Ok(1));
+    assert_eq!(TRY_KEY_CALLS.load(std::sync::atomic::Ordering::SeqCst), 3);
+    assert_eq!(KASH_DISK_TRY_KEY.connection().len(), 1);
+}
+
+static BYPASS_LONG_KEY_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, max_key_len = 8)]
+fn kash_disk_bypass_long_key(s: String) -> Result<usize, TestError> {
+    BYPASS_LONG_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok(s.len())
+}
+
+#[test]
+fn test_kash_disk_bypass_long_key() {
+    KASH_DISK_BYPASS_LONG_KEY.connection().clear().unwrap();
+    let long = "a".repeat(100);
+    assert_eq!(kash_disk_bypass_long_key("short".to_string()), Ok(5));
+    assert_eq!(kash_disk_bypass_long_key("short".to_string()), Ok(5));
+    assert_eq!(kash_disk_bypass_long_key(long.clone()), Ok(100));
+    assert_eq!(kash_disk_bypass_long_key(long.clone()), Ok(100));
+    assert_eq!(
+        BYPASS_LONG_KEY_CALLS.load(std::sync::atomic::Ordering::SeqCst),
+        3
+    );
+    assert_eq!(KASH_DISK_BYPASS_LONG_KEY.connection().len(), 1);
+}
+
+static HASH_LONG_KEY_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, max_key_len = 72, key_overflow = "hash")]
+fn kash_disk_hash_long_key(s: String) -> Result<usize, TestError> {
+    HASH_LONG_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok(s.len())
+}
+
+#[test]
+fn test_kash_disk_hash_long_key() {
+    KASH_DISK_HASH_LONG_KEY.connection().clear().unwrap();
+    let long = "a".repeat(100);
+    assert_eq!(kash_disk_hash_long_key(long.clone()), Ok(100));
+    assert_eq!(kash_disk_hash_long_key(long.clone()), Ok(100));
+    assert_eq!(
+        HASH_LONG_KEY_CALLS.load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+    assert!(KASH_DISK_HASH_LONG_KEY
+        .connection()
+        .iter()
+        .keys()
+        .all(|key| key.unwrap().starts_with(b"#sha256:")));
+    // the key is hashed like the one the cache stores
+    assert!(KASH_DISK_HASH_LONG_KEY
+        .connection()
+        .contains_key(kash_disk_hash_long_key_key(long))
+        .unwrap());
+    assert_eq!("short", kash_disk_hash_long_key_key("short".to_string()));
+}
+
+#[kash(disk, max_key_len = 8, key_overflow = "error")]
+fn kash_disk_reject_long_key(s: String) -> Result<usize, TestError> {
+    Ok(s.len())
+}
+
+#[test]
+fn test_kash_disk_reject_long_key() {
+    assert_eq!(kash_disk_reject_long_key("short".to_string()), Ok(5));
+    assert_eq!(
+        kash_disk_reject_long_key("a".repeat(100)),
+        Err(TestError::DiskError(
+            "KeyTooLong { len: 100, max: 8 }".to_string()
+        ))
+    );
+}
+
+static DISK_KEY_EXPR_EVALS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+fn counted_disk_key(a: u32, b: u32) -> String {
+    DISK_KEY_EXPR_EVALS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    format!("{a}/{b}")
+}
+
+#[kash(disk, key(ty = "String", expr = "counted_disk_key(a, b)"))]
+fn kash_disk_keyed_once(a: u32, b: u32) -> Result<u32, TestError> {
+    Ok(a + b)
+}
+
+#[test]
+fn test_kash_disk_key_expr_runs_once_per_call() {
+    kash_disk_keyed_once_cache().clear().unwrap();
+    let evals = || DISK_KEY_EXPR_EVALS.load(std::sync::atomic::Ordering::SeqCst);
+    // a miss, then a hit
+    assert_eq!(kash_disk_keyed_once(1, 2), Ok(3));
+    assert_eq!(kash_disk_keyed_once(1, 2), Ok(3));
+    assert_eq!(2, evals());
+}
+
+#[kash(disk)]
+fn kash_disk_handled(n: u32) -> Result<u32, TestError> {
+    Ok(n * 2)
+}
+
+#[test]
+fn test_kash_disk_cache_handle() {
+    let handle = kash_disk_handled_cache();
+    handle.clear().unwrap();
+
+    assert_eq!(kash_disk_handled(1), Ok(2));
+    assert_eq!(kash_disk_handled(2), Ok(4));
+    assert_eq!(2, handle.len().unwrap());
+    assert!(handle.contains(&1).unwrap());
+
+    let mut entries = handle.iter().unwrap();
+    entries.sort();
+    assert_eq!(vec![("1".to_string(), 2), ("2".to_string(), 4)], entries);
+
+    assert_eq!(Some(2), handle.remove(&1).unwrap());
+    assert!(!handle.contains(&1).unwrap());
+
+    handle.clear().unwrap();
+    assert!(handle.is_empty().unwrap());
+}
+
 #[kash(disk, ttl = "1")]
 fn kash_disk(n: u32) -> Result<u32, TestError> {
     if n < 5 {
@@ -51,6 +227,276 @@ fn test_kash_disk_cache_create() {
     assert_eq!(kash_disk_cache_create(6), Err(TestError::Count(6)));
 }
 
+static DISK_GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[kash(
+    disk,
+    generation = "DISK_GENERATION.load(std::sync::atomic::Ordering::SeqCst)",
+    key(ty = "u32", try_expr = "n.checked_sub(1).ok_or(())")
+)]
+fn kash_disk_generational(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_generation() {
+    use kash::IOKash;
+
+    KASH_DISK_GENERATIONAL.connection().clear().unwrap();
+    assert_eq!(kash_disk_generational(2), Ok(2));
+    assert_eq!(kash_disk_generational_key(2), Some("0:1".to_string()));
+    assert_eq!(
+        KASH_DISK_GENERATIONAL.get(&"0:1".to_string()).unwrap(),
+        Some(2)
+    );
+    assert_eq!(kash_disk_generational_key(0), None);
+
+    DISK_GENERATION.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(kash_disk_generational(2), Ok(2));
+    assert_eq!(KASH_DISK_GENERATIONAL.connection().len(), 2);
+}
+
+#[kash(disk, ttl_ms = "1500", with_ttl)]
+fn kash_disk_expiring(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_with_ttl() {
+    use kash::IOKash;
+    use std::time::Duration;
+
+    KASH_DISK_EXPIRING.connection().clear().unwrap();
+    // the given ttl overrides the one of the cache, whether it's shorter or longer
+    assert_eq!(
+        kash_disk_expiring_with_ttl(1, Duration::from_secs(1)),
+        Ok(1)
+    );
+    assert_eq!(
+        kash_disk_expiring_with_ttl(2, Duration::from_secs(60)),
+        Ok(2)
+    );
+    assert_eq!(kash_disk_expiring(3), Ok(3));
+
+    std::thread::sleep(Duration::from_millis(1100));
+    assert_eq!(KASH_DISK_EXPIRING.get(&1).unwrap(), None);
+    assert_eq!(KASH_DISK_EXPIRING.get(&3).unwrap(), Some(3));
+    std::thread::sleep(Duration::from_millis(600));
+    assert_eq!(KASH_DISK_EXPIRING.get(&2).unwrap(), Some(2));
+    assert_eq!(KASH_DISK_EXPIRING.get(&3).unwrap(), None);
+}
+
+#[kash(disk, normalize = "let n = n.min(10);")]
+fn kash_disk_normalized(n: u32) -> Result<u32, TestError> {
+    Ok(n * 2)
+}
+
+#[test]
+fn test_kash_disk_normalized() {
+    use kash::IOKash;
+
+    KASH_DISK_NORMALIZED.connection().clear().unwrap();
+    assert_eq!(kash_disk_normalized(50), Ok(20));
+    assert_eq!(KASH_DISK_NORMALIZED.get(&10).unwrap(), Some(20));
+    assert_eq!(kash_disk_normalized_key(11), 10);
+}
+
+#[kash(disk, stats)]
+fn kash_disk_stats(n: u32) -> Result<u32, TestError> {
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(TestError::Count(n))
+    }
+}
+
+#[test]
+fn test_kash_disk_stats() {
+    KASH_DISK_STATS.connection().clear().unwrap();
+    let stats = kash_disk_stats_cache_stats();
+    stats.reset();
+
+    assert_eq!(kash_disk_stats(1), Ok(1));
+    assert_eq!(kash_disk_stats(1), Ok(1));
+    assert_eq!(kash_disk_stats(1), Ok(1));
+    // errors aren't cached, so they're always a miss
+    assert_eq!(kash_disk_stats(6), Err(TestError::Count(6)));
+    assert_eq!(kash_disk_stats(6), Err(TestError::Count(6)));
+    assert_eq!((2, 3), (stats.hits(), stats.misses()));
+    assert_eq!(Some(0.4), stats.hit_rate());
+}
+
+#[kash(disk, disabled)]
+fn kash_disk_disabled(n: u32) -> Result<u32, TestError> {
+    Err(TestError::Count(n))
+}
+
+#[test]
+fn test_kash_disk_disabled() {
+    assert_eq!(kash_disk_disabled(1), Err(TestError::Count(1)));
+    assert_eq!(kash_disk_disabled_prime_cache(1), Err(TestError::Count(1)));
+    assert_eq!(kash_disk_disabled_no_cache(1), Err(TestError::Count(1)));
+
+    // the other generated items are kept, with an empty cache
+    kash_disk_disabled_set_cache(1, 10).unwrap();
+    assert_eq!(kash_disk_disabled(1), Err(TestError::Count(1)));
+    assert_eq!(1, kash_disk_disabled_key(1));
+    assert!(!kash_disk_disabled_cache().contains(&1).unwrap());
+}
+
+#[kash(disk, ttl = "1")]
+fn kash_disk_aged(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_aged() {
+    use kash::IOKash;
+
+    KASH_DISK_AGED.remove(&1).unwrap();
+    assert_eq!(kash_disk_aged_entry_age(1).unwrap(), None);
+    assert_eq!(kash_disk_aged(1), Ok(1));
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let age = kash_disk_aged_entry_age(1).unwrap().unwrap();
+    assert!(age >= std::time::Duration::from_millis(100));
+
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    assert_eq!(kash_disk_aged_entry_age(1).unwrap(), None);
+}
+
+// only the `Ok` values are cached, so the error doesn't need to be `Clone`
+#[derive(Error, Debug, PartialEq)]
+enum NoCloneError {
+    #[error("error with disk cache `{0}`")]
+    DiskError(String),
+    #[error("count `{0}`")]
+    Count(u32),
+}
+
+impl From<DiskCacheError> for NoCloneError {
+    fn from(e: DiskCacheError) -> Self {
+        NoCloneError::DiskError(format!("{e:?}"))
+    }
+}
+
+#[kash(disk)]
+fn kash_disk_no_clone_error(n: u32) -> Result<u32, NoCloneError> {
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(NoCloneError::Count(n))
+    }
+}
+
+#[test]
+fn test_kash_disk_no_clone_error() {
+    assert_eq!(kash_disk_no_clone_error(1), Ok(1));
+    assert_eq!(kash_disk_no_clone_error(1), Ok(1));
+    assert_eq!(kash_disk_no_clone_error(5), Err(NoCloneError::Count(5)));
+}
+
+static SCHEMA_VERSIONED_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, schema_version = "2")]
+fn kash_disk_schema_versioned(n: u32) -> Result<u32, TestError> {
+    SCHEMA_VERSIONED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok(n * 2)
+}
+
+#[test]
+fn test_kash_disk_schema_version() {
+    use kash::IOKash;
+
+    // a value stored before the schema version was bumped
+    let old: kash::DiskCache<u32, u32> = kash::DiskCache::new("KASH_DISK_SCHEMA_VERSIONED")
+        .build()
+        .unwrap();
+    old.set(1, 1).unwrap();
+    old.connection().flush().unwrap();
+    drop(old);
+
+    assert_eq!(kash_disk_schema_versioned(1), Ok(2));
+    assert_eq!(kash_disk_schema_versioned(1), Ok(2));
+    assert_eq!(
+        SCHEMA_VERSIONED_CALLS.load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+}
+
+#[kash(disk, schema_version = "2")]
+fn kash_disk_counted(n: u32) -> Result<u32, TestError> {
+    Ok(n * 2)
+}
+
+#[test]
+fn test_kash_disk_handle_counts_current_entries() {
+    use kash::IOKash;
+
+    // a value stored before the schema version was bumped, which is still a record of the cache
+    let old: kash::DiskCache<u32, u32> = kash::DiskCache::new("KASH_DISK_COUNTED").build().unwrap();
+    old.set(1, 1).unwrap();
+    old.connection().flush().unwrap();
+    drop(old);
+
+    assert_eq!(kash_disk_counted_cache().len().unwrap(), 0);
+    assert!(kash_disk_counted_cache().is_empty().unwrap());
+    assert_eq!(kash_disk_counted(1), Ok(2));
+    assert_eq!(kash_disk_counted_cache().len().unwrap(), 1);
+}
+
+struct DiskShapes;
+
+#[kash::kash_impl]
+impl DiskShapes {
+    #[kash(disk, in_impl)]
+    fn disk_square(n: u32) -> Result<u32, TestError> {
+        Ok(n * n)
+    }
+
+    #[cfg(any())]
+    #[kash(disk, in_impl)]
+    fn compiled_out(n: u32) -> Result<u32, TestError> {
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_kash_impl_clears_disk_caches() {
+    assert_eq!(DiskShapes::disk_square(2), Ok(4));
+    assert_eq!(DiskShapes::disk_square_cache().len().unwrap(), 1);
+
+    DiskShapes::kash_clear_all();
+    assert_eq!(DiskShapes::disk_square_cache().len().unwrap(), 0);
+}
+
+static SHARED_DB: once_cell::sync::Lazy<sled::Db> =
+    once_cell::sync::Lazy::new(|| sled::Config::new().temporary(true).open().unwrap());
+
+#[kash(disk(shared_db = "*SHARED_DB"))]
+fn kash_disk_shared_double(n: u32) -> Result<u32, TestError> {
+    Ok(n * 2)
+}
+
+#[kash(disk(shared_db = "*SHARED_DB"))]
+fn kash_disk_shared_triple(n: u32) -> Result<u32, TestError> {
+    Ok(n * 3)
+}
+
+#[test]
+fn test_kash_disk_shared_db() {
+    use kash::IOKash;
+
+    assert_eq!(kash_disk_shared_double(1), Ok(2));
+    assert_eq!(kash_disk_shared_triple(1), Ok(3));
+    assert_eq!(KASH_DISK_SHARED_DOUBLE.get(&1).unwrap(), Some(2));
+    assert_eq!(KASH_DISK_SHARED_TRIPLE.get(&1).unwrap(), Some(3));
+
+    KASH_DISK_SHARED_DOUBLE.remove(&1).unwrap();
+    assert_eq!(KASH_DISK_SHARED_TRIPLE.get(&1).unwrap(), Some(3));
+    // the default tree and a tree of each function
+    assert_eq!(SHARED_DB.tree_names().len(), 3);
+}
+
 /// Just calling the macro with connection_config to test, it doesn't break with an expected string
 /// for connection_config.
 /// There are no simple tests to test this here
@@ -74,6 +520,39 @@ fn kash_disk_sync_to_disk_on_cache_change(n: u32) -> Result<u32, TestError> {
     }
 }
 
+// without the periodic flush of sled, so the values are only on disk once the cache is flushed
+#[kash(disk(
+    flush_on_exit,
+    connection_config = r#"sled::Config::new().flush_every_ms(None)"#
+))]
+fn kash_disk_flush_on_exit(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_flush_on_exit() {
+    use kash::IOKash;
+
+    // a key of this run, since the values of the previous runs are on disk
+    let n = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    let read_flushed = || {
+        let reader: kash::DiskCache<u32, u32> = kash::DiskCache::new("KASH_DISK_FLUSH_ON_EXIT")
+            .set_read_only(true)
+            .build()
+            .unwrap();
+        reader.get(&n).unwrap()
+    };
+    assert_eq!(kash_disk_flush_on_exit(n), Ok(n));
+    assert_eq!(read_flushed(), None);
+
+    // the cache in the static is registered once it's built, and flushed like at the end of `main`
+    drop(kash::stores::DiskFlushGuard::new());
+    assert_eq!(read_flushed(), Some(n));
+}
+
 #[cfg(feature = "async")]
 mod async_test {
     use super::*;
@@ -94,6 +573,76 @@ mod async_test {
         assert_eq!(async_kash_disk(5).await, Err(TestError::Count(5)));
         assert_eq!(async_kash_disk(6).await, Err(TestError::Count(6)));
     }
+
+    #[kash(disk)]
+    async fn async_kash_disk_initialized(n: u32) -> Result<u32, TestError> {
+        Ok(n)
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_disk_init_cache() {
+        // the cache is locked while another cache of the same name and dir is open
+        let holder: kash::DiskCache<u32, u32> = kash::DiskCache::new("ASYNC_KASH_DISK_INITIALIZED")
+            .build()
+            .unwrap();
+        assert!(matches!(
+            async_kash_disk_initialized_init_cache().await,
+            Err(kash::stores::DiskCacheBuildError::Locked { .. })
+        ));
+        drop(holder);
+
+        async_kash_disk_initialized_init_cache().await.unwrap();
+        async_kash_disk_initialized_init_cache().await.unwrap();
+        assert_eq!(async_kash_disk_initialized(1).await, Ok(1));
+    }
+
+    #[kash(disk)]
+    async fn async_kash_disk_no_clone_error(n: u32) -> Result<u32, NoCloneError> {
+        if n < 5 {
+            Ok(n)
+        } else {
+            Err(NoCloneError::Count(n))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_disk_no_clone_error() {
+        assert_eq!(async_kash_disk_no_clone_error(1).await, Ok(1));
+        assert_eq!(async_kash_disk_no_clone_error(1).await, Ok(1));
+        assert_eq!(
+            async_kash_disk_no_clone_error(5).await,
+            Err(NoCloneError::Count(5))
+        );
+    }
+
+    #[kash(disk)]
+    async fn async_kash_disk_injected(n: u32) -> Result<u32, TestError> {
+        Err(TestError::Count(n))
+    }
+
+    #[kash(disk, stats)]
+    async fn async_kash_disk_stats(n: u32) -> Result<u32, TestError> {
+        Ok(n)
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_disk_stats() {
+        async_kash_disk_stats_set_cache(1, 1).await.unwrap();
+        async_kash_disk_stats_cache_stats().reset();
+        assert_eq!(async_kash_disk_stats(1).await, Ok(1));
+        assert_eq!(async_kash_disk_stats(1).await, Ok(1));
+        assert_eq!(2, async_kash_disk_stats_cache_stats().hits());
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_disk_injected() {
+        async_kash_disk_injected_set_cache(1, 10).await.unwrap();
+        assert_eq!(async_kash_disk_injected(1).await, Ok(10));
+        assert!(async_kash_disk_injected_entry_age(1)
+            .await
+            .unwrap()
+            .is_some());
+    }
 }
 
 #[kash(disk, ttl = "1", option)]
@@ -112,3 +661,216 @@ fn test_kash_disk_optional() {
     assert_eq!(kash_disk_optional(5), Err(TestError::Count(5)));
     assert_eq!(kash_disk_optional(6), Err(TestError::Count(6)));
 }
+
+#[kash(disk, result_option)]
+fn kash_disk_result_option(n: u32) -> Result<Option<u32>, TestError> {
+    match n {
+        0 => Ok(None),
+        1..=4 => Ok(Some(n)),
+        _ => Err(TestError::Count(n)),
+    }
+}
+
+#[test]
+fn test_kash_disk_result_option() {
+    KASH_DISK_RESULT_OPTION.connection().clear().unwrap();
+    assert_eq!(kash_disk_result_option(1), Ok(Some(1)));
+    assert_eq!(kash_disk_result_option(0), Ok(None));
+    assert_eq!(kash_disk_result_option(5), Err(TestError::Count(5)));
+    assert_eq!(KASH_DISK_RESULT_OPTION.connection().len(), 1);
+}
+
+#[kash(
+    disk,
+    ttl = "3600",
+    ttl_for = "|n| (*n == 0).then(|| std::time::Duration::from_secs(1))"
+)]
+fn kash_disk_ttl_for(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_ttl_for() {
+    use kash::IOKash;
+
+    KASH_DISK_TTL_FOR.connection().clear().unwrap();
+    assert_eq!(kash_disk_ttl_for(0), Ok(0));
+    assert_eq!(kash_disk_ttl_for(1), Ok(1));
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(KASH_DISK_TTL_FOR.get(&0).unwrap(), None);
+    assert_eq!(KASH_DISK_TTL_FOR.get(&1).unwrap(), Some(1));
+}
+
+static DISK_SKIP_IF_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+#[kash(disk, skip_if = "|tags| tags.is_empty()")]
+fn kash_disk_skip_if(n: u32) -> Result<Vec<u32>, TestError> {
+    DISK_SKIP_IF_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok((0..n).collect())
+}
+
+#[test]
+fn test_kash_disk_skip_if() {
+    use kash::IOKash;
+
+    let calls = || DISK_SKIP_IF_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    KASH_DISK_SKIP_IF.connection().clear().unwrap();
+    assert_eq!(kash_disk_skip_if(0), Ok(vec![]));
+    assert_eq!(kash_disk_skip_if(0), Ok(vec![]));
+    assert_eq!(calls(), 2);
+    assert_eq!(KASH_DISK_SKIP_IF.get(&0).unwrap(), None);
+
+    assert_eq!(kash_disk_skip_if(2), Ok(vec![0, 1]));
+    assert_eq!(kash_disk_skip_if(2), Ok(vec![0, 1]));
+    assert_eq!(calls(), 3);
+}
+
+#[kash(disk, option)]
+fn kash_disk_injected(n: u32) -> Result<Option<u32>, TestError> {
+    Err(TestError::Count(n))
+}
+
+#[test]
+fn test_kash_disk_injected() {
+    KASH_DISK_INJECTED.connection().clear().unwrap();
+    assert_eq!(kash_disk_injected(1), Err(TestError::Count(1)));
+    kash_disk_injected_set_cache(1, 10).unwrap();
+    assert_eq!(kash_disk_injected(1), Ok(Some(10)));
+}
+
+#[kash(disk, ttl_ms = "300")]
+fn kash_disk_ttl_ms(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_disk_ttl_ms() {
+    use kash::IOKash;
+
+    KASH_DISK_TTL_MS.connection().clear().unwrap();
+    assert_eq!(kash_disk_ttl_ms(1), Ok(1));
+    assert_eq!(KASH_DISK_TTL_MS.get(&1).unwrap(), Some(1));
+    std::thread::sleep(std::time::Duration::from_millis(400));
+    assert_eq!(KASH_DISK_TTL_MS.get(&1).unwrap(), None);
+}
+
+#[cfg(feature = "disk_compression")]
+#[kash(disk(use_compression, compression_factor = 3))]
+fn kash_disk_compressed(n: usize) -> Result<String, TestError> {
+    Ok("a".repeat(n))
+}
+
+#[cfg(feature = "disk_compression")]
+#[test]
+fn test_kash_disk_compressed() {
+    use kash::IOKash;
+
+    KASH_DISK_COMPRESSED.connection().clear().unwrap();
+    assert_eq!(kash_disk_compressed(100), Ok("a".repeat(100)));
+    assert_eq!(
+        KASH_DISK_COMPRESSED.get(&100).unwrap(),
+        Some("a".repeat(100))
+    );
+}
+
+fn sum_key(a: &u32, b: &u32) -> u32 {
+    a + b
+}
+
+#[kash(disk, key(ty = "u32", with = "sum_key"))]
+fn kash_disk_key_with(a: u32, b: u32) -> Result<u32, TestError> {
+    Ok(a * b)
+}
+
+#[test]
+fn test_kash_disk_key_with() {
+    use kash::IOKash;
+
+    KASH_DISK_KEY_WITH.connection().clear().unwrap();
+    assert_eq!(kash_disk_key_with(1, 4), Ok(4));
+    assert_eq!(KASH_DISK_KEY_WITH.get(&5).unwrap(), Some(4));
+    // the key of (2, 3) is also 5
+    assert_eq!(kash_disk_key_with(2, 3), Ok(4));
+}
+
+struct Tenant {
+    id: u32,
+}
+
+impl Tenant {
+    #[kash(
+        disk,
+        in_impl,
+        key(ty = "String", expr = r#"format!("{}:{}", self.id, n)"#)
+    )]
+    fn kash_disk_tenant(&self, n: u32) -> Result<u32, TestError> {
+        Ok(self.id * 100 + n)
+    }
+}
+
+#[test]
+fn test_kash_disk_in_impl_key_with_self() {
+    let a = Tenant { id: 1 };
+    let b = Tenant { id: 2 };
+    assert_eq!(a.kash_disk_tenant(5), Ok(105));
+    assert_eq!(b.kash_disk_tenant(5), Ok(205));
+    assert_eq!(a.kash_disk_tenant(5), Ok(105));
+}
+
+// a helper generic over the store of the cache
+#[cfg(feature = "mem_store")]
+fn use_cache<C: kash::IOKash<u32, u32>>(cache: &C) -> Result<(), C::Error> {
+    assert_eq!(None, cache.set(1, 10)?);
+    assert_eq!(Some(10), cache.set(1, 11)?);
+    assert_eq!(Some(11), cache.get(&1)?);
+    assert_eq!(Some(11), cache.remove(&1)?);
+    assert_eq!(None, cache.get(&1)?);
+    Ok(())
+}
+
+#[cfg(feature = "mem_store")]
+#[test]
+fn test_memory_and_disk_caches_are_io_kash() {
+    let dir = tempfile::tempdir().unwrap();
+    let disk: kash::DiskCache<u32, u32> = kash::DiskCache::new("io-kash-generic")
+        .set_disk_directory(dir.path())
+        .build()
+        .unwrap();
+    use_cache(&disk).unwrap();
+
+    let memory: kash::MemoryCache<u32, u32> = kash::MemoryCache::new(10);
+    use_cache(&memory).unwrap();
+}
+
+#[cfg(feature = "mem_store")]
+#[kash(disk, fallback = r#"feature = "disk_store""#)]
+fn kash_disk_fallback(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+// `any()` is always false, so it's cached in memory
+#[cfg(feature = "mem_store")]
+#[kash(disk, fallback = "any()", size = "10")]
+fn kash_memory_fallback(n: u32) -> Result<u32, TestError> {
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(TestError::Count(n))
+    }
+}
+
+#[cfg(feature = "mem_store")]
+#[test]
+fn test_kash_disk_fallback() {
+    use kash::IOKash;
+
+    KASH_DISK_FALLBACK.connection().clear().unwrap();
+    assert_eq!(kash_disk_fallback(1), Ok(1));
+    assert_eq!(KASH_DISK_FALLBACK.get(&1).unwrap(), Some(1));
+
+    assert_eq!(kash_memory_fallback(2), Ok(2));
+    assert_eq!(KASH_MEMORY_FALLBACK.get(&2), Some(2));
+    // the errors aren't cached, like the ones of a disk cache
+    assert_eq!(kash_memory_fallback(6), Err(TestError::Count(6)));
+    assert_eq!(KASH_MEMORY_FALLBACK.get(&6), None);
+}
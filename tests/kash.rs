@@ -1,3 +1,5 @@
+#![cfg(feature = "mem_store")]
+
 use kash::kash;
 use std::sync::Arc;
 use std::thread::{self, sleep};
@@ -15,7 +17,7 @@ fn fib0(n: u32) -> u32 {
 fn test_unbound_cache() {
     fib0(20);
     {
-        FIB0.run_pending_tasks();
+        fib0_sync();
         let cache_size = FIB0.entry_count();
         assert_eq!(21, cache_size);
     }
@@ -33,7 +35,7 @@ fn fib1(n: u32) -> u32 {
 fn test_sized_cache() {
     fib1(20);
     {
-        FIB1.run_pending_tasks();
+        fib1_sync();
         let cache_size = FIB1.entry_count();
         assert_eq!(3, cache_size);
         let items = FIB1.iter().collect::<Vec<_>>();
@@ -41,90 +43,62 @@ fn test_sized_cache() {
     }
 }
 
-// #[kash(size = "5", ttl = "2")]
-// fn timed(n: u32) -> u32 {
-//     sleep(Duration::new(3, 0));
-//     n
-// }
-
-// #[test]
-// fn test_timed_cache() {
-//     timed(1);
-//     timed(1);
-// let cache = TIMED.lock().unwrap();
-// assert_eq!(1, cache.cache_misses().unwrap());
-// assert_eq!(1, cache.cache_hits().unwrap());
-//     sleep(Duration::new(3, 0));
-//     timed(1);
-// let cache = TIMED.lock().unwrap();
-// assert_eq!(2, cache.cache_misses().unwrap());
-// assert_eq!(1, cache.cache_hits().unwrap());
-//     timed(1);
-//     sleep(Duration::new(1, 0));
-//     timed(1);
-// let cache = TIMED.lock().unwrap();
-// assert_eq!(3, cache.cache_misses().unwrap());
-// assert_eq!(2, cache.cache_hits().unwrap());
-// }
-
-// #[kash(size = "3", ttl = "2")]
-// fn timefac(n: u32) -> u32 {
-//     sleep(Duration::new(1, 0));
-//     if n > 1 {
-//         n * timefac(n - 1)
-//     } else {
-//         n
-//     }
-// }
-
-// #[test]
-// fn test_timed_sized_cache() {
-//     timefac(1);
-//     timefac(1);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(1, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-//     sleep(Duration::new(3, 0));
-//     timefac(1);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(2, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-//     timefac(1);
-//     sleep(Duration::new(1, 0));
-//     timefac(1);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(3, cache.cache_misses().unwrap());
-//         assert_eq!(2, cache.cache_hits().unwrap());
-//         let mut cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(1, cache.set_ttl(6).unwrap());
-//     timefac(2);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(4, cache.cache_misses().unwrap());
-//         assert_eq!(3, cache.cache_hits().unwrap());
-//     timefac(3);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(5, cache.cache_misses().unwrap());
-//         assert_eq!(4, cache.cache_hits().unwrap());
-//     timefac(3);
-//     timefac(2);
-//     timefac(1);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(5, cache.cache_misses().unwrap());
-//         assert_eq!(7, cache.cache_hits().unwrap());
-//     timefac(4);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(6, cache.cache_misses().unwrap());
-//         assert_eq!(8, cache.cache_hits().unwrap());
-//     timefac(6);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(8, cache.cache_misses().unwrap());
-//         assert_eq!(9, cache.cache_hits().unwrap());
-//     timefac(1);
-//         let cache = TIMEFAC.lock().unwrap();
-//         assert_eq!(9, cache.cache_misses().unwrap());
-//         assert_eq!(9, cache.cache_hits().unwrap());
-//         assert_eq!(3, cache.cache_size());
-// }
+static TIMED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(size = "5", ttl_ms = "100")]
+fn timed(n: u32) -> u32 {
+    TIMED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_timed_cache() {
+    let calls = || TIMED_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    timed(1);
+    timed(1);
+    assert_eq!(1, calls());
+    sleep(Duration::from_millis(150));
+    timed(1);
+    assert_eq!(2, calls());
+    timed(1);
+    assert_eq!(2, calls());
+    sleep(Duration::from_millis(150));
+    timed(1);
+    assert_eq!(3, calls());
+}
+
+static TIMEFAC_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(size = "3", ttl_ms = "100")]
+fn timefac(n: u32) -> u32 {
+    TIMEFAC_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    if n > 1 {
+        n * timefac(n - 1)
+    } else {
+        n
+    }
+}
+
+#[test]
+fn test_timed_sized_cache() {
+    let calls = || TIMEFAC_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(6, timefac(3));
+    assert_eq!(3, calls());
+    timefac(3);
+    timefac(2);
+    timefac(1);
+    assert_eq!(3, calls());
+    assert_eq!(24, timefac(4));
+    assert_eq!(4, calls());
+    timefac_sync();
+    assert_eq!(3, TIMEFAC.entry_count());
+
+    sleep(Duration::from_millis(150));
+    timefac_sync();
+    assert_eq!(0, TIMEFAC.entry_count());
+    assert_eq!(2, timefac(2));
+    assert_eq!(6, calls());
+}
 
 #[kash(size = "1")]
 fn string_1(a: String, b: String) -> String {
@@ -134,29 +108,65 @@ fn string_1(a: String, b: String) -> String {
 #[test]
 fn test_string_cache() {
     string_1("a".into(), "b".into());
-    STRING_1.run_pending_tasks();
+    string_1_sync();
     assert_eq!(1, STRING_1.entry_count());
 }
 
-// #[kash(size = "5", ttl = "2")]
-// fn timed_2(n: u32) -> u32 {
-//     sleep(Duration::new(3, 0));
-//     n
-// }
-
-// #[test]
-// fn test_timed_cache_key() {
-//     timed_2(1);
-//     timed_2(1);
-//         let cache = TIMED_2.lock().unwrap();
-//         assert_eq!(1, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-//     sleep(Duration::new(3, 0));
-//     timed_2(1);
-//         let cache = TIMED_2.lock().unwrap();
-//         assert_eq!(2, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-// }
+static TIMED_KEY_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(
+    size = "5",
+    ttl_ms = "100",
+    key(ty = "String", expr = r#"format!("{a}{b}")"#)
+)]
+fn timed_custom_key(a: &str, b: &str) -> usize {
+    TIMED_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    a.len() + b.len()
+}
+
+#[test]
+fn test_timed_cache_key() {
+    let calls = || TIMED_KEY_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    timed_custom_key("a", "1");
+    timed_custom_key("a", "1");
+    assert_eq!(1, calls());
+    assert!(TIMED_CUSTOM_KEY.contains_key("a1"));
+    sleep(Duration::from_millis(150));
+    assert!(!TIMED_CUSTOM_KEY.contains_key("a1"));
+    timed_custom_key("a", "1");
+    assert_eq!(2, calls());
+}
+
+static KEY_EXPR_EVALS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+fn counted_key(a: u32, b: u32) -> String {
+    KEY_EXPR_EVALS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    format!("{a}/{b}")
+}
+
+#[kash(key(ty = "String", expr = "counted_key(a, b)"))]
+fn keyed_once(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[kash(key(ty = "String", expr = "counted_key(a, b)"), lock_timeout = 100)]
+fn keyed_once_with_timeout(a: u32, b: u32) -> u32 {
+    a * b
+}
+
+#[test]
+fn test_key_expr_runs_once_per_call() {
+    let evals = || KEY_EXPR_EVALS.load(std::sync::atomic::Ordering::SeqCst);
+    let before = evals();
+    // a miss, then a hit
+    assert_eq!(3, keyed_once(1, 2));
+    assert_eq!(3, keyed_once(1, 2));
+    assert_eq!(before + 2, evals());
+
+    assert_eq!(2, keyed_once_with_timeout(1, 2));
+    assert_eq!(2, keyed_once_with_timeout(1, 2));
+    assert_eq!(before + 4, evals());
+}
 
 #[kash(
     size = "2",
@@ -175,11 +185,11 @@ fn test_sized_cache_key() {
     sized_key("a", "1");
     // assert_eq!(1, cache.cache_misses().unwrap());
     // assert_eq!(1, cache.cache_hits().unwrap());
-    SIZED_KEY.run_pending_tasks();
+    sized_key_sync();
     assert_eq!(1, SIZED_KEY.entry_count());
     sized_key("a", "2");
     // assert_eq!(2, cache.cache_hits().unwrap());
-    SIZED_KEY.run_pending_tasks();
+    sized_key_sync();
     assert_eq!(2, SIZED_KEY.entry_count());
 
     let (keys, values): (Vec<_>, Vec<_>) = SIZED_KEY.into_iter().unzip();
@@ -188,7 +198,7 @@ fn test_sized_cache_key() {
     assert_eq!(vec![2, 2], values);
 
     sized_key("a", "3");
-    SIZED_KEY.run_pending_tasks();
+    sized_key_sync();
     assert_eq!(2, SIZED_KEY.entry_count());
 
     let (keys, values): (Vec<_>, Vec<_>) = SIZED_KEY.into_iter().unzip();
@@ -198,7 +208,7 @@ fn test_sized_cache_key() {
 
     sized_key("a", "4");
     sized_key("a", "5");
-    SIZED_KEY.run_pending_tasks();
+    sized_key_sync();
     assert_eq!(2, SIZED_KEY.entry_count());
 
     let (keys, values): (Vec<_>, Vec<_>) = SIZED_KEY.into_iter().unzip();
@@ -208,7 +218,7 @@ fn test_sized_cache_key() {
 
     sized_key("a", "67");
     sized_key("a", "8");
-    SIZED_KEY.run_pending_tasks();
+    sized_key_sync();
     assert_eq!(2, SIZED_KEY.entry_count());
 
     let (keys, values): (Vec<_>, Vec<_>) = SIZED_KEY.into_iter().unzip();
@@ -218,6 +228,30 @@ fn test_sized_cache_key() {
     assert!(values.contains(&3));
 }
 
+#[kash(
+    size = r#"{ std::env::var("KASH_TEST_ENV_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(100) }"#,
+    ttl = r#"{ std::env::var("KASH_TEST_ENV_TTL").ok().and_then(|s| s.parse().ok()).unwrap_or(100) }"#
+)]
+fn env_sized(n: u32) -> u32 {
+    n
+}
+
+#[test]
+fn test_size_and_ttl_from_env() {
+    std::env::set_var("KASH_TEST_ENV_SIZE", "1");
+    std::env::set_var("KASH_TEST_ENV_TTL", "7");
+    env_sized(1);
+    env_sized(2);
+    env_sized(3);
+    env_sized_sync();
+    assert_eq!(1, ENV_SIZED.entry_count());
+    assert_eq!(Some(1), ENV_SIZED.policy().max_capacity());
+    assert_eq!(
+        Some(Duration::from_secs(7)),
+        ENV_SIZED.policy().time_to_live()
+    );
+}
+
 #[kash(result)]
 fn test_result_key(n: u32) -> Result<u32, ()> {
     if n < 5 {
@@ -235,7 +269,7 @@ fn cache_result_key() {
     assert!(test_result_key(6).is_err());
     assert!(test_result_key(2).is_ok());
     assert!(test_result_key(4).is_ok());
-    TEST_RESULT_KEY.run_pending_tasks();
+    test_result_key_sync();
     assert_eq!(2, TEST_RESULT_KEY.entry_count());
 }
 
@@ -256,10 +290,251 @@ fn cache_result_no_default() {
     assert!(test_result_no_default(6).is_err());
     assert!(test_result_no_default(2).is_ok());
     assert!(test_result_no_default(4).is_ok());
-    TEST_RESULT_NO_DEFAULT.run_pending_tasks();
+    test_result_no_default_sync();
     assert_eq!(2, TEST_RESULT_NO_DEFAULT.entry_count());
 }
 
+#[kash(recursive_no_cache)]
+fn recursive_fib(n: u64) -> u64 {
+    if n < 2 {
+        n
+    } else {
+        recursive_fib(n - 1) + recursive_fib(n - 2)
+    }
+}
+
+#[test]
+fn recursive_no_cache() {
+    assert_eq!(55, recursive_fib_no_cache(10));
+    recursive_fib_sync();
+    assert_eq!(0, RECURSIVE_FIB.entry_count());
+
+    assert_eq!(55, recursive_fib(10));
+    recursive_fib_sync();
+    assert_eq!(11, RECURSIVE_FIB.entry_count());
+}
+
+static TRY_KEY_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(key(ty = "u32", try_expr = "raw.trim().parse::<u32>()"))]
+fn test_try_key(raw: &str) -> usize {
+    TRY_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    raw.len()
+}
+
+#[test]
+fn try_key() {
+    assert_eq!(2, test_try_key("12"));
+    assert_eq!(2, test_try_key("12"));
+    assert_eq!(5, test_try_key("abcde"));
+    assert_eq!(5, test_try_key("abcde"));
+    assert_eq!(3, TRY_KEY_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+    test_try_key_sync();
+    assert_eq!(1, TEST_TRY_KEY.entry_count());
+    assert!(TEST_TRY_KEY.contains_key(&12));
+}
+
+#[kash(key(ty = "Vec<u8>", expr = "data.to_vec()"))]
+fn test_const_generic<const N: usize>(data: [u8; N]) -> u64 {
+    data.iter().map(|b| u64::from(*b)).sum()
+}
+
+#[test]
+fn const_generic() {
+    assert_eq!(3, test_const_generic([1, 2]));
+    assert_eq!(6, test_const_generic([1, 2, 3]));
+    test_const_generic_sync();
+    assert_eq!(2, TEST_CONST_GENERIC.entry_count());
+    assert!(TEST_CONST_GENERIC.contains_key(&vec![1, 2, 3]));
+}
+
+#[kash(key(ty = "String", expr = "value.to_string()"))]
+fn test_where_clause<T>(value: T) -> usize
+where
+    T: std::fmt::Display,
+{
+    value.to_string().len()
+}
+
+#[kash]
+fn test_where_lifetimes<'a, 'b>(first: &'a u32, second: &'b u32) -> u32
+where
+    'a: 'b,
+{
+    first + second
+}
+
+struct Formatter;
+
+impl Formatter {
+    #[kash(in_impl, key(ty = "String", expr = "value.to_string()"))]
+    fn width<T>(value: T) -> usize
+    where
+        T: std::fmt::Display,
+    {
+        value.to_string().len()
+    }
+}
+
+#[test]
+fn where_clause() {
+    assert_eq!(3, test_where_clause(100));
+    assert_eq!(3, test_where_clause("100"));
+    assert_eq!(3, test_where_clause_prime_cache(1.5));
+    test_where_clause_sync();
+    assert_eq!(2, TEST_WHERE_CLAUSE.entry_count());
+
+    assert_eq!(3, test_where_lifetimes(&1, &2));
+    assert!(test_where_lifetimes_is_cached(&1, &2));
+
+    // the accessor of the cache of a generic method has no type parameters to infer
+    assert_eq!(2, Formatter::width(10));
+    assert_eq!(2, Formatter::width("10"));
+    assert!(Formatter::width_get_cache_ident().contains_key("10"));
+}
+
+#[cfg(feature = "mem_persist")]
+#[kash(persist, result, arc)]
+fn test_persist(n: u32) -> Result<String, String> {
+    Ok(n.to_string())
+}
+
+#[cfg(feature = "mem_persist")]
+#[test]
+fn persist() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test_persist");
+    assert_eq!("1", *test_persist(1).unwrap());
+    assert_eq!("2", *test_persist(2).unwrap());
+    assert_eq!(2, test_persist_dump_cache(&path).unwrap());
+
+    TEST_PERSIST.invalidate_all();
+    assert!(!TEST_PERSIST.contains_key(&1));
+    assert_eq!(2, test_persist_load_cache(&path).unwrap());
+    assert_eq!(
+        Some("2"),
+        TEST_PERSIST.get(&2).as_deref().map(String::as_str)
+    );
+    assert_eq!("1", *test_persist(1).unwrap());
+}
+
+static SCALED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+trait Shape {
+    fn sides(&self) -> u32;
+
+    #[kash(in_trait)]
+    fn scaled(&self, n: u32) -> u32 {
+        SCALED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.sides() * n
+    }
+}
+
+struct Triangle;
+
+impl Shape for Triangle {
+    fn sides(&self) -> u32 {
+        3
+    }
+}
+
+struct Square;
+
+impl Shape for Square {
+    fn sides(&self) -> u32 {
+        4
+    }
+}
+
+#[test]
+fn in_trait() {
+    assert_eq!(6, Triangle.scaled(2));
+    assert_eq!(8, Square.scaled(2));
+    assert_eq!(6, Triangle.scaled(2));
+    assert_eq!(8, Square.scaled(2));
+    assert_eq!(2, SCALED_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+
+    assert_eq!(Ok(true), Triangle::scaled_cache().contains(&2));
+    Triangle::scaled_cache().clear().unwrap();
+    assert_eq!(Ok(false), Triangle::scaled_cache().contains(&2));
+    assert_eq!(Ok(true), Square::scaled_cache().contains(&2));
+}
+
+static RESULT_OPTION_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(result_option)]
+fn test_result_option(n: u32) -> Result<Option<u32>, String> {
+    RESULT_OPTION_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    match n {
+        0 => Ok(None),
+        1..=4 => Ok(Some(n)),
+        _ => Err(format!("{n} is too big")),
+    }
+}
+
+#[test]
+fn result_option() {
+    let calls = || RESULT_OPTION_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+
+    // `Ok(Some)` is cached
+    assert_eq!(Ok(Some(2)), test_result_option(2));
+    assert_eq!(Ok(Some(2)), test_result_option(2));
+    assert_eq!(1, calls());
+
+    // `Ok(None)` is not cached
+    assert_eq!(Ok(None), test_result_option(0));
+    assert_eq!(Ok(None), test_result_option(0));
+    assert_eq!(3, calls());
+
+    // `Err` is not cached
+    assert_eq!(Err("6 is too big".to_string()), test_result_option(6));
+    assert_eq!(Err("6 is too big".to_string()), test_result_option(6));
+    assert_eq!(5, calls());
+
+    assert_eq!(Ok(Some(3)), test_result_option_prime_cache(3));
+    assert_eq!(Ok(None), test_result_option_prime_cache(0));
+    test_result_option_sync();
+    assert_eq!(2, TEST_RESULT_OPTION.entry_count());
+    assert_eq!(Some(3), TEST_RESULT_OPTION.get(&3));
+}
+
+#[kash(result_option, arc)]
+fn test_result_option_arc(n: u32) -> Result<Option<Vec<u32>>, String> {
+    Ok(n.checked_sub(1).map(|n| vec![n]))
+}
+
+#[test]
+fn result_option_arc() {
+    let first = test_result_option_arc(2).unwrap().unwrap();
+    let second = test_result_option_arc(2).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(Ok(None), test_result_option_arc(0));
+}
+
+static CACHE_ERRORS_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(cache_errors)]
+fn test_cache_errors(n: u32) -> Result<u32, String> {
+    CACHE_ERRORS_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(format!("{n} is too big"))
+    }
+}
+
+#[test]
+fn cache_errors() {
+    assert_eq!(Ok(2), test_cache_errors(2));
+    assert_eq!(Err("6 is too big".to_string()), test_cache_errors(6));
+    assert_eq!(Err("6 is too big".to_string()), test_cache_errors(6));
+    assert_eq!(Ok(2), test_cache_errors(2));
+    assert_eq!(
+        2,
+        CACHE_ERRORS_CALLS.load(std::sync::atomic::Ordering::SeqCst)
+    );
+}
+
 #[kash(
     size = "2",
     key(ty = "String", expr = r#"format!("{a}/{b}")"#),
@@ -307,7 +582,7 @@ fn test_proc_kash_option() {
     assert!(proc_kash_option(2).is_some());
     assert!(proc_kash_option(1).is_some());
     assert!(proc_kash_option(4).is_some());
-    PROC_KASH_OPTION.run_pending_tasks();
+    proc_kash_option_sync();
     assert_eq!(3, PROC_KASH_OPTION.entry_count());
 }
 
@@ -321,73 +596,351 @@ fn test_result_key_missing_result_arm(n: u32) -> Result<u32, ()> {
     Ok(n)
 }
 
-// #[kash(size = "1", ttl = "1")]
-// fn proc_timed_sized_sleeper(n: u64) -> u64 {
-//     sleep(Duration::new(1, 0));
-//     n
-// }
-
-// #[test]
-// fn test_proc_timed_sized_cache() {
-//     proc_timed_sized_sleeper(1);
-//     proc_timed_sized_sleeper(1);
-//         let cache = PROC_TIMED_SIZED_SLEEPER.lock().unwrap();
-//         assert_eq!(1, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-//     // sleep to expire the one entry
-//     sleep(Duration::new(1, 0));
-//     proc_timed_sized_sleeper(1);
-//         let cache = PROC_TIMED_SIZED_SLEEPER.lock().unwrap();
-//         assert_eq!(2, cache.cache_misses().unwrap());
-//         assert_eq!(1, cache.cache_hits().unwrap());
-//         assert_eq!(cache.key_order().collect::<Vec<_>>(), vec![&1]);
-//     // sleep to expire the one entry
-//     sleep(Duration::new(1, 0));
-//         let cache = PROC_TIMED_SIZED_SLEEPER.lock().unwrap();
-//         assert!(cache.key_order().next().is_none());
-//     proc_timed_sized_sleeper(1);
-//     proc_timed_sized_sleeper(1);
-//         let cache = PROC_TIMED_SIZED_SLEEPER.lock().unwrap();
-//         assert_eq!(3, cache.cache_misses().unwrap());
-//         assert_eq!(2, cache.cache_hits().unwrap());
-//         assert_eq!(cache.key_order().collect::<Vec<_>>(), vec![&1]);
-//     // lru size is 1, so this new thing evicts the existing key
-//     proc_timed_sized_sleeper(2);
-//         let cache = PROC_TIMED_SIZED_SLEEPER.lock().unwrap();
-//         assert_eq!(4, cache.cache_misses().unwrap());
-//         assert_eq!(2, cache.cache_hits().unwrap());
-//         assert_eq!(cache.key_order().collect::<Vec<_>>(), vec![&2]);
-// }
-
-// #[kash(size = "2")]
-// fn kash_smartstring(s: smartstring::alias::String) -> smartstring::alias::String {
-//     if s == "very stringy" {
-//         smartstring::alias::String::from("equal")
-//     } else {
-//         smartstring::alias::String::from("not equal")
-//     }
-// }
-
-// #[test]
-// fn test_kash_smartstring() {
-//     let mut string = smartstring::alias::String::new();
-//     string.push_str("very stringy");
-//     assert_eq!("equal", kash_smartstring(string.clone()));
-//         let cache = KASH_SMARTSTRING.lock().unwrap();
-//         assert_eq!(cache.cache_hits(), Some(0));
-//         assert_eq!(cache.cache_misses(), Some(1));
-
-//     assert_eq!("equal", kash_smartstring(string.clone()));
-//         let cache = KASH_SMARTSTRING.lock().unwrap();
-//         assert_eq!(cache.cache_hits(), Some(1));
-//         assert_eq!(cache.cache_misses(), Some(1));
-
-//     let string = smartstring::alias::String::from("also stringy");
-//     assert_eq!("not equal", kash_smartstring(string));
-//         let cache = KASH_SMARTSTRING.lock().unwrap();
-//         assert_eq!(cache.cache_hits(), Some(1));
-//         assert_eq!(cache.cache_misses(), Some(2));
-// }
+static TIMED_SIZED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(size = "1", ttl_ms = "100", eviction_policy = "lru")]
+fn timed_sized(n: u64) -> u64 {
+    TIMED_SIZED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_timed_sized_single_entry_cache() {
+    let calls = || TIMED_SIZED_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    timed_sized(1);
+    timed_sized(1);
+    assert_eq!(1, calls());
+    // sleep to expire the one entry
+    sleep(Duration::from_millis(150));
+    timed_sized(1);
+    assert_eq!(2, calls());
+    timed_sized_sync();
+    assert_eq!(
+        vec![1],
+        TIMED_SIZED.iter().map(|(k, _)| *k).collect::<Vec<_>>()
+    );
+    // sleep to expire the one entry
+    sleep(Duration::from_millis(150));
+    timed_sized_sync();
+    assert_eq!(0, TIMED_SIZED.entry_count());
+    timed_sized(1);
+    timed_sized(1);
+    assert_eq!(3, calls());
+    // the size is 1, so this new value evicts the existing key
+    timed_sized(2);
+    timed_sized_sync();
+    assert_eq!(4, calls());
+    assert_eq!(
+        vec![2],
+        TIMED_SIZED.iter().map(|(k, _)| *k).collect::<Vec<_>>()
+    );
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[kash(key(ty = "Point", expr = "Point { x, y }"))]
+fn custom_key(x: i32, y: i32) -> i32 {
+    x * y
+}
+
+#[test]
+fn test_custom_key() {
+    assert_eq!(6, custom_key(2, 3));
+    assert_eq!(6, custom_key(2, 3));
+    assert!(CUSTOM_KEY.contains_key(&Point { x: 2, y: 3 }));
+    assert!(!CUSTOM_KEY.contains_key(&Point { x: 3, y: 2 }));
+}
+
+static KEY_ARC_CLONES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// a large string, counting its clones
+#[derive(Debug, Hash, PartialEq, Eq)]
+struct Document(String);
+
+impl Clone for Document {
+    fn clone(&self) -> Self {
+        KEY_ARC_CLONES.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Self(self.0.clone())
+    }
+}
+
+#[kash(key_arc)]
+fn key_arc(document: Document, n: usize) -> usize {
+    document.0.len() * n
+}
+
+#[test]
+fn test_key_arc() {
+    let document = || Document("x".repeat(10_000));
+    assert_eq!(20_000, key_arc(document(), 2));
+    let clones = KEY_ARC_CLONES.load(std::sync::atomic::Ordering::SeqCst);
+    assert!(clones <= 1);
+
+    // a hit doesn't clone the key
+    assert_eq!(20_000, key_arc(document(), 2));
+    assert_eq!(20_000, key_arc(document(), 2));
+    assert_eq!(
+        clones,
+        KEY_ARC_CLONES.load(std::sync::atomic::Ordering::SeqCst)
+    );
+    assert!(KEY_ARC.contains_key(&(Arc::new(document()), Arc::new(2))));
+
+    assert_eq!(20_000, key_arc_prime_cache(document(), 2));
+    assert_eq!(10_000, key_arc_get_or_compute(document(), 1, || 10_000));
+}
+
+#[kash(key(ty = "u32", try_expr = "n.checked_mul(2).ok_or(())"))]
+fn is_cached(n: u32) -> u32 {
+    n
+}
+
+#[test]
+fn test_is_cached() {
+    assert!(!is_cached_is_cached(1));
+    assert!(
+        !is_cached_is_cached(1),
+        "checking doesn't compute the value"
+    );
+    assert_eq!(1, is_cached(1));
+    assert!(is_cached_is_cached(1));
+    assert!(!is_cached_is_cached(u32::MAX));
+    IS_CACHED.invalidate(&2);
+    assert!(!is_cached_is_cached(1));
+}
+
+#[kash(key(ty = "String", expr = r#"format!("{user}:{page}")"#))]
+fn user_page(user: &str, page: u32) -> String {
+    format!("page {page} of {user}")
+}
+
+#[kash]
+fn page_count(pages: u32, per_page: u32) -> u32 {
+    pages * per_page
+}
+
+#[kash(key(ty = "u32", try_expr = "raw.parse::<u32>()"))]
+fn parsed_page(raw: &str) -> u32 {
+    raw.len() as u32
+}
+
+#[test]
+fn test_generated_key_fn() {
+    assert_eq!((3, 4), page_count_key(3, 4));
+    assert_eq!(Some(2), is_cached_key(1));
+    assert_eq!(None, is_cached_key(u32::MAX));
+
+    user_page("ann", 1);
+    user_page("ann", 2);
+    assert_eq!("ann:1", user_page_key("ann", 1));
+    USER_PAGE.invalidate(&user_page_key("ann", 1));
+    assert!(!user_page_is_cached("ann", 1));
+    assert!(user_page_is_cached("ann", 2));
+
+    assert_eq!(Some(12), parsed_page_key("12"));
+    assert_eq!(None, parsed_page_key("twelve"));
+}
+
+#[kash(result)]
+fn set_cache(n: u32) -> Result<u32, String> {
+    Err(format!("{n} isn't computed"))
+}
+
+#[kash(arc)]
+fn set_cache_arc(n: u32) -> String {
+    n.to_string()
+}
+
+#[test]
+fn test_set_cache() {
+    set_cache_set_cache(1, 10);
+    assert_eq!(Ok(10), set_cache(1));
+    assert!(set_cache(2).is_err());
+    set_cache_set_cache(1, 11);
+    assert_eq!(Ok(11), set_cache(1));
+
+    set_cache_arc_set_cache(1, Arc::new("one".to_string()));
+    assert_eq!("one", *set_cache_arc(1));
+}
+
+static SHARED: kash::once_cell::sync::Lazy<kash::moka::sync::Cache<(u8, u32), u32>> =
+    kash::once_cell::sync::Lazy::new(|| kash::moka::sync::Cache::new(10));
+
+#[kash(shared_cache = "SHARED", key(ty = "(u8, u32)", expr = "(0, n)"))]
+fn shared_double(n: u32) -> u32 {
+    n * 2
+}
+
+#[kash(shared_cache = "SHARED", key(ty = "(u8, u32)", expr = "(1, n)"))]
+fn shared_triple(n: u32) -> u32 {
+    n * 3
+}
+
+#[test]
+fn test_shared_cache() {
+    assert_eq!(4, shared_double(2));
+    assert_eq!(6, shared_triple(2));
+    assert_eq!(Some(4), SHARED.get(&(0, 2)));
+    assert_eq!(Some(6), SHARED.get(&(1, 2)));
+    assert!(std::ptr::eq(
+        shared_double_get_cache_ident(),
+        shared_triple_get_cache_ident()
+    ));
+
+    SHARED.insert((0, 3), 0);
+    assert_eq!(0, shared_double(3));
+    assert!(shared_double_is_cached(3));
+    assert!(!shared_triple_is_cached(3));
+}
+
+#[derive(Clone)]
+struct Schema {
+    version: u32,
+}
+
+#[kash(invalidate_if)]
+fn schema(n: u32) -> Schema {
+    Schema { version: n % 3 }
+}
+
+#[test]
+fn test_invalidate_if() {
+    for n in 0..9 {
+        schema(n);
+    }
+    schema_invalidate_if(|n, schema| schema.version == 0 || *n == 8);
+    schema_sync();
+    assert_eq!(5, SCHEMA.entry_count());
+    assert!(!schema_is_cached(3));
+    assert!(!schema_is_cached(8));
+    assert!(schema_is_cached(4));
+}
+
+fn tenant_key(tenant: &str, id: &u32) -> String {
+    format!("{}/{}", tenant, id)
+}
+
+#[kash(key(ty = "String", with = "tenant_key"))]
+fn tenant_name(tenant: String, id: u32) -> String {
+    format!("{}-{}", tenant, id)
+}
+
+#[kash(key(ty = "String", with = "tenant_key"))]
+fn tenant_email(tenant: &str, id: u32) -> String {
+    format!("{}@{}", id, tenant)
+}
+
+#[test]
+fn test_key_fn() {
+    assert_eq!("a-1", tenant_name("a".to_string(), 1));
+    assert_eq!("1@a", tenant_email("a", 1));
+    assert!(TENANT_NAME.contains_key("a/1"));
+    assert!(TENANT_EMAIL.contains_key("a/1"));
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Var(u32),
+}
+
+static RENDER_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(key_digest = "hash")]
+fn render(template: &[Token]) -> usize {
+    RENDER_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    template.len()
+}
+
+#[kash(key_digest = "verify")]
+fn render_verified(template: &[Token], scale: usize) -> usize {
+    template.len() * scale
+}
+
+#[test]
+fn test_key_digest() {
+    let template = (0..10_000)
+        .map(|i| match i % 2 {
+            0 => Token::Text(i.to_string()),
+            _ => Token::Var(i),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(10_000, render(&template));
+    assert_eq!(10_000, render(&template.clone()));
+    assert_eq!(1, RENDER_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+    assert_eq!(1, render(&template[..1]));
+    assert_eq!(2, RENDER_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+    // the key is a digest of the arguments, not a copy of the slice
+    assert!(RENDER.contains_key(&kash::key::digest(&(&template,))));
+
+    assert_eq!(20_000, render_verified(&template, 2));
+    let key = kash::key::Verified::new((template.clone(), 2));
+    assert!(RENDER_VERIFIED.contains_key(&key));
+    assert_eq!(&template, &key.key().0);
+}
+
+// a key comparing its strings ignoring their case, keeping the original string
+#[derive(Clone, Debug)]
+struct CaseInsensitive(String);
+
+impl PartialEq for CaseInsensitive {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for CaseInsensitive {}
+
+impl std::hash::Hash for CaseInsensitive {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for byte in self.0.bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+    }
+}
+
+static GREET_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(key_wrapper = "CaseInsensitive")]
+fn greet(name: String) -> String {
+    GREET_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    format!("hello {name}")
+}
+
+#[test]
+fn test_key_wrapper() {
+    assert_eq!("hello A", greet("A".to_string()));
+    // "a" shares the entry of "A"
+    assert_eq!("hello A", greet("a".to_string()));
+    assert_eq!(1, GREET_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+    assert_eq!("hello b", greet("b".to_string()));
+    assert_eq!(2, GREET_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+
+    let key = greet_key("B".to_string());
+    assert_eq!("B", key.0);
+    assert!(GREET.contains_key(&key));
+    assert!(greet_is_cached("B".to_string()));
+}
+
+#[kash(ttl_ms = "500")]
+fn ttl_ms(n: u32) -> u32 {
+    n
+}
+
+#[test]
+fn test_ttl_ms() {
+    assert_eq!(1, ttl_ms(1));
+    assert!(TTL_MS.contains_key(&1));
+    sleep(Duration::from_millis(600));
+    assert!(!TTL_MS.contains_key(&1));
+    assert_eq!(
+        Some(Duration::from_millis(500)),
+        TTL_MS.policy().time_to_live()
+    );
+}
 
 #[allow(unused_mut)]
 #[kash]
@@ -420,3 +973,1642 @@ fn test_mutable_args_str() {
 fn always_failing() -> Result<String, ()> {
     Err(())
 }
+
+struct Tenant {
+    id: u32,
+}
+
+impl Tenant {
+    #[kash(in_impl, key(ty = "String", expr = r#"format!("{}:{}", self.id, n)"#))]
+    fn tenant_value(&self, n: u32) -> String {
+        format!("{}-{}", self.id, n)
+    }
+}
+
+#[test]
+fn test_in_impl_key_with_self() {
+    let a = Tenant { id: 1 };
+    let b = Tenant { id: 2 };
+    assert_eq!("1-5", a.tenant_value(5));
+    assert_eq!("2-5", b.tenant_value(5));
+    assert_eq!("1-5", a.tenant_value(5));
+    Tenant::tenant_value_sync();
+    let cache = Tenant::tenant_value_get_cache_ident();
+    assert_eq!(2, cache.entry_count());
+    assert!(cache.contains_key("1:5"));
+    assert!(cache.contains_key("2:5"));
+}
+
+static COMPUTE_CALLS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+struct Calculator {
+    factor: u64,
+}
+
+impl Calculator {
+    #[kash(in_impl)]
+    fn compute(n: u32) -> u64 {
+        COMPUTE_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        u64::from(n) * 2
+    }
+
+    #[kash(in_impl)]
+    fn base() -> u64 {
+        10
+    }
+
+    // the lifetime is a generic parameter of the cache accessor, too
+    #[kash(in_impl, key(ty = "String", expr = "word.to_string()"))]
+    #[allow(clippy::needless_lifetimes)]
+    fn word_len<'a>(word: &'a str) -> usize {
+        word.len()
+    }
+
+    #[kash(in_impl, key(ty = "(u64, u32)", expr = "(self.factor, n)"))]
+    fn scaled(&self, n: u32) -> u64 {
+        self.factor * Self::compute(n) + Self::base()
+    }
+}
+
+#[test]
+fn test_in_impl_associated_functions() {
+    let calls = || COMPUTE_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(6, Calculator::compute(3));
+    assert_eq!(6, Calculator::compute(3));
+    assert_eq!(1, calls());
+    assert_eq!(10, Calculator::base());
+    assert_eq!(4, Calculator::word_len("word"));
+
+    let calculator = Calculator { factor: 2 };
+    assert_eq!(22, calculator.scaled(3));
+    assert_eq!(22, calculator.scaled(3));
+    // the method calls the cached associated function, which already has the value
+    assert_eq!(1, calls());
+
+    Calculator::compute_sync();
+    Calculator::scaled_sync();
+    assert_eq!(1, Calculator::compute_get_cache_ident().entry_count());
+    assert_eq!(1, Calculator::scaled_get_cache_ident().entry_count());
+    assert!(Calculator::scaled_get_cache_ident().contains_key(&(2, 3)));
+}
+
+struct Labeled<T> {
+    label: T,
+}
+
+impl<T: std::fmt::Display> Labeled<T> {
+    #[kash(in_impl)]
+    fn label_len(label: String) -> usize {
+        label.len()
+    }
+
+    #[kash(in_impl, key(ty = "String", expr = "self.label.to_string()"))]
+    fn own_label_len(&self) -> usize {
+        Self::label_len(self.label.to_string())
+    }
+}
+
+#[test]
+fn test_in_impl_of_a_generic_type() {
+    assert_eq!(3, Labeled::<u32>::label_len("abc".to_string()));
+    assert_eq!(3, Labeled { label: 123 }.own_label_len());
+    assert_eq!(5, Labeled { label: "hello" }.own_label_len());
+    // the caches don't depend on the type parameter, so they're shared by all the types
+    Labeled::<u32>::own_label_len_sync();
+    assert_eq!(
+        2,
+        Labeled::<&str>::own_label_len_get_cache_ident().entry_count()
+    );
+}
+
+static KEY_CLONES: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+// a `String` key counting its clones, i.e., its allocations
+#[derive(Debug, Hash, PartialEq, Eq)]
+struct CountedKey(String);
+
+impl Clone for CountedKey {
+    fn clone(&self) -> Self {
+        KEY_CLONES.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        CountedKey(self.0.clone())
+    }
+}
+
+#[kash]
+fn counted_key_len(key: &CountedKey) -> usize {
+    key.0.len()
+}
+
+#[test]
+fn test_hit_does_not_clone_the_key() {
+    let key = CountedKey("four".to_string());
+    assert_eq!(4, counted_key_len(&key));
+    let miss_clones = KEY_CLONES.load(std::sync::atomic::Ordering::SeqCst);
+    assert!(miss_clones > 0);
+    assert_eq!(4, counted_key_len(&key));
+    assert_eq!(4, counted_key_len(&key));
+    assert_eq!(
+        miss_clones,
+        KEY_CLONES.load(std::sync::atomic::Ordering::SeqCst)
+    );
+}
+
+static PRIMES_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(collect)]
+fn primes_up_to(n: u32) -> impl Iterator<Item = u32> {
+    PRIMES_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    (2..=n).filter(|i| (2..*i).all(|d| i % d != 0))
+}
+
+#[test]
+fn test_collect() {
+    let primes = primes_up_to(20).collect::<Vec<_>>();
+    assert_eq!(vec![2, 3, 5, 7, 11, 13, 17, 19], primes);
+    assert_eq!(primes, primes_up_to(20).collect::<Vec<_>>());
+    assert_eq!(1, PRIMES_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+
+    // a partially consumed iterator doesn't change the cached values
+    assert_eq!(Some(2), primes_up_to(20).next());
+    assert_eq!(77, primes_up_to(20).sum::<u32>());
+    assert_eq!(1, PRIMES_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+static MAYBE_ASYNC_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(maybe_async = r#"feature = "async""#)]
+fn maybe_async_square(n: u64) -> u64 {
+    n * n
+}
+
+#[kash(maybe_async = r#"feature = "async""#)]
+fn maybe_async_sum_of_squares(a: u64, b: u64) -> u64 {
+    MAYBE_ASYNC_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    maybe_await!(maybe_async_square(a)) + maybe_await!(maybe_async_square(b))
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_maybe_async() {
+    assert_eq!(25, maybe_async_sum_of_squares(3, 4).await);
+    assert_eq!(25, maybe_async_sum_of_squares(3, 4).await);
+    assert_eq!(
+        1,
+        MAYBE_ASYNC_CALLS.load(std::sync::atomic::Ordering::SeqCst)
+    );
+    assert_eq!(16, maybe_async_square_no_cache(4).await);
+}
+
+#[cfg(not(feature = "async"))]
+#[test]
+fn test_maybe_async() {
+    assert_eq!(25, maybe_async_sum_of_squares(3, 4));
+    assert_eq!(25, maybe_async_sum_of_squares(3, 4));
+    assert_eq!(
+        1,
+        MAYBE_ASYNC_CALLS.load(std::sync::atomic::Ordering::SeqCst)
+    );
+    assert_eq!(16, maybe_async_square_no_cache(4));
+}
+
+static TUNED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(size = "8", initial_capacity = "8", segments = "4")]
+fn tuned(n: u64) -> u64 {
+    TUNED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n * 10
+}
+
+#[test]
+fn test_tuned_cache() {
+    let threads = (0..8)
+        .map(|_| std::thread::spawn(|| (0..4).map(|n| tuned(n % 2)).sum::<u64>()))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        assert_eq!(20, thread.join().unwrap());
+    }
+    assert_eq!(2, TUNED_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+    assert_eq!(Some(10), TUNED.get(&1));
+
+    // each of the segments holds its share of the `size`
+    for n in 0..100 {
+        tuned(n);
+    }
+    tuned_sync();
+    assert!(TUNED.entry_count() <= 8);
+    tuned_cache().clear().unwrap();
+    tuned_sync();
+    assert_eq!(0, TUNED.entry_count());
+}
+
+struct Shapes;
+
+#[kash::kash_impl]
+impl Shapes {
+    #[kash(in_impl)]
+    fn square(n: u32) -> u32 {
+        n * n
+    }
+
+    #[kash(in_impl, key(ty = "u32", expr = "n"))]
+    fn cube(&self, n: u32) -> u32 {
+        n * n * n
+    }
+
+    #[kash(in_impl, key(ty = "String", expr = "n.to_string()"))]
+    fn describe<T>(n: T) -> String
+    where
+        T: std::fmt::Display,
+    {
+        format!("shape {n}")
+    }
+
+    fn not_cached(n: u32) -> u32 {
+        n
+    }
+}
+
+#[test]
+fn test_kash_impl_clear_all() {
+    assert_eq!(4, Shapes::square(2));
+    assert_eq!(8, Shapes.cube(2));
+    assert_eq!("shape 2", Shapes::describe(2));
+    assert_eq!(2, Shapes::not_cached(2));
+    Shapes::square_sync();
+    Shapes::cube_sync();
+    Shapes::describe_sync();
+    assert_eq!(1, Shapes::square_get_cache_ident().entry_count());
+    assert_eq!(1, Shapes::cube_get_cache_ident().entry_count());
+    assert_eq!(1, Shapes::describe_get_cache_ident().entry_count());
+
+    Shapes::kash_clear_all();
+    Shapes::square_sync();
+    Shapes::cube_sync();
+    Shapes::describe_sync();
+    assert_eq!(0, Shapes::square_get_cache_ident().entry_count());
+    assert_eq!(0, Shapes::cube_get_cache_ident().entry_count());
+    assert_eq!(0, Shapes::describe_get_cache_ident().entry_count());
+}
+
+mod cache_vis {
+    use kash::kash;
+
+    #[kash(cache_vis = "pub(super)")]
+    fn hidden_fn(n: u32) -> u32 {
+        n
+    }
+
+    pub fn call_hidden_fn(n: u32) -> u32 {
+        hidden_fn(n)
+    }
+}
+
+#[test]
+fn test_cache_vis() {
+    assert_eq!(3, cache_vis::call_hidden_fn(3));
+    cache_vis::HIDDEN_FN.run_pending_tasks();
+    assert_eq!(1, cache_vis::HIDDEN_FN.entry_count());
+}
+
+#[kash(result, refresh_cache)]
+fn refreshed(n: u32) -> Result<u32, ()> {
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(())
+    }
+}
+
+#[test]
+fn test_refresh_cache() {
+    assert_eq!((Ok(1), false), refreshed_refresh_cache(1));
+    assert_eq!((Ok(1), true), refreshed_refresh_cache(1));
+    assert_eq!((Err(()), false), refreshed_refresh_cache(6));
+    assert_eq!((Err(()), false), refreshed_refresh_cache(6));
+}
+
+#[kash(option)]
+fn computed(n: u32) -> Option<u32> {
+    Some(n)
+}
+
+#[test]
+fn test_get_or_compute() {
+    assert_eq!(10, computed_get_or_compute(1, || 10));
+    assert_eq!(10, computed_get_or_compute(1, || 20));
+    assert_eq!(Some(10), computed(1));
+    assert_eq!(Some(2), computed(2));
+    assert_eq!(2, computed_get_or_compute(2, || 20));
+}
+
+static STUCK_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(lock_timeout = 50)]
+fn stuck(n: u32) -> u32 {
+    // only the first call hangs
+    if STUCK_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+        sleep(Duration::from_millis(500));
+    }
+    n
+}
+
+#[test]
+fn test_lock_timeout() {
+    let leader = thread::spawn(|| stuck(1));
+    sleep(Duration::from_millis(20));
+
+    let started = std::time::Instant::now();
+    assert_eq!(1, stuck(1));
+    assert!(started.elapsed() < Duration::from_millis(400));
+    assert_eq!(2, STUCK_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+
+    assert_eq!(1, leader.join().unwrap());
+    assert_eq!(1, stuck(1));
+    assert_eq!(2, STUCK_CALLS.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+// the computations of two keys, which only get through it if they run at the same time
+struct KeysRendezvous {
+    started: std::sync::Mutex<usize>,
+    condvar: std::sync::Condvar,
+}
+
+impl KeysRendezvous {
+    const fn new() -> Self {
+        Self {
+            started: std::sync::Mutex::new(0),
+            condvar: std::sync::Condvar::new(),
+        }
+    }
+
+    // wait for the computation of the other key, panicking if it doesn't start
+    fn wait(&self) {
+        let mut started = self.started.lock().unwrap();
+        *started += 1;
+        self.condvar.notify_all();
+        let (started, _) = self
+            .condvar
+            .wait_timeout_while(started, Duration::from_secs(5), |started| *started < 2)
+            .unwrap();
+        assert!(*started >= 2, "the keys aren't computed concurrently");
+    }
+}
+
+static PER_KEY_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static PER_KEY_RENDEZVOUS: KeysRendezvous = KeysRendezvous::new();
+
+#[kash]
+fn per_key(n: u32) -> u32 {
+    PER_KEY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    PER_KEY_RENDEZVOUS.wait();
+    n
+}
+
+static PER_KEY_TIMEOUT_CALLS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+static PER_KEY_TIMEOUT_RENDEZVOUS: KeysRendezvous = KeysRendezvous::new();
+
+#[kash(lock_timeout = 5000)]
+fn per_key_timeout(n: u32) -> u32 {
+    PER_KEY_TIMEOUT_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    PER_KEY_TIMEOUT_RENDEZVOUS.wait();
+    n
+}
+
+// the callers of a key wait for its computation, but different keys are computed concurrently,
+// which the functions check by waiting for each other
+fn assert_keys_computed_concurrently(f: fn(u32) -> u32, calls: &std::sync::atomic::AtomicUsize) {
+    thread::scope(|s| {
+        let callers = [1, 2, 1, 2, 1, 2]
+            .into_iter()
+            .map(|n| s.spawn(move || assert_eq!(n, f(n))))
+            .collect::<Vec<_>>();
+        for caller in callers {
+            caller.join().unwrap();
+        }
+    });
+    assert_eq!(2, calls.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn test_keys_are_computed_concurrently() {
+    assert_keys_computed_concurrently(per_key, &PER_KEY_CALLS);
+}
+
+#[test]
+fn test_keys_are_computed_concurrently_with_lock_timeout() {
+    assert_keys_computed_concurrently(per_key_timeout, &PER_KEY_TIMEOUT_CALLS);
+}
+
+#[kash(
+    name = "BUILD_LABELED",
+    display_name = r#"format!("labeled-{}", env!("CARGO_PKG_VERSION"))"#
+)]
+fn build_labeled(n: u32) -> u32 {
+    n
+}
+
+#[test]
+fn test_display_name() {
+    assert_eq!(1, build_labeled(1));
+    assert_eq!(
+        Some(format!("labeled-{}", env!("CARGO_PKG_VERSION")).as_str()),
+        BUILD_LABELED.name()
+    );
+}
+
+#[kash]
+fn handled(n: u32) -> u32 {
+    n * 2
+}
+
+#[test]
+fn test_cache_handle() {
+    let handle = handled_cache();
+    assert!(handle.is_empty().unwrap());
+
+    handled(1);
+    handled(2);
+    assert_eq!(2, handle.len().unwrap());
+    assert!(handle.contains(&1).unwrap());
+    assert!(!handle.contains(&3).unwrap());
+
+    let mut entries: Vec<_> = handle
+        .iter()
+        .unwrap()
+        .into_iter()
+        .map(|(k, v)| (*k, v))
+        .collect();
+    entries.sort();
+    assert_eq!(vec![(1, 2), (2, 4)], entries);
+
+    assert_eq!(Some(2), handle.remove(&1).unwrap());
+    assert!(!handle.contains(&1).unwrap());
+
+    handle.clear().unwrap();
+    assert!(handle.is_empty().unwrap());
+}
+
+struct ExpireAfterValue;
+
+impl moka::Expiry<u64, u64> for ExpireAfterValue {
+    fn expire_after_create(
+        &self,
+        _key: &u64,
+        value: &u64,
+        _created_at: std::time::Instant,
+    ) -> Option<Duration> {
+        Some(Duration::from_millis(*value))
+    }
+}
+
+#[kash(option, expiry = "ExpireAfterValue")]
+fn expire_after_millis(millis: u64) -> Option<u64> {
+    Some(millis)
+}
+
+#[test]
+fn test_expiry() {
+    expire_after_millis(100);
+    expire_after_millis(10_000);
+    assert!(EXPIRE_AFTER_MILLIS.contains_key(&100));
+    sleep(Duration::from_millis(200));
+    assert!(!EXPIRE_AFTER_MILLIS.contains_key(&100));
+    assert!(EXPIRE_AFTER_MILLIS.contains_key(&10_000));
+}
+
+#[kash(ttl_for = r#"|status| match status {
+    200 => Some(Duration::from_secs(10)),
+    _ => Some(Duration::from_millis(100)),
+}"#)]
+fn fetch_status(path: String) -> u16 {
+    if path == "/found" {
+        200
+    } else {
+        404
+    }
+}
+
+#[test]
+fn test_ttl_for() {
+    assert_eq!(200, fetch_status("/found".to_string()));
+    assert_eq!(404, fetch_status("/missing".to_string()));
+    assert!(FETCH_STATUS.contains_key("/found"));
+    assert!(FETCH_STATUS.contains_key("/missing"));
+    sleep(Duration::from_millis(200));
+    assert!(FETCH_STATUS.contains_key("/found"));
+    assert!(!FETCH_STATUS.contains_key("/missing"));
+}
+
+static LONG_TTL_FOR_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(
+    ttl = "1",
+    ttl_for = r#"|n| if *n == 0 { None } else { Some(Duration::from_secs(3)) }"#
+)]
+fn long_ttl_for(n: u32) -> u32 {
+    LONG_TTL_FOR_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_ttl_for_longer_than_ttl() {
+    let calls = || LONG_TTL_FOR_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(1, long_ttl_for(1));
+    assert_eq!(0, long_ttl_for(0));
+    assert_eq!(2, calls());
+    sleep(Duration::from_millis(1500));
+    // the ttl of `ttl_for` replaces the `ttl` of the cache, which only applies to its `None`
+    assert_eq!(1, long_ttl_for(1));
+    assert_eq!(2, calls());
+    assert_eq!(0, long_ttl_for(0));
+    assert_eq!(3, calls());
+}
+
+static SKIP_EMPTY_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(skip_if = "|tags| tags.is_empty()")]
+fn tags_of(n: u32) -> Vec<u32> {
+    SKIP_EMPTY_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    (0..n).collect()
+}
+
+static SKIP_EMPTY_LOCKED_CALLS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(result, arc, lock_timeout = 1000, skip_if = "|tags| tags.is_empty()")]
+fn locked_tags_of(n: u32) -> Result<Vec<u32>, ()> {
+    SKIP_EMPTY_LOCKED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    Ok((0..n).collect())
+}
+
+#[test]
+fn test_skip_if() {
+    let calls = || SKIP_EMPTY_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+
+    // an empty collection is returned, but computed again on each call
+    assert_eq!(Vec::<u32>::new(), tags_of(0));
+    assert_eq!(Vec::<u32>::new(), tags_of(0));
+    assert_eq!(2, calls());
+    assert!(!TAGS_OF.contains_key(&0));
+
+    // the other values are cached
+    assert_eq!(vec![0, 1], tags_of(2));
+    assert_eq!(vec![0, 1], tags_of(2));
+    assert_eq!(3, calls());
+
+    assert_eq!(Vec::<u32>::new(), tags_of_prime_cache(0));
+    assert!(!TAGS_OF.contains_key(&0));
+
+    let calls = || SKIP_EMPTY_LOCKED_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert!(locked_tags_of(0).unwrap().is_empty());
+    assert!(locked_tags_of(0).unwrap().is_empty());
+    assert_eq!(2, calls());
+    assert_eq!(Ok(Arc::new(vec![0])), locked_tags_of(1));
+    assert_eq!(Ok(Arc::new(vec![0])), locked_tags_of(1));
+    assert_eq!(3, calls());
+}
+
+#[kash(arc)]
+fn parsed_config(n: u32) -> Vec<u32> {
+    vec![n; 1000]
+}
+
+#[kash(result, arc)]
+fn fallible_config(n: u32) -> Result<Vec<u32>, ()> {
+    if n < 5 {
+        Ok(vec![n; 1000])
+    } else {
+        Err(())
+    }
+}
+
+#[test]
+fn test_arc() {
+    let first: Arc<Vec<u32>> = parsed_config(1);
+    let second = parsed_config(1);
+    assert!(Arc::ptr_eq(&first, &second));
+    assert!(!Arc::ptr_eq(&first, &parsed_config_prime_cache(1)));
+
+    let first = fallible_config(1).unwrap();
+    assert!(Arc::ptr_eq(&first, &fallible_config(1).unwrap()));
+    assert_eq!(Err(()), fallible_config(6));
+}
+
+#[kash(guard)]
+fn large_vec(n: u32) -> Vec<u32> {
+    vec![n; 100_000]
+}
+
+#[kash(option, guard)]
+fn maybe_large_vec(n: u32) -> Option<Vec<u32>> {
+    (n > 0).then(|| vec![n; 100_000])
+}
+
+#[test]
+fn test_guard() {
+    let first: kash::Cached<Vec<u32>> = large_vec(1);
+    let second = large_vec(1);
+    assert!(kash::Cached::ptr_eq(&first, &second));
+    assert_eq!(100_000, first.len());
+    assert_eq!(&[1, 1], &first[..2]);
+
+    // a guard outlives its entry, and the next call caches a new value
+    LARGE_VEC.invalidate_all();
+    let third = large_vec(1);
+    assert!(!kash::Cached::ptr_eq(&first, &third));
+    assert_eq!(first, third);
+
+    let maybe = maybe_large_vec(2).unwrap();
+    assert!(kash::Cached::ptr_eq(&maybe, &maybe_large_vec(2).unwrap()));
+    assert_eq!(None, maybe_large_vec(0));
+}
+
+#[deny(unused_attributes)]
+mod must_use {
+    use kash::kash;
+
+    #[kash(result, must_use)]
+    fn checked(n: u32) -> Result<u32, ()> {
+        Ok(n)
+    }
+
+    #[kash(must_use)]
+    #[must_use]
+    fn already_checked(n: u32) -> u32 {
+        n
+    }
+
+    #[test]
+    fn test_must_use() {
+        assert_eq!(Ok(1), checked(1));
+        assert_eq!(Ok(2), checked_prime_cache(2));
+        assert_eq!(Ok(3), checked_no_cache(3));
+        assert_eq!(1, already_checked(1));
+    }
+}
+
+static KASH_ABOVE_TEST_CALLS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+// `#[test]` isn't forwarded to the generated functions, so only the cached function is a test
+#[kash]
+#[test]
+fn test_kash_above_test() {
+    assert_eq!(
+        0,
+        KASH_ABOVE_TEST_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    );
+}
+
+#[kash]
+#[inline]
+#[allow(clippy::identity_op)]
+fn inlined(n: u32) -> u32 {
+    n + 0
+}
+
+#[test]
+fn test_forwarded_attributes() {
+    assert_eq!(1, inlined(1));
+    assert_eq!(2, inlined_no_cache(2));
+    inlined_prime_cache(3);
+    assert!(inlined_is_cached(3));
+}
+
+static NORMALIZED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+// `None` is the default page, so it's cached like `Some(1)`
+#[kash(normalize = "let page = Some(page.unwrap_or(1)); let query = query.trim().to_string();")]
+fn normalized(query: String, page: Option<u32>) -> String {
+    NORMALIZED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    format!("{query}#{}", page.unwrap())
+}
+
+#[test]
+fn test_normalize() {
+    let calls = || NORMALIZED_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    let query = |query: &str| query.to_string();
+    assert_eq!("rust#1", normalized(query("rust"), None));
+    assert_eq!("rust#1", normalized(query(" rust "), Some(1)));
+    assert_eq!(1, calls());
+    assert_eq!(
+        (query("rust"), Some(1)),
+        normalized_key(query("rust "), None)
+    );
+    assert!(normalized_is_cached(query("rust"), None));
+
+    assert_eq!("rust#2", normalized(query("rust"), Some(2)));
+    assert_eq!(2, calls());
+    // the original function isn't normalized
+    assert_eq!(" rust #3", normalized_no_cache(query(" rust "), Some(3)));
+}
+
+static GENERATION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static GENERATION_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(generation = "GENERATION.load(std::sync::atomic::Ordering::SeqCst)")]
+fn generational(n: u32) -> u32 {
+    GENERATION_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_generation() {
+    let calls = || GENERATION_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(1, generational(1));
+    assert_eq!(1, generational(1));
+    assert_eq!(1, calls());
+    assert_eq!((0, 1), generational_key(1));
+
+    // a new generation misses all the entries of the previous one
+    GENERATION.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(1, generational(1));
+    assert_eq!(2, calls());
+    assert_eq!(1, generational(1));
+    assert_eq!(2, calls());
+    assert!(GENERATIONAL.contains_key(&(1, 1)));
+}
+
+static REFRESHED_AHEAD_CALLS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+// returns the number of its computations, refreshed during the last half of its ttl
+#[kash(ttl_ms = "1500", refresh_ahead = "0.5")]
+fn refreshed_ahead(n: u32) -> usize {
+    sleep(Duration::from_millis(200));
+    n as usize + REFRESHED_AHEAD_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1
+}
+
+#[test]
+fn test_refresh_ahead() {
+    let calls = || REFRESHED_AHEAD_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(1, refreshed_ahead(0));
+    assert_eq!(1, refreshed_ahead(0));
+    assert_eq!(1, calls());
+
+    // a read close to the expiry returns the cached value, and refreshes it once in the background
+    sleep(Duration::from_millis(900));
+    let start = std::time::Instant::now();
+    assert_eq!(1, refreshed_ahead(0));
+    assert_eq!(1, refreshed_ahead(0));
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    sleep(Duration::from_millis(400));
+    assert_eq!(2, calls());
+    assert_eq!(2, refreshed_ahead(0));
+    // the refreshed value expires after a new ttl
+    sleep(Duration::from_millis(600));
+    assert_eq!(2, refreshed_ahead(0));
+    assert_eq!(2, calls());
+}
+
+static MISSED_AHEAD_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(ttl_ms = "1000", refresh_ahead = "0.5")]
+fn missed_ahead(n: u32) -> u32 {
+    MISSED_AHEAD_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_refresh_ahead_miss() {
+    let calls = || MISSED_AHEAD_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    missed_ahead(1);
+    sleep(Duration::from_millis(600));
+
+    // a miss in the refresh window, e.g. after an invalidation, is only computed by the caller
+    MISSED_AHEAD.invalidate(&1);
+    missed_ahead(1);
+    sleep(Duration::from_millis(200));
+    assert_eq!(2, calls());
+}
+
+static EXPIRING_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(ttl_ms = "300", with_ttl)]
+fn expiring(n: u32) -> u32 {
+    EXPIRING_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n
+}
+
+#[test]
+fn test_with_ttl() {
+    let calls = || EXPIRING_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    // the given ttl overrides the one of the cache, whether it's shorter or longer
+    assert_eq!(1, expiring_with_ttl(1, Duration::from_millis(100)));
+    assert_eq!(2, expiring_with_ttl(2, Duration::from_millis(800)));
+    assert_eq!(3, expiring(3));
+    assert_eq!(2, expiring_with_ttl(2, Duration::from_millis(100)));
+    assert_eq!(3, calls());
+
+    sleep(Duration::from_millis(150));
+    assert!(!expiring_is_cached(1));
+    assert!(expiring_is_cached(2));
+    assert_eq!(3, expiring(3));
+    assert_eq!(3, calls());
+
+    sleep(Duration::from_millis(250));
+    assert!(expiring_is_cached(2));
+    assert_eq!(3, expiring(3));
+    assert_eq!(4, calls());
+}
+
+static SLOW_EXPIRING_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash(with_ttl)]
+fn slow_expiring(n: u32) -> u32 {
+    SLOW_EXPIRING_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    sleep(Duration::from_millis(100));
+    n
+}
+
+#[test]
+fn test_with_ttl_single_flight() {
+    let calls = || SLOW_EXPIRING_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    let threads = (0..4)
+        .map(|_| thread::spawn(|| slow_expiring_with_ttl(1, Duration::from_millis(300))))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        assert_eq!(1, thread.join().unwrap());
+    }
+    assert_eq!(1, slow_expiring(1));
+    assert_eq!(1, calls());
+
+    sleep(Duration::from_millis(400));
+    assert!(!slow_expiring_is_cached(1));
+}
+
+static SINGLETON_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[kash]
+fn singleton() -> Vec<u32> {
+    SINGLETON_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    sleep(Duration::from_millis(50));
+    vec![1, 2, 3]
+}
+
+#[test]
+fn test_zero_arg_single_slot() {
+    let calls = || SINGLETON_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    let cache: &kash::SlotCache<Vec<u32>> = &SINGLETON;
+    assert!(!cache.contains_key(&()));
+
+    let threads = (0..4).map(|_| thread::spawn(singleton)).collect::<Vec<_>>();
+    for thread in threads {
+        assert_eq!(vec![1, 2, 3], thread.join().unwrap());
+    }
+    assert_eq!(vec![1, 2, 3], singleton());
+    assert_eq!(1, calls());
+    assert!(singleton_is_cached());
+
+    assert_eq!(vec![1, 2, 3], singleton_prime_cache());
+    assert_eq!(2, calls());
+    SINGLETON.invalidate_all();
+    assert_eq!(0, SINGLETON.entry_count());
+    assert_eq!(vec![1, 2, 3], singleton());
+    assert_eq!(3, calls());
+}
+
+#[kash(option)]
+fn missing_config() -> Option<u32> {
+    sleep(Duration::from_millis(300));
+    None
+}
+
+#[test]
+fn test_zero_arg_single_slot_option_with_get_or_compute() {
+    // the `None` of the computing call isn't returned to `missing_config_get_or_compute`, which computes its value
+    let computing = thread::spawn(missing_config);
+    sleep(Duration::from_millis(100));
+    assert_eq!(7, missing_config_get_or_compute(|| 7));
+    assert!(matches!(computing.join().unwrap(), None | Some(7)));
+    assert_eq!(Some(7), missing_config());
+}
+
+static DISABLED_CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg_attr(not(target_arch = "wasm32"), kash(size = "10", disabled))]
+fn disabled(n: u32) -> u32 {
+    DISABLED_CALLS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    n * 2
+}
+
+#[kash(arc, disabled)]
+fn disabled_arc(n: u32) -> Vec<u32> {
+    vec![n]
+}
+
+#[test]
+fn test_disabled() {
+    let calls = || DISABLED_CALLS.load(std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(2, disabled(1));
+    assert_eq!(2, disabled(1));
+    assert_eq!(2, calls());
+    assert_eq!(4, disabled_prime_cache(2));
+    assert_eq!(4, disabled_no_cache(2));
+    assert_eq!(4, calls());
+
+    let value: std::sync::Arc<Vec<u32>> = disabled_arc(1);
+    assert_eq!(vec![1], *value);
+    assert!(!std::sync::Arc::ptr_eq(&value, &disabled_arc(1)));
+
+    // the other generated items are kept, with an empty cache
+    disabled_set_cache(3, 100);
+    assert!(!disabled_is_cached(3));
+    assert_eq!(6, disabled(3));
+    assert_eq!(10, disabled_get_or_compute(3, || 10));
+    assert_eq!(5, calls());
+    assert_eq!(3, disabled_key(3));
+    disabled_sync();
+    assert_eq!(0, DISABLED.entry_count());
+    assert_eq!(0, disabled_cache().len().unwrap());
+}
+
+#[cfg(feature = "async")]
+mod async_test {
+    use kash::kash;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    static SINGLE_FLIGHT_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[kash]
+    async fn single_flight(n: u32) -> u32 {
+        SINGLE_FLIGHT_CALLS.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        n
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_single_flight() {
+        let tasks = (0..8)
+            .map(|_| tokio::spawn(single_flight(1)))
+            .collect::<Vec<_>>();
+        for task in tasks {
+            assert_eq!(1, task.await.unwrap());
+        }
+        assert_eq!(1, SINGLE_FLIGHT_CALLS.load(Ordering::SeqCst));
+    }
+
+    static REFRESHED_AHEAD_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[cfg(feature = "tokio")]
+    #[kash(ttl_ms = "1500", refresh_ahead = "0.5")]
+    async fn async_refreshed(n: u32) -> usize {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        n as usize + REFRESHED_AHEAD_CALLS.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_refresh_ahead() {
+        assert_eq!(1, async_refreshed(0).await);
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        let start = std::time::Instant::now();
+        assert_eq!(1, async_refreshed(0).await);
+        assert_eq!(1, async_refreshed(0).await);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(2, REFRESHED_AHEAD_CALLS.load(Ordering::SeqCst));
+        assert_eq!(2, async_refreshed(0).await);
+    }
+
+    static FLAKY_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static FLAKY_RELEASE: std::sync::OnceLock<tokio::sync::Notify> = std::sync::OnceLock::new();
+
+    // fails on its first computation, then succeeds, once it's released
+    #[kash(result)]
+    async fn async_flaky(n: u32) -> Result<u32, String> {
+        let calls = FLAKY_CALLS.fetch_add(1, Ordering::SeqCst);
+        FLAKY_RELEASE
+            .get_or_init(tokio::sync::Notify::new)
+            .notified()
+            .await;
+        if calls == 0 {
+            Err("unavailable".to_string())
+        } else {
+            Ok(n)
+        }
+    }
+
+    // call `async_flaky` concurrently, releasing the computation once all the callers are waiting for it
+    async fn call_flaky_concurrently() -> Vec<Result<u32, String>> {
+        let mut callers = (0..8).map(|_| Box::pin(async_flaky(1))).collect::<Vec<_>>();
+        // the first caller leads the computation, and the others wait for it
+        for caller in &mut callers {
+            let waiting = std::future::poll_fn(|cx| {
+                std::task::Poll::Ready(std::future::Future::poll(caller.as_mut(), cx).is_pending())
+            })
+            .await;
+            assert!(waiting);
+        }
+        FLAKY_RELEASE
+            .get_or_init(tokio::sync::Notify::new)
+            .notify_one();
+        let mut results = Vec::new();
+        for caller in callers {
+            results.push(caller.await);
+        }
+        results
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_result_single_flight() {
+        // the concurrent callers share the error of a single computation
+        for result in call_flaky_concurrently().await {
+            assert_eq!(Err("unavailable".to_string()), result);
+        }
+        assert_eq!(1, FLAKY_CALLS.load(Ordering::SeqCst));
+
+        // the error isn't cached, so the next callers compute the value once
+        for result in call_flaky_concurrently().await {
+            assert_eq!(Ok(1), result);
+        }
+        assert_eq!(2, FLAKY_CALLS.load(Ordering::SeqCst));
+        assert_eq!(Ok(1), async_flaky(1).await);
+        assert_eq!(2, FLAKY_CALLS.load(Ordering::SeqCst));
+    }
+
+    static ASYNC_DISABLED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[kash(result, disabled)]
+    async fn async_disabled(n: u32) -> Result<u32, String> {
+        ASYNC_DISABLED_CALLS.fetch_add(1, Ordering::SeqCst);
+        Ok(n)
+    }
+
+    #[tokio::test]
+    async fn test_async_disabled() {
+        assert_eq!(Ok(1), async_disabled(1).await);
+        assert_eq!(Ok(1), async_disabled_prime_cache(1).await);
+        assert_eq!(2, ASYNC_DISABLED_CALLS.load(Ordering::SeqCst));
+
+        async_disabled_set_cache(1, 10).await;
+        assert!(!async_disabled_is_cached(1));
+        assert_eq!(10, async_disabled_get_or_compute(1, async { 10 }).await);
+        assert_eq!(Ok(1), async_disabled(1).await);
+    }
+
+    static ASYNC_PER_KEY_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static ASYNC_PER_KEY_BARRIER: std::sync::OnceLock<tokio::sync::Barrier> =
+        std::sync::OnceLock::new();
+
+    #[kash]
+    async fn async_per_key(n: u32) -> u32 {
+        ASYNC_PER_KEY_CALLS.fetch_add(1, Ordering::SeqCst);
+        // the computations of both keys only get through the barrier if they run at the same time
+        let barrier = ASYNC_PER_KEY_BARRIER.get_or_init(|| tokio::sync::Barrier::new(2));
+        tokio::time::timeout(Duration::from_secs(5), barrier.wait())
+            .await
+            .expect("the keys aren't computed concurrently");
+        n
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_keys_are_computed_concurrently() {
+        let tasks = [1, 2, 1, 2, 1, 2]
+            .into_iter()
+            .map(|n| tokio::spawn(async_per_key(n)))
+            .collect::<Vec<_>>();
+        for (n, task) in [1, 2, 1, 2, 1, 2].into_iter().zip(tasks) {
+            assert_eq!(n, task.await.unwrap());
+        }
+        assert_eq!(2, ASYNC_PER_KEY_CALLS.load(Ordering::SeqCst));
+    }
+
+    static ASYNC_KEY_ARC_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Hash, PartialEq, Eq)]
+    struct AsyncDocument(String);
+
+    impl Clone for AsyncDocument {
+        fn clone(&self) -> Self {
+            ASYNC_KEY_ARC_CLONES.fetch_add(1, Ordering::SeqCst);
+            Self(self.0.clone())
+        }
+    }
+
+    #[kash(key_arc)]
+    async fn async_key_arc(document: AsyncDocument) -> usize {
+        document.0.len()
+    }
+
+    #[tokio::test]
+    async fn test_async_key_arc() {
+        let document = || AsyncDocument("x".repeat(10_000));
+        assert_eq!(10_000, async_key_arc(document()).await);
+        let clones = ASYNC_KEY_ARC_CLONES.load(Ordering::SeqCst);
+
+        // a hit doesn't clone the key
+        assert_eq!(10_000, async_key_arc(document()).await);
+        assert_eq!(clones, ASYNC_KEY_ARC_CLONES.load(Ordering::SeqCst));
+    }
+
+    #[kash]
+    async fn async_is_cached(n: u32) -> u32 {
+        n
+    }
+
+    #[tokio::test]
+    async fn test_async_is_cached() {
+        assert!(!async_is_cached_is_cached(1));
+        assert_eq!(1, async_is_cached(1).await);
+        assert!(async_is_cached_is_cached(1));
+    }
+
+    #[kash(size = "2")]
+    async fn async_sized(n: u32) -> u32 {
+        n
+    }
+
+    static ASYNC_METHOD_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct AsyncTenant {
+        id: u32,
+    }
+
+    impl AsyncTenant {
+        // the key doesn't include `self`, so all instances share the cached values
+        #[kash(in_impl)]
+        async fn shared_value(&self, n: u32) -> u32 {
+            ASYNC_METHOD_CALLS.fetch_add(1, Ordering::SeqCst);
+            self.id * 100 + n
+        }
+
+        #[kash(in_impl, key(ty = "(u32, u32)", expr = "(self.id, n)"))]
+        async fn own_value(&self, n: u32) -> u32 {
+            ASYNC_METHOD_CALLS.fetch_add(1, Ordering::SeqCst);
+            self.id * 100 + n
+        }
+
+        // an associated function, without `self`
+        #[kash(in_impl)]
+        async fn base(n: u32) -> u32 {
+            ASYNC_METHOD_CALLS.fetch_add(1, Ordering::SeqCst);
+            n * 1000
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_in_impl() {
+        let a = AsyncTenant { id: 1 };
+        let b = AsyncTenant { id: 2 };
+        assert_eq!(105, a.shared_value(5).await);
+        assert_eq!(105, a.shared_value(5).await);
+        assert_eq!(105, b.shared_value(5).await);
+        assert_eq!(1, ASYNC_METHOD_CALLS.load(Ordering::SeqCst));
+
+        assert_eq!(105, a.own_value(5).await);
+        assert_eq!(205, b.own_value(5).await);
+        assert_eq!(105, a.own_value(5).await);
+        assert_eq!(205, b.own_value(5).await);
+        assert_eq!(3, ASYNC_METHOD_CALLS.load(Ordering::SeqCst));
+
+        AsyncTenant::own_value_sync().await;
+        let cache = AsyncTenant::own_value_get_cache_ident();
+        assert_eq!(2, cache.entry_count());
+        assert!(a.own_value_is_cached(5));
+        assert!(!a.own_value_is_cached(6));
+
+        assert_eq!(5000, AsyncTenant::base(5).await);
+        assert_eq!(5000, AsyncTenant::base(5).await);
+        assert_eq!(4, ASYNC_METHOD_CALLS.load(Ordering::SeqCst));
+    }
+
+    #[kash(option)]
+    async fn async_set_cache(n: u32) -> Option<u32> {
+        (n > 1).then_some(n)
+    }
+
+    #[tokio::test]
+    async fn test_async_set_cache() {
+        assert_eq!(None, async_set_cache(1).await);
+        async_set_cache_set_cache(1, 10).await;
+        assert_eq!(Some(10), async_set_cache(1).await);
+    }
+
+    #[tokio::test]
+    async fn test_async_sync() {
+        for n in 0..10 {
+            async_sized(n).await;
+        }
+        async_sized_sync().await;
+        assert_eq!(2, ASYNC_SIZED.entry_count());
+    }
+
+    #[kash(invalidate_if)]
+    async fn async_invalidated(n: u32) -> u32 {
+        n
+    }
+
+    #[tokio::test]
+    async fn test_async_invalidate_if() {
+        for n in 0..4 {
+            async_invalidated(n).await;
+        }
+        async_invalidated_invalidate_if(|_, n| n % 2 == 0);
+        async_invalidated_sync().await;
+        assert_eq!(2, ASYNC_INVALIDATED.entry_count());
+        assert!(!async_invalidated_is_cached(2));
+    }
+
+    static ASYNC_SHARED: kash::once_cell::sync::Lazy<kash::moka::future::Cache<u32, u32>> =
+        kash::once_cell::sync::Lazy::new(|| kash::moka::future::Cache::new(10));
+
+    #[kash(shared_cache = "ASYNC_SHARED")]
+    async fn async_shared(n: u32) -> u32 {
+        n * 2
+    }
+
+    #[tokio::test]
+    async fn test_async_shared_cache() {
+        assert_eq!(4, async_shared(2).await);
+        assert_eq!(Some(4), ASYNC_SHARED.get(&2).await);
+    }
+
+    #[kash]
+    async fn async_primed_blocking(n: u32) -> u32 {
+        n * 2
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_prime_cache_blocking() {
+        let primed = tokio::task::spawn_blocking(|| async_primed_blocking_prime_cache_blocking(3))
+            .await
+            .unwrap();
+        assert_eq!(6, primed);
+        assert!(ASYNC_PRIMED_BLOCKING.contains_key(&3));
+    }
+
+    #[cfg(feature = "mem_persist")]
+    #[kash(persist)]
+    async fn async_persist(n: u32) -> u32 {
+        n * 2
+    }
+
+    #[cfg(feature = "mem_persist")]
+    #[tokio::test]
+    async fn test_async_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("async_persist");
+        assert_eq!(2, async_persist(1).await);
+        assert_eq!(1, async_persist_dump_cache(&path).unwrap());
+
+        ASYNC_PERSIST.invalidate_all();
+        assert_eq!(1, async_persist_load_cache(&path).await.unwrap());
+        assert_eq!(Some(2), ASYNC_PERSIST.get(&1).await);
+    }
+
+    static CANCELLED_LEADER_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[kash]
+    async fn cancelled_leader(n: u32) -> u32 {
+        // the first call never finishes, until its task is aborted
+        if CANCELLED_LEADER_CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+            std::future::pending::<()>().await;
+        }
+        n
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_cancelled_leader() {
+        let leader = tokio::spawn(cancelled_leader(1));
+        while CANCELLED_LEADER_CALLS.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        // the waiter is polled in place, so it's known to be waiting for the leader when it's aborted
+        let mut waiter = std::pin::pin!(cancelled_leader(1));
+        let waiting = std::future::poll_fn(|cx| {
+            std::task::Poll::Ready(std::future::Future::poll(waiter.as_mut(), cx).is_pending())
+        })
+        .await;
+        assert!(waiting);
+        assert_eq!(1, CANCELLED_LEADER_CALLS.load(Ordering::SeqCst));
+
+        leader.abort();
+        assert!(leader.await.unwrap_err().is_cancelled());
+        assert_eq!(1, waiter.await);
+        assert_eq!(2, CANCELLED_LEADER_CALLS.load(Ordering::SeqCst));
+
+        assert_eq!(1, cancelled_leader(1).await);
+        assert_eq!(2, CANCELLED_LEADER_CALLS.load(Ordering::SeqCst));
+    }
+
+    static IMPL_FUTURE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[kash]
+    fn impl_future(n: u32) -> impl std::future::Future<Output = u32> {
+        IMPL_FUTURE_CALLS.fetch_add(1, Ordering::SeqCst);
+        async move { n * 2 }
+    }
+
+    #[kash(option)]
+    fn boxed_future(
+        n: u32,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Option<u32>> + Send>> {
+        Box::pin(async move { n.checked_sub(1) })
+    }
+
+    #[tokio::test]
+    async fn test_impl_future() {
+        assert_eq!(4, impl_future(2).await);
+        assert_eq!(4, impl_future(2).await);
+        assert_eq!(1, IMPL_FUTURE_CALLS.load(Ordering::SeqCst));
+        assert!(IMPL_FUTURE.contains_key(&2));
+
+        assert_eq!(None, boxed_future(0).await);
+        assert_eq!(Some(1), boxed_future(2).await);
+        assert!(BOXED_FUTURE.contains_key(&2));
+        assert!(!BOXED_FUTURE.contains_key(&0));
+    }
+
+    static SPAWNED_FUTURE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    #[kash]
+    fn spawned_future(n: u64) -> std::pin::Pin<Box<dyn std::future::Future<Output = u64> + Send>> {
+        SPAWNED_FUTURE_CALLS.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move {
+            if n < 2 {
+                n
+            } else {
+                spawned_future(n - 1).await + spawned_future(n - 2).await
+            }
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_spawned_future() {
+        let future: std::pin::Pin<Box<dyn std::future::Future<Output = u64> + Send>> =
+            spawned_future(10);
+        assert_eq!(55, tokio::spawn(future).await.unwrap());
+        assert_eq!(11, SPAWNED_FUTURE_CALLS.load(Ordering::SeqCst));
+        assert_eq!(55, tokio::spawn(spawned_future(10)).await.unwrap());
+        assert_eq!(11, SPAWNED_FUTURE_CALLS.load(Ordering::SeqCst));
+    }
+
+    #[kash(arc)]
+    async fn async_parsed_config(n: u32) -> Vec<u32> {
+        vec![n; 1000]
+    }
+
+    #[tokio::test]
+    async fn test_async_arc() {
+        let first = async_parsed_config(1).await;
+        let second = async_parsed_config(1).await;
+        assert!(std::sync::Arc::ptr_eq(&first, &second));
+    }
+
+    #[kash]
+    async fn async_computed(n: u32) -> u32 {
+        n
+    }
+
+    #[tokio::test]
+    async fn test_async_get_or_compute() {
+        assert_eq!(10, async_computed_get_or_compute(1, async { 10 }).await);
+        assert_eq!(10, async_computed_get_or_compute(1, async { 20 }).await);
+        assert_eq!(10, async_computed(1).await);
+    }
+
+    #[derive(Default)]
+    struct MemoryStore(std::sync::Mutex<std::collections::HashMap<u32, u32>>);
+
+    #[kash::async_trait]
+    impl kash::IOKashAsync<u32, u32> for MemoryStore {
+        type Error = String;
+
+        async fn get(&self, k: &u32) -> Result<Option<u32>, Self::Error> {
+            Ok(self.0.lock().unwrap().get(k).copied())
+        }
+
+        async fn set(&self, k: u32, v: u32) -> Result<Option<u32>, Self::Error> {
+            Ok(self.0.lock().unwrap().insert(k, v))
+        }
+
+        async fn remove(&self, k: &u32) -> Result<Option<u32>, Self::Error> {
+            Ok(self.0.lock().unwrap().remove(k))
+        }
+
+        fn ttl(&self) -> Option<u64> {
+            Some(10)
+        }
+    }
+
+    struct FailingStore;
+
+    #[kash::async_trait]
+    impl kash::IOKashAsync<u32, u32> for FailingStore {
+        type Error = String;
+
+        async fn get(&self, _k: &u32) -> Result<Option<u32>, Self::Error> {
+            Err("unavailable".to_string())
+        }
+
+        async fn set(&self, _k: u32, _v: u32) -> Result<Option<u32>, Self::Error> {
+            Err("unavailable".to_string())
+        }
+
+        async fn remove(&self, _k: &u32) -> Result<Option<u32>, Self::Error> {
+            Err("unavailable".to_string())
+        }
+    }
+
+    type BoxedStore = Box<dyn kash::IOKashAsync<u32, u32, Error = String> + Send + Sync>;
+
+    #[tokio::test]
+    async fn test_boxed_io_kash_async() {
+        let mut stores: Vec<BoxedStore> =
+            vec![Box::new(MemoryStore::default()), Box::new(FailingStore)];
+
+        assert_eq!(Ok(None), stores[0].set(1, 10).await);
+        assert_eq!(Ok(Some(10)), stores[0].get(&1).await);
+        assert_eq!(Ok(Some(10)), stores[0].remove(&1).await);
+        assert_eq!(Ok(None), stores[0].get(&1).await);
+        assert_eq!(Some(10), stores[0].ttl());
+
+        assert!(stores[1].set(1, 10).await.is_err());
+        assert_eq!(None, stores[1].ttl());
+        assert_eq!(None, stores[1].set_ttl(5));
+
+        // the boxed stores can be moved to other tasks
+        let store = stores.remove(0);
+        let handle = tokio::spawn(async move { store.set(2, 20).await });
+        assert_eq!(Ok(None), handle.await.unwrap());
+    }
+
+    static ASYNC_TUNED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // the cache of `async` functions isn't segmented, `segments` is ignored
+    #[kash(initial_capacity = "16", segments = "4")]
+    async fn async_tuned(n: u64) -> u64 {
+        ASYNC_TUNED_CALLS.fetch_add(1, Ordering::SeqCst);
+        n * 10
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_async_tuned_cache() {
+        let tasks = (0..8)
+            .map(|n| tokio::spawn(async_tuned(n % 2)))
+            .collect::<Vec<_>>();
+        for (n, task) in tasks.into_iter().enumerate() {
+            assert_eq!(n as u64 % 2 * 10, task.await.unwrap());
+        }
+        assert_eq!(2, ASYNC_TUNED_CALLS.load(Ordering::SeqCst));
+    }
+}
+
+#[cfg(feature = "trace")]
+mod trace_test {
+    use kash::kash;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct HitCounter {
+        hits: AtomicUsize,
+        misses: AtomicUsize,
+        keys: std::sync::Mutex<Vec<String>>,
+    }
+
+    struct HitVisitor<'a>(&'a HitCounter);
+
+    impl Visit for HitVisitor<'_> {
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            if field.name() == "hit" {
+                if value {
+                    self.0.hits.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    self.0.misses.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "key" {
+                self.0.keys.lock().unwrap().push(value.to_string());
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "key" {
+                self.0.keys.lock().unwrap().push(format!("{value:?}"));
+            }
+        }
+    }
+
+    struct CountingSubscriber(Arc<HitCounter>);
+
+    impl Subscriber for CountingSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            event.record(&mut HitVisitor(&self.0));
+        }
+        fn enter(&self, _span: &Id) {}
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[kash(trace)]
+    fn traced(n: u32) -> u32 {
+        n * 2
+    }
+
+    #[test]
+    fn test_trace_hits_and_misses() {
+        let counter = Arc::new(HitCounter::default());
+        tracing::subscriber::with_default(CountingSubscriber(counter.clone()), || {
+            assert_eq!(2, traced(1));
+            assert_eq!(2, traced(1));
+            assert_eq!(4, traced(2));
+        });
+        assert_eq!(1, counter.hits.load(Ordering::SeqCst));
+        assert_eq!(2, counter.misses.load(Ordering::SeqCst));
+        assert_eq!(vec!["1", "1", "2"], *counter.keys.lock().unwrap());
+    }
+
+    #[kash(trace, redact_key)]
+    fn traced_email(email: String) -> usize {
+        email.len()
+    }
+
+    #[test]
+    fn test_trace_redact_key() {
+        let counter = Arc::new(HitCounter::default());
+        tracing::subscriber::with_default(CountingSubscriber(counter.clone()), || {
+            traced_email("someone@example.com".to_string());
+            traced_email("someone@example.com".to_string());
+        });
+        assert_eq!(1, counter.hits.load(Ordering::SeqCst));
+        assert_eq!(
+            vec!["<redacted>", "<redacted>"],
+            *counter.keys.lock().unwrap()
+        );
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod metrics_test {
+    use kash::kash;
+    use kash::metrics::Recorder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+    static MISSES: AtomicUsize = AtomicUsize::new(0);
+    static EVICTIONS: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountingRecorder;
+
+    impl Recorder for CountingRecorder {
+        fn record_hit(&self, cache_name: &'static str) {
+            if cache_name == "METERED" {
+                HITS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn record_miss(&self, cache_name: &'static str) {
+            if cache_name == "METERED" {
+                MISSES.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn record_evict(&self, cache_name: &'static str) {
+            if cache_name == "METERED" {
+                EVICTIONS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[kash(size = "1")]
+    fn metered(n: u32) -> u32 {
+        n
+    }
+
+    #[test]
+    fn test_metrics_recorder() {
+        kash::set_metrics_recorder(CountingRecorder).unwrap();
+        assert!(kash::set_metrics_recorder(CountingRecorder).is_err());
+
+        metered(1);
+        metered(1);
+        metered(2);
+        metered_sync();
+        metered(3);
+        metered_sync();
+
+        assert_eq!(1, HITS.load(Ordering::SeqCst));
+        assert_eq!(3, MISSES.load(Ordering::SeqCst));
+        assert!(EVICTIONS.load(Ordering::SeqCst) >= 1);
+    }
+}
@@ -1,6 +1,7 @@
 #![cfg(feature = "redis_store")]
 
 use kash::{kash, RedisCacheError};
+use std::sync::atomic::{AtomicU32, Ordering};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq, Clone)]
@@ -18,7 +19,7 @@ impl From<RedisCacheError> for TestError {
 }
 
 #[kash(
-    redis(prefix_block = "{ \"__kash_redis_proc_macro_test_fn_kash_redis:\" }"),
+    redis(prefix_block = "{ \"__kash_redis_proc_macro_test_fn_kash_redis\" }"),
     ttl = "1"
 )]
 fn kash_redis(n: u32) -> Result<u32, TestError> {
@@ -37,6 +38,37 @@ fn test_kash_redis() {
     assert_eq!(kash_redis(6), Err(TestError::Count(6)));
 }
 
+// only the `Ok` values are cached, so the error doesn't need to be `Clone`
+#[derive(Error, Debug, PartialEq)]
+enum NoCloneError {
+    #[error("error with redis cache `{0}`")]
+    RedisError(String),
+    #[error("count `{0}`")]
+    Count(u32),
+}
+
+impl From<RedisCacheError> for NoCloneError {
+    fn from(e: RedisCacheError) -> Self {
+        NoCloneError::RedisError(format!("{e:?}"))
+    }
+}
+
+#[kash(redis)]
+fn kash_redis_no_clone_error(n: u32) -> Result<u32, NoCloneError> {
+    if n < 5 {
+        Ok(n)
+    } else {
+        Err(NoCloneError::Count(n))
+    }
+}
+
+#[test]
+fn test_kash_redis_no_clone_error() {
+    assert_eq!(kash_redis_no_clone_error(1), Ok(1));
+    assert_eq!(kash_redis_no_clone_error(1), Ok(1));
+    assert_eq!(kash_redis_no_clone_error(5), Err(NoCloneError::Count(5)));
+}
+
 #[kash(redis)]
 fn kash_redis_cache_create(n: u32) -> Result<u32, TestError> {
     if n < 5 {
@@ -46,6 +78,22 @@ fn kash_redis_cache_create(n: u32) -> Result<u32, TestError> {
     }
 }
 
+#[kash(redis)]
+fn kash_redis_handled(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_redis_cache_handle() {
+    let handle = kash_redis_handled_cache();
+    handle.clear().unwrap();
+    assert_eq!(kash_redis_handled(1), Ok(1));
+    assert!(handle.contains(&1).unwrap());
+    assert_eq!(vec![("1".to_string(), 1)], handle.iter().unwrap());
+    handle.clear().unwrap();
+    assert!(handle.is_empty().unwrap());
+}
+
 #[test]
 fn test_kash_redis_cache_create() {
     assert_eq!(kash_redis_cache_create(1), Ok(1));
@@ -54,12 +102,174 @@ fn test_kash_redis_cache_create() {
     assert_eq!(kash_redis_cache_create(6), Err(TestError::Count(6)));
 }
 
+#[kash(redis(
+    namespace = "__kash_redis_test_namespace",
+    prefix = "kash_redis_namespaced"
+))]
+fn kash_redis_namespaced(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_redis_namespace_and_prefix() {
+    assert_eq!(kash_redis_namespaced(1), Ok(1));
+
+    let client = redis::Client::open(KASH_REDIS_NAMESPACED.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let exists: bool = redis::cmd("EXISTS")
+        .arg("__kash_redis_test_namespace:kash_redis_namespaced:1")
+        .query(&mut conn)
+        .unwrap();
+    assert!(exists);
+}
+
+#[kash(redis, ttl = "60", with_ttl)]
+fn kash_redis_expiring(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_redis_with_ttl() {
+    kash_redis_expiring_cache().clear().unwrap();
+    assert_eq!(
+        kash_redis_expiring_with_ttl(1, std::time::Duration::from_secs(600)),
+        Ok(1)
+    );
+
+    let client = redis::Client::open(KASH_REDIS_EXPIRING.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let ttl: i64 = redis::cmd("TTL")
+        .arg(concat!("kash:", module_path!(), "::KASH_REDIS_EXPIRING:1"))
+        .query(&mut conn)
+        .unwrap();
+    assert!(ttl > 60);
+}
+
+mod first {
+    use super::*;
+
+    #[kash(redis)]
+    pub fn same_name(n: u32) -> Result<u32, TestError> {
+        Ok(n)
+    }
+}
+
+mod second {
+    use super::*;
+
+    #[kash(redis)]
+    pub fn same_name(n: u32) -> Result<u32, TestError> {
+        Ok(n * 2)
+    }
+}
+
+#[test]
+fn test_kash_redis_default_prefix_has_module_path() {
+    first::same_name_cache().clear().unwrap();
+    second::same_name_cache().clear().unwrap();
+    assert_eq!(first::same_name(1), Ok(1));
+    assert_eq!(second::same_name(1), Ok(2));
+    assert_eq!(first::same_name(1), Ok(1));
+
+    let client = redis::Client::open(first::SAME_NAME.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let exists: bool = redis::cmd("EXISTS")
+        .arg(concat!("kash:", module_path!(), "::first::SAME_NAME:1"))
+        .query(&mut conn)
+        .unwrap();
+    assert!(exists);
+}
+
+static REFRESHED_CALLS: AtomicU32 = AtomicU32::new(0);
+
+#[kash(redis)]
+fn kash_redis_refreshed(n: u32) -> Result<u32, TestError> {
+    Ok(n + REFRESHED_CALLS.fetch_add(1, Ordering::SeqCst))
+}
+
+#[test]
+fn test_kash_redis_refresh_if_stale() {
+    kash_redis_refreshed_cache().clear().unwrap();
+    assert_eq!(kash_redis_refreshed(1), Ok(1));
+    assert_eq!(kash_redis_refreshed_refresh_if_stale(1), Ok(2));
+    assert_eq!(kash_redis_refreshed(1), Ok(2));
+}
+
+#[kash(
+    redis,
+    ttl = "3600",
+    ttl_for = "|n| (*n == 0).then(|| std::time::Duration::from_secs(1))"
+)]
+fn kash_redis_ttl_for(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_redis_ttl_for() {
+    use kash::IOKash;
+
+    assert_eq!(kash_redis_ttl_for(0), Ok(0));
+    assert_eq!(kash_redis_ttl_for(1), Ok(1));
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(KASH_REDIS_TTL_FOR.get(&0).unwrap(), None);
+    assert_eq!(KASH_REDIS_TTL_FOR.get(&1).unwrap(), Some(1));
+}
+
+#[kash(redis, ttl_ms = "500")]
+fn kash_redis_ttl_ms(n: u32) -> Result<u32, TestError> {
+    Ok(n)
+}
+
+#[test]
+fn test_kash_redis_ttl_ms() {
+    use kash::IOKash;
+
+    assert_eq!(kash_redis_ttl_ms(1), Ok(1));
+    assert_eq!(KASH_REDIS_TTL_MS.get(&1).unwrap(), Some(1));
+    std::thread::sleep(std::time::Duration::from_millis(600));
+    assert_eq!(KASH_REDIS_TTL_MS.get(&1).unwrap(), None);
+}
+
+#[kash(redis, max_key_len = 72, key_overflow = "hash")]
+fn kash_redis_hash_long_key(s: String) -> Result<usize, TestError> {
+    Ok(s.len())
+}
+
+#[kash(redis)]
+fn kash_redis_injected(n: u32) -> Result<u32, TestError> {
+    Err(TestError::Count(n))
+}
+
+#[test]
+fn test_kash_redis_injected() {
+    kash_redis_injected_set_cache(1, 10).unwrap();
+    assert_eq!(kash_redis_injected(1), Ok(10));
+}
+
+#[test]
+fn test_kash_redis_hash_long_key() {
+    assert_eq!(kash_redis_hash_long_key("a".repeat(100)), Ok(100));
+    assert_eq!(kash_redis_hash_long_key("a".repeat(100)), Ok(100));
+}
+
 #[cfg(feature = "redis_tokio")]
 mod async_redis_tests {
     use super::*;
 
+    #[kash(redis, max_key_len = 8, key_overflow = "error")]
+    async fn async_kash_redis_reject_long_key(s: String) -> Result<usize, TestError> {
+        Ok(s.len())
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_reject_long_key() {
+        assert!(async_kash_redis_reject_long_key("a".repeat(100))
+            .await
+            .is_err());
+    }
+
     #[kash(
-        redis(prefix_block = "{ \"__kash_redis_proc_macro_test_fn_async_kash_redis:\" }"),
+        redis(prefix_block = "{ \"__kash_redis_proc_macro_test_fn_async_kash_redis\" }"),
         ttl = "1"
     )]
     async fn async_kash_redis(n: u32) -> Result<u32, TestError> {
@@ -70,6 +280,23 @@ mod async_redis_tests {
         }
     }
 
+    #[kash(redis)]
+    async fn async_kash_redis_injected(n: u32) -> Result<u32, TestError> {
+        Err(TestError::Count(n))
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_injected() {
+        async_kash_redis_injected_set_cache(1, 10).await.unwrap();
+        assert_eq!(async_kash_redis_injected(1).await, Ok(10));
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_init_cache() {
+        async_kash_redis_init_cache().await.unwrap();
+        assert_eq!(async_kash_redis(1).await, Ok(1));
+    }
+
     #[tokio::test]
     async fn test_async_kash_redis() {
         assert_eq!(async_kash_redis(1).await, Ok(1));
@@ -78,6 +305,60 @@ mod async_redis_tests {
         assert_eq!(async_kash_redis(6).await, Err(TestError::Count(6)));
     }
 
+    static ASYNC_REFRESHED_CALLS: AtomicU32 = AtomicU32::new(0);
+
+    #[kash(redis(
+        prefix_block = "{ format!(\"__kash_redis_proc_macro_test_fn_async_refreshed:{:?}\", std::time::SystemTime::now()) }"
+    ))]
+    async fn async_kash_redis_refreshed(n: u32) -> Result<u32, TestError> {
+        Ok(n + ASYNC_REFRESHED_CALLS.fetch_add(1, Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_refresh_if_stale() {
+        assert_eq!(async_kash_redis_refreshed(1).await, Ok(1));
+        assert_eq!(async_kash_redis_refreshed_refresh_if_stale(1).await, Ok(2));
+        assert_eq!(async_kash_redis_refreshed(1).await, Ok(2));
+    }
+
+    #[kash(
+        redis,
+        option,
+        ttl_for = "|n| (*n == 0).then(|| std::time::Duration::from_secs(1))"
+    )]
+    async fn async_kash_redis_ttl_for(n: u32) -> Result<Option<u32>, TestError> {
+        Ok((n < 5).then_some(n))
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_ttl_for() {
+        assert_eq!(async_kash_redis_ttl_for(0).await, Ok(Some(0)));
+        assert_eq!(async_kash_redis_ttl_for(5).await, Ok(None));
+    }
+
+    #[kash(redis(
+        namespace = "__kash_redis_test_namespace",
+        prefix = "async_kash_redis_namespaced"
+    ))]
+    async fn async_kash_redis_namespaced(n: u32) -> Result<u32, TestError> {
+        Ok(n)
+    }
+
+    #[tokio::test]
+    async fn test_async_kash_redis_namespace_and_prefix() {
+        assert_eq!(async_kash_redis_namespaced(1).await, Ok(1));
+
+        let cache = ASYNC_KASH_REDIS_NAMESPACED.get().unwrap();
+        let client = redis::Client::open(cache.connection_string()).unwrap();
+        let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+        let exists: bool = redis::cmd("EXISTS")
+            .arg("__kash_redis_test_namespace:async_kash_redis_namespaced:1")
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        assert!(exists);
+    }
+
     #[kash(redis, ttl = "1", name = "async_kash_redis_test_cache_create")]
     async fn async_kash_redis_cache_create(n: u32) -> Result<u32, TestError> {
         if n < 5 {
@@ -0,0 +1,62 @@
+#![cfg(all(feature = "registry", feature = "mem_store"))]
+
+use kash::kash;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static CALLS: AtomicU32 = AtomicU32::new(0);
+
+#[kash]
+fn registered(n: u32) -> u32 {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+    n
+}
+
+struct Registered;
+
+impl Registered {
+    #[kash(in_impl)]
+    fn method(n: u32) -> u32 {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        n
+    }
+}
+
+trait RegisteredTrait {
+    #[kash(in_trait)]
+    fn default_method(n: u32) -> u32 {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        n
+    }
+}
+
+impl RegisteredTrait for Registered {}
+
+#[cfg(feature = "disk_store")]
+#[kash(disk)]
+fn registered_disk(n: u32) -> Result<u32, kash::DiskCacheError> {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+    Ok(n)
+}
+
+// call all the functions, and return how many of them were computed
+fn call_all() -> u32 {
+    let calls = CALLS.load(Ordering::SeqCst);
+    assert_eq!(1, registered(1));
+    assert_eq!(1, Registered::method(1));
+    assert_eq!(1, Registered::default_method(1));
+    #[cfg(feature = "disk_store")]
+    assert_eq!(1, registered_disk(1).unwrap());
+    CALLS.load(Ordering::SeqCst) - calls
+}
+
+#[test]
+fn clear_all_clears_every_cache() {
+    // the disk cache may be kept from a previous run
+    call_all();
+    assert_eq!(0, call_all());
+
+    kash::clear_all();
+    let functions = if cfg!(feature = "disk_store") { 4 } else { 3 };
+    assert_eq!(functions, call_all());
+    assert_eq!(0, call_all());
+}
@@ -0,0 +1,14 @@
+#![cfg(feature = "mem_store")]
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+    t.pass("tests/ui/pass/*.rs");
+    if cfg!(feature = "disk_store") {
+        t.compile_fail("tests/ui/disk/*.rs");
+    }
+    if cfg!(feature = "redis_store") {
+        t.compile_fail("tests/ui/redis/*.rs");
+    }
+}
@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(collect)]
+fn not_an_iterator(n: u32) -> Vec<u32> {
+    (0..n).collect()
+}
+
+fn main() {}
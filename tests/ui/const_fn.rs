@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash]
+const fn double(n: u32) -> u32 {
+    n * 2
+}
+
+fn main() {}
@@ -0,0 +1,21 @@
+use kash::kash;
+
+mod users {
+    use super::*;
+
+    #[kash(disk, result)]
+    pub fn load(id: u32) -> Result<String, kash::DiskCacheError> {
+        Ok(format!("user {id}"))
+    }
+}
+
+mod orders {
+    use super::*;
+
+    #[kash(disk, result)]
+    pub fn load(id: u32) -> Result<String, kash::DiskCacheError> {
+        Ok(format!("order {id}"))
+    }
+}
+
+fn main() {}
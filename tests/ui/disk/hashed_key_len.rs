@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(disk, max_key_len = 8, key_overflow = "hash")]
+fn hashed(s: String) -> Result<usize, String> {
+    Ok(s.len())
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(disk, option)]
+fn option_not_nested(n: u32) -> Result<u32, kash::DiskCacheError> {
+    Ok(n)
+}
+
+fn main() {}
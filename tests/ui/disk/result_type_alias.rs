@@ -0,0 +1,10 @@
+use kash::kash;
+
+type Fallible = Result<u32, kash::DiskCacheError>;
+
+#[kash(disk)]
+fn type_alias(n: u32) -> Fallible {
+    Ok(n)
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use kash::kash;
+
+const TTL_MS: i32 = 500;
+
+#[kash(disk, ttl_ms = "TTL_MS")]
+fn ttl_ms_mistyped(n: u32) -> Result<u32, kash::DiskCacheError> {
+    Ok(n)
+}
+
+fn main() {}
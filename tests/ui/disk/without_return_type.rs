@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(disk)]
+fn without_return_type(n: u32) {
+    let _ = n;
+}
+
+fn main() {}
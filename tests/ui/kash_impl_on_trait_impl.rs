@@ -0,0 +1,12 @@
+use kash::kash_impl;
+
+struct Shapes;
+
+#[kash_impl]
+impl Clone for Shapes {
+    fn clone(&self) -> Self {
+        Shapes
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(key(ty = "Vec<u32", expr = "vec![n]"))]
+fn key_ty_typo(n: u32) -> u32 {
+    n
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(maybe_async = r#"feature = "async""#)]
+async fn already_async(n: u32) -> u32 {
+    n
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+#![deny(unused_must_use)]
+
+use kash::kash;
+
+#[kash(must_use)]
+fn must_use_ignored(n: u32) -> u32 {
+    n
+}
+
+fn main() {
+    must_use_ignored(1);
+    must_use_ignored_prime_cache(1);
+}
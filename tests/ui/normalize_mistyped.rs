@@ -0,0 +1,13 @@
+use kash::kash;
+
+#[kash(normalize = "let n = n.trim();")]
+fn normalize_mistyped(n: u32) -> u32 {
+    n
+}
+
+#[kash(normalize = "let n = n +;")]
+fn normalize_typo(n: u32) -> u32 {
+    n
+}
+
+fn main() {}
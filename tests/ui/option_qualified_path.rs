@@ -0,0 +1,16 @@
+use kash::kash;
+
+trait Lookup {
+    type Output;
+}
+
+impl Lookup for u32 {
+    type Output = Option<u32>;
+}
+
+#[kash(option)]
+fn qualified_path(n: u32) -> <u32 as Lookup>::Output {
+    Some(n)
+}
+
+fn main() {}
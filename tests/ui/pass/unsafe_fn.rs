@@ -0,0 +1,22 @@
+use kash::kash;
+
+#[kash]
+unsafe fn double(n: u32) -> u32 {
+    n * 2
+}
+
+#[kash]
+unsafe extern "C" fn triple(n: u32) -> u32 {
+    n * 3
+}
+
+#[kash]
+extern "C" fn quadruple(n: u32) -> u32 {
+    n * 4
+}
+
+fn main() {
+    let n = unsafe { double(1) + double_no_cache(1) + double_prime_cache(1) };
+    let n = n + unsafe { triple(n) + triple_prime_cache(n) };
+    assert_eq!(quadruple(n), quadruple_no_cache(n));
+}
@@ -0,0 +1,13 @@
+use kash::kash;
+
+#[kash(redis(prefix = "users"), result)]
+fn user(id: u32) -> Result<String, kash::RedisCacheError> {
+    Ok(format!("user {id}"))
+}
+
+#[kash(redis(prefix = "users"), result)]
+fn user_name(id: u32) -> Result<String, kash::RedisCacheError> {
+    Ok(format!("name {id}"))
+}
+
+fn main() {}
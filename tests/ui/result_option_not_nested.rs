@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(result_option)]
+fn not_nested(n: u32) -> Result<u32, String> {
+    Ok(n)
+}
+
+fn main() {}
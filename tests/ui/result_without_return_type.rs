@@ -0,0 +1,8 @@
+use kash::kash;
+
+#[kash(result)]
+fn no_return_type(n: u32) {
+    println!("{n}");
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use kash::kash;
+
+static SHARED: kash::once_cell::sync::Lazy<kash::moka::sync::Cache<u32, u32>> =
+    kash::once_cell::sync::Lazy::new(|| kash::moka::sync::Cache::new(10));
+
+#[kash(shared_cache = "SHARED", size = "10")]
+fn shared_with_size(n: u32) -> u32 {
+    n
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use kash::kash;
+
+const TTL: &str = "60";
+
+#[kash(ttl = "TTL")]
+fn ttl_mistyped(n: u32) -> u32 {
+    n
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use kash::kash;
+use std::fmt::Display;
+
+#[kash(arc)]
+fn value_not_send_sync(n: u32) -> Box<dyn Display> {
+    Box::new(n)
+}
+
+fn main() {}